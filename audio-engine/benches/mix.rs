@@ -0,0 +1,47 @@
+use audio_engine::audio_buffer::AudioBuffer;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+
+/// The frame counts that mixing is measured at.
+const BUFFER_SIZES: [usize; 3] = [128, 1024, 4096];
+
+/// Mixes `src` onto `dst` one sample at a time. This is how `mix_from` was
+/// implemented before it was unrolled.
+fn mix_naive(dst: &mut AudioBuffer, src: &AudioBuffer, volume: f32) {
+    for (src, dst) in src.iter_channels().zip(dst.iter_channels_mut()) {
+        for (s, d) in src.iter().zip(dst.iter_mut()) {
+            *d += *s * volume;
+        }
+    }
+}
+
+/// Create a stereo buffer of `buffer_size` frames with a ramp in each channel.
+fn ramp(buffer_size: usize) -> AudioBuffer {
+    let channel: Vec<f32> = (0..buffer_size)
+        .map(|i| i as f32 / buffer_size as f32)
+        .collect();
+    AudioBuffer::from_channels(&[channel.clone(), channel])
+}
+
+fn bench_mix_from(c: &mut Criterion) {
+    let mut group = c.benchmark_group("mix_from");
+    for buffer_size in BUFFER_SIZES {
+        let src = ramp(buffer_size);
+        let mut dst = AudioBuffer::with_stereo(buffer_size);
+        for (name, volume) in [("unity", 1.0), ("scaled", 0.5)] {
+            group.bench_with_input(
+                BenchmarkId::new(format!("naive/{name}"), buffer_size),
+                &volume,
+                |b, volume| b.iter(|| mix_naive(&mut dst, black_box(&src), *volume)),
+            );
+            group.bench_with_input(
+                BenchmarkId::new(format!("unrolled/{name}"), buffer_size),
+                &volume,
+                |b, volume| b.iter(|| dst.mix_from(black_box(&src), *volume)),
+            );
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_mix_from);
+criterion_main!(benches);
@@ -1,7 +1,47 @@
+/// Describes a failure to load a wav file.
+#[derive(Debug)]
+pub enum WavError {
+    /// The file could not be opened or decoded.
+    Read(hound::Error),
+    /// The file uses a sample format that is not supported.
+    UnsupportedFormat {
+        format: hound::SampleFormat,
+        bits: u16,
+    },
+    /// The file has no channels.
+    NoChannels,
+    /// The file does not have a sample for each channel.
+    NoSamples,
+}
+
+impl From<hound::Error> for WavError {
+    fn from(value: hound::Error) -> Self {
+        WavError::Read(value)
+    }
+}
+
+impl std::fmt::Display for WavError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WavError::Read(err) => write!(f, "failed to read wav: {err}"),
+            WavError::UnsupportedFormat { format, bits } => {
+                write!(f, "{bits} bit {format:?} samples are not supported")
+            }
+            WavError::NoChannels => write!(f, "the wav has no channels"),
+            WavError::NoSamples => write!(f, "the wav has no samples"),
+        }
+    }
+}
+
+impl std::error::Error for WavError {}
+
 /// Contains audio data for several channels.
+#[derive(Clone)]
 pub struct AudioBuffer {
     buffer: Vec<f32>,
     buffer_size: usize,
+    /// The sample rate of the file the audio was loaded from, if any.
+    sample_rate: Option<f64>,
 }
 
 impl AudioBuffer {
@@ -10,6 +50,7 @@ impl AudioBuffer {
         AudioBuffer {
             buffer: vec![0f32; channels * buffer_size],
             buffer_size,
+            sample_rate: None,
         }
     }
 
@@ -18,76 +59,348 @@ impl AudioBuffer {
         AudioBuffer::new(2, buffer_size)
     }
 
-    /// Create a new audio buffer from a wave file.
-    pub fn with_wav(p: &std::path::Path) -> AudioBuffer {
-        let reader = hound::WavReader::open(p).unwrap();
+    /// Create a new audio buffer from the samples of each channel. Shorter
+    /// channels are padded with silence.
+    pub fn from_channels(channels: &[Vec<f32>]) -> AudioBuffer {
+        let buffer_size = channels.iter().map(Vec::len).max().unwrap_or(0);
+        let mut buffer = Vec::with_capacity(channels.len() * buffer_size);
+        for channel in channels {
+            buffer.extend_from_slice(channel);
+            buffer.resize(buffer.len() + buffer_size - channel.len(), 0.0);
+        }
+        AudioBuffer {
+            buffer,
+            buffer_size,
+            sample_rate: None,
+        }
+    }
+
+    /// Create a new audio buffer from the contents of a wav file. Each
+    /// channel of the file becomes a channel of the buffer.
+    pub fn with_wav(p: &std::path::Path) -> Result<AudioBuffer, WavError> {
+        AudioBuffer::from_wav_reader(hound::WavReader::open(p)?)
+    }
+
+    /// Create a new audio buffer from the contents of a wav file that is
+    /// already in memory.
+    pub fn from_wav_bytes(bytes: &[u8]) -> Result<AudioBuffer, WavError> {
+        AudioBuffer::from_wav_reader(hound::WavReader::new(std::io::Cursor::new(bytes))?)
+    }
+
+    fn from_wav_reader<R: std::io::Read>(
+        reader: hound::WavReader<R>,
+    ) -> Result<AudioBuffer, WavError> {
         let specs = reader.spec();
-        if specs.channels != 1 {
-            unimplemented!(
-                "Only a single channel is supported bug {p:?} contains {} channels.",
-                specs.channels
-            );
+        if specs.channels == 0 {
+            return Err(WavError::NoChannels);
+        }
+        let interleaved: Vec<f32> = match (specs.sample_format, specs.bits_per_sample) {
+            (hound::SampleFormat::Int, bits @ (8 | 16 | 24 | 32)) => {
+                // Integer samples are scaled so that the full range of the
+                // bit depth maps to [-1.0, 1.0).
+                let scale = 1.0 / (1u64 << (bits - 1)) as f64;
+                reader
+                    .into_samples()
+                    .map(|s: Result<i32, _>| s.map(|s| (s as f64 * scale) as f32))
+                    .collect::<Result<_, _>>()?
+            }
+            (hound::SampleFormat::Float, 32) => reader.into_samples().collect::<Result<_, _>>()?,
+            (format, bits) => return Err(WavError::UnsupportedFormat { format, bits }),
+        };
+        // A file with fewer samples than channels does not hold a single
+        // complete frame.
+        if interleaved.len() < specs.channels as usize {
+            return Err(WavError::NoSamples);
+        }
+        let mut buffer = AudioBuffer::from_interleaved(&interleaved, specs.channels as usize);
+        buffer.sample_rate = Some(specs.sample_rate as f64);
+        Ok(buffer)
+    }
+
+    /// Get the sample rate of the file the audio was loaded from. This is
+    /// `None` for buffers that were not loaded from a file.
+    pub fn sample_rate(&self) -> Option<f64> {
+        self.sample_rate
+    }
+
+    /// Create a copy of the audio converted from `from_rate` to `to_rate`
+    /// using linear interpolation.
+    pub fn resampled(&self, from_rate: f64, to_rate: f64) -> AudioBuffer {
+        let ratio = from_rate / to_rate;
+        let buffer_size = (self.buffer_size as f64 / ratio).round() as usize;
+        let mut buffer = Vec::with_capacity(buffer_size * self.channels());
+        for src in self.iter_channels() {
+            buffer.extend((0..buffer_size).map(|frame| {
+                let position = frame as f64 * ratio;
+                let idx = position.floor() as usize;
+                let frac = (position - idx as f64) as f32;
+                let a = src.get(idx).copied().unwrap_or(0.0);
+                let b = src.get(idx + 1).copied().unwrap_or(a);
+                a + (b - a) * frac
+            }));
+        }
+        AudioBuffer {
+            buffer,
+            buffer_size,
+            sample_rate: Some(to_rate),
         }
-        if specs.bits_per_sample != 16 {
-            unimplemented!(
-                "Only 16 bits per sample supported for wav but {p:?} contains {} bits per channel.",
-                specs.bits_per_sample
+    }
+
+    /// Create a new audio buffer from samples that alternate between
+    /// `channels` channels. A trailing partial frame is dropped.
+    pub fn from_interleaved(interleaved: &[f32], channels: usize) -> AudioBuffer {
+        let buffer_size = interleaved.len() / channels;
+        let mut buffer = Vec::with_capacity(buffer_size * channels);
+        for channel in 0..channels {
+            buffer.extend(
+                interleaved
+                    .iter()
+                    .skip(channel)
+                    .step_by(channels)
+                    .take(buffer_size),
             );
         }
-        let buffer: Vec<f32> = reader
-            .into_samples()
-            .map(Result::unwrap)
-            .map(|s: i16| s as f64 / i16::MAX as f64)
-            .map(|s| s as f32)
-            .collect();
-        let buffer_size = buffer.len();
         AudioBuffer {
             buffer,
             buffer_size,
+            sample_rate: None,
         }
     }
 
+    /// Returns the number of frames in each channel.
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
     /// Returns the number of channels.
     pub fn channels(&self) -> usize {
         self.buffer.len() / self.buffer_size
     }
 
-    /// Sets all the values to 0.
+    /// Sets all the values to 0, or to a tiny DC offset on platforms where
+    /// denormals can't be flushed to zero.
     pub fn reset(&mut self) {
         for v in self.buffer.iter_mut() {
-            *v = 0f32;
+            *v = crate::denormals::RESET_VALUE;
         }
     }
 
     /// Resizes by `buffer_size` and rests the values.
     pub fn reset_with_buffer_size(&mut self, buffer_size: usize) {
         if buffer_size != self.buffer_size {
-            self.buffer_size = buffer_size;
             let desired_len = buffer_size * self.channels();
+            self.buffer_size = buffer_size;
             self.buffer.resize(desired_len, 0.0);
         }
         self.reset();
     }
 
+    /// Copy the channels of `src` into this buffer starting at frame `offset`.
+    /// Frames that don't fit are dropped.
+    pub fn copy_from_at(&mut self, src: &AudioBuffer, offset: usize) {
+        for (dst, src) in self.iter_channels_mut().zip(src.iter_channels()) {
+            let dst = match dst.get_mut(offset..) {
+                Some(d) => d,
+                None => continue,
+            };
+            let len = dst.len().min(src.len());
+            dst[..len].copy_from_slice(&src[..len]);
+        }
+    }
+
+    /// Multiplies all values by `gain`.
+    pub fn apply_gain(&mut self, gain: f32) {
+        for v in self.buffer.iter_mut() {
+            *v *= gain;
+        }
+    }
+
+    /// Scales all channels by the same gain so that the largest absolute
+    /// value is `target_peak`. Silent buffers are left untouched.
+    pub fn normalize(&mut self, target_peak: f32) {
+        let peak = self.buffer.iter().fold(0f32, |peak, v| peak.max(v.abs()));
+        if peak > 0.0 {
+            self.apply_gain(target_peak / peak);
+        }
+    }
+
+    /// Fades in linearly from silence over the first `samples` frames.
+    pub fn fade_in(&mut self, samples: usize) {
+        let samples = samples.min(self.buffer_size);
+        if samples == 0 {
+            return;
+        }
+        for channel in self.iter_channels_mut() {
+            for (idx, v) in channel[..samples].iter_mut().enumerate() {
+                *v *= idx as f32 / samples as f32;
+            }
+        }
+    }
+
+    /// Fades out linearly to silence over the last `samples` frames.
+    pub fn fade_out(&mut self, samples: usize) {
+        let samples = samples.min(self.buffer_size);
+        if samples == 0 {
+            return;
+        }
+        let start = self.buffer_size - samples;
+        for channel in self.iter_channels_mut() {
+            for (idx, v) in channel[start..].iter_mut().enumerate() {
+                *v *= (samples - 1 - idx) as f32 / samples as f32;
+            }
+        }
+    }
+
+    /// Create a copy of the frames in `start..end`. `end` is clamped to the
+    /// length of the buffer. Returns `None` if the range is empty.
+    pub fn trim(&self, start: usize, end: usize) -> Option<AudioBuffer> {
+        let end = end.min(self.buffer_size);
+        if start >= end {
+            return None;
+        }
+        let buffer_size = end - start;
+        let mut buffer = Vec::with_capacity(buffer_size * self.channels());
+        for channel in self.iter_channels() {
+            buffer.extend_from_slice(&channel[start..end]);
+        }
+        Some(AudioBuffer {
+            buffer,
+            buffer_size,
+            sample_rate: self.sample_rate,
+        })
+    }
+
+    /// Multiplies all values by a gain that moves linearly from `from` to `to`
+    /// across the buffer. A gain of 0.0 produces exact zeros.
+    pub fn apply_gain_ramp(&mut self, from: f32, to: f32) {
+        if from == 0.0 && to == 0.0 {
+            for v in self.buffer.iter_mut() {
+                *v = 0.0;
+            }
+            return;
+        }
+        let step = (to - from) / self.buffer_size as f32;
+        for channel in self.iter_channels_mut() {
+            for (idx, v) in channel.iter_mut().enumerate() {
+                *v *= from + step * (idx + 1) as f32;
+            }
+        }
+    }
+
+    /// Get the largest absolute value in each channel.
+    pub fn peaks(&self) -> impl '_ + Iterator<Item = f32> {
+        self.iter_channels()
+            .map(|c| c.iter().fold(0f32, |peak, v| peak.max(v.abs())))
+    }
+
+    /// Get the largest absolute value of the first two channels without
+    /// allocating. A mono buffer reports the same value for both.
+    pub fn peak_per_channel(&self) -> [f32; 2] {
+        let mut peaks = [0f32; 2];
+        let mut channel_peaks = self.peaks();
+        peaks[0] = channel_peaks.next().unwrap_or(0.0);
+        peaks[1] = channel_peaks.next().unwrap_or(peaks[0]);
+        peaks
+    }
+
+    /// Get the root mean square of the first two channels without allocating.
+    /// A mono buffer reports the same value for both.
+    pub fn rms_per_channel(&self) -> [f32; 2] {
+        let mut rms = [0f32; 2];
+        let mut channels = self.iter_channels().map(|c| {
+            let sum = c.iter().fold(0f32, |sum, v| sum + v * v);
+            (sum / c.len().max(1) as f32).sqrt()
+        });
+        rms[0] = channels.next().unwrap_or(0.0);
+        rms[1] = channels.next().unwrap_or(rms[0]);
+        rms
+    }
+
+    /// Softly clip all values so that they never exceed 1.0. Values below
+    /// `threshold` are left untouched and values above it are smoothly
+    /// compressed into the remaining headroom.
+    pub fn soft_clip(&mut self, threshold: f32) {
+        let headroom = 1.0 - threshold;
+        for v in self.buffer.iter_mut() {
+            let magnitude = v.abs();
+            if magnitude > threshold {
+                let clipped = threshold + headroom * ((magnitude - threshold) / headroom).tanh();
+                *v = clipped.min(1.0).copysign(*v);
+            }
+        }
+    }
+
     /// Mixes the buffers from `src` onto `self`.
     pub fn mix_from(&mut self, src: &AudioBuffer, volume: f32) {
         for (src, dst) in src.iter_channels().zip(self.iter_channels_mut()) {
-            for (src, dst) in src.iter().zip(dst.iter_mut()) {
-                *dst += *src * volume;
-            }
+            mix_slice(src, dst, volume);
+        }
+    }
+
+    /// Mixes the buffers from `src` onto `self` with constant-power panning.
+    ///
+    /// `pan` ranges from -1.0 (left) to 1.0 (right). A centered pan leaves the
+    /// signal at unity gain. If `src` has fewer channels than `self`, its last
+    /// channel is used for the remaining channels. Nothing is mixed if `src`
+    /// has no channels.
+    pub fn mix_from_panned(&mut self, src: &AudioBuffer, volume: f32, pan: f32) {
+        let angle = (pan.clamp(-1.0, 1.0) + 1.0) * std::f32::consts::FRAC_PI_4;
+        let gains = [
+            angle.cos() * std::f32::consts::SQRT_2,
+            angle.sin() * std::f32::consts::SQRT_2,
+        ];
+        let src_channels = src.channels();
+        if src_channels == 0 {
+            return;
+        }
+        for (idx, dst) in self.iter_channels_mut().enumerate() {
+            let src = src.iter_channels().nth(idx.min(src_channels - 1)).unwrap();
+            let volume = volume * gains.get(idx).copied().unwrap_or(1.0);
+            mix_slice(src, dst, volume);
         }
     }
 
     /// Iterate over all the channels.
-    pub fn iter_channels(&self) -> impl ExactSizeIterator + Iterator<Item = &[f32]> {
+    pub fn iter_channels(&self) -> impl ExactSizeIterator<Item = &[f32]> {
         self.buffer.chunks_exact(self.buffer_size)
     }
 
     /// Iterate over all the channels mutably.
-    pub fn iter_channels_mut(&mut self) -> impl ExactSizeIterator + Iterator<Item = &mut [f32]> {
+    pub fn iter_channels_mut(&mut self) -> impl ExactSizeIterator<Item = &mut [f32]> {
         self.buffer.chunks_exact_mut(self.buffer_size)
     }
 
+    /// Replaces the first channel with the average of all channels.
+    pub fn downmix_to_first_channel(&mut self) {
+        let channels = self.channels();
+        if channels < 2 {
+            return;
+        }
+        let (first, rest) = self.buffer.split_at_mut(self.buffer_size);
+        for src in rest.chunks_exact(self.buffer_size) {
+            for (dst, src) in first.iter_mut().zip(src) {
+                *dst += src;
+            }
+        }
+        let gain = 1.0 / channels as f32;
+        for v in first.iter_mut() {
+            *v *= gain;
+        }
+    }
+
+    /// Replaces the contents with the channels of `src` where channel `i` of
+    /// `src` is summed into channel `i % self.channels()`. For a stereo buffer,
+    /// odd channels of `src` are mixed to the left and even channels to the
+    /// right.
+    pub fn fold_channels_from(&mut self, src: &AudioBuffer) {
+        self.reset();
+        let channels = self.channels();
+        for (idx, src) in src.iter_channels().enumerate() {
+            let dst = self.iter_channels_mut().nth(idx % channels).unwrap();
+            mix_slice(src, dst, 1.0);
+        }
+    }
+
     /// Copies the data from the first channel to all other channels.
     pub fn copy_first_channel_to_all(&mut self) {
         let (src, dsts) = self.buffer.split_at_mut(self.buffer_size);
@@ -106,3 +419,388 @@ impl std::fmt::Debug for AudioBuffer {
             .finish()
     }
 }
+
+/// Adds `src` scaled by `volume` onto `dst`. This is the hot path of mixing so
+/// the loop works on fixed chunks of 4 that the compiler can vectorize, and a
+/// volume of 0.0 or 1.0 skips the work or the multiply.
+fn mix_slice(src: &[f32], dst: &mut [f32], volume: f32) {
+    if volume == 0.0 {
+        return;
+    }
+    let len = src.len().min(dst.len());
+    let mut src = src[..len].chunks_exact(4);
+    let mut dst = dst[..len].chunks_exact_mut(4);
+    if volume == 1.0 {
+        for (s, d) in (&mut src).zip(&mut dst) {
+            d[0] += s[0];
+            d[1] += s[1];
+            d[2] += s[2];
+            d[3] += s[3];
+        }
+        for (s, d) in src.remainder().iter().zip(dst.into_remainder()) {
+            *d += *s;
+        }
+    } else {
+        for (s, d) in (&mut src).zip(&mut dst) {
+            d[0] += s[0] * volume;
+            d[1] += s[1] * volume;
+            d[2] += s[2] * volume;
+            d[3] += s[3] * volume;
+        }
+        for (s, d) in src.remainder().iter().zip(dst.into_remainder()) {
+            *d += *s * volume;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Generates deterministic pseudo random numbers for property tests.
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next_f32(&mut self) -> f32 {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            ((self.0 >> 40) as f32 / (1u64 << 24) as f32) * 2.0 - 1.0
+        }
+
+        fn next_len(&mut self, max: usize) -> usize {
+            ((self.next_f32() + 1.0) / 2.0 * max as f32) as usize % (max + 1)
+        }
+    }
+
+    fn random_buffer(rng: &mut Lcg, channels: usize, buffer_size: usize) -> AudioBuffer {
+        let channels: Vec<Vec<f32>> = (0..channels)
+            .map(|_| (0..buffer_size).map(|_| rng.next_f32()).collect())
+            .collect();
+        AudioBuffer::from_channels(&channels)
+    }
+
+    fn mix_naive(dst: &mut AudioBuffer, src: &AudioBuffer, volume: f32) {
+        for (src, dst) in src.iter_channels().zip(dst.iter_channels_mut()) {
+            for (s, d) in src.iter().zip(dst.iter_mut()) {
+                *d += *s * volume;
+            }
+        }
+    }
+
+    #[test]
+    fn reset_with_buffer_size_keeps_the_channels() {
+        let mut buffer = AudioBuffer::with_stereo(1024);
+        for buffer_size in [64, 480, 1024, 1, 1024] {
+            buffer.reset_with_buffer_size(buffer_size);
+            assert_eq!(buffer.buffer_size(), buffer_size);
+            assert_eq!(buffer.channels(), 2);
+        }
+    }
+
+    #[test]
+    fn soft_clip_only_compresses_values_above_the_threshold() {
+        let values: Vec<f32> = (-400..=400).map(|v| v as f32 / 100.0).collect();
+        let mut buffer = AudioBuffer::from_channels(std::slice::from_ref(&values));
+        buffer.soft_clip(0.8);
+        let clipped = buffer.iter_channels().next().unwrap();
+        for (v, clipped) in values.iter().zip(clipped.iter()) {
+            if v.abs() <= 0.8 {
+                assert_eq!(v, clipped);
+            } else {
+                assert!(clipped.abs() <= 1.0, "{v} was clipped to {clipped}");
+                assert!(clipped.abs() > 0.8, "{v} was clipped to {clipped}");
+                assert_eq!(v.signum(), clipped.signum());
+            }
+        }
+        assert!(clipped.windows(2).all(|w| w[0] <= w[1]));
+    }
+
+    #[test]
+    fn mix_from_matches_naive_mix() {
+        let mut rng = Lcg(42);
+        for _ in 0..256 {
+            let buffer_size = rng.next_len(67).max(1);
+            let src = random_buffer(&mut rng, 2, buffer_size);
+            let dst = random_buffer(&mut rng, 2, buffer_size);
+            for volume in [0.0, 1.0, rng.next_f32(), rng.next_f32() * 4.0] {
+                let mut expected = dst.clone();
+                mix_naive(&mut expected, &src, volume);
+                let mut actual = dst.clone();
+                actual.mix_from(&src, volume);
+                assert_eq!(actual.buffer, expected.buffer, "volume {volume}");
+            }
+        }
+    }
+
+    #[test]
+    fn mix_from_mixes_up_to_the_shorter_buffer() {
+        let src = AudioBuffer::from_channels(&[vec![1.0; 7], vec![2.0; 7]]);
+        let mut dst = AudioBuffer::with_stereo(5);
+        dst.mix_from(&src, 0.5);
+        assert_eq!(
+            dst.iter_channels().collect::<Vec<_>>(),
+            vec![&[0.5; 5][..], &[1.0; 5][..]]
+        );
+    }
+
+    /// Write `interleaved` samples to an in memory wav file.
+    fn wav_bytes<S: hound::Sample + Copy>(
+        channels: u16,
+        bits_per_sample: u16,
+        sample_format: hound::SampleFormat,
+        interleaved: &[S],
+    ) -> Vec<u8> {
+        let spec = hound::WavSpec {
+            channels,
+            sample_rate: 44100,
+            bits_per_sample,
+            sample_format,
+        };
+        let mut bytes = std::io::Cursor::new(Vec::new());
+        let mut writer = hound::WavWriter::new(&mut bytes, spec).unwrap();
+        for sample in interleaved {
+            writer.write_sample(*sample).unwrap();
+        }
+        writer.finalize().unwrap();
+        bytes.into_inner()
+    }
+
+    #[test]
+    fn wav_channels_are_deinterleaved() {
+        let frames = 50;
+        for channels in [1, 2, 4] {
+            let value = |channel: usize, frame: usize| (channel * 1000 + frame) as i16;
+            let interleaved: Vec<i16> = (0..frames)
+                .flat_map(|frame| (0..channels).map(move |channel| value(channel, frame)))
+                .collect();
+            let bytes = wav_bytes(channels as u16, 16, hound::SampleFormat::Int, &interleaved);
+            let buffer = AudioBuffer::from_wav_bytes(&bytes).unwrap();
+            assert_eq!(buffer.channels(), channels);
+            assert_eq!(buffer.buffer_size(), frames);
+            assert_eq!(buffer.sample_rate(), Some(44100.0));
+            for (channel, samples) in buffer.iter_channels().enumerate() {
+                let expected: Vec<f32> = (0..frames)
+                    .map(|frame| value(channel, frame) as f32 / 32768.0)
+                    .collect();
+                assert_eq!(samples, expected.as_slice(), "channel {channel}");
+            }
+        }
+    }
+
+    #[test]
+    fn wav_sample_formats_round_trip() {
+        let sine: Vec<f32> = (0..200).map(|i| (i as f32 * 0.1).sin() * 0.99).collect();
+        let check = |bytes: Vec<u8>, epsilon: f32| {
+            let buffer = AudioBuffer::from_wav_bytes(&bytes).unwrap();
+            let loaded = buffer.iter_channels().next().unwrap();
+            assert_eq!(loaded.len(), sine.len());
+            for (expected, actual) in sine.iter().zip(loaded.iter()) {
+                assert!(
+                    (expected - actual).abs() <= epsilon,
+                    "{expected} != {actual}"
+                );
+                assert!(actual.abs() <= 1.0);
+            }
+        };
+        let quantize = |v: f32, bits: u16| {
+            let max = (1i64 << (bits - 1)) as f64;
+            (v as f64 * max).round().clamp(-max, max - 1.0) as i64
+        };
+        let int = hound::SampleFormat::Int;
+        let as_i8: Vec<i8> = sine.iter().map(|v| quantize(*v, 8) as i8).collect();
+        check(wav_bytes(1, 8, int, &as_i8), 1.0 / 128.0);
+        let as_i16: Vec<i16> = sine.iter().map(|v| quantize(*v, 16) as i16).collect();
+        check(wav_bytes(1, 16, int, &as_i16), 1.0 / 32768.0);
+        for bits in [24, 32] {
+            let as_i32: Vec<i32> = sine.iter().map(|v| quantize(*v, bits) as i32).collect();
+            check(wav_bytes(1, bits, int, &as_i32), 1e-6);
+        }
+        check(wav_bytes(1, 32, hound::SampleFormat::Float, &sine), 0.0);
+    }
+
+    #[test]
+    fn wav_full_scale_maps_to_unity() {
+        let bytes = wav_bytes(
+            1,
+            24,
+            hound::SampleFormat::Int,
+            &[-(1 << 23), (1 << 23) - 1],
+        );
+        let buffer = AudioBuffer::from_wav_bytes(&bytes).unwrap();
+        let loaded = buffer.iter_channels().next().unwrap();
+        assert_eq!(loaded[0], -1.0);
+        assert!(loaded[1] < 1.0 && loaded[1] > 0.9999);
+    }
+
+    #[test]
+    fn missing_wav_is_an_error() {
+        let path = std::env::temp_dir().join("mini-leebee-missing-test.wav");
+        assert!(matches!(
+            AudioBuffer::with_wav(&path),
+            Err(WavError::Read(hound::Error::IoError(_)))
+        ));
+    }
+
+    #[test]
+    fn truncated_wav_is_an_error() {
+        let bytes = wav_bytes(2, 16, hound::SampleFormat::Int, &[1i16; 64]);
+        for len in [0, 4, 20, 40] {
+            assert!(
+                matches!(
+                    AudioBuffer::from_wav_bytes(&bytes[..len]),
+                    Err(WavError::Read(_))
+                ),
+                "{len} bytes"
+            );
+        }
+        // The header claims more samples than the data holds.
+        assert!(matches!(
+            AudioBuffer::from_wav_bytes(&bytes[..bytes.len() - 3]),
+            Err(WavError::Read(_))
+        ));
+        let empty = wav_bytes::<i16>(2, 16, hound::SampleFormat::Int, &[]);
+        assert!(matches!(
+            AudioBuffer::from_wav_bytes(&empty),
+            Err(WavError::NoSamples)
+        ));
+    }
+
+    #[test]
+    fn unsupported_wav_format_is_an_error() {
+        // Hound only writes whole bytes, so mark 20 of the 24 bits of each
+        // sample as valid in the WAVE_FORMAT_EXTENSIBLE header.
+        let mut bytes = wav_bytes(1, 24, hound::SampleFormat::Int, &[1i32; 8]);
+        bytes[38..40].copy_from_slice(&20u16.to_le_bytes());
+        assert!(matches!(
+            AudioBuffer::from_wav_bytes(&bytes),
+            Err(WavError::UnsupportedFormat {
+                format: hound::SampleFormat::Int,
+                bits: 20
+            })
+        ));
+    }
+
+    /// Count the upward zero crossings of `channel`.
+    fn upward_zero_crossings(channel: &[f32]) -> usize {
+        channel
+            .windows(2)
+            .filter(|w| w[0] < 0.0 && w[1] >= 0.0)
+            .count()
+    }
+
+    #[test]
+    fn resampled_length_scales_by_the_rate_ratio() {
+        let buffer = AudioBuffer::from_channels(&[vec![0.5; 44100], vec![-0.5; 44100]]);
+        for (to_rate, expected) in [(96000.0, 96000), (48000.0, 48000), (22050.0, 22050)] {
+            let resampled = buffer.resampled(44100.0, to_rate);
+            assert_eq!(resampled.buffer_size(), expected);
+            assert_eq!(resampled.channels(), 2);
+            assert_eq!(resampled.sample_rate(), Some(to_rate));
+            let channels: Vec<&[f32]> = resampled.iter_channels().collect();
+            assert!(channels[0].iter().all(|v| *v == 0.5));
+            assert!(channels[1].iter().all(|v| *v == -0.5));
+        }
+    }
+
+    #[test]
+    fn resampled_tone_keeps_its_pitch() {
+        let from_rate = 44100.0;
+        // The tone starts just after an upward crossing, so a second of it
+        // holds 439 more.
+        let tone: Vec<f32> = (0..44100)
+            .map(|i| ((i as f64 + 0.5) * 440.0 / from_rate * std::f64::consts::TAU).sin() as f32)
+            .collect();
+        let buffer = AudioBuffer::from_channels(&[tone]);
+        assert_eq!(
+            upward_zero_crossings(buffer.iter_channels().next().unwrap()),
+            439
+        );
+        for to_rate in [96000.0, 48000.0, 22050.0] {
+            let resampled = buffer.resampled(from_rate, to_rate);
+            let channel = resampled.iter_channels().next().unwrap();
+            let seconds = channel.len() as f64 / to_rate;
+            assert_eq!(seconds, 1.0);
+            assert_eq!(upward_zero_crossings(channel), 439, "{to_rate} Hz");
+        }
+    }
+
+    fn channels_of(buffer: &AudioBuffer) -> Vec<Vec<f32>> {
+        buffer.iter_channels().map(|c| c.to_vec()).collect()
+    }
+
+    #[test]
+    fn apply_gain_scales_every_channel() {
+        let mut buffer = AudioBuffer::from_channels(&[vec![1.0, -0.5], vec![0.25, 0.0]]);
+        buffer.apply_gain(2.0);
+        assert_eq!(channels_of(&buffer), vec![vec![2.0, -1.0], vec![0.5, 0.0]]);
+    }
+
+    #[test]
+    fn normalize_scales_the_loudest_channel_to_the_target() {
+        let mut buffer = AudioBuffer::from_channels(&[vec![0.1, -0.2], vec![0.4, -0.5]]);
+        buffer.normalize(1.0);
+        assert_eq!(channels_of(&buffer), vec![vec![0.2, -0.4], vec![0.8, -1.0]]);
+
+        let mut single = AudioBuffer::from_channels(&[vec![-0.25]]);
+        single.normalize(0.5);
+        assert_eq!(channels_of(&single), vec![vec![-0.5]]);
+
+        let mut silent = AudioBuffer::from_channels(&[vec![0.0; 4]]);
+        silent.normalize(1.0);
+        assert_eq!(channels_of(&silent), vec![vec![0.0; 4]]);
+    }
+
+    #[test]
+    fn fades_ramp_from_and_to_silence() {
+        let mut buffer = AudioBuffer::from_channels(&[vec![1.0; 8], vec![-1.0; 8]]);
+        buffer.fade_in(4);
+        buffer.fade_out(2);
+        assert_eq!(
+            channels_of(&buffer),
+            vec![
+                vec![0.0, 0.25, 0.5, 0.75, 1.0, 1.0, 0.5, 0.0],
+                vec![0.0, -0.25, -0.5, -0.75, -1.0, -1.0, -0.5, 0.0],
+            ]
+        );
+
+        // Fades longer than the buffer cover the whole buffer.
+        let mut buffer = AudioBuffer::from_channels(&[vec![1.0; 4]]);
+        buffer.fade_out(100);
+        assert_eq!(channels_of(&buffer), vec![vec![0.75, 0.5, 0.25, 0.0]]);
+
+        let mut single = AudioBuffer::from_channels(&[vec![1.0]]);
+        single.fade_in(0);
+        single.fade_out(0);
+        assert_eq!(channels_of(&single), vec![vec![1.0]]);
+        single.fade_in(1);
+        assert_eq!(channels_of(&single), vec![vec![0.0]]);
+    }
+
+    #[test]
+    fn edits_of_empty_buffers_do_nothing() {
+        let mut buffer = AudioBuffer::new(2, 0);
+        buffer.apply_gain(2.0);
+        buffer.normalize(1.0);
+        buffer.fade_in(4);
+        buffer.fade_out(4);
+        assert_eq!(buffer.buffer_size(), 0);
+        assert!(buffer.trim(0, 4).is_none());
+    }
+
+    #[test]
+    fn trim_copies_the_range_of_every_channel() {
+        let mut buffer =
+            AudioBuffer::from_channels(&[vec![0.0, 1.0, 2.0, 3.0], vec![4.0, 5.0, 6.0, 7.0]]);
+        buffer.sample_rate = Some(48000.0);
+        let trimmed = buffer.trim(1, 3).unwrap();
+        assert_eq!(channels_of(&trimmed), vec![vec![1.0, 2.0], vec![5.0, 6.0]]);
+        assert_eq!(trimmed.sample_rate(), Some(48000.0));
+        let single = buffer.trim(3, 100).unwrap();
+        assert_eq!(channels_of(&single), vec![vec![3.0], vec![7.0]]);
+        assert!(buffer.trim(2, 2).is_none());
+        assert!(buffer.trim(3, 1).is_none());
+        assert!(buffer.trim(4, 8).is_none());
+    }
+}
@@ -0,0 +1,216 @@
+use std::sync::{Arc, Mutex};
+
+use ringbuf::{HeapConsumer, HeapProducer, HeapRb};
+
+/// Create a channel that holds up to `capacity` values. The receiver is lock
+/// free and may be used on the processing thread. Senders share a lock so they
+/// should only be used outside of the processing thread.
+pub fn command_channel<T>(capacity: usize) -> (Sender<T>, RtReceiver<T>) {
+    let (producer, consumer) = HeapRb::new(capacity).split();
+    let sender = Sender {
+        producer: Arc::new(Mutex::new(producer)),
+    };
+    (sender, RtReceiver { consumer })
+}
+
+/// Create a channel that holds up to `capacity` values. The sender is lock free
+/// and may be used on the processing thread.
+pub fn notification_channel<T>(capacity: usize) -> (RtSender<T>, Receiver<T>) {
+    let (producer, consumer) = HeapRb::new(capacity).split();
+    let receiver = Receiver {
+        consumer: Mutex::new(consumer),
+    };
+    (RtSender { producer }, receiver)
+}
+
+/// The error returned when sending to a channel that is full. It contains the
+/// value that could not be sent.
+#[derive(Debug, PartialEq)]
+pub struct Full<T>(pub T);
+
+impl<T> std::fmt::Display for Full<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "channel is full")
+    }
+}
+
+impl<T: std::fmt::Debug> std::error::Error for Full<T> {}
+
+/// Sends values to the processing thread.
+pub struct Sender<T> {
+    producer: Arc<Mutex<HeapProducer<T>>>,
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Sender<T> {
+        Sender {
+            producer: self.producer.clone(),
+        }
+    }
+}
+
+impl<T> Sender<T> {
+    /// Send `value` without blocking. If the channel is full, `value` is
+    /// returned in the error.
+    pub fn send(&self, value: T) -> Result<(), Full<T>> {
+        self.producer.lock().unwrap().push(value).map_err(Full)
+    }
+}
+
+impl<T> std::fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Sender").finish_non_exhaustive()
+    }
+}
+
+/// Receives values from the processing thread.
+pub struct Receiver<T> {
+    consumer: Mutex<HeapConsumer<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// Iterate over the values that are in the channel without blocking.
+    pub fn try_iter(&self) -> impl '_ + Iterator<Item = T> {
+        std::iter::from_fn(|| self.consumer.lock().unwrap().pop())
+    }
+}
+
+impl<T> std::fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Receiver").finish_non_exhaustive()
+    }
+}
+
+/// Sends values from the processing thread without locking or allocating.
+pub struct RtSender<T> {
+    producer: HeapProducer<T>,
+}
+
+impl<T> RtSender<T> {
+    /// Send `value` without blocking. If the channel is full, `value` is
+    /// returned in the error.
+    pub fn send(&mut self, value: T) -> Result<(), Full<T>> {
+        self.producer.push(value).map_err(Full)
+    }
+}
+
+impl<T> std::fmt::Debug for RtSender<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RtSender")
+            .field("len", &self.producer.len())
+            .finish()
+    }
+}
+
+/// Receives values on the processing thread without locking or allocating.
+pub struct RtReceiver<T> {
+    consumer: HeapConsumer<T>,
+}
+
+impl<T> RtReceiver<T> {
+    /// Take the next value in the channel, if any.
+    pub fn try_recv(&mut self) -> Option<T> {
+        self.consumer.pop()
+    }
+}
+
+impl<T> std::fmt::Debug for RtReceiver<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RtReceiver")
+            .field("len", &self.consumer.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use super::*;
+
+    #[test]
+    fn full_channels_return_the_value() {
+        let (sender, mut receiver) = command_channel(4);
+        for v in 0..4 {
+            sender.send(v).unwrap();
+        }
+        assert_eq!(sender.send(4), Err(Full(4)));
+        assert_eq!(receiver.try_recv(), Some(0));
+        sender.send(4).unwrap();
+
+        let (mut sender, receiver) = notification_channel(2);
+        sender.send(0).unwrap();
+        sender.send(1).unwrap();
+        assert_eq!(sender.send(2), Err(Full(2)));
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), vec![0, 1]);
+    }
+
+    #[test]
+    fn commands_from_many_threads_arrive_in_order_while_processing() {
+        const SENDERS: usize = 4;
+        const VALUES: usize = 5000;
+        let (sender, mut receiver) = command_channel::<(usize, usize)>(64);
+        let senders: Vec<_> = (0..SENDERS)
+            .map(|id| {
+                let sender = sender.clone();
+                std::thread::spawn(move || {
+                    for v in 0..VALUES {
+                        let mut value = (id, v);
+                        while let Err(Full(rejected)) = sender.send(value) {
+                            value = rejected;
+                            std::thread::yield_now();
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        // Receive like the processing thread does, in short bursts.
+        let mut next = [0; SENDERS];
+        while next.iter().any(|n| *n < VALUES) {
+            for _ in 0..16 {
+                match receiver.try_recv() {
+                    Some((id, v)) => {
+                        assert_eq!(v, next[id]);
+                        next[id] += 1;
+                    }
+                    None => break,
+                }
+            }
+            std::thread::yield_now();
+        }
+        for sender in senders {
+            sender.join().unwrap();
+        }
+        assert_eq!(receiver.try_recv(), None);
+    }
+
+    #[test]
+    fn notifications_never_block_the_sender() {
+        const VALUES: usize = 20000;
+        let (mut sender, receiver) = notification_channel::<usize>(64);
+        let done = Arc::new(AtomicBool::new(false));
+        let reader = {
+            let done = done.clone();
+            std::thread::spawn(move || {
+                let mut received = Vec::new();
+                while !done.load(Ordering::Acquire) {
+                    received.extend(receiver.try_iter());
+                    std::thread::yield_now();
+                }
+                received.extend(receiver.try_iter());
+                received
+            })
+        };
+        let mut dropped = 0;
+        for v in 0..VALUES {
+            if sender.send(v).is_err() {
+                dropped += 1;
+            }
+        }
+        done.store(true, Ordering::Release);
+        let received = reader.join().unwrap();
+        assert_eq!(received.len() + dropped, VALUES);
+        assert!(received.windows(2).all(|w| w[0] < w[1]));
+    }
+}
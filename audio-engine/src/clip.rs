@@ -0,0 +1,162 @@
+use livi::event::LV2AtomSequence;
+use log::*;
+
+use crate::metronome::SampleTimeInfo;
+
+/// A midi event positioned in beats from the start.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MidiEvent {
+    /// The position of the event in beats.
+    pub beat: f64,
+    /// The midi message.
+    pub message: wmidi::MidiMessage<'static>,
+}
+
+/// A sequence of midi events that is played in a loop.
+#[derive(Debug)]
+pub struct Clip {
+    /// The events sorted by their beat. All events are within `[0, length)`.
+    events: Vec<MidiEvent>,
+    /// The length of the loop in beats.
+    length: f64,
+    /// The events that fall within the current block as `(frame, index)`.
+    scheduled: Vec<(usize, usize)>,
+}
+
+impl Clip {
+    /// The number of beats in a measure.
+    const BEATS_PER_MEASURE: f64 = crate::metronome::BEATS_PER_MEASURE;
+
+    /// Create a new clip from `events` with a length of `Clip::length_for`.
+    /// Events before the start are dropped and events on the end of the loop
+    /// are moved to the start. When events share a beat, note offs come first
+    /// so that a note ending on the loop boundary does not cut off a note
+    /// starting there.
+    pub fn new(mut events: Vec<MidiEvent>) -> Clip {
+        events.retain(|e| e.beat >= 0.0);
+        let length = Clip::length_for(&events);
+        for event in events.iter_mut() {
+            event.beat = event.beat.rem_euclid(length);
+        }
+        events.sort_by(|a, b| {
+            let is_note_on = |e: &MidiEvent| matches!(e.message, wmidi::MidiMessage::NoteOn(..));
+            a.beat
+                .total_cmp(&b.beat)
+                .then(is_note_on(a).cmp(&is_note_on(b)))
+        });
+        let scheduled = Vec::with_capacity(events.len() * 2);
+        Clip {
+            events,
+            length,
+            scheduled,
+        }
+    }
+
+    /// Get the loop length for `events`. This is the position of the last
+    /// event rounded up to the nearest measure and is at least 1 measure.
+    pub fn length_for(events: &[MidiEvent]) -> f64 {
+        let last_beat = events.iter().map(|e| e.beat).fold(0.0, f64::max);
+        let measures = (last_beat / Clip::BEATS_PER_MEASURE).ceil().max(1.0);
+        measures * Clip::BEATS_PER_MEASURE
+    }
+
+    /// The events of the clip, sorted by beat.
+    pub fn events(&self) -> &[MidiEvent] {
+        &self.events
+    }
+
+    /// The length of the loop in beats.
+    pub fn length(&self) -> f64 {
+        self.length
+    }
+
+    /// Fill the scheduled events with the events that fall within the block
+    /// described by `time_info` and within `[from, to)` beats. `time_info`
+    /// contains the time at the start of each frame followed by the time at
+    /// the end of the block.
+    pub fn schedule(&mut self, time_info: &[SampleTimeInfo], from: f64, to: f64) {
+        self.scheduled.clear();
+        let (start, end) = match (time_info.first(), time_info.last()) {
+            (Some(s), Some(e)) => (s.to_beats().max(from).max(0.0), e.to_beats().min(to)),
+            _ => return,
+        };
+        if end <= start || self.events.is_empty() {
+            return;
+        }
+        let loop_start = (start / self.length).floor() * self.length;
+        for offset in [loop_start, loop_start + self.length] {
+            for (idx, event) in self.events.iter().enumerate() {
+                let beat = offset + event.beat;
+                if beat < start || beat >= end {
+                    continue;
+                }
+                if self.scheduled.len() == self.scheduled.capacity() {
+                    return;
+                }
+                let frame = time_info[1..].partition_point(|t| t.to_beats() <= beat);
+                self.scheduled.push((frame, idx));
+            }
+        }
+    }
+
+    /// Iterate over the scheduled events as `(frame, message)` in frame order.
+    pub fn iter_scheduled(
+        &self,
+    ) -> impl '_ + Iterator<Item = (usize, &wmidi::MidiMessage<'static>)> {
+        self.scheduled
+            .iter()
+            .map(|(frame, idx)| (*frame, &self.events[*idx].message))
+    }
+
+    /// Get the first loop boundary after `beat`.
+    pub fn next_loop_start(&self, beat: f64) -> f64 {
+        ((beat / self.length).floor() + 1.0) * self.length
+    }
+}
+
+/// Write `clip_events` and the events of `input` into `seq` in frame order.
+/// `clip_events` must be sorted by frame.
+pub fn merge_into<'a>(
+    seq: &mut LV2AtomSequence,
+    midi_urid: lv2_raw::LV2Urid,
+    input: &LV2AtomSequence,
+    clip_events: impl Iterator<Item = (usize, &'a wmidi::MidiMessage<'static>)>,
+) {
+    seq.clear();
+    let mut input = input.iter().peekable();
+    let mut clip_events = clip_events.peekable();
+    loop {
+        let input_frame = input.peek().map(|e| e.event.time_in_frames as usize);
+        let clip_frame = clip_events.peek().map(|(frame, _)| *frame);
+        let res = match (input_frame, clip_frame) {
+            (None, None) => break,
+            (Some(i), Some(c)) if c < i => {
+                let (frame, message) = clip_events.next().unwrap();
+                push_message(seq, midi_urid, frame, message)
+            }
+            (None, Some(_)) => {
+                let (frame, message) = clip_events.next().unwrap();
+                push_message(seq, midi_urid, frame, message)
+            }
+            (Some(_), _) => {
+                let event = input.next().unwrap();
+                seq.push_midi_event::<4>(event.event.time_in_frames, midi_urid, event.data)
+            }
+        };
+        if let Err(err) = res {
+            warn!("Dropping midi message: {:?}", err);
+        }
+    }
+}
+
+/// Push `message` at `frame` to the end of `seq`.
+pub(crate) fn push_message(
+    seq: &mut LV2AtomSequence,
+    midi_urid: lv2_raw::LV2Urid,
+    frame: usize,
+    message: &wmidi::MidiMessage<'static>,
+) -> Result<(), livi::error::EventError> {
+    let mut data = [0u8; 3];
+    let size = message.copy_to_slice(&mut data).unwrap_or(0);
+    seq.push_midi_event::<3>(frame as i64, midi_urid, &data[..size])
+}
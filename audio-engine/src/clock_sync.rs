@@ -0,0 +1,307 @@
+use crate::metronome::CLOCK_PULSES_PER_BEAT;
+
+/// How much each new pulse interval moves the tempo estimate.
+const SMOOTHING: f64 = 0.1;
+
+/// Pulse intervals within this fraction of the estimate are considered
+/// stable.
+const STABLE_TOLERANCE: f64 = 0.05;
+
+/// The number of seconds without a pulse before the clock is considered lost.
+const DROPOUT_SECONDS: f64 = 1.0;
+
+/// Something that happened on an incoming midi clock.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ClockEvent {
+    /// A clock pulse was received and the tempo estimate was updated. `beat`
+    /// is true if the pulse is the first pulse of a beat since the clock
+    /// started.
+    Tempo { beats_per_sample: f64, beat: bool },
+    /// Start playing from the start.
+    Start,
+    /// Continue playing from the current position.
+    Continue,
+    /// Stop playing.
+    Stop,
+}
+
+/// Follows midi clock received on the midi input and estimates its tempo.
+#[derive(Debug)]
+pub struct ClockSync {
+    /// If false, incoming clock messages are ignored.
+    enabled: bool,
+    sample_rate: f64,
+    /// The frame that the current `process` call starts at.
+    frame: u64,
+    /// The frame of the last pulse.
+    last_pulse: Option<u64>,
+    /// The number of pulses received since the clock started.
+    pulses: u64,
+    /// The smoothed number of frames between pulses.
+    frames_per_pulse: Option<f64>,
+    /// The estimate from the last pulse that arrived close to the estimate.
+    stable_frames_per_pulse: Option<f64>,
+    /// True if the clock stopped sending pulses.
+    lost: bool,
+}
+
+impl ClockSync {
+    /// Create a new clock sync that is disabled.
+    pub fn new(sample_rate: f64) -> ClockSync {
+        ClockSync {
+            enabled: false,
+            sample_rate,
+            frame: 0,
+            last_pulse: None,
+            pulses: 0,
+            frames_per_pulse: None,
+            stable_frames_per_pulse: None,
+            lost: false,
+        }
+    }
+
+    /// Set whether incoming clock messages are followed. The tempo is
+    /// estimated again from scratch when enabled.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        if enabled && !self.enabled {
+            self.last_pulse = None;
+            self.pulses = 0;
+            self.frames_per_pulse = None;
+            self.stable_frames_per_pulse = None;
+            self.lost = false;
+        }
+        self.enabled = enabled;
+    }
+
+    /// Handle a midi message received at `frame` within the current `process`
+    /// call.
+    pub fn handle_message(&mut self, frame: u32, data: &[u8]) -> Option<ClockEvent> {
+        if !self.enabled {
+            return None;
+        }
+        match data {
+            [0xF8] => self.handle_pulse(self.frame + frame as u64),
+            [0xFA] => {
+                self.pulses = 0;
+                Some(ClockEvent::Start)
+            }
+            [0xFB] => Some(ClockEvent::Continue),
+            [0xFC] => Some(ClockEvent::Stop),
+            _ => None,
+        }
+    }
+
+    /// Advance past the `samples` frames of the current `process` call.
+    /// Returns the last stable tempo in beats per sample if the clock stopped
+    /// sending pulses.
+    pub fn advance(&mut self, samples: usize) -> Option<f64> {
+        self.frame += samples as u64;
+        let last_pulse = self.last_pulse?;
+        if self.lost || !self.enabled || self.frame - last_pulse < self.dropout_frames() {
+            return None;
+        }
+        self.lost = true;
+        self.stable_frames_per_pulse
+            .or(self.frames_per_pulse)
+            .map(to_beats_per_sample)
+    }
+
+    /// Update the tempo estimate with a pulse at `frame`.
+    fn handle_pulse(&mut self, frame: u64) -> Option<ClockEvent> {
+        let interval = self
+            .last_pulse
+            .replace(frame)
+            .map(|last| frame.saturating_sub(last) as f64)
+            // The first pulse after the clock was lost does not say anything
+            // about the tempo.
+            .filter(|interval| *interval > 0.0 && *interval < self.dropout_frames() as f64)?;
+        self.lost = false;
+        let beat = self.pulses.is_multiple_of(CLOCK_PULSES_PER_BEAT as u64);
+        self.pulses += 1;
+        let estimate = match self.frames_per_pulse {
+            Some(estimate) => {
+                if (interval - estimate).abs() <= estimate * STABLE_TOLERANCE {
+                    self.stable_frames_per_pulse = Some(estimate);
+                }
+                estimate + (interval - estimate) * SMOOTHING
+            }
+            None => interval,
+        };
+        self.frames_per_pulse = Some(estimate);
+        Some(ClockEvent::Tempo {
+            beats_per_sample: to_beats_per_sample(estimate),
+            beat,
+        })
+    }
+
+    /// Get the number of frames without a pulse before the clock is lost.
+    fn dropout_frames(&self) -> u64 {
+        (DROPOUT_SECONDS * self.sample_rate) as u64
+    }
+}
+
+fn to_beats_per_sample(frames_per_pulse: f64) -> f64 {
+    1.0 / (frames_per_pulse * CLOCK_PULSES_PER_BEAT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f64 = 48000.0;
+
+    /// The frames between pulses at 120 beats per minute.
+    const FRAMES_PER_PULSE: usize = 1000;
+
+    const PULSE: [u8; 1] = [0xF8];
+
+    fn enabled() -> ClockSync {
+        let mut sync = ClockSync::new(SAMPLE_RATE);
+        sync.set_enabled(true);
+        sync
+    }
+
+    /// Send `count` pulses that are `frames` frames apart, one per block, and
+    /// return the event of each pulse.
+    fn pulses(sync: &mut ClockSync, count: usize, frames: usize) -> Vec<Option<ClockEvent>> {
+        (0..count)
+            .map(|_| {
+                let event = sync.handle_message(0, &PULSE);
+                assert_eq!(sync.advance(frames), None);
+                event
+            })
+            .collect()
+    }
+
+    fn beats_per_sample(event: Option<ClockEvent>) -> f64 {
+        match event {
+            Some(ClockEvent::Tempo {
+                beats_per_sample, ..
+            }) => beats_per_sample,
+            event => panic!("{event:?} is not a tempo"),
+        }
+    }
+
+    fn assert_tempo(beats_per_sample: f64, frames_per_pulse: f64) {
+        let expected = to_beats_per_sample(frames_per_pulse);
+        assert!(
+            (beats_per_sample / expected - 1.0).abs() < 1e-9,
+            "{beats_per_sample} != {expected}"
+        );
+    }
+
+    #[test]
+    fn disabled_clock_is_ignored() {
+        let mut sync = ClockSync::new(SAMPLE_RATE);
+        for data in [[0xF8], [0xFA], [0xFB], [0xFC]] {
+            assert_eq!(sync.handle_message(0, &data), None);
+        }
+        assert_eq!(sync.advance(SAMPLE_RATE as usize * 2), None);
+    }
+
+    #[test]
+    fn steady_clock_gives_its_tempo() {
+        let mut sync = enabled();
+        let events = pulses(&mut sync, 49, FRAMES_PER_PULSE);
+        // The first pulse has no interval to estimate the tempo from.
+        assert_eq!(events[0], None);
+        for event in events[1..].iter() {
+            assert_tempo(beats_per_sample(*event), FRAMES_PER_PULSE as f64);
+        }
+        // 120 beats per minute.
+        assert!((beats_per_sample(events[48]) * SAMPLE_RATE * 60.0 - 120.0).abs() < 1e-9);
+        let beats: Vec<usize> = events
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| matches!(e, Some(ClockEvent::Tempo { beat: true, .. })))
+            .map(|(idx, _)| idx)
+            .collect();
+        assert_eq!(beats, vec![1, 25]);
+    }
+
+    #[test]
+    fn pulses_within_a_block_use_their_frame() {
+        let mut sync = enabled();
+        assert_eq!(sync.handle_message(600, &PULSE), None);
+        assert_eq!(sync.advance(1024), None);
+        // The pulses are at frames 600, 1600 and 2600 of the blocks of 1024
+        // frames.
+        let event = sync.handle_message(576, &PULSE);
+        assert_tempo(beats_per_sample(event), FRAMES_PER_PULSE as f64);
+        assert_eq!(sync.advance(1024), None);
+        let event = sync.handle_message(552, &PULSE);
+        assert_tempo(beats_per_sample(event), FRAMES_PER_PULSE as f64);
+    }
+
+    #[test]
+    fn tempo_changes_are_smoothed() {
+        let mut sync = enabled();
+        pulses(&mut sync, 10, FRAMES_PER_PULSE);
+        let events = pulses(&mut sync, 3, FRAMES_PER_PULSE / 2);
+        // The first pulse of the new tempo ends an interval at the old tempo.
+        assert_tempo(beats_per_sample(events[0]), 1000.0);
+        assert_tempo(beats_per_sample(events[1]), 950.0);
+        assert_tempo(beats_per_sample(events[2]), 905.0);
+        let events = pulses(&mut sync, 200, FRAMES_PER_PULSE / 2);
+        assert_tempo(beats_per_sample(events[199]), 500.0);
+    }
+
+    #[test]
+    fn transport_messages_are_events() {
+        let mut sync = enabled();
+        assert_eq!(sync.handle_message(0, &[0xFA]), Some(ClockEvent::Start));
+        assert_eq!(sync.handle_message(0, &[0xFB]), Some(ClockEvent::Continue));
+        assert_eq!(sync.handle_message(0, &[0xFC]), Some(ClockEvent::Stop));
+        assert_eq!(sync.handle_message(0, &[0x90, 60, 127]), None);
+    }
+
+    #[test]
+    fn start_restarts_the_beat_count() {
+        let mut sync = enabled();
+        pulses(&mut sync, 10, FRAMES_PER_PULSE);
+        assert_eq!(sync.handle_message(0, &[0xFA]), Some(ClockEvent::Start));
+        let events = pulses(&mut sync, 2, FRAMES_PER_PULSE);
+        assert!(matches!(
+            events[0],
+            Some(ClockEvent::Tempo { beat: true, .. })
+        ));
+        assert!(matches!(
+            events[1],
+            Some(ClockEvent::Tempo { beat: false, .. })
+        ));
+    }
+
+    #[test]
+    fn dropout_falls_back_to_the_last_stable_tempo() {
+        let mut sync = enabled();
+        pulses(&mut sync, 10, FRAMES_PER_PULSE);
+        // The clock slows down erratically before it stops, which moves the
+        // estimate but not the stable tempo.
+        pulses(&mut sync, 3, 2 * FRAMES_PER_PULSE);
+        // Just under a second without a pulse.
+        assert_eq!(
+            sync.advance(SAMPLE_RATE as usize - 2 * FRAMES_PER_PULSE - 1),
+            None
+        );
+        let fallback = sync.advance(1).unwrap();
+        assert_tempo(fallback, FRAMES_PER_PULSE as f64);
+        // The dropout is only reported once.
+        assert_eq!(sync.advance(SAMPLE_RATE as usize), None);
+        // The first pulse after the dropout only restarts the intervals.
+        let events = pulses(&mut sync, 2, FRAMES_PER_PULSE);
+        assert_eq!(events[0], None);
+        assert!(events[1].is_some());
+    }
+
+    #[test]
+    fn enabling_estimates_the_tempo_from_scratch() {
+        let mut sync = enabled();
+        pulses(&mut sync, 10, FRAMES_PER_PULSE);
+        sync.set_enabled(false);
+        assert_eq!(sync.handle_message(0, &PULSE), None);
+        sync.set_enabled(true);
+        let events = pulses(&mut sync, 2, FRAMES_PER_PULSE / 4);
+        assert_eq!(events[0], None);
+        assert_tempo(beats_per_sample(events[1]), 250.0);
+    }
+}
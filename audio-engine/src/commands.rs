@@ -1,6 +1,13 @@
+use std::time::Duration;
+
 use crate::{
+    clip::{Clip, MidiEvent},
+    drum_kit::DrumKit,
+    freeze::FrozenAudio,
+    meter::{Level, TrackLevels},
     metronome::SampleTimeInfo,
-    plugin::{PluginInstance, SampleTrigger},
+    plugin::{PluginInstance, PluginProcessError, SampleTrigger},
+    recorder::MasterRecorder,
     track::Track,
 };
 
@@ -8,22 +15,244 @@ use crate::{
 #[derive(Debug)]
 pub enum Command {
     /// Add a new track.
-    AddTrack(Track),
+    AddTrack(Box<Track>),
     /// Delete tracks.
     DeleteTrack(i32),
     /// Add a plugin to the track.
     AddPluginToTrack(i32, PluginInstance),
     /// Delete a plugin from a track.
     DeletePlugin(i32, usize),
-    /// Set metronome properties.
-    SetMetronome { volume: f32, beats_per_minute: f32 },
-    /// Arm a single track by id.
+    /// Insert a plugin into a track at the index. Indices past the end add
+    /// the plugin to the end. The master track is addressed with
+    /// `MASTER_TRACK_ID`.
+    InsertPlugin(i32, usize, PluginInstance),
+    /// Replace the plugin at `plugin_index` of a track with `instance`.
+    /// Nothing changes if the track does not have a plugin at the index. The
+    /// master track is addressed with `MASTER_TRACK_ID`.
+    ReplacePlugin {
+        track_id: i32,
+        plugin_index: usize,
+        instance: PluginInstance,
+    },
+    /// Move the plugin at index `from` of a track to index `to`. The master
+    /// track is addressed with `MASTER_TRACK_ID`.
+    MovePlugin {
+        track_id: i32,
+        from: usize,
+        to: usize,
+    },
+    /// Set the gain applied to the output. The change is ramped over one
+    /// block.
+    SetMasterVolume(f32),
+    /// Enable or disable the limiter on the output.
+    SetLimiter(bool),
+    /// Add a plugin to the master track.
+    AddPluginToMaster(PluginInstance),
+    /// Delete a plugin from the master track.
+    DeletePluginFromMaster(usize),
+    /// Set the value of a plugin's control port. The master track is
+    /// addressed with `MASTER_TRACK_ID`.
+    SetPluginParam {
+        track_id: i32,
+        plugin_index: usize,
+        port_index: usize,
+        value: f32,
+    },
+    /// Replace the pads of the drum kit plugin at `plugin_index` of a track.
+    /// Nothing changes if the plugin is not a drum kit. The master track is
+    /// addressed with `MASTER_TRACK_ID`.
+    SetDrumKit {
+        track_id: i32,
+        plugin_index: usize,
+        kit: Box<DrumKit>,
+    },
+    /// Set metronome properties. If `apply_on_downbeat` is set and the
+    /// transport is playing, the tempo changes at the start of the next
+    /// measure. If `external_sync` is set, `beats_per_minute` is ignored and
+    /// the tempo and transport follow the midi clock on the midi input.
+    SetMetronome {
+        volume: f32,
+        beats_per_minute: f32,
+        swing: f32,
+        apply_on_downbeat: bool,
+        external_sync: bool,
+    },
+    /// Set the sample that the metronome plays on each beat.
+    SetMetronomeSample(SampleTrigger),
+    /// Set whether midi clock, start and stop are sent to the midi output.
+    SetMidiClock(bool),
+    /// Set whether the transport and the metronome follow the shared
+    /// transport passed to `Processor::follow_transport`. Swing is disabled
+    /// while following so that beats line up with the shared transport.
+    SetFollowTransport(bool),
+    /// Arm a single track by id. The armed track starts monitoring and the
+    /// track that is disarmed stops monitoring and is sent all notes off.
     ArmTrack(i32),
+    /// Set whether a track receives live midi input by id. A track that stops
+    /// monitoring is sent all notes off.
+    SetTrackMonitoring(i32, bool),
+    /// Set the midi channel that a track receives live input from by id. If
+    /// `None`, the track receives every channel.
+    SetTrackMidiChannel(i32, Option<u8>),
+    /// Set the midi input that a track takes live input from by id. If `None`,
+    /// the track takes input from every input that no track is routed to.
+    SetTrackMidiSource(i32, Option<usize>),
+    /// Set whether the midi produced by the plugins of a track is sent to the
+    /// midi output by id.
+    SetTrackSendMidi(i32, bool),
+    /// Set the number of semitones to shift the notes of a track by id.
+    SetTrackTranspose(i32, i8),
+    /// Send all notes off and all sound off to every track.
+    MidiPanic,
+    /// Add a midi message to the live input at the start of the next block.
+    /// The message goes to the input of the armed track.
+    InjectMidi(wmidi::MidiMessage<'static>),
+    /// Play a note on the first channel of a track and release it after
+    /// `frames` frames. The note does not depend on the track being armed or
+    /// monitoring the live input.
+    TriggerNote {
+        track_id: i32,
+        note: wmidi::Note,
+        velocity: wmidi::Velocity,
+        frames: usize,
+    },
+    /// Set the solo state of a track by id.
+    SetTrackSolo(i32, bool),
+    /// Set the stereo panning of a track by id.
+    SetTrackPan(i32, f32),
+    /// Set the volume multiplier of a track by id.
+    SetTrackVolume(i32, f32),
+    /// Run the plugins of a track that was disabled by a plugin failure again.
+    /// The master track is addressed with `MASTER_TRACK_ID`.
+    EnableTrack(i32),
+    /// Start advancing time.
+    TransportPlay,
+    /// Stop advancing time. The position is held.
+    TransportStop,
+    /// Move the position to the start of a beat. Positions before the start
+    /// are clamped to the start.
+    TransportSeek { measure: i16, beat: i16 },
+    /// Start playing from `measures` measures before the start. Midi input for
+    /// the armed track is discarded until the start is reached.
+    TransportCountIn(u8),
+    /// Set the clip that a track plays in a loop while the transport is
+    /// playing.
+    SetTrackClip(i32, Clip),
+    /// Set the clip that a track plays starting from the next loop boundary of
+    /// its current clip.
+    QueueTrackClip(i32, Clip),
+    /// Play the frozen audio on a track instead of running its plugins. Midi
+    /// input is ignored while a track is frozen.
+    FreezeTrack(i32, FrozenAudio),
+    /// Run the plugins of a frozen track again.
+    UnfreezeTrack(i32),
+    /// Start recording the midi input of a track into the given buffer. Events
+    /// are only recorded while the track is armed and the transport is playing
+    /// and are dropped once the buffer is at capacity.
+    StartRecording(i32, Vec<MidiEvent>),
+    /// Start recording the audio input of a track into the given buffers, one
+    /// per channel. Like midi recording, audio is only recorded while the
+    /// track is armed and the transport is playing and is dropped once the
+    /// buffers are at capacity.
+    StartAudioRecording(i32, Vec<Vec<f32>>),
+    /// Stop recording and send the recorded clip and audio as notifications.
+    StopRecording,
+    /// Start capturing the audio input for a track into the given buffers,
+    /// one per channel. Unlike audio recording, the input is captured whether
+    /// or not the track is armed and the transport is playing. Capturing stops
+    /// once the buffers are at capacity.
+    StartSampleRecording(i32, Vec<Vec<f32>>),
+    /// Stop capturing the audio input and send the captured audio as a
+    /// notification.
+    StopSampleRecording,
+    /// Start streaming the output to a recorder.
+    StartMasterRecord(MasterRecorder),
+    /// Stop streaming the output.
+    StopMasterRecord,
     /// Play a sound.
     PlaySound(SampleTrigger),
+    /// Set the minimum interval between time info notifications.
+    SetTimeInfoInterval(Duration),
+    /// Set the minimum interval between meter notifications.
+    SetMeterInterval(Duration),
 }
 
-#[derive(Clone, Debug)]
+// `Meters` is large since its levels are stored inline to avoid allocating on
+// the processing thread.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug)]
 pub enum Notifications {
     TimeInfo(SampleTimeInfo),
+    /// The count in has finished and input is being captured.
+    CountInFinished,
+    /// The tempo detected from the midi clock on the midi input. This is sent
+    /// once per beat while following an external clock.
+    ExternalTempo(f32),
+    /// The midi clock on the midi input stopped sending pulses. The tempo
+    /// fell back to `beats_per_minute`, the last stable tempo.
+    ExternalClockLost {
+        beats_per_minute: f32,
+    },
+    /// The transport was started or stopped by the midi input or the shared
+    /// transport.
+    ExternalTransport {
+        playing: bool,
+    },
+    /// A control change was received on a midi input. `channel` is from 0 to
+    /// 15.
+    MidiCc {
+        cc: u8,
+        channel: u8,
+        value: u8,
+    },
+    /// The midi events recorded for a track.
+    RecordedClip(i32, Vec<MidiEvent>),
+    /// The audio recorded for a track, one buffer per channel.
+    RecordedAudio(i32, Vec<Vec<f32>>),
+    /// The audio input captured for a track by
+    /// `Command::StartSampleRecording`, one buffer per channel. This is sent
+    /// when capturing is stopped or the buffers are full.
+    RecordedSample(i32, Vec<Vec<f32>>),
+    /// The output exceeded 1.0 before limiting. `channel` is the loudest
+    /// channel and `peak` is its largest absolute value.
+    ClipDetected {
+        channel: usize,
+        peak: f32,
+    },
+    /// The levels of the tracks and the output for the last block.
+    Meters {
+        track_levels: TrackLevels,
+        master: Level,
+    },
+    /// The progress of recording the output.
+    RecordingStats {
+        frames_written: u64,
+        overruns: u64,
+    },
+    /// A plugin failed in consecutive blocks and its track was disabled. The
+    /// track stays silent until `Command::EnableTrack` is sent.
+    PluginError {
+        track_id: i32,
+        plugin_index: usize,
+        error: PluginProcessError,
+    },
+    /// An object that was removed from the processor. It should be dropped
+    /// by the receiver.
+    Disposed(Disposed),
+}
+
+/// An object that was removed from the processor. Objects are sent back
+/// instead of being dropped since freeing memory or plugin instances may block
+/// the processing thread.
+#[derive(Debug)]
+pub enum Disposed {
+    Track(Box<Track>),
+    Plugin(PluginInstance),
+    Clip(Clip),
+    Frozen(FrozenAudio),
+    /// A midi recording buffer that was replaced.
+    MidiEvents(Vec<MidiEvent>),
+    /// An audio recording buffer that was replaced, one buffer per channel.
+    Audio(Vec<Vec<f32>>),
+    Recorder(MasterRecorder),
 }
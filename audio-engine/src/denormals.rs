@@ -0,0 +1,82 @@
+/// The value that `AudioBuffer::reset` fills buffers with. On platforms where
+/// denormals can't be flushed to zero, a tiny DC offset keeps the signal out of
+/// the denormal range.
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+pub const RESET_VALUE: f32 = 0.0;
+/// The value that `AudioBuffer::reset` fills buffers with. On platforms where
+/// denormals can't be flushed to zero, a tiny DC offset keeps the signal out of
+/// the denormal range.
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+pub const RESET_VALUE: f32 = 1e-18;
+
+/// The flush to zero and denormals are zero bits of MXCSR.
+#[cfg(target_arch = "x86_64")]
+const MXCSR_FTZ_DAZ: u32 = (1 << 15) | (1 << 6);
+
+/// The flush to zero bit of FPCR.
+#[cfg(target_arch = "aarch64")]
+const FPCR_FZ: u64 = 1 << 24;
+
+/// Flush denormals to zero for floating point operations on the current thread.
+/// Denormals are very slow to process on most CPUs and commonly appear in
+/// decaying reverb and delay tails. This is a no-op on platforms that do not
+/// support it.
+pub fn flush_denormals_to_zero() {
+    #[cfg(target_arch = "x86_64")]
+    unsafe {
+        let mut csr = 0u32;
+        std::arch::asm!("stmxcsr [{}]", in(reg) &mut csr, options(nostack, preserves_flags));
+        csr |= MXCSR_FTZ_DAZ;
+        std::arch::asm!("ldmxcsr [{}]", in(reg) &csr, options(nostack, readonly, preserves_flags));
+    }
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        let mut fpcr: u64;
+        std::arch::asm!("mrs {}, fpcr", out(reg) fpcr, options(nomem, nostack, preserves_flags));
+        fpcr |= FPCR_FZ;
+        std::arch::asm!("msr fpcr, {}", in(reg) fpcr, options(nomem, nostack, preserves_flags));
+    }
+}
+
+/// Returns true if denormals are flushed to zero on the current thread.
+pub fn denormals_flushed_to_zero() -> bool {
+    #[cfg(target_arch = "x86_64")]
+    {
+        let mut csr = 0u32;
+        unsafe {
+            std::arch::asm!("stmxcsr [{}]", in(reg) &mut csr, options(nostack, preserves_flags));
+        }
+        csr & MXCSR_FTZ_DAZ == MXCSR_FTZ_DAZ
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        let fpcr: u64;
+        unsafe {
+            std::arch::asm!("mrs {}, fpcr", out(reg) fpcr, options(nomem, nostack, preserves_flags));
+        }
+        fpcr & FPCR_FZ == FPCR_FZ
+    }
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    false
+}
+
+#[cfg(all(test, any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flush_only_applies_to_the_processing_thread() {
+        let processing = std::thread::spawn(|| {
+            let before = denormals_flushed_to_zero();
+            flush_denormals_to_zero();
+            let denormal = std::hint::black_box(f32::MIN_POSITIVE) / 2.0;
+            (before, denormals_flushed_to_zero(), denormal)
+        });
+        let (before, after, denormal) = processing.join().unwrap();
+        assert!(!before);
+        assert!(after);
+        assert_eq!(denormal, 0.0);
+        assert!(!denormals_flushed_to_zero());
+        assert!(std::hint::black_box(f32::MIN_POSITIVE) / 2.0 > 0.0);
+    }
+}
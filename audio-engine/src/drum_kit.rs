@@ -0,0 +1,292 @@
+use std::{path::Path, sync::Arc};
+
+use livi::event::LV2AtomSequence;
+
+use crate::{
+    audio_buffer::{AudioBuffer, WavError},
+    plugin::{PluginProcessError, VelocityCurve},
+};
+
+/// The number of midi notes.
+const NOTES: usize = 128;
+
+/// The maximum number of samples that play at once. When all voices are busy,
+/// the voice that has played the longest is stopped for the new note.
+const MAX_VOICES: usize = 32;
+
+/// A sample that is played by a note of a `DrumKit`.
+#[derive(Clone, Debug)]
+pub struct DrumPad {
+    pub sample: Arc<AudioBuffer>,
+    /// The gain applied to the sample.
+    pub gain: f32,
+}
+
+impl DrumPad {
+    /// Create a pad from a wave path. The sample is resampled to `sample_rate`
+    /// if the file has a different rate.
+    pub fn from_wav(p: &Path, sample_rate: f64, gain: f32) -> Result<DrumPad, WavError> {
+        let mut sample = AudioBuffer::with_wav(p)?;
+        match sample.sample_rate() {
+            Some(rate) if rate != sample_rate => sample = sample.resampled(rate, sample_rate),
+            _ => (),
+        }
+        Ok(DrumPad {
+            sample: Arc::new(sample),
+            gain,
+        })
+    }
+}
+
+/// A sample that is playing.
+#[derive(Copy, Clone, Debug)]
+struct Voice {
+    /// The note of the pad that is playing.
+    note: usize,
+    /// The next frame of the sample to play.
+    index: usize,
+    /// The gain of the pad scaled by the velocity of the note.
+    gain: f32,
+}
+
+/// Plays a sample for each note that has a pad. Each note on starts a new
+/// voice so hits ring out over each other.
+#[derive(Clone, Debug)]
+pub struct DrumKit {
+    /// The pads by midi note number.
+    pads: Vec<Option<DrumPad>>,
+    /// The samples that are playing. The capacity is reserved up front so that
+    /// starting a voice does not allocate.
+    voices: Vec<Voice>,
+}
+
+impl Default for DrumKit {
+    fn default() -> DrumKit {
+        DrumKit::new(std::iter::empty())
+    }
+}
+
+impl DrumKit {
+    /// Create a kit that plays each pad on its note.
+    pub fn new(pads: impl IntoIterator<Item = (wmidi::Note, DrumPad)>) -> DrumKit {
+        let mut kit = DrumKit {
+            pads: vec![None; NOTES],
+            voices: Vec::with_capacity(MAX_VOICES),
+        };
+        for (note, pad) in pads {
+            kit.pads[u8::from(note) as usize] = Some(pad);
+        }
+        kit
+    }
+
+    /// Get the pad for `note`.
+    pub fn pad(&self, note: wmidi::Note) -> Option<&DrumPad> {
+        self.pads[u8::from(note) as usize].as_ref()
+    }
+
+    /// Iterate over the notes that have pads and their pads.
+    pub fn iter_pads(&self) -> impl '_ + Iterator<Item = (wmidi::Note, &DrumPad)> {
+        self.pads.iter().enumerate().filter_map(|(note, pad)| {
+            pad.as_ref()
+                .map(|pad| (wmidi::Note::from_u8_lossy(note as u8), pad))
+        })
+    }
+
+    /// Processes the first `samples` frames of `output`. The output is
+    /// replaced by the mix of the voices. Note offs are ignored since each hit
+    /// plays to the end of its sample.
+    pub fn process(
+        &mut self,
+        samples: usize,
+        midi_input: &LV2AtomSequence,
+        output: &mut AudioBuffer,
+    ) -> Result<(), PluginProcessError> {
+        for dst in output.iter_channels_mut() {
+            for v in dst[..samples].iter_mut() {
+                *v = crate::denormals::RESET_VALUE;
+            }
+        }
+        let mut midi = midi_input.iter().peekable();
+        for frame in 0..samples {
+            while let Some(event) = midi.next_if(|m| m.event.time_in_frames as usize <= frame) {
+                if let Ok(wmidi::MidiMessage::NoteOn(_, note, v)) =
+                    wmidi::MidiMessage::try_from(event.data)
+                {
+                    self.trigger(note, v);
+                }
+            }
+            for voice in self.voices.iter_mut() {
+                let pad = match self.pads[voice.note].as_ref() {
+                    Some(pad) => pad,
+                    None => continue,
+                };
+                let sample_channels = pad.sample.channels();
+                for (idx, dst) in output.iter_channels_mut().enumerate() {
+                    let src = pad
+                        .sample
+                        .iter_channels()
+                        .nth(idx.min(sample_channels - 1))
+                        .unwrap();
+                    dst[frame] += src[voice.index] * voice.gain;
+                }
+                voice.index += 1;
+            }
+            let pads = &self.pads;
+            self.voices.retain(|voice| match pads[voice.note].as_ref() {
+                Some(pad) => voice.index < pad.sample.buffer_size(),
+                None => false,
+            });
+        }
+        Ok(())
+    }
+
+    /// Start a voice for the pad of `note`. A note on with a velocity of 0 is
+    /// ignored.
+    fn trigger(&mut self, note: wmidi::Note, velocity: wmidi::U7) {
+        let note = u8::from(note) as usize;
+        let pad = match self.pads[note].as_ref() {
+            Some(pad) if u8::from(velocity) > 0 && pad.sample.buffer_size() > 0 => pad,
+            _ => return,
+        };
+        let voice = Voice {
+            note,
+            index: 0,
+            gain: pad.gain * VelocityCurve::Linear.gain(velocity),
+        };
+        if self.voices.len() == MAX_VOICES {
+            self.voices.remove(0);
+        }
+        self.voices.push(voice);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn features() -> Arc<livi::Features> {
+        livi::FeaturesBuilder {
+            min_block_length: 1,
+            max_block_length: 1024,
+        }
+        .build(&livi::World::with_plugin_predicate(|_| false))
+    }
+
+    /// Create a pad with a mono sample of `frames` frames at `level`.
+    fn pad(frames: usize, level: f32, gain: f32) -> DrumPad {
+        DrumPad {
+            sample: Arc::new(AudioBuffer::from_channels(&[vec![level; frames]])),
+            gain,
+        }
+    }
+
+    /// Process `samples` frames of `kit` with a note on at each frame of
+    /// `hits` and return the output.
+    fn play(kit: &mut DrumKit, samples: usize, hits: &[(i64, wmidi::Note, u8)]) -> AudioBuffer {
+        let features = features();
+        let mut midi = LV2AtomSequence::new(&features, 1024);
+        for (frame, note, velocity) in hits {
+            midi.push_midi_event::<3>(
+                *frame,
+                features.midi_urid(),
+                &[0x90, u8::from(*note), *velocity],
+            )
+            .unwrap();
+        }
+        let mut output = AudioBuffer::with_stereo(samples);
+        kit.process(samples, &midi, &mut output).unwrap();
+        output
+    }
+
+    fn assert_close(actual: &[f32], expected: impl IntoIterator<Item = f32>) {
+        let expected: Vec<f32> = expected.into_iter().collect();
+        assert_eq!(actual.len(), expected.len());
+        for (frame, (a, b)) in actual.iter().zip(expected.iter()).enumerate() {
+            assert!((a - b).abs() < 1e-6, "frame {frame}: {a} != {b}");
+        }
+    }
+
+    #[test]
+    fn hits_ring_out_over_each_other() {
+        let mut kit = DrumKit::new([(wmidi::Note::C1, pad(100, 0.25, 1.0))]);
+        let output = play(
+            &mut kit,
+            200,
+            &[(0, wmidi::Note::C1, 127), (50, wmidi::Note::C1, 127)],
+        );
+        for channel in output.iter_channels() {
+            assert_close(
+                channel,
+                std::iter::repeat_n(0.25, 50)
+                    .chain(std::iter::repeat_n(0.5, 50))
+                    .chain(std::iter::repeat_n(0.25, 50))
+                    .chain(std::iter::repeat_n(0.0, 50)),
+            );
+        }
+    }
+
+    #[test]
+    fn each_pad_plays_on_its_note() {
+        let mut kit = DrumKit::new([
+            (wmidi::Note::C1, pad(10, 1.0, 1.0)),
+            (wmidi::Note::D1, pad(10, 1.0, 0.5)),
+        ]);
+        assert!(kit.pad(wmidi::Note::C1).is_some());
+        assert!(kit.pad(wmidi::Note::CSharp1).is_none());
+        assert_eq!(
+            kit.iter_pads().map(|(note, _)| note).collect::<Vec<_>>(),
+            vec![wmidi::Note::C1, wmidi::Note::D1]
+        );
+        let output = play(
+            &mut kit,
+            40,
+            &[
+                (0, wmidi::Note::D1, 127),
+                (10, wmidi::Note::CSharp1, 127),
+                (20, wmidi::Note::C1, 127),
+            ],
+        );
+        assert_close(
+            output.iter_channels().next().unwrap(),
+            std::iter::repeat_n(0.5, 10)
+                .chain(std::iter::repeat_n(0.0, 10))
+                .chain(std::iter::repeat_n(1.0, 10))
+                .chain(std::iter::repeat_n(0.0, 10)),
+        );
+    }
+
+    #[test]
+    fn velocity_scales_each_hit() {
+        let mut kit = DrumKit::new([(wmidi::Note::C1, pad(10, 1.0, 1.0))]);
+        let output = play(
+            &mut kit,
+            10,
+            &[(0, wmidi::Note::C1, 64), (5, wmidi::Note::C1, 0)],
+        );
+        let soft = 64.0 / 127.0;
+        assert_close(
+            output.iter_channels().next().unwrap(),
+            std::iter::repeat_n(soft, 10),
+        );
+    }
+
+    #[test]
+    fn oldest_voice_is_stopped_when_all_voices_are_busy() {
+        let mut kit = DrumKit::new([(wmidi::Note::C1, pad(100, 1.0, 1.0))]);
+        let hits: Vec<_> = (0..MAX_VOICES as i64 + 1)
+            .map(|frame| (frame, wmidi::Note::C1, 127))
+            .collect();
+        let output = play(&mut kit, 200, &hits);
+        let output = output.iter_channels().next().unwrap();
+        assert_eq!(output[MAX_VOICES - 1], MAX_VOICES as f32);
+        // The voice that started at frame 0 was stopped for the last hit, so
+        // there are never more than `MAX_VOICES` voices playing.
+        assert!(output.iter().all(|v| *v <= MAX_VOICES as f32));
+        // The remaining voices end one frame at a time, 100 frames after they
+        // started.
+        assert_eq!(output[100], MAX_VOICES as f32);
+        assert_eq!(output[101], (MAX_VOICES - 1) as f32);
+        assert_eq!(output[100 + MAX_VOICES - 1], 1.0);
+        assert_eq!(output[100 + MAX_VOICES], 0.0);
+    }
+}
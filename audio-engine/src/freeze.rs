@@ -0,0 +1,105 @@
+use std::sync::Arc;
+
+use livi::event::LV2AtomSequence;
+
+use crate::{
+    audio_buffer::AudioBuffer,
+    metronome::{Metronome, SampleTimeInfo},
+    midi_output::MidiOutput,
+    track::Track,
+};
+
+/// A loop of a track's clip rendered through its plugins.
+#[derive(Clone, Debug)]
+pub struct FrozenAudio {
+    audio: Arc<AudioBuffer>,
+    /// The length of the loop in beats.
+    length: f64,
+    /// The tempo the audio was rendered at.
+    beats_per_sample: f64,
+    /// The swing the audio was rendered with.
+    swing: f64,
+}
+
+impl FrozenAudio {
+    /// Render a loop of the clip of `track` through its plugins. Returns `None`
+    /// if the track has no clip.
+    ///
+    /// Two loops are rendered and only the second is kept so that notes that
+    /// ring past the end of the loop are heard at its start. This runs the
+    /// plugins directly and should only be used on tracks that are not part
+    /// of a live processor.
+    pub fn render(
+        track: &mut Track,
+        features: &livi::Features,
+        sample_rate: f64,
+        bpm: f32,
+        swing: f32,
+    ) -> Option<FrozenAudio> {
+        let length = track.clip()?.length();
+        let mut metronome = Metronome::new(sample_rate, features);
+        metronome.set_properties(sample_rate, 0.0, bpm, false);
+        metronome.set_swing(swing);
+        let midi_urid = features.midi_urid();
+        let empty_midi = LV2AtomSequence::new(features, 0);
+        let mut midi = LV2AtomSequence::new(features, 1024 * 1024 /*1 MiB*/);
+        let mut midi_output = MidiOutput::new(features, 0);
+        let mut channels = vec![Vec::new(), Vec::new()];
+        let block_size = features.max_block_length();
+        while metronome.current_time_info().to_beats() < 2.0 * length {
+            let _ = metronome.process(block_size, true);
+            let time_info = metronome.time_info();
+            let midi = if track.merge_clip_events(time_info, &empty_midi, midi_urid, &mut midi) {
+                &midi
+            } else {
+                &empty_midi
+            };
+            let output = track.process(block_size, midi, &mut midi_output);
+            for (frame, t) in time_info[..block_size].iter().enumerate() {
+                let beat = t.to_beats();
+                if !(length..2.0 * length).contains(&beat) {
+                    continue;
+                }
+                for (dst, src) in channels.iter_mut().zip(output.iter_channels()) {
+                    dst.push(src[frame]);
+                }
+            }
+        }
+        Some(FrozenAudio {
+            audio: Arc::new(AudioBuffer::from_channels(&channels)),
+            length,
+            beats_per_sample: metronome.beats_per_sample(),
+            swing: metronome.swing(),
+        })
+    }
+
+    /// Get the rendered audio.
+    pub fn audio(&self) -> &Arc<AudioBuffer> {
+        &self.audio
+    }
+
+    /// Get the frame within the loop that was rendered at `beat`. This
+    /// inverts the swing applied by the metronome so that playback lines up
+    /// with the beats as long as the tempo and swing are unchanged.
+    fn frame_at(&self, beat: f64) -> usize {
+        let beat = beat.rem_euclid(self.length);
+        let pair = (beat / 2.0).floor();
+        let within = beat - 2.0 * pair;
+        let beats = if within < 1.0 {
+            2.0 * pair + within * 2.0 * self.swing
+        } else {
+            2.0 * pair + 2.0 * self.swing + (within - 1.0) * 2.0 * (1.0 - self.swing)
+        };
+        (beats / self.beats_per_sample) as usize
+    }
+
+    /// Write the audio for each frame described by `time_info` into the first
+    /// `samples` frames of `output`.
+    pub fn process(&self, samples: usize, time_info: &[SampleTimeInfo], output: &mut AudioBuffer) {
+        for (src, dst) in self.audio.iter_channels().zip(output.iter_channels_mut()) {
+            for (frame, t) in dst[..samples].iter_mut().zip(time_info.iter()) {
+                *frame = src.get(self.frame_at(t.to_beats())).copied().unwrap_or(0.0);
+            }
+        }
+    }
+}
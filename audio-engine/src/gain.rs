@@ -0,0 +1,195 @@
+use crate::{audio_buffer::AudioBuffer, plugin::AUDIO_CHANNELS};
+
+/// Adjusts the level, polarity and balance of the audio that passes through
+/// it. Changes to the parameters are ramped over a block to avoid zipper
+/// noise.
+#[derive(Clone, Debug)]
+pub struct Gain {
+    /// The gain in decibels.
+    gain_db: f32,
+    /// If true, the polarity of the audio is inverted.
+    invert: bool,
+    /// The balance from -1.0 (left) to 1.0 (right).
+    balance: f32,
+    /// The gains of each channel at the end of the last block.
+    gains: [f32; AUDIO_CHANNELS],
+}
+
+impl Default for Gain {
+    fn default() -> Gain {
+        let mut gain = Gain {
+            gain_db: 0.0,
+            invert: false,
+            balance: 0.0,
+            gains: [1.0; AUDIO_CHANNELS],
+        };
+        gain.gains = gain.target_gains();
+        gain
+    }
+}
+
+impl Gain {
+    /// The port indices of the parameters for `Gain::set_control_input`.
+    pub const GAIN_DB_PORT: usize = 0;
+    pub const INVERT_PORT: usize = 1;
+    pub const BALANCE_PORT: usize = 2;
+
+    /// The range of the gain in decibels.
+    pub const MIN_GAIN_DB: f32 = -60.0;
+    pub const MAX_GAIN_DB: f32 = 24.0;
+
+    /// Set a parameter by its port index. The polarity is inverted for values
+    /// above 0.5. Returns the value that was set or `None` if the port does
+    /// not exist.
+    pub fn set_control_input(&mut self, port_index: usize, value: f32) -> Option<f32> {
+        match port_index {
+            Gain::GAIN_DB_PORT => {
+                self.gain_db = value.clamp(Gain::MIN_GAIN_DB, Gain::MAX_GAIN_DB);
+                Some(self.gain_db)
+            }
+            Gain::INVERT_PORT => {
+                self.invert = value > 0.5;
+                Some(if self.invert { 1.0 } else { 0.0 })
+            }
+            Gain::BALANCE_PORT => {
+                self.balance = value.clamp(-1.0, 1.0);
+                Some(self.balance)
+            }
+            _ => None,
+        }
+    }
+
+    /// Write the first `samples` frames of `input` to `output` with the gain
+    /// applied. The gain moves linearly from its value at the end of the last
+    /// block to its current value over the block.
+    pub fn process(&mut self, samples: usize, input: &AudioBuffer, output: &mut AudioBuffer) {
+        let target = self.target_gains();
+        let channels = input.iter_channels().zip(output.iter_channels_mut());
+        for ((src, dst), (from, to)) in channels.zip(self.gains.iter().zip(target.iter())) {
+            let step = (to - from) / samples as f32;
+            for (frame, (src, dst)) in src[..samples].iter().zip(dst.iter_mut()).enumerate() {
+                *dst = src * (from + step * (frame + 1) as f32);
+            }
+        }
+        self.gains = target;
+    }
+
+    /// Get the gain of each channel for the current parameters. The balance
+    /// attenuates the opposite channel and leaves the centered signal at unity
+    /// gain.
+    fn target_gains(&self) -> [f32; AUDIO_CHANNELS] {
+        let mut gain = 10f32.powf(self.gain_db / 20.0);
+        if self.invert {
+            gain = -gain;
+        }
+        [
+            gain * (1.0 - self.balance).min(1.0),
+            gain * (1.0 + self.balance).min(1.0),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A value that processing never writes.
+    const UNTOUCHED: f32 = 7.0;
+
+    /// Process a block of `samples` frames of full level audio and return the
+    /// output channels.
+    fn process(gain: &mut Gain, samples: usize) -> Vec<Vec<f32>> {
+        let input = AudioBuffer::from_channels(&[vec![1.0; samples], vec![1.0; samples]]);
+        let mut output = AudioBuffer::with_stereo(samples);
+        gain.process(samples, &input, &mut output);
+        output.iter_channels().map(|c| c.to_vec()).collect()
+    }
+
+    fn assert_close(actual: &[f32], expected: impl IntoIterator<Item = f32>) {
+        let expected: Vec<f32> = expected.into_iter().collect();
+        assert_eq!(actual.len(), expected.len());
+        for (frame, (a, b)) in actual.iter().zip(expected.iter()).enumerate() {
+            assert!((a - b).abs() < 1e-5, "frame {frame}: {a} != {b}");
+        }
+    }
+
+    #[test]
+    fn default_gain_is_unity() {
+        let mut gain = Gain::default();
+        for channel in process(&mut gain, 64) {
+            assert_close(&channel, std::iter::repeat_n(1.0, 64));
+        }
+    }
+
+    #[test]
+    fn gain_changes_ramp_over_the_block() {
+        let mut gain = Gain::default();
+        assert_eq!(
+            gain.set_control_input(Gain::GAIN_DB_PORT, -20.0),
+            Some(-20.0)
+        );
+        for channel in process(&mut gain, 10) {
+            assert_close(&channel, (1..=10).map(|i| 1.0 - 0.9 * i as f32 / 10.0));
+        }
+        for channel in process(&mut gain, 10) {
+            assert_close(&channel, std::iter::repeat_n(0.1, 10));
+        }
+    }
+
+    #[test]
+    fn invert_flips_the_polarity() {
+        let mut gain = Gain::default();
+        assert_eq!(gain.set_control_input(Gain::INVERT_PORT, 0.7), Some(1.0));
+        gain.set_control_input(Gain::GAIN_DB_PORT, 6.0);
+        process(&mut gain, 16);
+        let expected = -10f32.powf(6.0 / 20.0);
+        for channel in process(&mut gain, 16) {
+            assert_close(&channel, std::iter::repeat_n(expected, 16));
+        }
+        assert_eq!(gain.set_control_input(Gain::INVERT_PORT, 0.2), Some(0.0));
+        process(&mut gain, 16);
+        for channel in process(&mut gain, 16) {
+            assert_close(&channel, std::iter::repeat_n(-expected, 16));
+        }
+    }
+
+    #[test]
+    fn balance_attenuates_the_opposite_channel() {
+        let mut gain = Gain::default();
+        for (balance, left, right) in [(1.0, 0.0, 1.0), (-0.5, 1.0, 0.5), (0.25, 0.75, 1.0)] {
+            gain.set_control_input(Gain::BALANCE_PORT, balance);
+            process(&mut gain, 8);
+            let channels = process(&mut gain, 8);
+            assert_close(&channels[0], std::iter::repeat_n(left, 8));
+            assert_close(&channels[1], std::iter::repeat_n(right, 8));
+        }
+    }
+
+    #[test]
+    fn parameters_are_clamped() {
+        let mut gain = Gain::default();
+        assert_eq!(
+            gain.set_control_input(Gain::GAIN_DB_PORT, 100.0),
+            Some(Gain::MAX_GAIN_DB)
+        );
+        assert_eq!(
+            gain.set_control_input(Gain::GAIN_DB_PORT, -1000.0),
+            Some(Gain::MIN_GAIN_DB)
+        );
+        assert_eq!(gain.set_control_input(Gain::BALANCE_PORT, 3.0), Some(1.0));
+        assert_eq!(gain.set_control_input(Gain::BALANCE_PORT, -3.0), Some(-1.0));
+        assert_eq!(gain.set_control_input(3, 1.0), None);
+    }
+
+    #[test]
+    fn only_the_processed_frames_are_written() {
+        let mut gain = Gain::default();
+        let input = AudioBuffer::from_channels(&[vec![1.0; 32], vec![1.0; 32]]);
+        let mut output = AudioBuffer::from_channels(&[vec![UNTOUCHED; 32], vec![UNTOUCHED; 32]]);
+        gain.process(8, &input, &mut output);
+        for channel in output.iter_channels() {
+            assert_close(&channel[..8], std::iter::repeat_n(1.0, 8));
+            assert!(channel[8..].iter().all(|v| *v == UNTOUCHED));
+        }
+    }
+}
@@ -1,40 +1,130 @@
-use std::sync::{
-    mpsc::{Receiver, SyncSender},
-    Arc,
+use std::{
+    path::PathBuf,
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
 };
 
 use audio_buffer::AudioBuffer;
-use commands::{Command, Notifications};
+use channel::{Receiver, RtReceiver, Sender};
+use clip::MidiEvent;
+use clock_sync::{ClockEvent, ClockSync};
+use commands::{Command, Disposed, Notifications};
 use livi::event::LV2AtomSequence;
 use log::*;
+use meter::{Level, TrackLevels};
 use metronome::Metronome;
-use plugin::SampleTrigger;
-use track::Track;
+use midi_output::{MidiOutput, MidiOutputEvent};
+use notifier::{EngineStats, Notifier};
+use plugin::{PluginInstance, SampleTrigger};
+use plugin_scan::PluginScan;
+use recorder::MasterRecorder;
+use track::{Track, MASTER_TRACK_ID};
+use transport::{SharedTransport, Transport};
 
 pub mod audio_buffer;
+pub mod channel;
+pub mod clip;
+pub mod clock_sync;
 pub mod commands;
+pub mod denormals;
+pub mod drum_kit;
+pub mod freeze;
+pub mod gain;
+pub mod lv2_state;
+pub mod meter;
 pub mod metronome;
+pub mod midi_output;
+pub mod notifier;
 pub mod plugin;
+pub mod plugin_scan;
+pub mod recorder;
+pub mod resources;
 pub mod track;
+pub mod transport;
+
+/// The level above which the limiter starts to compress the output.
+const LIMITER_THRESHOLD: f32 = 0.8;
+
+/// The midi message for a clock pulse.
+const MIDI_CLOCK: u8 = 0xF8;
+
+/// The midi message that starts playback from the start.
+const MIDI_START: u8 = 0xFA;
+
+/// The midi message that stops playback.
+const MIDI_STOP: u8 = 0xFC;
+
+/// The most critical notifications that handling a single command sends.
+/// Commands are left in the queue until the notifier has room for this many
+/// so that the values they dispose of are never dropped while processing.
+const MAX_COMMAND_NOTIFICATIONS: usize = 2;
 
 /// Manages audio and midi processing.
 #[derive(Debug)]
 pub struct Communicator {
     /// A channel to send commands to the main processing.
-    pub commands: SyncSender<Command>,
+    pub commands: Sender<Command>,
     /// A channel to receive notifications from the main processing.
     pub notifications: Receiver<Notifications>,
-    /// Object for managing lv2 plugins.
-    pub livi: Arc<livi::World>,
+    /// The installed lv2 plugins. Plugins must be instantiated with
+    /// `lv2_features`.
+    pub plugins: PluginScan,
     /// Object for managing lv2 features.
     pub lv2_features: Arc<livi::Features>,
+    /// Counters describing the health of the processing.
+    pub stats: Arc<EngineStats>,
+}
+
+/// Configuration for creating a `Processor`.
+#[derive(Clone, Debug)]
+pub struct ProcessorConfig {
+    /// The number of commands that may be queued for the processor.
+    pub commands_capacity: usize,
+    /// The number of notifications that may be queued from the processor.
+    pub notifications_capacity: usize,
+    /// The minimum interval between time info notifications.
+    pub time_info_interval: Duration,
+    /// The minimum interval between meter notifications. The loudest levels
+    /// since the previous notification are sent.
+    pub meter_interval: Duration,
+    /// The directory to load sounds, like the metronome click, from. Sounds
+    /// that are not in the directory use the built in defaults.
+    pub resources_dir: PathBuf,
+    /// The number of midi inputs. Each input may be routed to specific tracks
+    /// with `Command::SetTrackMidiSource`.
+    pub midi_inputs: usize,
+}
+
+impl Default for ProcessorConfig {
+    fn default() -> Self {
+        ProcessorConfig {
+            commands_capacity: 1024,
+            notifications_capacity: 2048,
+            time_info_interval: Duration::ZERO,
+            meter_interval: Duration::ZERO,
+            resources_dir: PathBuf::from("resources"),
+            midi_inputs: 1,
+        }
+    }
 }
 
 /// Implements the `jack::ProcessHandler` trait.
 #[derive(Debug)]
 pub struct Processor {
-    /// The tracks to process.
-    tracks: Vec<Track>,
+    /// The tracks to process. Tracks are kept in the box they are sent in
+    /// so that adding a track does not free memory on the processing thread.
+    #[allow(clippy::vec_box)]
+    tracks: Vec<Box<Track>>,
+    /// The track that the mix of all other audio is run through.
+    master: Track,
+    /// The gain applied to the output.
+    master_volume: f32,
+    /// The gain to ramp to over the next block.
+    master_volume_target: f32,
+    /// If true, the output is softly clipped so that it never exceeds 1.0.
+    limiter_enabled: bool,
+    /// The levels of the tracks in the current block.
+    track_levels: TrackLevels,
     /// Sound effects to process.
     sound_effect: Option<SampleTrigger>,
     /// The sample rate.
@@ -43,146 +133,1670 @@ pub struct Processor {
     midi_urid: lv2_raw::LV2Urid,
     /// An emtpy buffer for midi.
     empty_midi: LV2AtomSequence,
-    /// Buffer for midi input.
+    /// Buffers for the midi of each input over the whole `process` call.
+    cycle_midi: Vec<LV2AtomSequence>,
+    /// Buffers for the midi of each input within the current block.
+    source_midi: Vec<LV2AtomSequence>,
+    /// Messages to add to the live input of the armed track at the start of
+    /// the next `process` call. This is never grown.
+    injected_midi: Vec<wmidi::MidiMessage<'static>>,
+    /// Buffer for the midi of the inputs that are not routed to a track.
     midi_input: LV2AtomSequence,
+    /// Buffer used while merging inputs into `midi_input`.
+    merged_midi: LV2AtomSequence,
+    /// Buffer for the midi input that is on a track's midi channel.
+    channel_midi: LV2AtomSequence,
+    /// Buffer for a track's midi input merged with its clip.
+    track_midi: LV2AtomSequence,
+    /// Buffer for a track's midi after transposition.
+    transposed_midi: LV2AtomSequence,
+    /// Buffer for a track's midi merged with its triggered notes.
+    triggered_midi: LV2AtomSequence,
+    /// Buffer for a track's midi input preceded by all notes off.
+    notes_off_midi: LV2AtomSequence,
     /// Buffer to write output to.
     audio_out: AudioBuffer,
+    /// The midi produced by plugins in the current `process` call.
+    midi_output: MidiOutput,
+    /// The most frames that plugins may process at once.
+    max_block_length: usize,
+    /// Buffer to collect the output of each chunk when more than
+    /// `max_block_length` frames are processed.
+    chunked_out: AudioBuffer,
     /// A channel to receive commands from.
-    commands: Receiver<Command>,
-    /// A channel to send notifications to.
-    notifications: SyncSender<Notifications>,
+    commands: RtReceiver<Command>,
+    /// Sends notifications.
+    notifier: Notifier,
+    /// Counters describing the health of the processing.
+    stats: Arc<EngineStats>,
     /// The metronome.
     metronome: metronome::Metronome,
+    /// The playback state.
+    transport: Transport,
+    /// If true, midi clock is sent to the midi output while the transport is
+    /// playing.
+    midi_clock: bool,
+    /// True if midi start was sent and midi stop has not been sent since.
+    midi_clock_running: bool,
+    /// Follows the midi clock on the midi input if external sync is enabled.
+    clock_sync: ClockSync,
+    /// If true, the transport and metronome follow the shared transport.
+    follow_transport: bool,
+    /// Whether the shared transport was rolling on the last block it was
+    /// followed.
+    shared_playing: Option<bool>,
+    /// The swing set by the user. It is not applied while following the
+    /// shared transport.
+    swing: f32,
+    /// The track being recorded and the events recorded so far. The buffer is
+    /// allocated outside of the processing thread and is never grown.
+    recording: Option<(i32, Vec<MidiEvent>)>,
+    /// The track being recorded and the audio recorded so far for each
+    /// channel. Like `recording`, the buffers are never grown.
+    audio_recording: Option<(i32, Vec<Vec<f32>>)>,
+    /// The track that a sample is being captured for and the audio captured
+    /// so far for each channel. Like `recording`, the buffers are never grown.
+    sample_recording: Option<(i32, Vec<Vec<f32>>)>,
+    /// Streams the output to a writer if the output is being recorded.
+    master_recorder: Option<MasterRecorder>,
 }
 
 impl Processor {
-    /// Create a new processor.
-    pub fn new(sample_rate: f64, buffer_size: usize) -> (Processor, Communicator) {
-        let (commands_tx, commands_rx) = std::sync::mpsc::sync_channel(1024);
-        let (notifications_tx, notifications_rx) = std::sync::mpsc::sync_channel(2048);
-        let livi = Arc::new(livi::World::new());
+    /// Create a new processor with new lv2 features. `plugins` may still be
+    /// scanning.
+    pub fn new(
+        sample_rate: f64,
+        buffer_size: usize,
+        config: &ProcessorConfig,
+        plugins: PluginScan,
+    ) -> (Processor, Communicator) {
+        // The features do not depend on the installed plugins, so they are
+        // built from a world that skips every plugin. This lets the processor
+        // start before the plugin scan finishes.
         let lv2_features = livi::FeaturesBuilder {
             min_block_length: 1,
             max_block_length: buffer_size,
         }
-        .build(&livi);
+        .build(&livi::World::with_plugin_predicate(|_| false));
+        Processor::with_world(sample_rate, buffer_size, config, plugins, lv2_features)
+    }
+
+    /// Create a new processor that shares existing plugins and features.
+    /// Plugins instantiated with `lv2_features` may be added to the processor.
+    pub fn with_world(
+        sample_rate: f64,
+        buffer_size: usize,
+        config: &ProcessorConfig,
+        plugins: PluginScan,
+        lv2_features: Arc<livi::Features>,
+    ) -> (Processor, Communicator) {
+        let (commands_tx, commands_rx) = channel::command_channel(config.commands_capacity);
+        let (notifications_tx, notifications_rx) =
+            channel::notification_channel(config.notifications_capacity);
+        let stats = Arc::new(EngineStats::default());
+        let mut metronome = Metronome::new(sample_rate, &lv2_features);
+        metronome.set_sample(SampleTrigger::from_wav_or_default(
+            &config.resources_dir.join("click.wav"),
+            sample_rate,
+            resources::CLICK_WAV,
+        ));
         let processor = Processor {
             tracks: Vec::with_capacity(32),
+            master: Track::new(MASTER_TRACK_ID, buffer_size),
+            master_volume: 1.0,
+            master_volume_target: 1.0,
+            limiter_enabled: true,
+            track_levels: TrackLevels::default(),
             sound_effect: None,
             sample_rate,
             midi_urid: lv2_features.midi_urid(),
             empty_midi: LV2AtomSequence::new(&lv2_features, 0),
+            cycle_midi: (0..config.midi_inputs.max(1))
+                .map(|_| LV2AtomSequence::new(&lv2_features, 1024 * 1024 /*1 MiB*/))
+                .collect(),
+            source_midi: (0..config.midi_inputs.max(1))
+                .map(|_| LV2AtomSequence::new(&lv2_features, 1024 * 1024 /*1 MiB*/))
+                .collect(),
+            injected_midi: Vec::with_capacity(256),
             midi_input: LV2AtomSequence::new(&lv2_features, 1024 * 1024 /*1 MiB*/),
+            merged_midi: LV2AtomSequence::new(&lv2_features, 1024 * 1024 /*1 MiB*/),
+            channel_midi: LV2AtomSequence::new(&lv2_features, 1024 * 1024 /*1 MiB*/),
+            track_midi: LV2AtomSequence::new(&lv2_features, 1024 * 1024 /*1 MiB*/),
+            transposed_midi: LV2AtomSequence::new(&lv2_features, 1024 * 1024 /*1 MiB*/),
+            triggered_midi: LV2AtomSequence::new(&lv2_features, 1024 * 1024 /*1 MiB*/),
+            notes_off_midi: LV2AtomSequence::new(&lv2_features, 1024 * 1024 /*1 MiB*/),
             audio_out: AudioBuffer::with_stereo(buffer_size),
+            midi_output: MidiOutput::new(&lv2_features, 1024),
+            max_block_length: lv2_features.max_block_length(),
+            chunked_out: AudioBuffer::with_stereo(buffer_size),
             commands: commands_rx,
-            notifications: notifications_tx,
-            metronome: Metronome::new(sample_rate, &lv2_features),
+            notifier: Notifier::new(
+                notifications_tx,
+                stats.clone(),
+                sample_rate,
+                config.time_info_interval,
+                config.meter_interval,
+            ),
+            stats: stats.clone(),
+            metronome,
+            transport: Transport::default(),
+            midi_clock: false,
+            midi_clock_running: false,
+            clock_sync: ClockSync::new(sample_rate),
+            follow_transport: false,
+            shared_playing: None,
+            swing: 0.5,
+            recording: None,
+            audio_recording: None,
+            sample_recording: None,
+            master_recorder: None,
         };
         let communicator = Communicator {
             commands: commands_tx,
             notifications: notifications_rx,
-            livi,
+            plugins,
             lv2_features,
+            stats,
         };
         (processor, communicator)
     }
 
-    /// Do processing and return the results in an audio buffer.
-    pub fn process<'a, I>(&mut self, samples: usize, input_midi: I) -> &AudioBuffer
+    /// Resize the buffers for blocks of `buffer_size` frames so that
+    /// processing blocks of that size does not allocate. This allocates so it
+    /// should be called between calls to `process`, for example from JACK's
+    /// buffer size callback.
+    pub fn set_buffer_size(&mut self, buffer_size: usize) {
+        if buffer_size > self.max_block_length {
+            info!(
+                "Buffer size {} exceeds the max block length {}, blocks will be processed in chunks.",
+                buffer_size, self.max_block_length
+            );
+            self.chunked_out.reset_with_buffer_size(buffer_size);
+        }
+        self.audio_out.reset_with_buffer_size(buffer_size);
+        self.master.set_buffer_size(buffer_size);
+        for track in self.tracks.iter_mut() {
+            track.set_buffer_size(buffer_size);
+        }
+        self.metronome.set_buffer_size(buffer_size);
+    }
+
+    /// Do processing and return the results in an audio buffer. `input_midi`
+    /// contains `(input, frame, data)` for each midi event where the events of
+    /// each input are sorted by frame. `input_audio` contains the audio input
+    /// for each channel. Blocks larger than the lv2 max block length are split
+    /// into chunks, which only avoids allocating if `set_buffer_size` was
+    /// called with the block size.
+    pub fn process<'a, I>(
+        &mut self,
+        samples: usize,
+        input_midi: I,
+        input_audio: &[&[f32]],
+    ) -> &AudioBuffer
     where
-        I: Iterator<Item = (u32, &'a [u8])>,
+        I: Iterator<Item = (usize, u32, &'a [u8])>,
     {
-        // 1. Handle commands.
-        self.handle_commands();
+        profiling::scope!("Processor::process");
+        {
+            profiling::scope!("handle commands");
+            self.handle_commands();
+        }
+        self.track_levels.clear();
+        self.midi_output.clear();
+        for seq in self.cycle_midi.iter_mut() {
+            seq.clear();
+        }
+        if !self.injected_midi.is_empty() {
+            let source = self.injected_midi_source();
+            for message in self.injected_midi.drain(..) {
+                if let Err(err) =
+                    clip::push_message(&mut self.cycle_midi[source], self.midi_urid, 0, &message)
+                {
+                    warn!("Dropping injected midi message: {:?}", err);
+                }
+            }
+        }
+        for (input, frame, data) in input_midi {
+            let seq = match self.cycle_midi.get_mut(input) {
+                Some(seq) => seq,
+                None => {
+                    warn!("Dropping midi message from unknown input {}.", input);
+                    continue;
+                }
+            };
+            if let Err(err) = seq.push_midi_event::<4>(frame as i64, self.midi_urid, data) {
+                warn!("Dropping midi message: {:?}", err);
+            }
+            if let Some(event) = self.clock_sync.handle_message(frame, data) {
+                self.handle_clock_event(event);
+            }
+            if let [status, cc, value] = *data {
+                if status & 0xF0 == 0xB0 {
+                    self.notifier.send(Notifications::MidiCc {
+                        cc,
+                        channel: status & 0x0F,
+                        value,
+                    });
+                }
+            }
+        }
+        let output = if samples <= self.max_block_length {
+            self.process_block(samples, input_audio, 0);
+            &self.audio_out
+        } else {
+            // Plugins may not run on more than `max_block_length` frames at a
+            // time so the block is processed in chunks.
+            self.chunked_out.reset_with_buffer_size(samples);
+            let mut offset = 0;
+            while offset < samples {
+                let chunk = self.max_block_length.min(samples - offset);
+                self.midi_output.set_frame_offset(offset);
+                self.process_block(chunk, input_audio, offset);
+                self.chunked_out.copy_from_at(&self.audio_out, offset);
+                offset += chunk;
+            }
+            &self.chunked_out
+        };
+        self.notifier.send(Notifications::Meters {
+            track_levels: self.track_levels,
+            master: Level::from_buffer(output),
+        });
+        self.notifier
+            .send(Notifications::TimeInfo(self.metronome.current_time_info()));
+        if let Some(beats_per_sample) = self.clock_sync.advance(samples) {
+            self.metronome.set_beats_per_sample(beats_per_sample);
+            self.notifier.send(Notifications::ExternalClockLost {
+                beats_per_minute: (beats_per_sample * self.sample_rate * 60.0) as f32,
+            });
+        }
+        let dropped = self.midi_output.take_dropped();
+        if dropped > 0 {
+            self.stats
+                .midi_output_dropped
+                .fetch_add(dropped, Ordering::Relaxed);
+        }
+        output
+    }
+
+    /// Returns true if the transport follows the shared transport. If so,
+    /// `follow_transport` should be called before each call to `process`.
+    pub fn follows_transport(&self) -> bool {
+        self.follow_transport
+    }
+
+    /// Move the transport and metronome to the shared transport. This does
+    /// nothing unless following was enabled with
+    /// `Command::SetFollowTransport`. The transport is only started or
+    /// stopped when the shared transport starts or stops so that the shared
+    /// transport may lag behind a local play or stop for a few blocks.
+    pub fn follow_transport(&mut self, shared: SharedTransport) {
+        if !self.follow_transport {
+            return;
+        }
+        if let Some(bpm) = shared.beats_per_minute {
+            self.metronome
+                .set_beats_per_sample(bpm / (60.0 * self.sample_rate));
+        }
+        let beats = shared
+            .beats
+            .unwrap_or(shared.frame as f64 * self.metronome.beats_per_sample());
+        self.metronome.sync_to(beats);
+        if self.shared_playing != Some(shared.playing) {
+            self.shared_playing = Some(shared.playing);
+            self.transport = if shared.playing {
+                Transport::Playing
+            } else {
+                Transport::Stopped
+            };
+            self.notifier.send(Notifications::ExternalTransport {
+                playing: shared.playing,
+            });
+        }
+    }
+
+    /// Get the midi that plugins produced in the last call to `process`,
+    /// sorted by frame.
+    pub fn midi_output(&self) -> &[MidiOutputEvent] {
+        self.midi_output.events()
+    }
+
+    /// Count `count` midi output events as dropped. This is used when the
+    /// midi output cannot hold all the events.
+    pub fn add_dropped_midi_output(&self, count: u64) {
+        self.stats
+            .midi_output_dropped
+            .fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Process a block of at most `max_block_length` frames into
+    /// `self.audio_out`. The midi and audio input are read starting at
+    /// `input_offset`.
+    fn process_block(&mut self, samples: usize, input_audio: &[&[f32]], input_offset: usize) {
+        // 1. Advance the notifier.
+        self.notifier.advance(samples);
 
         // 2. Handle sound effect.
-        let clear_sound_effect = if let Some(e) = self.sound_effect.as_mut() {
-            e.process(&self.empty_midi, &mut self.audio_out).unwrap();
-            !e.is_active()
-        } else {
+        {
+            profiling::scope!("sound effect");
             self.audio_out.reset_with_buffer_size(samples);
-            false
-        };
-        if clear_sound_effect {
-            self.sound_effect.take();
+            let clear_sound_effect = if let Some(e) = self.sound_effect.as_mut() {
+                e.process(samples, &self.empty_midi, &mut self.audio_out)
+                    .unwrap();
+                !e.is_active()
+            } else {
+                false
+            };
+            // A finished sound effect is kept until it can be disposed.
+            if clear_sound_effect && self.notifier.has_room_for_critical(1) {
+                if let Some(e) = self.sound_effect.take() {
+                    self.dispose(Disposed::Plugin(e.into()));
+                }
+            }
         }
 
         // 3. Handle timings and metronome.
-        let metronome_volume = self.metronome.volume();
-        let (metronome_out, _) = self.metronome.process(samples);
-        self.audio_out.mix_from(metronome_out, metronome_volume);
+        let input_start = {
+            profiling::scope!("metronome");
+            let metronome_volume = self.metronome.volume();
+            let count_in_end = {
+                let (metronome_out, mut time_info) =
+                    self.metronome.process(samples, self.transport.is_playing());
+                self.audio_out.mix_from(metronome_out, metronome_volume);
+                match self.transport {
+                    Transport::CountingIn => Some(time_info.position(|(_, t)| t.measure >= 0)),
+                    _ => None,
+                }
+            };
+            // Input before `input_start` is discarded while counting in.
+            let input_start = match count_in_end {
+                Some(Some(frame)) => {
+                    self.transport = Transport::Playing;
+                    self.notifier.send(Notifications::CountInFinished);
+                    frame as u32
+                }
+                Some(None) => samples as u32,
+                None => 0,
+            };
+            self.send_midi_clock(input_start as usize);
+            input_start
+        };
 
         // 4. Handle tracks.
-        midi_iter_to_atom_sequence(&mut self.midi_input, self.midi_urid, input_midi);
-        for track in self.tracks.iter_mut() {
-            if track.properties.disabled {
+        {
+            profiling::scope!("tracks");
+            let midi_events = self.split_midi_input(
+                (input_offset + input_start as usize) as i64,
+                (input_offset + samples) as i64,
+                input_offset as i64,
+            );
+            if midi_events > 0 {
+                self.stats
+                    .midi_events_received
+                    .fetch_add(midi_events as u64, Ordering::Relaxed);
+            }
+            if self.transport.is_playing() {
+                self.record_midi_input();
+                self.record_audio_input(
+                    input_audio,
+                    input_offset + input_start as usize..input_offset + samples,
+                );
+            }
+            self.record_sample_input(
+                input_audio,
+                input_offset + input_start as usize..input_offset + samples,
+            );
+            let playing = self.transport.is_playing();
+            let any_solo = self.tracks.iter().any(|t| t.properties.solo);
+            for track in self.tracks.iter_mut() {
+                if track.properties.disabled || (any_solo && !track.properties.solo) {
+                    continue;
+                }
+                profiling::scope!("track");
+                let id = track.id();
+                let volume = track.properties.volume;
+                let pan = track.properties.pan;
+                if track.is_frozen() {
+                    let output = track.process_frozen(samples, self.metronome.time_info(), playing);
+                    self.track_levels.push(id, Level::from_buffer(output));
+                    self.audio_out.mix_from_panned(output, volume, pan);
+                    continue;
+                }
+                let input = match track.properties.midi_source {
+                    Some(source) => self.source_midi.get(source).unwrap_or(&self.empty_midi),
+                    None => &self.midi_input,
+                };
+                let mut midi = match (track.properties.monitoring, track.properties.midi_channel) {
+                    (false, _) => &self.empty_midi,
+                    (true, None) => input,
+                    (true, Some(channel)) => {
+                        filter_midi_channel(&mut self.channel_midi, self.midi_urid, input, channel);
+                        &self.channel_midi
+                    }
+                };
+                if playing
+                    && track.merge_clip_events(
+                        self.metronome.time_info(),
+                        midi,
+                        self.midi_urid,
+                        &mut self.track_midi,
+                    )
+                {
+                    midi = &self.track_midi;
+                }
+                if track.transpose_midi(midi, self.midi_urid, &mut self.transposed_midi) {
+                    midi = &self.transposed_midi;
+                }
+                if track.merge_triggered_notes(
+                    samples,
+                    midi,
+                    self.midi_urid,
+                    &mut self.triggered_midi,
+                ) {
+                    midi = &self.triggered_midi;
+                }
+                if track.take_notes_off_request() {
+                    prepend_notes_off(&mut self.notes_off_midi, self.midi_urid, midi);
+                    midi = &self.notes_off_midi;
+                }
+                let output = track.process(samples, midi, &mut self.midi_output);
+                self.track_levels.push(id, Level::from_buffer(output));
+                self.audio_out.mix_from_panned(output, volume, pan);
+                if let Some((plugin_index, error)) = track.take_plugin_error() {
+                    self.notifier.send(Notifications::PluginError {
+                        track_id: id,
+                        plugin_index,
+                        error,
+                    });
+                }
+                if self.notifier.has_room_for_critical(1) {
+                    if let Some(clip) = track.take_finished_clip() {
+                        self.notifier
+                            .send(Notifications::Disposed(Disposed::Clip(clip)));
+                    }
+                }
+            }
+        }
+
+        // 5. Run the mix through the master track and apply the master volume.
+        {
+            profiling::scope!("master");
+            if !self.master.properties.disabled {
+                let output = self.master.process_with_input(
+                    samples,
+                    &self.audio_out,
+                    &self.empty_midi,
+                    &mut self.midi_output,
+                );
+                self.audio_out.reset_with_buffer_size(samples);
+                self.audio_out.mix_from(output, 1.0);
+                if let Some((plugin_index, error)) = self.master.take_plugin_error() {
+                    self.notifier.send(Notifications::PluginError {
+                        track_id: MASTER_TRACK_ID,
+                        plugin_index,
+                        error,
+                    });
+                }
+            }
+            if self.master_volume != 1.0 || self.master_volume_target != 1.0 {
+                self.audio_out
+                    .apply_gain_ramp(self.master_volume, self.master_volume_target);
+                self.master_volume = self.master_volume_target;
+            }
+        }
+
+        // 6. Protect the output from clipping.
+        {
+            profiling::scope!("limiter");
+            let loudest =
+                self.audio_out
+                    .peaks()
+                    .enumerate()
+                    .fold((0, 0f32), |a, b| if b.1 > a.1 { b } else { a });
+            if loudest.1 > 1.0 {
+                self.notifier.send(Notifications::ClipDetected {
+                    channel: loudest.0,
+                    peak: loudest.1,
+                });
+            }
+            if self.limiter_enabled {
+                self.audio_out.soft_clip(LIMITER_THRESHOLD);
+            }
+        }
+
+        // 7. Record the output.
+        if let Some(recorder) = self.master_recorder.as_mut() {
+            profiling::scope!("record output");
+            recorder.record(samples, &self.audio_out);
+            if let Some((frames_written, overruns)) = recorder.stats_due(self.sample_rate as usize)
+            {
+                self.notifier.send(Notifications::RecordingStats {
+                    frames_written,
+                    overruns,
+                });
+            }
+        }
+    }
+
+    /// Apply an event from the midi clock on the midi input to the metronome
+    /// and transport.
+    fn handle_clock_event(&mut self, event: ClockEvent) {
+        match event {
+            ClockEvent::Tempo {
+                beats_per_sample,
+                beat,
+            } => {
+                self.metronome.set_beats_per_sample(beats_per_sample);
+                if beat {
+                    self.notifier.send(Notifications::ExternalTempo(
+                        (beats_per_sample * self.sample_rate * 60.0) as f32,
+                    ));
+                }
+            }
+            ClockEvent::Start => {
+                self.metronome.seek(0, 0);
+                self.transport = Transport::Playing;
+                self.notifier
+                    .send(Notifications::ExternalTransport { playing: true });
+            }
+            ClockEvent::Continue => {
+                self.transport = Transport::Playing;
+                self.notifier
+                    .send(Notifications::ExternalTransport { playing: true });
+            }
+            ClockEvent::Stop => {
+                self.transport = Transport::Stopped;
+                self.notifier
+                    .send(Notifications::ExternalTransport { playing: false });
+            }
+        }
+    }
+
+    /// Set the swing of the metronome to the user's swing, or to straight
+    /// timing while following the shared transport.
+    fn apply_swing(&mut self) {
+        let swing = if self.follow_transport {
+            0.5
+        } else {
+            self.swing
+        };
+        self.metronome.set_swing(swing);
+    }
+
+    /// Send midi start or stop if the clock started or stopped and send the
+    /// clock pulses of the block from `start`, the frame the transport started
+    /// playing at.
+    fn send_midi_clock(&mut self, start: usize) {
+        let playing = self.midi_clock && self.transport == Transport::Playing;
+        if playing != self.midi_clock_running {
+            let message = if playing { MIDI_START } else { MIDI_STOP };
+            self.midi_output.push(start, &[message]);
+            self.midi_clock_running = playing;
+        }
+        if !playing {
+            return;
+        }
+        for frame in self.metronome.clock_pulses() {
+            if *frame >= start {
+                self.midi_output.push(*frame, &[MIDI_CLOCK]);
+            }
+        }
+    }
+
+    /// Get the input that injected midi is added to. This is the input of the
+    /// armed track, or the first input that no track is routed to if the
+    /// armed track takes input from all unrouted inputs.
+    fn injected_midi_source(&self) -> usize {
+        let armed_source = self
+            .tracks
+            .iter()
+            .find(|t| t.properties.armed)
+            .and_then(|t| t.properties.midi_source)
+            .filter(|source| *source < self.cycle_midi.len());
+        armed_source
+            .or_else(|| {
+                (0..self.cycle_midi.len()).find(|source| {
+                    !self
+                        .tracks
+                        .iter()
+                        .any(|t| t.properties.midi_source == Some(*source))
+                })
+            })
+            .unwrap_or(0)
+    }
+
+    /// Fill `source_midi` with the events of each input in `[start, end)`
+    /// frames, shifted back by `offset` frames, and fill `midi_input` with the
+    /// events of the inputs that no track is routed to. Returns the number of
+    /// events.
+    fn split_midi_input(&mut self, start: i64, end: i64, offset: i64) -> usize {
+        let mut count = 0;
+        for (cycle, block) in self.cycle_midi.iter().zip(self.source_midi.iter_mut()) {
+            block.clear();
+            for event in cycle.iter() {
+                let frame = event.event.time_in_frames;
+                if !(start..end).contains(&frame) {
+                    continue;
+                }
+                count += 1;
+                if let Err(err) =
+                    block.push_midi_event::<4>(frame - offset, self.midi_urid, event.data)
+                {
+                    warn!("Dropping midi message: {:?}", err);
+                }
+            }
+        }
+        self.midi_input.clear();
+        for (source, block) in self.source_midi.iter().enumerate() {
+            let routed = self
+                .tracks
+                .iter()
+                .any(|t| t.properties.midi_source == Some(source));
+            if routed || block.iter().next().is_none() {
                 continue;
             }
-            let volume = track.properties.volume;
-            let armed = track.properties.armed;
-            let output = track.process(
-                samples,
-                if armed {
-                    &self.midi_input
-                } else {
-                    &self.empty_midi
-                },
+            merge_midi(
+                &mut self.merged_midi,
+                self.midi_urid,
+                &self.midi_input,
+                block,
             );
-            self.audio_out.mix_from(output, volume);
+            std::mem::swap(&mut self.midi_input, &mut self.merged_midi);
         }
+        count
+    }
 
-        // 5. Return the outputs.
-        self.notifications
-            .try_send(Notifications::TimeInfo(self.metronome.current_time_info()))
-            .ok();
-        &self.audio_out
+    /// Append the midi input to the recording if the recorded track is armed.
+    fn record_midi_input(&mut self) {
+        let (track_id, events) = match self.recording.as_mut() {
+            Some(r) => r,
+            None => return,
+        };
+        let (channel, input) = match self
+            .tracks
+            .iter()
+            .find(|t| t.id() == *track_id && t.properties.armed)
+        {
+            Some(t) => match t.properties.midi_source {
+                Some(source) => (
+                    t.properties.midi_channel,
+                    self.source_midi.get(source).unwrap_or(&self.empty_midi),
+                ),
+                None => (t.properties.midi_channel, &self.midi_input),
+            },
+            None => return,
+        };
+        for event in input.iter() {
+            if let (Some(channel), Some(status)) = (channel, event.data.first()) {
+                if *status < 0xF0 && status & 0x0F != channel {
+                    continue;
+                }
+            }
+            let message = match wmidi::MidiMessage::try_from(event.data)
+                .ok()
+                .and_then(wmidi::MidiMessage::drop_unowned_sysex)
+            {
+                Some(m) => m,
+                None => continue,
+            };
+            if events.len() == events.capacity() {
+                self.stats
+                    .recorded_events_dropped
+                    .fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+            let frame = event.event.time_in_frames as usize;
+            events.push(MidiEvent {
+                beat: self.metronome.time_info_at(frame).to_beats(),
+                message,
+            });
+        }
     }
 
-    /// Handle all commands in `self.commands`.
+    /// Append `frames` of the audio input to the audio recording if the
+    /// recorded track is armed.
+    fn record_audio_input(&mut self, input_audio: &[&[f32]], frames: std::ops::Range<usize>) {
+        let (track_id, channels) = match self.audio_recording.as_mut() {
+            Some(r) => r,
+            None => return,
+        };
+        let armed = self
+            .tracks
+            .iter()
+            .any(|t| t.id() == *track_id && t.properties.armed);
+        if !armed || frames.is_empty() {
+            return;
+        }
+        for (channel, input) in channels.iter_mut().zip(input_audio.iter()) {
+            let input = match input.get(frames.clone()) {
+                Some(i) => i,
+                None => continue,
+            };
+            let available = channel.capacity() - channel.len();
+            if available < input.len() {
+                self.stats
+                    .recorded_frames_dropped
+                    .fetch_add((input.len() - available) as u64, Ordering::Relaxed);
+            }
+            channel.extend_from_slice(&input[..available.min(input.len())]);
+        }
+    }
+
+    /// Append `frames` of the audio input to the sample recording. The
+    /// recording is sent as a notification once its buffers are full.
+    fn record_sample_input(&mut self, input_audio: &[&[f32]], frames: std::ops::Range<usize>) {
+        let channels = match self.sample_recording.as_mut() {
+            Some((_, channels)) => channels,
+            None => return,
+        };
+        let mut full = false;
+        for (channel, input) in channels.iter_mut().zip(input_audio.iter()) {
+            let input = match input.get(frames.clone()) {
+                Some(i) => i,
+                None => continue,
+            };
+            let available = channel.capacity() - channel.len();
+            channel.extend_from_slice(&input[..available.min(input.len())]);
+            full |= channel.len() == channel.capacity();
+        }
+        // A full recording is kept until it can be sent.
+        if full && self.notifier.has_room_for_critical(1) {
+            if let Some((track_id, channels)) = self.sample_recording.take() {
+                self.notifier
+                    .send(Notifications::RecordedSample(track_id, channels));
+            }
+        }
+    }
+
+    /// Send `disposed` to be dropped outside of the processing thread.
+    fn dispose(&mut self, disposed: Disposed) {
+        self.notifier.send(Notifications::Disposed(disposed));
+    }
+
+    /// Handle the commands in `self.commands`. Commands are left in the queue
+    /// while the notifier has no room for the notifications they may send.
     fn handle_commands(&mut self) {
-        for cmd in self.commands.try_iter() {
+        while self
+            .notifier
+            .has_room_for_critical(MAX_COMMAND_NOTIFICATIONS)
+        {
+            let cmd = match self.commands.try_recv() {
+                Some(cmd) => cmd,
+                None => break,
+            };
             match cmd {
                 Command::AddTrack(track) => self.tracks.push(track),
-                Command::DeleteTrack(id) => self.tracks.retain(|t| t.id() != id),
+                Command::DeleteTrack(id) => {
+                    if let Some(idx) = self.tracks.iter().position(|t| t.id() == id) {
+                        let track = self.tracks.remove(idx);
+                        self.dispose(Disposed::Track(track));
+                    }
+                }
                 Command::AddPluginToTrack(id, instance) => {
-                    if let Some(t) = self.tracks.iter_mut().find(|t| t.id() == id) {
-                        t.push_plugin(instance);
+                    match self.tracks.iter_mut().find(|t| t.id() == id) {
+                        Some(t) => t.push_plugin(instance),
+                        None => self.dispose(Disposed::Plugin(instance)),
                     }
                 }
                 Command::DeletePlugin(track_id, plugin_index) => {
-                    if let Some(t) = self.tracks.iter_mut().find(|t| t.id() == track_id) {
-                        t.remove_plugin(plugin_index);
+                    let removed = self
+                        .tracks
+                        .iter_mut()
+                        .find(|t| t.id() == track_id)
+                        .and_then(|t| t.remove_plugin(plugin_index));
+                    if let Some(plugin) = removed {
+                        self.dispose(Disposed::Plugin(plugin));
                     }
                 }
+                Command::InsertPlugin(track_id, plugin_index, instance) => {
+                    let track = if track_id == MASTER_TRACK_ID {
+                        Some(&mut self.master)
+                    } else {
+                        self.tracks
+                            .iter_mut()
+                            .find(|t| t.id() == track_id)
+                            .map(|t| t.as_mut())
+                    };
+                    match track {
+                        Some(t) => t.insert_plugin(plugin_index, instance),
+                        None => self.dispose(Disposed::Plugin(instance)),
+                    }
+                }
+                Command::ReplacePlugin {
+                    track_id,
+                    plugin_index,
+                    instance,
+                } => {
+                    let track = if track_id == MASTER_TRACK_ID {
+                        Some(&mut self.master)
+                    } else {
+                        self.tracks
+                            .iter_mut()
+                            .find(|t| t.id() == track_id)
+                            .map(|t| t.as_mut())
+                    };
+                    let disposed = match track.and_then(|t| t.plugin_mut(plugin_index)) {
+                        Some(current) => std::mem::replace(current, instance),
+                        None => instance,
+                    };
+                    self.dispose(Disposed::Plugin(disposed));
+                }
+                Command::MovePlugin { track_id, from, to } => {
+                    let track = if track_id == MASTER_TRACK_ID {
+                        Some(&mut self.master)
+                    } else {
+                        self.tracks
+                            .iter_mut()
+                            .find(|t| t.id() == track_id)
+                            .map(|t| t.as_mut())
+                    };
+                    if let Some(t) = track {
+                        t.move_plugin(from, to);
+                    }
+                }
+                Command::SetMasterVolume(volume) => self.master_volume_target = volume,
+                Command::SetLimiter(enabled) => self.limiter_enabled = enabled,
+                Command::AddPluginToMaster(instance) => self.master.push_plugin(instance),
+                Command::DeletePluginFromMaster(plugin_index) => {
+                    if let Some(plugin) = self.master.remove_plugin(plugin_index) {
+                        self.dispose(Disposed::Plugin(plugin));
+                    }
+                }
+                Command::SetPluginParam {
+                    track_id,
+                    plugin_index,
+                    port_index,
+                    value,
+                } => {
+                    let track = if track_id == MASTER_TRACK_ID {
+                        Some(&mut self.master)
+                    } else {
+                        self.tracks
+                            .iter_mut()
+                            .find(|t| t.id() == track_id)
+                            .map(|t| t.as_mut())
+                    };
+                    if let Some(p) = track.and_then(|t| t.plugin_mut(plugin_index)) {
+                        p.set_control_input(port_index, value);
+                    }
+                }
+                Command::SetDrumKit {
+                    track_id,
+                    plugin_index,
+                    kit,
+                } => {
+                    let track = if track_id == MASTER_TRACK_ID {
+                        Some(&mut self.master)
+                    } else {
+                        self.tracks
+                            .iter_mut()
+                            .find(|t| t.id() == track_id)
+                            .map(|t| t.as_mut())
+                    };
+                    let disposed = match track.and_then(|t| t.plugin_mut(plugin_index)) {
+                        Some(PluginInstance::DrumKit(current)) => std::mem::replace(current, kit),
+                        _ => kit,
+                    };
+                    self.dispose(Disposed::Plugin(PluginInstance::DrumKit(disposed)));
+                }
                 Command::SetMetronome {
                     volume,
                     beats_per_minute,
-                } => self
-                    .metronome
-                    .set_properties(self.sample_rate, volume, beats_per_minute),
+                    swing,
+                    apply_on_downbeat,
+                    external_sync,
+                } => {
+                    self.swing = swing;
+                    self.apply_swing();
+                    self.clock_sync.set_enabled(external_sync);
+                    if external_sync {
+                        self.metronome.set_volume(volume);
+                    } else {
+                        self.metronome.set_properties(
+                            self.sample_rate,
+                            volume,
+                            beats_per_minute,
+                            apply_on_downbeat && self.transport.is_playing(),
+                        )
+                    }
+                }
+                Command::SetMetronomeSample(sample) => {
+                    if let Some(previous) = self.metronome.set_sample(sample) {
+                        self.dispose(Disposed::Plugin(previous));
+                    }
+                }
+                Command::SetMidiClock(enabled) => self.midi_clock = enabled,
+                Command::SetFollowTransport(enabled) => {
+                    self.follow_transport = enabled;
+                    self.shared_playing = None;
+                    self.apply_swing();
+                }
                 Command::ArmTrack(track_id) => {
                     for track in self.tracks.iter_mut() {
-                        track.properties.armed = track.id() == track_id;
+                        let armed = track.id() == track_id;
+                        if armed {
+                            track.properties.monitoring = true;
+                        } else if track.properties.armed {
+                            track.properties.monitoring = false;
+                            track.request_notes_off();
+                        }
+                        track.properties.armed = armed;
+                    }
+                }
+                Command::SetTrackMidiChannel(track_id, channel) => {
+                    if let Some(t) = self.tracks.iter_mut().find(|t| t.id() == track_id) {
+                        if t.properties.midi_channel != channel {
+                            t.request_notes_off();
+                        }
+                        t.properties.midi_channel = channel;
+                    }
+                }
+                Command::SetTrackMidiSource(track_id, source) => {
+                    if let Some(t) = self.tracks.iter_mut().find(|t| t.id() == track_id) {
+                        if t.properties.midi_source != source {
+                            t.request_notes_off();
+                        }
+                        t.properties.midi_source = source;
+                    }
+                }
+                Command::SetTrackSendMidi(track_id, send_midi) => {
+                    if let Some(t) = self.tracks.iter_mut().find(|t| t.id() == track_id) {
+                        t.properties.send_midi = send_midi;
+                    }
+                }
+                Command::SetTrackTranspose(track_id, transpose) => {
+                    if let Some(t) = self.tracks.iter_mut().find(|t| t.id() == track_id) {
+                        t.set_transpose(transpose);
+                    }
+                }
+                Command::SetTrackMonitoring(track_id, monitoring) => {
+                    if let Some(t) = self.tracks.iter_mut().find(|t| t.id() == track_id) {
+                        if t.properties.monitoring && !monitoring {
+                            t.request_notes_off();
+                        }
+                        t.properties.monitoring = monitoring;
+                    }
+                }
+                Command::InjectMidi(message) => {
+                    if self.injected_midi.len() < self.injected_midi.capacity() {
+                        self.injected_midi.push(message);
+                    } else {
+                        warn!("Dropping injected midi message {:?}.", message);
+                    }
+                }
+                Command::TriggerNote {
+                    track_id,
+                    note,
+                    velocity,
+                    frames,
+                } => {
+                    if let Some(t) = self.tracks.iter_mut().find(|t| t.id() == track_id) {
+                        if !t.trigger_note(note, velocity, frames) {
+                            warn!("Dropping triggered note {:?} on track {}.", note, track_id);
+                        }
+                    }
+                }
+                Command::MidiPanic => {
+                    for track in self.tracks.iter_mut() {
+                        track.request_notes_off();
+                    }
+                }
+                Command::SetTrackSolo(track_id, solo) => {
+                    if let Some(t) = self.tracks.iter_mut().find(|t| t.id() == track_id) {
+                        t.properties.solo = solo;
+                    }
+                    // Tracks that are muted by the solo would otherwise keep
+                    // their held notes when they are heard again.
+                    let any_solo = self.tracks.iter().any(|t| t.properties.solo);
+                    for track in self.tracks.iter_mut() {
+                        if any_solo && !track.properties.solo {
+                            track.request_notes_off();
+                        }
+                    }
+                }
+                Command::SetTrackPan(track_id, pan) => {
+                    if let Some(t) = self.tracks.iter_mut().find(|t| t.id() == track_id) {
+                        t.properties.pan = pan;
+                    }
+                }
+                Command::SetTrackVolume(track_id, volume) => {
+                    if let Some(t) = self.tracks.iter_mut().find(|t| t.id() == track_id) {
+                        t.properties.volume = volume;
+                    }
+                }
+                Command::EnableTrack(track_id) => {
+                    if track_id == MASTER_TRACK_ID {
+                        self.master.enable();
+                    } else if let Some(t) = self.tracks.iter_mut().find(|t| t.id() == track_id) {
+                        t.enable();
+                    }
+                }
+                Command::TransportPlay => self.transport = Transport::Playing,
+                Command::TransportStop => self.transport = Transport::Stopped,
+                Command::TransportCountIn(measures) => {
+                    self.metronome.count_in(measures);
+                    self.transport = if measures == 0 {
+                        Transport::Playing
+                    } else {
+                        Transport::CountingIn
+                    };
+                }
+                Command::TransportSeek { measure, beat } => self.metronome.seek(measure, beat),
+                Command::FreezeTrack(track_id, frozen) => {
+                    let previous = match self.tracks.iter_mut().find(|t| t.id() == track_id) {
+                        Some(t) => t.set_frozen(Some(frozen)),
+                        None => Some(frozen),
+                    };
+                    if let Some(previous) = previous {
+                        self.dispose(Disposed::Frozen(previous));
+                    }
+                }
+                Command::UnfreezeTrack(track_id) => {
+                    let previous = self
+                        .tracks
+                        .iter_mut()
+                        .find(|t| t.id() == track_id)
+                        .and_then(|t| t.set_frozen(None));
+                    if let Some(previous) = previous {
+                        self.dispose(Disposed::Frozen(previous));
+                    }
+                }
+                Command::SetTrackClip(track_id, clip) => {
+                    let (previous, queued) =
+                        match self.tracks.iter_mut().find(|t| t.id() == track_id) {
+                            Some(t) => t.set_clip(clip),
+                            None => (Some(clip), None),
+                        };
+                    for clip in previous.into_iter().chain(queued) {
+                        self.dispose(Disposed::Clip(clip));
+                    }
+                }
+                Command::QueueTrackClip(track_id, clip) => {
+                    let previous = match self.tracks.iter_mut().find(|t| t.id() == track_id) {
+                        Some(t) => t.queue_clip(clip),
+                        None => Some(clip),
+                    };
+                    if let Some(previous) = previous {
+                        self.dispose(Disposed::Clip(previous));
+                    }
+                }
+                Command::StartRecording(track_id, events) => {
+                    if let Some((_, previous)) = self.recording.replace((track_id, events)) {
+                        self.dispose(Disposed::MidiEvents(previous));
                     }
                 }
-                Command::PlaySound(e) => self.sound_effect = Some(e),
+                Command::StartAudioRecording(track_id, channels) => {
+                    if let Some((_, previous)) = self.audio_recording.replace((track_id, channels))
+                    {
+                        self.dispose(Disposed::Audio(previous));
+                    }
+                }
+                Command::StopRecording => {
+                    if let Some((track_id, events)) = self.recording.take() {
+                        self.notifier
+                            .send(Notifications::RecordedClip(track_id, events));
+                    }
+                    if let Some((track_id, channels)) = self.audio_recording.take() {
+                        self.notifier
+                            .send(Notifications::RecordedAudio(track_id, channels));
+                    }
+                }
+                Command::StartSampleRecording(track_id, channels) => {
+                    if let Some((_, previous)) = self.sample_recording.replace((track_id, channels))
+                    {
+                        self.dispose(Disposed::Audio(previous));
+                    }
+                }
+                Command::StopSampleRecording => {
+                    if let Some((track_id, channels)) = self.sample_recording.take() {
+                        self.notifier
+                            .send(Notifications::RecordedSample(track_id, channels));
+                    }
+                }
+                Command::StartMasterRecord(recorder) => {
+                    if let Some(previous) = self.master_recorder.replace(recorder) {
+                        previous.finish();
+                        self.dispose(Disposed::Recorder(previous));
+                    }
+                }
+                Command::StopMasterRecord => {
+                    if let Some(recorder) = self.master_recorder.take() {
+                        recorder.finish();
+                        let (frames_written, overruns) = recorder.stats();
+                        self.notifier.send(Notifications::RecordingStats {
+                            frames_written,
+                            overruns,
+                        });
+                        self.dispose(Disposed::Recorder(recorder));
+                    }
+                }
+                Command::PlaySound(e) => {
+                    if let Some(previous) = self.sound_effect.replace(e) {
+                        self.dispose(Disposed::Plugin(previous.into()));
+                    }
+                }
+                Command::SetTimeInfoInterval(interval) => {
+                    self.notifier.set_time_info_interval(interval)
+                }
+                Command::SetMeterInterval(interval) => self.notifier.set_meter_interval(interval),
             }
         }
     }
 }
 
-/// Reset the midi input with the contents of `midi_input.`
-fn midi_iter_to_atom_sequence<'a, I>(
+/// Fill `seq` with all notes off and all sound off on every channel at the
+/// first frame followed by the events of `input`.
+fn prepend_notes_off(
     seq: &mut LV2AtomSequence,
     midi_urid: lv2_raw::LV2Urid,
-    midi_input: I,
-) where
-    I: Iterator<Item = (u32, &'a [u8])>,
-{
+    input: &LV2AtomSequence,
+) {
+    /// The controller numbers for all sound off and all notes off.
+    const CONTROLLERS: [u8; 2] = [120, 123];
     seq.clear();
-    for (frame, data) in midi_input {
-        if let Err(err) = seq.push_midi_event::<4>(frame as i64, midi_urid, data) {
+    for channel in 0..16u8 {
+        for controller in CONTROLLERS {
+            let data = [0xB0 | channel, controller, 0];
+            if let Err(err) = seq.push_midi_event::<3>(0, midi_urid, &data) {
+                warn!("Dropping all notes off message: {:?}", err);
+            }
+        }
+    }
+    for event in input.iter() {
+        if let Err(err) =
+            seq.push_midi_event::<4>(event.event.time_in_frames, midi_urid, event.data)
+        {
             warn!("Dropping midi message: {:?}", err);
+        }
+    }
+}
+
+/// Fill `seq` with the events of `input` that are on `channel` or that are not
+/// channel messages.
+fn filter_midi_channel(
+    seq: &mut LV2AtomSequence,
+    midi_urid: lv2_raw::LV2Urid,
+    input: &LV2AtomSequence,
+    channel: u8,
+) {
+    seq.clear();
+    for event in input.iter() {
+        let keep = match event.data.first() {
+            Some(status) if *status < 0xF0 => status & 0x0F == channel,
+            _ => true,
+        };
+        if !keep {
+            continue;
+        }
+        if let Err(err) =
+            seq.push_midi_event::<4>(event.event.time_in_frames, midi_urid, event.data)
+        {
+            warn!("Dropping midi message: {:?}", err);
+        }
+    }
+}
+
+/// Fill `seq` with the events of `a` and `b` sorted by frame. Events of `a`
+/// come first when both have events on the same frame.
+fn merge_midi(
+    seq: &mut LV2AtomSequence,
+    midi_urid: lv2_raw::LV2Urid,
+    a: &LV2AtomSequence,
+    b: &LV2AtomSequence,
+) {
+    seq.clear();
+    let mut a = a.iter().peekable();
+    let mut b = b.iter().peekable();
+    loop {
+        let a_frame = a.peek().map(|e| e.event.time_in_frames);
+        let b_frame = b.peek().map(|e| e.event.time_in_frames);
+        let event = match (a_frame, b_frame) {
+            (None, None) => break,
+            (Some(a_frame), Some(b_frame)) if b_frame < a_frame => b.next().unwrap(),
+            (Some(_), _) => a.next().unwrap(),
+            (None, Some(_)) => b.next().unwrap(),
+        };
+        if let Err(err) =
+            seq.push_midi_event::<4>(event.event.time_in_frames, midi_urid, event.data)
+        {
+            warn!("Dropping midi message: {:?}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        alloc::{GlobalAlloc, Layout, System},
+        cell::Cell,
+    };
+
+    use super::*;
+    use crate::{clip::Clip, gain::Gain};
+
+    /// Counts the allocations and frees of a thread while it is guarded.
+    struct GuardedAllocator;
+
+    thread_local! {
+        static GUARDED: Cell<bool> = const { Cell::new(false) };
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    fn record_allocation() {
+        // The thread locals may already be destroyed while a thread exits.
+        let _ = GUARDED.try_with(|guarded| {
+            if guarded.get() {
+                ALLOCATIONS.with(|a| a.set(a.get() + 1));
+            }
+        });
+    }
+
+    unsafe impl GlobalAlloc for GuardedAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            record_allocation();
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            record_allocation();
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: GuardedAllocator = GuardedAllocator;
+
+    /// Run `f` and return the number of times it allocated or freed memory.
+    fn count_allocations(f: impl FnOnce()) -> usize {
+        ALLOCATIONS.with(|a| a.set(0));
+        GUARDED.with(|g| g.set(true));
+        f();
+        GUARDED.with(|g| g.set(false));
+        ALLOCATIONS.with(|a| a.get())
+    }
+
+    #[test]
+    fn handle_commands_disposes_replaced_values_without_allocating() {
+        let buffer_size = 128;
+        let (mut processor, communicator) = Processor::new(
+            44100.0,
+            buffer_size,
+            &ProcessorConfig::default(),
+            PluginScan::default(),
+        );
+        processor.set_buffer_size(buffer_size);
+        let recording = || {
+            (0..2)
+                .map(|_| Vec::with_capacity(buffer_size * 4))
+                .collect()
         };
+        let commands = [
+            Command::AddTrack(Box::new(Track::new(1, buffer_size))),
+            Command::AddPluginToTrack(1, PluginInstance::Gain(Gain::default())),
+            // Track 2 does not exist.
+            Command::AddPluginToTrack(2, PluginInstance::Gain(Gain::default())),
+            Command::InsertPlugin(2, 0, PluginInstance::Gain(Gain::default())),
+            Command::SetTrackClip(2, Clip::new(Vec::new())),
+            Command::SetTrackClip(1, Clip::new(Vec::new())),
+            Command::QueueTrackClip(1, Clip::new(Vec::new())),
+            Command::QueueTrackClip(1, Clip::new(Vec::new())),
+            Command::SetTrackClip(1, Clip::new(Vec::new())),
+            Command::StartRecording(1, Vec::with_capacity(16)),
+            Command::StartRecording(1, Vec::with_capacity(16)),
+            Command::StartAudioRecording(1, recording()),
+            Command::StartAudioRecording(1, recording()),
+            Command::StartSampleRecording(1, recording()),
+            Command::StartSampleRecording(1, recording()),
+            Command::StartMasterRecord(MasterRecorder::new(buffer_size * 4).0),
+            Command::StartMasterRecord(MasterRecorder::new(buffer_size * 4).0),
+            Command::StopMasterRecord,
+            Command::DeleteTrack(1),
+        ];
+        for command in commands {
+            communicator.commands.send(command).unwrap();
+        }
+
+        let input = vec![0.0; buffer_size];
+        let input_audio = [input.as_slice(), input.as_slice()];
+        let allocations = count_allocations(|| {
+            processor.process(buffer_size, std::iter::empty(), &input_audio);
+        });
+        assert_eq!(allocations, 0);
+
+        let disposed = communicator
+            .notifications
+            .try_iter()
+            .filter(|n| matches!(n, Notifications::Disposed(_)))
+            .count();
+        // 2 plugins and 1 clip for the missing track, 3 replaced clips, 4
+        // replaced recordings, 2 master recorders and the deleted track.
+        assert_eq!(disposed, 12);
+    }
+
+    /// Play a sine wave with an amplitude of 2.0 through a processor and return
+    /// the peak of each block and the notifications.
+    fn play_loud_sine(limiter_enabled: bool) -> (Vec<f32>, Vec<Notifications>) {
+        let buffer_size = 128;
+        let sample_rate = 44100.0;
+        let (mut processor, communicator) = Processor::new(
+            sample_rate,
+            buffer_size,
+            &ProcessorConfig::default(),
+            PluginScan::default(),
+        );
+        let sine: Vec<f32> = (0..buffer_size * 16)
+            .map(|frame| {
+                2.0 * (frame as f32 * 440.0 / sample_rate as f32 * std::f32::consts::TAU).sin()
+            })
+            .collect();
+        let mut sound = SampleTrigger::new(
+            Arc::new(AudioBuffer::from_channels(&[sine.clone(), sine])),
+            sample_rate,
+        );
+        sound.start();
+        communicator
+            .commands
+            .send(Command::SetLimiter(limiter_enabled))
+            .unwrap();
+        communicator
+            .commands
+            .send(Command::PlaySound(sound))
+            .unwrap();
+
+        let input = vec![0.0; buffer_size];
+        let input_audio = [input.as_slice(), input.as_slice()];
+        let peaks = (0..16)
+            .map(|_| {
+                let output = processor.process(buffer_size, std::iter::empty(), &input_audio);
+                output.peaks().fold(0f32, f32::max)
+            })
+            .collect();
+        (peaks, communicator.notifications.try_iter().collect())
+    }
+
+    #[test]
+    fn limiter_keeps_the_output_within_unity() {
+        let (peaks, notifications) = play_loud_sine(true);
+        assert!(peaks.iter().all(|peak| *peak <= 1.0), "{peaks:?}");
+        assert!(peaks.iter().any(|peak| *peak > LIMITER_THRESHOLD));
+        let clip_peaks: Vec<f32> = notifications
+            .iter()
+            .filter_map(|n| match n {
+                Notifications::ClipDetected { peak, .. } => Some(*peak),
+                _ => None,
+            })
+            .collect();
+        assert!(!clip_peaks.is_empty());
+        assert!(clip_peaks.iter().all(|peak| *peak > 1.9));
+    }
+
+    #[test]
+    fn disabled_limiter_lets_the_output_clip() {
+        let (peaks, notifications) = play_loud_sine(false);
+        assert!(peaks.iter().any(|peak| *peak > 1.9), "{peaks:?}");
+        assert!(notifications
+            .iter()
+            .any(|n| matches!(n, Notifications::ClipDetected { .. })));
+    }
+
+    /// The value of the ramp played by `ramp_sound` at `frame`.
+    fn ramp_value(frame: usize) -> f32 {
+        frame as f32 * 1e-4
+    }
+
+    /// Create a started sound that rises slowly enough to stay below the
+    /// limiter threshold.
+    fn ramp_sound(frames: usize) -> SampleTrigger {
+        let ramp: Vec<f32> = (0..frames).map(ramp_value).collect();
+        let mut sound = SampleTrigger::new(
+            Arc::new(AudioBuffer::from_channels(&[ramp.clone(), ramp])),
+            44100.0,
+        );
+        sound.start();
+        sound
+    }
+
+    /// Create a processor for blocks of `buffer_size` frames whose plugins run
+    /// on at most `max_block_length` frames.
+    fn processor_with_max_block_length(
+        buffer_size: usize,
+        max_block_length: usize,
+    ) -> (Processor, Communicator) {
+        let lv2_features = livi::FeaturesBuilder {
+            min_block_length: 1,
+            max_block_length,
+        }
+        .build(&livi::World::with_plugin_predicate(|_| false));
+        Processor::with_world(
+            44100.0,
+            buffer_size,
+            &ProcessorConfig::default(),
+            PluginScan::default(),
+            lv2_features,
+        )
+    }
+
+    /// Process a block of silent input and return the first channel of the
+    /// output.
+    fn process_silence(processor: &mut Processor, samples: usize) -> Vec<f32> {
+        let input = vec![0.0; samples];
+        let input_audio = [input.as_slice(), input.as_slice()];
+        let output = processor.process(samples, std::iter::empty(), &input_audio);
+        assert_eq!(output.buffer_size(), samples);
+        output.iter_channels().next().unwrap().to_vec()
+    }
+
+    #[test]
+    fn meter_interval_changes_while_processing() {
+        let (mut processor, communicator) = processor_with_max_block_length(441, 441);
+        communicator
+            .commands
+            .send(Command::PlaySound(ramp_sound(441 * 40)))
+            .unwrap();
+        let meters = |processor: &mut Processor| {
+            for _ in 0..10 {
+                process_silence(processor, 441);
+            }
+            communicator
+                .notifications
+                .try_iter()
+                .filter(|n| matches!(n, Notifications::Meters { .. }))
+                .count()
+        };
+        assert_eq!(meters(&mut processor), 10);
+
+        communicator
+            .commands
+            .send(Command::SetMeterInterval(Duration::from_millis(50)))
+            .unwrap();
+        assert_eq!(meters(&mut processor), 2);
+        communicator
+            .commands
+            .send(Command::SetMeterInterval(Duration::ZERO))
+            .unwrap();
+        assert_eq!(meters(&mut processor), 10);
+    }
+
+    /// Assert that `output` holds the values of `ramp_sound` at `frames`.
+    fn assert_ramp(output: &[f32], frames: impl IntoIterator<Item = usize>) {
+        let expected: Vec<f32> = frames.into_iter().map(ramp_value).collect();
+        assert_eq!(output.len(), expected.len());
+        for (frame, (actual, expected)) in output.iter().zip(expected).enumerate() {
+            assert!(
+                (actual - expected).abs() < 1e-6,
+                "frame {frame}: {actual} != {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn buffer_size_changes_between_blocks() {
+        let (mut processor, communicator) = processor_with_max_block_length(128, 4096);
+        for command in [
+            Command::AddTrack(Box::new(Track::new(1, 128))),
+            Command::AddPluginToTrack(1, PluginInstance::Gain(Gain::default())),
+            Command::PlaySound(ramp_sound(4096)),
+        ] {
+            communicator.commands.send(command).unwrap();
+        }
+        let mut output = process_silence(&mut processor, 128);
+        processor.set_buffer_size(1024);
+        let input = vec![0.0; 1024];
+        let input_audio = [input.as_slice(), input.as_slice()];
+        let allocations = count_allocations(|| {
+            processor.process(1024, std::iter::empty(), &input_audio);
+        });
+        assert_eq!(allocations, 0);
+        output.extend(process_silence(&mut processor, 1024));
+        processor.set_buffer_size(128);
+        output.extend(process_silence(&mut processor, 128));
+        assert_ramp(&output, (0..128).chain(1152..2304));
+    }
+
+    #[test]
+    fn oversized_blocks_are_processed_in_chunks() {
+        let (mut processor, communicator) = processor_with_max_block_length(128, 128);
+        processor.set_buffer_size(512);
+        let sampler = SampleTrigger::new(
+            Arc::new(AudioBuffer::from_channels(&[vec![0.5; 1024]])),
+            44100.0,
+        );
+        for command in [
+            Command::AddTrack(Box::new(Track::new(1, 128))),
+            Command::AddPluginToTrack(1, sampler.into()),
+            Command::SetTrackMonitoring(1, true),
+            Command::SetTrackVolume(1, 1.0),
+            Command::PlaySound(ramp_sound(4096)),
+        ] {
+            communicator.commands.send(command).unwrap();
+        }
+        let note_on = [0x90, 60, 127];
+        let input = vec![0.0; 512];
+        let input_audio = [input.as_slice(), input.as_slice()];
+        let midi = [(0, 300, note_on.as_slice())];
+        let output = processor.process(512, midi.into_iter(), &input_audio);
+        let output = output.iter_channels().next().unwrap();
+        // The sampler starts at the frame of the note on, which is within the
+        // third chunk, and the ramp continues across the chunk boundaries.
+        for (frame, actual) in output.iter().enumerate() {
+            let sampler = if frame >= 300 { 0.5 } else { 0.0 };
+            let expected = ramp_value(frame) + sampler;
+            assert!(
+                (actual - expected).abs() < 1e-6,
+                "frame {frame}: {actual} != {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn commands_sent_while_processing_are_all_handled() {
+        const COMMANDS: usize = 10000;
+        let (mut processor, communicator) = processor_with_max_block_length(128, 128);
+        let done = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let processing = {
+            let done = done.clone();
+            std::thread::spawn(move || {
+                let input = vec![0.0; 128];
+                let input_audio = [input.as_slice(), input.as_slice()];
+                while !done.load(Ordering::Acquire) {
+                    processor.process(128, std::iter::empty(), &input_audio);
+                }
+                processor.process(128, std::iter::empty(), &input_audio);
+                processor
+            })
+        };
+        for idx in 0..COMMANDS {
+            let mut command = Command::SetMasterVolume(idx as f32 / COMMANDS as f32);
+            while let Err(channel::Full(rejected)) = communicator.commands.send(command) {
+                command = rejected;
+                std::thread::yield_now();
+            }
+        }
+        done.store(true, Ordering::Release);
+        let processor = processing.join().unwrap();
+        assert_eq!(
+            processor.master_volume_target,
+            (COMMANDS - 1) as f32 / COMMANDS as f32
+        );
+    }
+
+    #[test]
+    fn deleted_plugins_and_tracks_are_sent_out_intact() {
+        let (mut processor, communicator) = processor_with_max_block_length(128, 128);
+        let sample = Arc::new(AudioBuffer::from_channels(&[vec![0.5; 16]]));
+        let sampler = || SampleTrigger::new(sample.clone(), 44100.0).into();
+        for command in [
+            Command::AddTrack(Box::new(Track::new(1, 128))),
+            Command::AddPluginToTrack(1, sampler()),
+            Command::AddPluginToTrack(1, sampler()),
+            Command::DeletePlugin(1, 0),
+            Command::DeleteTrack(1),
+        ] {
+            communicator.commands.send(command).unwrap();
+        }
+        process_silence(&mut processor, 128);
+
+        let disposed: Vec<Disposed> = communicator
+            .notifications
+            .try_iter()
+            .filter_map(|n| match n {
+                Notifications::Disposed(d) => Some(d),
+                _ => None,
+            })
+            .collect();
+        match disposed.as_slice() {
+            [Disposed::Plugin(PluginInstance::Sample(_)), Disposed::Track(track)] => {
+                assert_eq!(track.id(), 1);
+                assert!(matches!(track.plugin(0), Some(PluginInstance::Sample(_))));
+                assert!(track.plugin(1).is_none());
+            }
+            d => panic!("expected the deleted plugin and track, got {d:?}"),
+        }
+        // Both samplers are alive until the control thread drops them.
+        assert_eq!(Arc::strong_count(&sample), 3);
+        drop(disposed);
+        assert_eq!(Arc::strong_count(&sample), 1);
+    }
+
+    #[test]
+    fn disposed_values_wait_for_room_in_a_full_channel() {
+        let config = ProcessorConfig {
+            notifications_capacity: 4,
+            ..ProcessorConfig::default()
+        };
+        let (mut processor, communicator) =
+            Processor::new(44100.0, 128, &config, PluginScan::default());
+        processor.set_buffer_size(128);
+        // Each recording replaces the previous one, which owns memory that has
+        // to be freed outside of the processing thread.
+        let recordings = 1000;
+        for _ in 0..recordings {
+            communicator
+                .commands
+                .send(Command::StartRecording(1, Vec::with_capacity(16)))
+                .unwrap();
+        }
+        let input = vec![0.0; 128];
+        let input_audio = [input.as_slice(), input.as_slice()];
+        let allocations = count_allocations(|| {
+            for _ in 0..4 {
+                processor.process(128, std::iter::empty(), &input_audio);
+            }
+        });
+        assert_eq!(allocations, 0);
+
+        let mut disposed = 0;
+        for _ in 0..recordings {
+            disposed += communicator
+                .notifications
+                .try_iter()
+                .filter(|n| matches!(n, Notifications::Disposed(Disposed::MidiEvents(_))))
+                .count();
+            processor.process(128, std::iter::empty(), &input_audio);
+        }
+        assert_eq!(disposed, recordings - 1);
+        assert_eq!(
+            communicator.stats.critical_dropped.load(Ordering::Relaxed),
+            0
+        );
     }
 }
@@ -0,0 +1,274 @@
+use std::{
+    ffi::{c_void, CString},
+    ptr::NonNull,
+    sync::{Arc, Mutex},
+};
+
+use lv2_sys::{LV2_Feature, LV2_State_Flags, LV2_State_Handle, LV2_State_Interface};
+
+/// The URI of the LV2 State interface without its nul terminator.
+const STATE_INTERFACE_URI: &str = "http://lv2plug.in/ns/ext/state#interface";
+
+/// The state that an LV2 plugin saved through the LV2 State extension. Keys
+/// and types are stored as URIs since URIDs are only valid within a session.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Lv2State {
+    pub properties: Vec<Lv2StateProperty>,
+}
+
+/// A property of an `Lv2State`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lv2StateProperty {
+    /// The URI of the key.
+    pub key: String,
+    /// The URI of the type of the value.
+    pub value_type: String,
+    /// The `LV2_State_Flags` that the plugin stored the value with.
+    pub flags: u32,
+    /// The raw bytes of the value.
+    pub value: Vec<u8>,
+}
+
+/// Describes a failure to save or restore the state of an LV2 plugin.
+#[derive(Debug)]
+pub enum Lv2StateError {
+    /// The plugin returned an `LV2_State_Status` other than success.
+    Status(lv2_sys::LV2_State_Status),
+    /// The URI of a key or type is not valid.
+    InvalidUri(String),
+}
+
+impl std::fmt::Display for Lv2StateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Lv2StateError::Status(status) => {
+                write!(f, "the plugin failed with LV2 State status {status}")
+            }
+            Lv2StateError::InvalidUri(uri) => write!(f, "{uri:?} is not a valid URI"),
+        }
+    }
+}
+
+impl std::error::Error for Lv2StateError {}
+
+/// The handle of an LV2 instance and its state interface.
+struct RawState {
+    handle: lv2_sys::LV2_Handle,
+    interface: NonNull<LV2_State_Interface>,
+}
+
+/// Saves the state of a running LV2 plugin instance from outside of the
+/// processing thread. The LV2 State extension allows `save` to run
+/// concurrently with `run`.
+///
+/// The handle is detached when the instance is dropped, after which `save`
+/// returns `None`.
+pub struct Lv2StateHandle {
+    raw: Mutex<Option<RawState>>,
+}
+
+// The raw pointers are only used while `raw` is locked and are cleared under
+// the lock before the instance is freed.
+unsafe impl Send for Lv2StateHandle {}
+unsafe impl Sync for Lv2StateHandle {}
+
+impl std::fmt::Debug for Lv2StateHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Lv2StateHandle")
+            .field("attached", &self.raw.lock().unwrap().is_some())
+            .finish()
+    }
+}
+
+impl Lv2StateHandle {
+    /// Create a handle for `instance`. Returns `None` if the plugin does not
+    /// support the LV2 State extension. The handle must be detached before
+    /// `instance` is dropped.
+    fn new(instance: &livi::Instance) -> Option<Lv2StateHandle> {
+        let raw = state_interface(instance)?;
+        Some(Lv2StateHandle {
+            raw: Mutex::new(Some(raw)),
+        })
+    }
+
+    /// Save the state of the instance. Keys and types are unmapped with
+    /// `features`, which must be the features the instance was created with.
+    /// Only plain old data is saved. Returns `None` if the instance has been
+    /// dropped.
+    pub fn save(&self, features: &livi::Features) -> Option<Result<Lv2State, Lv2StateError>> {
+        let raw = self.raw.lock().unwrap();
+        let raw = raw.as_ref()?;
+        let save = unsafe { raw.interface.as_ref() }.save?;
+        let mut store = Store {
+            features,
+            state: Lv2State::default(),
+        };
+        let flags = LV2_State_Flags::LV2_STATE_IS_POD | LV2_State_Flags::LV2_STATE_IS_PORTABLE;
+        let no_features: [*const LV2_Feature; 1] = [std::ptr::null()];
+        let status = unsafe {
+            save(
+                raw.handle,
+                Some(store_property),
+                &mut store as *mut Store as LV2_State_Handle,
+                flags.into(),
+                no_features.as_ptr(),
+            )
+        };
+        Some(match status {
+            lv2_sys::LV2_State_Status_LV2_STATE_SUCCESS => Ok(store.state),
+            status => Err(Lv2StateError::Status(status)),
+        })
+    }
+
+    /// Detach the handle from its instance. Blocks until any `save` in progress
+    /// completes.
+    fn detach(&self) {
+        *self.raw.lock().unwrap() = None;
+    }
+}
+
+/// Keeps the `Lv2StateHandle` of an instance and detaches it when dropped. It
+/// must be dropped before the instance.
+#[derive(Debug)]
+pub struct Lv2StateGuard(Arc<Lv2StateHandle>);
+
+impl Lv2StateGuard {
+    /// Create a guard for the state of `instance`. Returns `None` if the plugin
+    /// does not support the LV2 State extension.
+    pub(crate) fn new(instance: &livi::Instance) -> Option<Lv2StateGuard> {
+        Lv2StateHandle::new(instance).map(|handle| Lv2StateGuard(Arc::new(handle)))
+    }
+
+    /// Get the handle that saves the state of the instance.
+    pub fn handle(&self) -> &Arc<Lv2StateHandle> {
+        &self.0
+    }
+}
+
+impl Drop for Lv2StateGuard {
+    fn drop(&mut self) {
+        self.0.detach();
+    }
+}
+
+/// Restore `state` to `instance`. This may not run concurrently with any other
+/// function of the instance, so it should be called before the instance is
+/// added to a processor. Plugins that do not support the LV2 State extension
+/// are left unchanged.
+pub fn restore(
+    instance: &mut livi::Instance,
+    features: &livi::Features,
+    state: &Lv2State,
+) -> Result<(), Lv2StateError> {
+    let raw = match state_interface(instance) {
+        Some(raw) => raw,
+        None => return Ok(()),
+    };
+    let restore = match unsafe { raw.interface.as_ref() }.restore {
+        Some(restore) => restore,
+        None => return Ok(()),
+    };
+    let mut properties = Vec::with_capacity(state.properties.len());
+    for property in state.properties.iter() {
+        let urid = |uri: &str| match CString::new(uri) {
+            Ok(uri) => Ok(features.urid(&uri)),
+            Err(_) => Err(Lv2StateError::InvalidUri(uri.to_string())),
+        };
+        properties.push((urid(&property.key)?, urid(&property.value_type)?, property));
+    }
+    let mut retrieve = Retrieve { properties };
+    let no_features: [*const LV2_Feature; 1] = [std::ptr::null()];
+    let status = unsafe {
+        restore(
+            raw.handle,
+            Some(retrieve_property),
+            &mut retrieve as *mut Retrieve as LV2_State_Handle,
+            0,
+            no_features.as_ptr(),
+        )
+    };
+    match status {
+        lv2_sys::LV2_State_Status_LV2_STATE_SUCCESS => Ok(()),
+        status => Err(Lv2StateError::Status(status)),
+    }
+}
+
+/// Get the state interface of `instance` if the plugin supports it.
+fn state_interface(instance: &livi::Instance) -> Option<RawState> {
+    let instance = instance.raw().instance();
+    let interface = unsafe { instance.extension_data::<LV2_State_Interface>(STATE_INTERFACE_URI) }?;
+    Some(RawState {
+        handle: instance.handle(),
+        interface,
+    })
+}
+
+/// The state of a `save` call that is passed to `store_property`.
+struct Store<'a> {
+    features: &'a livi::Features,
+    state: Lv2State,
+}
+
+/// Store a property for `Lv2StateHandle::save`. Properties that are not plain
+/// old data are rejected.
+unsafe extern "C" fn store_property(
+    handle: LV2_State_Handle,
+    key: u32,
+    value: *const c_void,
+    size: usize,
+    value_type: u32,
+    flags: u32,
+) -> lv2_sys::LV2_State_Status {
+    let store = &mut *(handle as *mut Store);
+    if flags & u32::from(LV2_State_Flags::LV2_STATE_IS_POD) == 0 {
+        return lv2_sys::LV2_State_Status_LV2_STATE_ERR_BAD_FLAGS;
+    }
+    let (key, value_type) = match (store.features.uri(key), store.features.uri(value_type)) {
+        (Some(key), Some(value_type)) => (key.to_string(), value_type.to_string()),
+        _ => return lv2_sys::LV2_State_Status_LV2_STATE_ERR_BAD_TYPE,
+    };
+    let value = if size == 0 {
+        Vec::new()
+    } else {
+        std::slice::from_raw_parts(value as *const u8, size).to_vec()
+    };
+    store.state.properties.push(Lv2StateProperty {
+        key,
+        value_type,
+        flags,
+        value,
+    });
+    lv2_sys::LV2_State_Status_LV2_STATE_SUCCESS
+}
+
+/// The properties of a `restore` call that are passed to `retrieve_property`
+/// as `(key, type, property)` with mapped keys and types.
+struct Retrieve<'a> {
+    properties: Vec<(u32, u32, &'a Lv2StateProperty)>,
+}
+
+/// Retrieve a property for `restore`. Returns null if there is no property
+/// for `key`.
+unsafe extern "C" fn retrieve_property(
+    handle: LV2_State_Handle,
+    key: u32,
+    size: *mut usize,
+    value_type: *mut u32,
+    flags: *mut u32,
+) -> *const c_void {
+    let retrieve = &*(handle as *const Retrieve);
+    let (_, urid, property) = match retrieve.properties.iter().find(|(k, _, _)| *k == key) {
+        Some(p) => p,
+        None => return std::ptr::null(),
+    };
+    if !size.is_null() {
+        *size = property.value.len();
+    }
+    if !value_type.is_null() {
+        *value_type = *urid;
+    }
+    if !flags.is_null() {
+        *flags = property.flags;
+    }
+    property.value.as_ptr() as *const c_void
+}
@@ -0,0 +1,87 @@
+use crate::audio_buffer::AudioBuffer;
+
+/// The most tracks that are included in a meter update.
+pub const MAX_METERED_TRACKS: usize = 32;
+
+/// The level of a stereo signal over one block.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Level {
+    /// The largest absolute value of each channel.
+    pub peak: [f32; 2],
+    /// The root mean square of each channel.
+    pub rms: [f32; 2],
+}
+
+impl Level {
+    /// Measure the level of `buffer`.
+    pub fn from_buffer(buffer: &AudioBuffer) -> Level {
+        Level {
+            peak: buffer.peak_per_channel(),
+            rms: buffer.rms_per_channel(),
+        }
+    }
+
+    /// Keep the louder of `self` and `other` for each value.
+    pub fn merge(&mut self, other: &Level) {
+        for (a, b) in self.peak.iter_mut().zip(other.peak) {
+            *a = a.max(b);
+        }
+        for (a, b) in self.rms.iter_mut().zip(other.rms) {
+            *a = a.max(b);
+        }
+    }
+}
+
+/// The levels of up to `MAX_METERED_TRACKS` tracks. The levels are stored
+/// inline so that they can be sent from the processing thread without
+/// allocating.
+#[derive(Copy, Clone, Debug)]
+pub struct TrackLevels {
+    levels: [(i32, Level); MAX_METERED_TRACKS],
+    len: usize,
+}
+
+impl Default for TrackLevels {
+    fn default() -> TrackLevels {
+        TrackLevels {
+            levels: [(0, Level::default()); MAX_METERED_TRACKS],
+            len: 0,
+        }
+    }
+}
+
+impl TrackLevels {
+    /// Remove all levels.
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Add the level of the track with `track_id`. If the track already has a
+    /// level, the louder of the two is kept for each value. The level is
+    /// dropped if there are already `MAX_METERED_TRACKS` levels.
+    pub fn push(&mut self, track_id: i32, level: Level) {
+        if let Some((_, existing)) = self.levels[..self.len]
+            .iter_mut()
+            .find(|(id, _)| *id == track_id)
+        {
+            existing.merge(&level);
+            return;
+        }
+        if let Some(slot) = self.levels.get_mut(self.len) {
+            *slot = (track_id, level);
+            self.len += 1;
+        }
+    }
+
+    /// Add all levels of `other` with `push`.
+    pub fn merge(&mut self, other: &TrackLevels) {
+        for (track_id, level) in other.iter() {
+            self.push(*track_id, *level);
+        }
+    }
+
+    /// Iterate over the track ids and their levels.
+    pub fn iter(&self) -> impl '_ + Iterator<Item = &(i32, Level)> {
+        self.levels[..self.len].iter()
+    }
+}
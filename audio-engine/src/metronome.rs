@@ -1,8 +1,21 @@
-use std::path::Path;
-
 use livi::event::LV2AtomSequence;
 
-use crate::{audio_buffer::AudioBuffer, plugin::SampleTrigger, track::Track};
+use crate::{
+    audio_buffer::AudioBuffer,
+    midi_output::MidiOutput,
+    plugin::{PluginInstance, SampleTrigger},
+    track::Track,
+};
+
+/// The number of beats in each measure.
+pub const BEATS_PER_MEASURE: f64 = 4.0;
+
+/// The number of midi clock pulses in each beat.
+pub const CLOCK_PULSES_PER_BEAT: f64 = 24.0;
+
+/// The number of samples that the position may differ from a synced position
+/// before it is moved.
+const SYNC_TOLERANCE_SAMPLES: f64 = 64.0;
 
 /// Produces metronome ticks and timing information.
 #[derive(Debug)]
@@ -10,9 +23,22 @@ pub struct Metronome {
     track: Track,
     midi_urid: lv2_raw::LV2Urid,
     events: LV2AtomSequence,
+    /// The click does not produce midi but plugins require somewhere to
+    /// write it.
+    midi_output: MidiOutput,
     current_time_info: SampleTimeInfo,
     time_info: Vec<SampleTimeInfo>,
     beats_per_sample: f64,
+    /// The beats per sample to switch to on the next downbeat.
+    pending_beats_per_sample: Option<f64>,
+    /// The fraction of each pair of beats taken by the first beat.
+    swing: f64,
+    /// The fraction of a midi clock pulse that has passed since the last
+    /// pulse.
+    clock_phase: f64,
+    /// The frames of the last processed block that a midi clock pulse falls
+    /// on.
+    clock_pulses: Vec<usize>,
 }
 
 /// Contains information for the timing of a frame.
@@ -34,14 +60,18 @@ impl Metronome {
     pub fn new(sample_rate: f64, features: &livi::Features) -> Metronome {
         let mut track = Track::new(-1, features.max_block_length());
         track.properties.volume = 0.0;
-        track.push_plugin(SampleTrigger::from_wav(Path::new("resources/click.wav")).into());
+        let click = SampleTrigger::from_wav_bytes(crate::resources::CLICK_WAV, sample_rate)
+            .expect("default sounds are valid");
+        track.push_plugin(click.into());
         let events = LV2AtomSequence::new(features, 1024 /*1 KiB*/);
+        let midi_output = MidiOutput::new(features, 0);
         let bpm = 120.0;
         let beats_per_sample = bpm_to_beats_per_sample(sample_rate, bpm);
         Metronome {
             track,
             midi_urid: features.midi_urid(),
             events,
+            midi_output,
             current_time_info: SampleTimeInfo {
                 measure: -1,
                 beat: 3,
@@ -49,58 +79,207 @@ impl Metronome {
             },
             time_info: Vec::with_capacity(features.max_block_length() + 1),
             beats_per_sample,
+            pending_beats_per_sample: None,
+            swing: 0.5,
+            clock_phase: 1.0 - beats_per_sample * CLOCK_PULSES_PER_BEAT,
+            clock_pulses: Vec::with_capacity(features.max_block_length()),
+        }
+    }
+
+    /// Set metronome properties. If `on_downbeat` is true, then the tempo
+    /// change is delayed until the start of the next measure.
+    pub fn set_properties(&mut self, sample_rate: f64, volume: f32, bpm: f32, on_downbeat: bool) {
+        let beats_per_sample = bpm_to_beats_per_sample(sample_rate, bpm);
+        if on_downbeat {
+            self.pending_beats_per_sample = Some(beats_per_sample);
+        } else {
+            self.beats_per_sample = beats_per_sample;
+            self.pending_beats_per_sample = None;
         }
+        self.track.properties.volume = volume;
     }
 
-    /// Set metronome properties.
-    pub fn set_properties(&mut self, sample_rate: f64, volume: f32, bpm: f32) {
-        self.beats_per_sample = bpm_to_beats_per_sample(sample_rate, bpm);
+    /// Set the volume of the metronome.
+    pub fn set_volume(&mut self, volume: f32) {
         self.track.properties.volume = volume;
     }
 
+    /// Set the number of beats that pass in each sample. This takes effect
+    /// immediately and cancels a tempo change that is waiting for the next
+    /// downbeat.
+    pub fn set_beats_per_sample(&mut self, beats_per_sample: f64) {
+        self.beats_per_sample = beats_per_sample;
+        self.pending_beats_per_sample = None;
+    }
+
+    /// Replace the sample that is played on each beat.
+    pub fn set_sample(&mut self, sample: SampleTrigger) -> Option<PluginInstance> {
+        let previous = self.track.remove_plugin(0);
+        self.track.push_plugin(sample.into());
+        previous
+    }
+
+    /// Set the fraction of each pair of beats taken by the first beat. 0.5 is
+    /// straight timing. The value is clamped to [0.1, 0.9] so that every beat
+    /// is still heard.
+    pub fn set_swing(&mut self, swing: f32) {
+        self.swing = (swing as f64).clamp(0.1, 0.9);
+    }
+
+    /// Get the fraction of each pair of beats taken by the first beat.
+    pub fn swing(&self) -> f64 {
+        self.swing
+    }
+
+    /// Get the number of beats that pass in each sample.
+    pub fn beats_per_sample(&self) -> f64 {
+        self.beats_per_sample
+    }
+
     /// Get the volume of the metronome.
     pub fn volume(&self) -> f32 {
         self.track.properties.volume
     }
 
+    /// Resize the buffers to hold `buffer_size` frames. This allocates and
+    /// should not be called while processing.
+    pub fn set_buffer_size(&mut self, buffer_size: usize) {
+        self.track.set_buffer_size(buffer_size);
+        self.time_info.reserve(buffer_size + 1);
+        self.clock_pulses.reserve(buffer_size);
+    }
+
     /// Get the current time info.
     pub fn current_time_info(&self) -> SampleTimeInfo {
         self.current_time_info
     }
 
-    /// Process the metronome for the given number of samples.
+    /// Get the time info at the start of each frame of the last processed
+    /// block followed by the time info at the end of the block.
+    pub fn time_info(&self) -> &[SampleTimeInfo] {
+        &self.time_info
+    }
+
+    /// Get the time info at `frame` within the last processed block.
+    pub fn time_info_at(&self, frame: usize) -> SampleTimeInfo {
+        match self.time_info.get(frame) {
+            Some(t) => *t,
+            None => self.current_time_info,
+        }
+    }
+
+    /// Get the frames of the last processed block that a midi clock pulse
+    /// falls on. Pulses follow the tempo without swing.
+    pub fn clock_pulses(&self) -> &[usize] {
+        &self.clock_pulses
+    }
+
+    /// Move to the start of `beat` within `measure`. Positions before the
+    /// start are clamped to the start.
+    pub fn seek(&mut self, measure: i16, beat: i16) {
+        let beats = (measure as f64 * BEATS_PER_MEASURE + beat as f64).max(0.0);
+        // Start right before the beat so that it ticks in full on the next
+        // sample instead of partway through.
+        self.current_time_info = SampleTimeInfo::from_beats(beats - self.beats_per_sample);
+        self.clock_phase = 1.0 - self.beats_per_sample * CLOCK_PULSES_PER_BEAT;
+    }
+
+    /// Move so that the next processed sample is `beats` beats from the start.
+    /// Small differences are ignored so that following a shared transport
+    /// does not repeat or skip ticks on every block.
+    pub fn sync_to(&mut self, beats: f64) {
+        let next = self.current_time_info.to_beats() + self.beats_per_sample;
+        if (beats - next).abs() <= self.beats_per_sample * SYNC_TOLERANCE_SAMPLES {
+            return;
+        }
+        self.current_time_info = SampleTimeInfo::from_beats(beats - self.beats_per_sample);
+        self.clock_phase = 1.0 - self.beats_per_sample * CLOCK_PULSES_PER_BEAT;
+    }
+
+    /// Move to the start of the measure `measures` measures before the start.
+    pub fn count_in(&mut self, measures: u8) {
+        let beats = -BEATS_PER_MEASURE * measures as f64;
+        self.current_time_info = SampleTimeInfo::from_beats(beats - self.beats_per_sample);
+        self.clock_phase = 1.0 - self.beats_per_sample * CLOCK_PULSES_PER_BEAT;
+    }
+
+    /// Process the metronome for the given number of samples. If not
+    /// `playing`, then the position is held and no ticks are produced.
     pub fn process(
         &mut self,
         samples: usize,
+        playing: bool,
     ) -> (
         &AudioBuffer,
-        impl '_ + Clone + ExactSizeIterator + Iterator<Item = (SampleTimeInfo, SampleTimeInfo)>,
+        impl '_ + Clone + ExactSizeIterator<Item = (SampleTimeInfo, SampleTimeInfo)>,
     ) {
         self.time_info.clear();
         self.time_info.push(self.current_time_info);
         self.events.clear();
-        for frame in 0..samples {
-            self.current_time_info.sub_beat += self.beats_per_sample;
-            if self.current_time_info.sub_beat >= 1.0 {
-                self.current_time_info.beat += 1;
-                self.current_time_info.sub_beat -= 1.0;
-                let mut data = [0u8; 3];
-                Metronome::NOTE.copy_to_slice(&mut data).unwrap();
-                self.events
-                    .push_midi_event::<3>(frame as i64, self.midi_urid, &data)
-                    .unwrap();
+        self.clock_pulses.clear();
+        if playing {
+            for frame in 0..samples {
+                // The clock advances with the tempo of each frame so pulses
+                // stay in place when the tempo changes mid block.
+                self.clock_phase += self.beats_per_sample * CLOCK_PULSES_PER_BEAT;
+                if self.clock_phase >= 1.0 {
+                    self.clock_phase -= 1.0;
+                    self.clock_pulses.push(frame);
+                }
+                // Stretch even beats and compress odd beats so that each pair
+                // of beats takes the same amount of time as without swing.
+                let swing = if self.current_time_info.beat % 2 == 0 {
+                    self.swing
+                } else {
+                    1.0 - self.swing
+                };
+                self.current_time_info.sub_beat += self.beats_per_sample * 0.5 / swing;
+                if self.current_time_info.sub_beat >= 1.0 {
+                    self.current_time_info.beat += 1;
+                    self.current_time_info.sub_beat -= 1.0;
+                    let mut data = [0u8; 3];
+                    Metronome::NOTE.copy_to_slice(&mut data).unwrap();
+                    self.events
+                        .push_midi_event::<3>(frame as i64, self.midi_urid, &data)
+                        .unwrap();
+                }
+                if self.current_time_info.beat as f64 >= BEATS_PER_MEASURE {
+                    self.current_time_info.beat = 0;
+                    self.current_time_info.measure += 1;
+                    if let Some(beats_per_sample) = self.pending_beats_per_sample.take() {
+                        self.beats_per_sample = beats_per_sample;
+                    }
+                }
+                self.time_info.push(self.current_time_info);
             }
-            if self.current_time_info.beat >= 4 {
-                self.current_time_info.beat = 0;
-                self.current_time_info.measure += 1;
-            }
-            self.time_info.push(self.current_time_info);
+        } else {
+            self.time_info
+                .extend(std::iter::repeat_n(self.current_time_info, samples));
         }
-        let audio_out = self.track.process(samples, &self.events);
+        let audio_out = self
+            .track
+            .process(samples, &self.events, &mut self.midi_output);
         (audio_out, self.time_info.windows(2).map(|w| (w[0], w[1])))
     }
 }
 
+impl SampleTimeInfo {
+    /// Create the time info for `beats` beats from the start.
+    pub fn from_beats(beats: f64) -> SampleTimeInfo {
+        let whole_beats = beats.floor();
+        SampleTimeInfo {
+            measure: whole_beats.div_euclid(BEATS_PER_MEASURE) as i16,
+            beat: whole_beats.rem_euclid(BEATS_PER_MEASURE) as i16,
+            sub_beat: beats - whole_beats,
+        }
+    }
+
+    /// Get the number of beats from the start.
+    pub fn to_beats(&self) -> f64 {
+        self.measure as f64 * BEATS_PER_MEASURE + self.beat as f64 + self.sub_beat
+    }
+}
+
 impl std::fmt::Display for SampleTimeInfo {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -119,3 +298,104 @@ fn bpm_to_beats_per_sample(sample_rate: f64, bpm: f32) -> f64 {
     let seconds_per_sample = 1.0 / sample_rate;
     beats_per_minute * minutes_per_second * seconds_per_sample
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f64 = 44100.0;
+
+    fn metronome() -> Metronome {
+        let features = livi::FeaturesBuilder {
+            min_block_length: 1,
+            max_block_length: 1024,
+        }
+        .build(&livi::World::with_plugin_predicate(|_| false));
+        let mut metronome = Metronome::new(SAMPLE_RATE, &features);
+        metronome.set_properties(SAMPLE_RATE, 1.0, 120.0, false);
+        metronome
+    }
+
+    /// Process the metronome in blocks of `block_sizes` and return the first
+    /// channel of the output and the time info of every frame.
+    fn play(metronome: &mut Metronome, block_sizes: &[usize]) -> (Vec<f32>, Vec<SampleTimeInfo>) {
+        let mut audio = Vec::new();
+        let mut time_info = Vec::new();
+        for samples in block_sizes.iter().copied() {
+            let (out, info) = metronome.process(samples, true);
+            assert_eq!(info.len(), samples);
+            audio.extend_from_slice(&out.iter_channels().next().unwrap()[..samples]);
+            time_info.extend(info.map(|(_, end)| end));
+        }
+        (audio, time_info)
+    }
+
+    #[test]
+    fn output_does_not_depend_on_the_block_sizes() {
+        let block_sizes: Vec<usize> = [64, 480, 1024, 1].into_iter().cycle().take(100).collect();
+        let frames: usize = block_sizes.iter().sum();
+        let (audio, time_info) = play(&mut metronome(), &block_sizes);
+        let (expected_audio, expected_time_info) =
+            play(&mut metronome(), &vec![128; frames / 128 + 1]);
+        assert_eq!(audio.as_slice(), &expected_audio[..frames]);
+        assert_eq!(time_info.as_slice(), &expected_time_info[..frames]);
+        assert!(audio.iter().any(|v| v.abs() > 0.1));
+    }
+
+    #[test]
+    fn position_advances_by_the_processed_frames() {
+        let mut metronome = metronome();
+        let start = metronome.current_time_info().to_beats();
+        let mut frames = 0;
+        for samples in [64, 480, 1024, 1] {
+            let _ = metronome.process(samples, true);
+            frames += samples;
+            let beats = metronome.current_time_info().to_beats() - start;
+            let expected = frames as f64 * metronome.beats_per_sample();
+            assert!((beats - expected).abs() < 1e-9, "{beats} != {expected}");
+        }
+    }
+
+    #[test]
+    fn tempo_change_on_downbeat_waits_for_the_next_measure() {
+        let mut metronome = metronome();
+        let _ = play(&mut metronome, &[1000]);
+        let old = metronome.beats_per_sample();
+        let new = bpm_to_beats_per_sample(SAMPLE_RATE, 90.0);
+        metronome.set_properties(SAMPLE_RATE, 1.0, 90.0, true);
+        assert_eq!(metronome.beats_per_sample(), old);
+
+        let start = metronome.current_time_info();
+        let (_, time_info) = play(&mut metronome, &[512; 200]);
+        let downbeat = time_info
+            .iter()
+            .position(|t| t.measure != start.measure)
+            .unwrap();
+        assert_eq!(time_info[downbeat].beat, 0);
+        let beats = std::iter::once(start.to_beats()).chain(time_info.iter().map(|t| t.to_beats()));
+        let steps: Vec<f64> = beats
+            .collect::<Vec<_>>()
+            .windows(2)
+            .map(|w| w[1] - w[0])
+            .collect();
+        for (frame, step) in steps.iter().enumerate() {
+            let expected = if frame <= downbeat { old } else { new };
+            assert!(
+                (step - expected).abs() < 1e-9,
+                "frame {frame}: {step} != {expected}"
+            );
+        }
+        assert_eq!(metronome.beats_per_sample(), new);
+    }
+
+    #[test]
+    fn tempo_change_without_downbeat_is_immediate() {
+        let mut metronome = metronome();
+        let _ = play(&mut metronome, &[1000]);
+        metronome.set_properties(SAMPLE_RATE, 1.0, 90.0, false);
+        assert_eq!(
+            metronome.beats_per_sample(),
+            bpm_to_beats_per_sample(SAMPLE_RATE, 90.0)
+        );
+    }
+}
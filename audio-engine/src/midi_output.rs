@@ -0,0 +1,130 @@
+use livi::event::LV2AtomSequence;
+
+/// The largest midi message that may be sent to the midi output.
+const MAX_MESSAGE_SIZE: usize = 3;
+
+/// A midi message for the midi output.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MidiOutputEvent {
+    /// The frame within the `process` call to send the message at.
+    pub frame: u32,
+    data: [u8; MAX_MESSAGE_SIZE],
+    size: u8,
+}
+
+impl MidiOutputEvent {
+    /// Get the bytes of the midi message.
+    pub fn data(&self) -> &[u8] {
+        &self.data[..self.size as usize]
+    }
+}
+
+/// Collects the midi that plugins produce so that it can be sent to the midi
+/// output or to the next plugin of a track. Nothing is allocated after
+/// creation.
+#[derive(Debug)]
+pub struct MidiOutput {
+    /// The sequence that plugins write their midi output to.
+    plugin_output: LV2AtomSequence,
+    /// The midi output of the previous plugin of a track, used as the midi
+    /// input of the next plugin.
+    chained_input: LV2AtomSequence,
+    /// The events to send, sorted by frame.
+    events: Vec<MidiOutputEvent>,
+    /// The frame within the `process` call that the current block starts at.
+    frame_offset: u32,
+    /// URID for midi.
+    midi_urid: lv2_raw::LV2Urid,
+    /// The number of events that were dropped because there were too many or
+    /// they were too large.
+    dropped: u64,
+}
+
+impl MidiOutput {
+    /// Create a new midi output that holds up to `capacity` events.
+    pub fn new(features: &livi::Features, capacity: usize) -> MidiOutput {
+        MidiOutput {
+            plugin_output: LV2AtomSequence::new(features, 64 * 1024 /*64 KiB*/),
+            chained_input: LV2AtomSequence::new(features, 64 * 1024 /*64 KiB*/),
+            events: Vec::with_capacity(capacity),
+            frame_offset: 0,
+            midi_urid: features.midi_urid(),
+            dropped: 0,
+        }
+    }
+
+    /// Remove all events.
+    pub fn clear(&mut self) {
+        self.events.clear();
+        self.frame_offset = 0;
+    }
+
+    /// Set the frame that the current block starts at. Events that are
+    /// collected are offset by `frame_offset`.
+    pub fn set_frame_offset(&mut self, frame_offset: usize) {
+        self.frame_offset = frame_offset as u32;
+    }
+
+    /// Get the events, sorted by frame.
+    pub fn events(&self) -> &[MidiOutputEvent] {
+        &self.events
+    }
+
+    /// Add a message at `frame` within the current block. The message is
+    /// dropped if it is too large or there are too many events.
+    pub fn push(&mut self, frame: usize, data: &[u8]) {
+        let frame = self.frame_offset + frame as u32;
+        if !insert_event(&mut self.events, frame, data) {
+            self.dropped += 1;
+        }
+    }
+
+    /// Get the number of events that were dropped since the last call.
+    pub fn take_dropped(&mut self) -> u64 {
+        std::mem::take(&mut self.dropped)
+    }
+
+    /// Get the midi output of the previous plugin and the sequence for the
+    /// next plugin to write its midi output to.
+    pub(crate) fn plugin_buffers(&mut self) -> (&LV2AtomSequence, &mut LV2AtomSequence) {
+        (&self.chained_input, &mut self.plugin_output)
+    }
+
+    /// Use the midi output of the last plugin as the input of the next plugin.
+    pub(crate) fn chain_plugin_output(&mut self) {
+        std::mem::swap(&mut self.chained_input, &mut self.plugin_output);
+    }
+
+    /// Add the midi events that were written to `plugin_output`.
+    pub(crate) fn collect_plugin_output(&mut self) {
+        for event in self.plugin_output.iter() {
+            if event.event.body.mytype != self.midi_urid {
+                continue;
+            }
+            let frame = self.frame_offset + event.event.time_in_frames as u32;
+            if !insert_event(&mut self.events, frame, event.data) {
+                self.dropped += 1;
+            }
+        }
+    }
+}
+
+/// Insert a message at `frame` into `events` after the events on the same
+/// frame. Returns false if the message is too large or `events` is full.
+fn insert_event(events: &mut Vec<MidiOutputEvent>, frame: u32, data: &[u8]) -> bool {
+    if data.len() > MAX_MESSAGE_SIZE || events.len() == events.capacity() {
+        return false;
+    }
+    let mut event = MidiOutputEvent {
+        frame,
+        data: [0; MAX_MESSAGE_SIZE],
+        size: data.len() as u8,
+    };
+    event.data[..data.len()].copy_from_slice(data);
+    // Events of each source are in order so they are usually pushed to the
+    // end. Inserting after events on the same frame keeps the order of
+    // messages from a source.
+    let index = events.partition_point(|e| e.frame <= event.frame);
+    events.insert(index, event);
+    true
+}
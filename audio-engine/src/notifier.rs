@@ -0,0 +1,406 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use crate::{
+    channel::{Full, RtSender},
+    commands::Notifications,
+    meter::{Level, TrackLevels},
+};
+
+/// The minimum interval between clip notifications.
+const CLIP_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The number of critical notifications that may be held back while the
+/// channel is full.
+const PENDING_CAPACITY: usize = 256;
+
+/// Counters describing the health of the audio engine.
+#[derive(Debug, Default)]
+pub struct EngineStats {
+    /// The number of time info notifications dropped because the channel was
+    /// full.
+    pub time_info_dropped: AtomicU64,
+    /// The number of meter notifications dropped because the channel was
+    /// full.
+    pub meters_dropped: AtomicU64,
+    /// The number of clip notifications dropped because the channel was full.
+    pub clip_detected_dropped: AtomicU64,
+    /// The number of midi CC notifications dropped because the channel was
+    /// full.
+    pub midi_cc_dropped: AtomicU64,
+    /// The number of recording stats notifications dropped because the
+    /// channel was full.
+    pub recording_stats_dropped: AtomicU64,
+    /// The number of external tempo notifications dropped because the channel
+    /// was full.
+    pub external_tempo_dropped: AtomicU64,
+    /// The number of critical notifications that had to be delayed because the
+    /// channel was full.
+    pub critical_delayed: AtomicU64,
+    /// The number of critical notifications dropped because too many were
+    /// already held back.
+    pub critical_dropped: AtomicU64,
+    /// The number of midi events received from the input.
+    pub midi_events_received: AtomicU64,
+    /// The number of midi events that were not recorded because the recording
+    /// buffer was full.
+    pub recorded_events_dropped: AtomicU64,
+    /// The number of audio frames that were not recorded because the
+    /// recording buffer was full.
+    pub recorded_frames_dropped: AtomicU64,
+    /// The number of midi events that were not sent to the midi output
+    /// because there were too many or they were too large.
+    pub midi_output_dropped: AtomicU64,
+}
+
+/// Sends notifications from the processing thread while coalescing frequent
+/// updates.
+///
+/// Coalescable notifications, like time info, are rate limited and dropped
+/// when the channel is full. Critical notifications are held back and retried
+/// on the next cycle instead. At most `PENDING_CAPACITY` notifications are held
+/// back so that holding them never allocates.
+#[derive(Debug)]
+pub struct Notifier {
+    sender: RtSender<Notifications>,
+    stats: Arc<EngineStats>,
+    sample_rate: f64,
+    time_info_interval: usize,
+    samples_since_time_info: usize,
+    meter_interval: usize,
+    samples_since_meters: usize,
+    /// The loudest levels since meters were last sent.
+    held_meters: Option<(TrackLevels, Level)>,
+    samples_since_clip: usize,
+    pending: VecDeque<Notifications>,
+}
+
+impl Notifier {
+    /// Create a new notifier.
+    pub fn new(
+        sender: RtSender<Notifications>,
+        stats: Arc<EngineStats>,
+        sample_rate: f64,
+        time_info_interval: Duration,
+        meter_interval: Duration,
+    ) -> Notifier {
+        Notifier {
+            sender,
+            stats,
+            sample_rate,
+            time_info_interval: duration_to_samples(sample_rate, time_info_interval),
+            samples_since_time_info: usize::MAX,
+            meter_interval: duration_to_samples(sample_rate, meter_interval),
+            samples_since_meters: usize::MAX,
+            held_meters: None,
+            samples_since_clip: usize::MAX,
+            pending: VecDeque::with_capacity(PENDING_CAPACITY),
+        }
+    }
+
+    /// Set the minimum interval between time info notifications.
+    pub fn set_time_info_interval(&mut self, interval: Duration) {
+        self.time_info_interval = duration_to_samples(self.sample_rate, interval);
+    }
+
+    /// Set the minimum interval between meter notifications.
+    pub fn set_meter_interval(&mut self, interval: Duration) {
+        self.meter_interval = duration_to_samples(self.sample_rate, interval);
+    }
+
+    /// Advance the internal clock by `samples`. This should be called once per
+    /// process cycle before sending notifications.
+    pub fn advance(&mut self, samples: usize) {
+        self.samples_since_time_info = self.samples_since_time_info.saturating_add(samples);
+        self.samples_since_meters = self.samples_since_meters.saturating_add(samples);
+        self.samples_since_clip = self.samples_since_clip.saturating_add(samples);
+        while let Some(n) = self.pending.pop_front() {
+            if let Err(Full(n)) = self.sender.send(n) {
+                self.pending.push_front(n);
+                break;
+            }
+        }
+    }
+
+    /// Returns true if `count` more critical notifications can be sent without
+    /// any of them being dropped. Notifications that own memory, like
+    /// `Notifications::Disposed`, must only be sent after checking this since
+    /// dropping them would free the memory on the processing thread.
+    pub fn has_room_for_critical(&self, count: usize) -> bool {
+        self.pending.len() + count <= PENDING_CAPACITY
+    }
+
+    /// Send a notification.
+    pub fn send(&mut self, notification: Notifications) {
+        match notification {
+            Notifications::TimeInfo(_) => {
+                if self.samples_since_time_info < self.time_info_interval {
+                    return;
+                }
+                self.samples_since_time_info = 0;
+                if self.sender.send(notification).is_err() {
+                    self.stats.time_info_dropped.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            Notifications::ClipDetected { .. } => {
+                // Clipping tends to last for many cycles so it is reported at
+                // most every `CLIP_INTERVAL`.
+                if self.samples_since_clip < duration_to_samples(self.sample_rate, CLIP_INTERVAL) {
+                    return;
+                }
+                self.samples_since_clip = 0;
+                self.send_or_count(notification, |s| &s.clip_detected_dropped);
+            }
+            Notifications::MidiCc { .. } => {
+                // Knobs send a stream of values so a dropped value is soon
+                // replaced by the next one.
+                self.send_or_count(notification, |s| &s.midi_cc_dropped);
+            }
+            // These are sent periodically so a dropped update is soon
+            // replaced.
+            Notifications::RecordingStats { .. } => {
+                self.send_or_count(notification, |s| &s.recording_stats_dropped);
+            }
+            Notifications::Meters {
+                track_levels,
+                master,
+            } => {
+                // The loudest levels between updates are sent so that short
+                // peaks still show on the meters.
+                let (held_levels, held_master) = self
+                    .held_meters
+                    .get_or_insert((TrackLevels::default(), Level::default()));
+                held_levels.merge(&track_levels);
+                held_master.merge(&master);
+                if self.samples_since_meters < self.meter_interval {
+                    return;
+                }
+                self.samples_since_meters = 0;
+                if let Some((track_levels, master)) = self.held_meters.take() {
+                    let notification = Notifications::Meters {
+                        track_levels,
+                        master,
+                    };
+                    self.send_or_count(notification, |s| &s.meters_dropped);
+                }
+            }
+            Notifications::ExternalTempo(_) => {
+                self.send_or_count(notification, |s| &s.external_tempo_dropped);
+            }
+            notification => self.send_critical(notification),
+        }
+    }
+
+    /// Send a notification that may be dropped. If the channel is full, the
+    /// notification is dropped and the counter returned by `dropped` is
+    /// incremented.
+    fn send_or_count(
+        &mut self,
+        notification: Notifications,
+        dropped: impl Fn(&EngineStats) -> &AtomicU64,
+    ) {
+        if self.sender.send(notification).is_err() {
+            dropped(&self.stats).fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Send a notification that must not be dropped. It is dropped only if
+    /// `PENDING_CAPACITY` notifications are already held back, which callers
+    /// prevent for notifications that own memory by checking
+    /// `has_room_for_critical`.
+    fn send_critical(&mut self, notification: Notifications) {
+        let notification = if self.pending.is_empty() {
+            match self.sender.send(notification) {
+                Ok(()) => return,
+                Err(Full(n)) => n,
+            }
+        } else {
+            notification
+        };
+        if self.pending.len() < PENDING_CAPACITY {
+            self.stats.critical_delayed.fetch_add(1, Ordering::Relaxed);
+            self.pending.push_back(notification);
+        } else {
+            self.stats.critical_dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+fn duration_to_samples(sample_rate: f64, d: Duration) -> usize {
+    (d.as_secs_f64() * sample_rate) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::{notification_channel, Receiver};
+
+    fn notifier(capacity: usize) -> (Notifier, Receiver<Notifications>, Arc<EngineStats>) {
+        let (sender, receiver) = notification_channel(capacity);
+        let stats = Arc::new(EngineStats::default());
+        let notifier = Notifier::new(
+            sender,
+            stats.clone(),
+            44100.0,
+            Duration::ZERO,
+            Duration::ZERO,
+        );
+        (notifier, receiver, stats)
+    }
+
+    fn midi_cc(value: u8) -> Notifications {
+        Notifications::MidiCc {
+            cc: 1,
+            channel: 0,
+            value,
+        }
+    }
+
+    #[test]
+    fn coalescable_notifications_are_dropped_and_counted_when_full() {
+        let (mut notifier, receiver, stats) = notifier(1);
+        for value in 0..4 {
+            notifier.send(midi_cc(value));
+        }
+        notifier.send(Notifications::ExternalTempo(120.0));
+        assert_eq!(stats.midi_cc_dropped.load(Ordering::Relaxed), 3);
+        assert_eq!(stats.external_tempo_dropped.load(Ordering::Relaxed), 1);
+        assert_eq!(stats.critical_dropped.load(Ordering::Relaxed), 0);
+        let received: Vec<_> = receiver.try_iter().collect();
+        assert!(matches!(
+            received.as_slice(),
+            [Notifications::MidiCc { value: 0, .. }]
+        ));
+    }
+
+    #[test]
+    fn critical_notifications_are_delayed_instead_of_dropped() {
+        let (mut notifier, receiver, stats) = notifier(1);
+        notifier.send(midi_cc(0));
+        for playing in [true, false] {
+            notifier.send(Notifications::ExternalTransport { playing });
+        }
+        assert_eq!(stats.critical_delayed.load(Ordering::Relaxed), 2);
+        assert_eq!(stats.critical_dropped.load(Ordering::Relaxed), 0);
+
+        let mut received = Vec::new();
+        for _ in 0..3 {
+            received.extend(receiver.try_iter());
+            notifier.advance(1);
+        }
+        assert!(matches!(
+            received.as_slice(),
+            [
+                Notifications::MidiCc { .. },
+                Notifications::ExternalTransport { playing: true },
+                Notifications::ExternalTransport { playing: false },
+            ]
+        ));
+    }
+
+    #[test]
+    fn critical_notifications_beyond_the_pending_capacity_are_counted() {
+        let (mut notifier, _receiver, stats) = notifier(1);
+        for _ in 0..PENDING_CAPACITY + 2 {
+            notifier.send(Notifications::CountInFinished);
+        }
+        assert_eq!(
+            stats.critical_delayed.load(Ordering::Relaxed),
+            PENDING_CAPACITY as u64
+        );
+        assert_eq!(stats.critical_dropped.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn time_info_interval_changes_without_recreating_the_notifier() {
+        let (mut notifier, receiver, stats) = notifier(64);
+        let time_info = || {
+            Notifications::TimeInfo(crate::metronome::SampleTimeInfo {
+                measure: 0,
+                beat: 0,
+                sub_beat: 0.0,
+            })
+        };
+        for _ in 0..4 {
+            notifier.advance(128);
+            notifier.send(time_info());
+        }
+        assert_eq!(receiver.try_iter().count(), 4);
+
+        notifier.set_time_info_interval(Duration::from_secs(1));
+        for _ in 0..4 {
+            notifier.advance(128);
+            notifier.send(time_info());
+        }
+        assert_eq!(receiver.try_iter().count(), 0);
+        notifier.advance(44100);
+        notifier.send(time_info());
+        assert_eq!(receiver.try_iter().count(), 1);
+        assert_eq!(stats.time_info_dropped.load(Ordering::Relaxed), 0);
+    }
+
+    fn meters(peak: f32) -> Notifications {
+        let level = Level {
+            peak: [peak; 2],
+            rms: [peak / 2.0; 2],
+        };
+        let mut track_levels = TrackLevels::default();
+        track_levels.push(1, level);
+        Notifications::Meters {
+            track_levels,
+            master: level,
+        }
+    }
+
+    #[test]
+    fn meters_hold_the_loudest_levels_between_updates() {
+        let (mut notifier, receiver, stats) = notifier(64);
+        notifier.set_meter_interval(Duration::from_millis(10));
+        for peak in [0.1, 0.2, 0.9, 0.3] {
+            notifier.advance(128);
+            notifier.send(meters(peak));
+        }
+        // The first update is sent right away.
+        notifier.advance(441);
+        notifier.send(meters(0.4));
+        let received: Vec<_> = receiver.try_iter().collect();
+        let peaks: Vec<_> = received
+            .iter()
+            .map(|n| match n {
+                Notifications::Meters {
+                    track_levels,
+                    master,
+                } => {
+                    let (_, track) = track_levels.iter().next().unwrap();
+                    assert_eq!(track, master);
+                    master.peak[0]
+                }
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(peaks, vec![0.1, 0.9]);
+        assert_eq!(stats.meters_dropped.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn clip_notifications_are_rate_limited() {
+        let (mut notifier, receiver, stats) = notifier(16);
+        let clip = || Notifications::ClipDetected {
+            channel: 0,
+            peak: 1.5,
+        };
+        notifier.send(clip());
+        notifier.advance(128);
+        notifier.send(clip());
+        notifier.advance(duration_to_samples(44100.0, CLIP_INTERVAL));
+        notifier.send(clip());
+        assert_eq!(receiver.try_iter().count(), 2);
+        assert_eq!(stats.clip_detected_dropped.load(Ordering::Relaxed), 0);
+    }
+}
@@ -1,8 +1,19 @@
-use std::{path::Path, sync::Arc};
+use std::{
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use livi::event::LV2AtomSequence;
 
-use crate::audio_buffer::AudioBuffer;
+use log::*;
+
+use crate::{
+    audio_buffer::{AudioBuffer, WavError},
+    drum_kit::DrumKit,
+    gain::Gain,
+    lv2_state::{Lv2StateGuard, Lv2StateHandle},
+};
 
 /// Describes a process failure.
 #[derive(Copy, Clone, Debug)]
@@ -17,19 +28,105 @@ impl From<livi::error::RunError> for PluginProcessError {
     }
 }
 
+impl std::fmt::Display for PluginProcessError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PluginProcessError::Livi(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for PluginProcessError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PluginProcessError::Livi(err) => Some(err),
+        }
+    }
+}
+
+/// The number of audio channels that are connected to a plugin's inputs and
+/// outputs.
+pub const AUDIO_CHANNELS: usize = 2;
+
+/// A plugin port that `PluginInstance::process` can not connect.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UnsupportedPort {
+    /// The human readable name of the port.
+    pub name: String,
+    /// The index of the port.
+    pub index: usize,
+    /// Why the port is not supported.
+    pub reason: &'static str,
+}
+
+impl std::fmt::Display for UnsupportedPort {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "port {} ({}) {}", self.index, self.name, self.reason)
+    }
+}
+
+/// Returns the first port of `plugin` that can not be connected when the
+/// plugin is run. Plugins with such ports fail every run.
+pub fn unsupported_port(plugin: &livi::Plugin) -> Option<UnsupportedPort> {
+    let (mut audio_inputs, mut atom_inputs, mut atom_outputs) = (0, 0, 0);
+    for port in plugin.ports() {
+        let reason = match port.port_type {
+            livi::PortType::CVInput | livi::PortType::CVOutput => Some("is a CV port"),
+            livi::PortType::AudioInput => {
+                audio_inputs += 1;
+                (audio_inputs > AUDIO_CHANNELS).then_some("is more than 2 audio inputs")
+            }
+            livi::PortType::AtomSequenceInput => {
+                atom_inputs += 1;
+                (atom_inputs > 1).then_some("is more than 1 atom sequence input")
+            }
+            livi::PortType::AtomSequenceOutput => {
+                atom_outputs += 1;
+                (atom_outputs > 1).then_some("is more than 1 atom sequence output")
+            }
+            livi::PortType::AudioOutput
+            | livi::PortType::ControlInput
+            | livi::PortType::ControlOutput => None,
+        };
+        if let Some(reason) = reason {
+            return Some(UnsupportedPort {
+                name: port.name,
+                index: port.index.0,
+                reason,
+            });
+        }
+    }
+    None
+}
+
 /// Stores a plugin instance.
 #[derive(Debug)]
 pub enum PluginInstance {
     /// A sample is triggered for each note.
     Sample(SampleTrigger),
+    /// A sample is triggered for each note that has a pad in the kit.
+    DrumKit(Box<DrumKit>),
+    /// Adjusts the level, polarity and balance of the audio.
+    Gain(Gain),
     /// An LV2 plugin instance.
-    Lv2(livi::Instance),
-}
-
-impl From<livi::Instance> for PluginInstance {
-    fn from(value: livi::Instance) -> Self {
-        PluginInstance::Lv2(value)
-    }
+    Lv2 {
+        /// Saves the state of the instance if the plugin supports the LV2
+        /// State extension. Fields are dropped in order, so this is detached
+        /// before the instance is freed.
+        state: Option<Lv2StateGuard>,
+        instance: Box<livi::Instance>,
+        /// The audio outputs of a plugin with more than `AUDIO_CHANNELS`
+        /// outputs. They are folded into the stereo output after each run so
+        /// that no output port is left unconnected.
+        outputs: Option<AudioBuffer>,
+    },
+    /// A placeholder for a plugin that could not be instantiated. Audio passes
+    /// through unchanged.
+    Missing,
+    /// A midi effect that rewrites each midi message and passes audio through
+    /// unchanged. It stands in for LV2 midi effects in tests.
+    #[cfg(test)]
+    MidiEffect(fn(wmidi::MidiMessage<'static>) -> wmidi::MidiMessage<'static>),
 }
 
 impl From<SampleTrigger> for PluginInstance {
@@ -38,102 +135,1187 @@ impl From<SampleTrigger> for PluginInstance {
     }
 }
 
+impl From<Gain> for PluginInstance {
+    fn from(value: Gain) -> PluginInstance {
+        PluginInstance::Gain(value)
+    }
+}
+
+impl From<DrumKit> for PluginInstance {
+    fn from(value: DrumKit) -> PluginInstance {
+        PluginInstance::DrumKit(Box::new(value))
+    }
+}
+
 impl PluginInstance {
-    /// Run the plugin processing.
+    /// Create a plugin instance from an LV2 instance that processes up to
+    /// `buffer_size` frames at a time.
+    pub fn lv2(instance: livi::Instance, buffer_size: usize) -> PluginInstance {
+        let audio_outputs = instance.port_counts().audio_outputs;
+        PluginInstance::Lv2 {
+            state: Lv2StateGuard::new(&instance),
+            instance: Box::new(instance),
+            outputs: (audio_outputs > AUDIO_CHANNELS)
+                .then(|| AudioBuffer::new(audio_outputs, buffer_size)),
+        }
+    }
+
+    /// Get the handle that saves the state of an LV2 plugin while it runs.
+    /// Returns `None` if the plugin does not support the LV2 State extension.
+    pub fn lv2_state_handle(&self) -> Option<Arc<Lv2StateHandle>> {
+        match self {
+            PluginInstance::Lv2 {
+                state: Some(state), ..
+            } => Some(state.handle().clone()),
+            _ => None,
+        }
+    }
+
+    /// Resize the buffers to hold `buffer_size` frames. This allocates and
+    /// should not be called while processing.
+    pub fn set_buffer_size(&mut self, buffer_size: usize) {
+        if let PluginInstance::Lv2 {
+            outputs: Some(outputs),
+            ..
+        } = self
+        {
+            outputs.reset_with_buffer_size(buffer_size);
+        }
+    }
+
+    /// Set the value of the control port at `port_index`. Returns the value
+    /// that was set or `None` if the port does not exist.
+    pub fn set_control_input(&mut self, port_index: usize, value: f32) -> Option<f32> {
+        match self {
+            PluginInstance::Sample(sample) => sample.set_control_input(port_index, value),
+            PluginInstance::Gain(gain) => gain.set_control_input(port_index, value),
+            PluginInstance::DrumKit(_) | PluginInstance::Missing => None,
+            #[cfg(test)]
+            PluginInstance::MidiEffect(_) => None,
+            PluginInstance::Lv2 { instance, .. } => {
+                instance.set_control_input(livi::PortIndex(port_index), value)
+            }
+        }
+    }
+
+    /// Returns true if the plugin produces midi.
+    pub fn has_midi_output(&self) -> bool {
+        match self {
+            PluginInstance::Sample(_)
+            | PluginInstance::DrumKit(_)
+            | PluginInstance::Gain(_)
+            | PluginInstance::Missing => false,
+            PluginInstance::Lv2 { instance, .. } => {
+                instance.port_counts().atom_sequence_outputs > 0
+            }
+            #[cfg(test)]
+            PluginInstance::MidiEffect(_) => true,
+        }
+    }
+
+    /// Run the plugin processing. Any midi that the plugin produces is written
+    /// to `midi_output`.
+    ///
+    /// Stereo audio is adapted to LV2 plugins with fewer channels:
+    /// - A plugin with a single audio input receives the average of the input
+    ///   channels. The downmix is written to the first channel of `input`.
+    /// - The single audio output of a plugin is copied to both channels of
+    ///   `output`.
+    /// - Plugins with more than 2 audio outputs write to their own buffer
+    ///   which is folded into `output` with `AudioBuffer::fold_channels_from`.
+    /// - Plugins without audio outputs, like midi effects, pass `input`
+    ///   through unchanged.
+    ///
+    /// Mono plugins are run once rather than once per channel since running a
+    /// stateful plugin twice per block would advance its state twice.
     pub fn process(
         &mut self,
         samples: usize,
         midi_input: &LV2AtomSequence,
-        input: &AudioBuffer,
+        midi_output: &mut LV2AtomSequence,
+        input: &mut AudioBuffer,
         output: &mut AudioBuffer,
     ) -> Result<(), PluginProcessError> {
+        profiling::scope!("PluginInstance::process");
         match self {
-            PluginInstance::Sample(sample) => sample.process(midi_input, output),
-            PluginInstance::Lv2(instance) => {
+            PluginInstance::Sample(sample) => {
+                midi_output.clear();
+                sample.process(samples, midi_input, output)
+            }
+            PluginInstance::DrumKit(kit) => {
+                midi_output.clear();
+                kit.process(samples, midi_input, output)
+            }
+            PluginInstance::Gain(gain) => {
+                midi_output.clear();
+                gain.process(samples, input, output);
+                Ok(())
+            }
+            PluginInstance::Missing => {
+                midi_output.clear();
+                output.copy_from_at(input, 0);
+                Ok(())
+            }
+            #[cfg(test)]
+            PluginInstance::MidiEffect(effect) => {
+                midi_output.clear();
+                for event in midi_input.iter() {
+                    if let Ok(message) = wmidi::MidiMessage::try_from(event.data) {
+                        let mut data = [0u8; 3];
+                        let size = effect(message.to_owned()).copy_to_slice(&mut data).unwrap();
+                        midi_output
+                            .push_midi_event::<3>(
+                                event.event.time_in_frames,
+                                event.event.body.mytype,
+                                &data[..size],
+                            )
+                            .unwrap();
+                    }
+                }
+                output.copy_from_at(input, 0);
+                Ok(())
+            }
+            PluginInstance::Lv2 {
+                instance, outputs, ..
+            } => {
                 let port_counts = instance.port_counts();
+                midi_output.clear_as_chunk();
+                if port_counts.audio_inputs == 1 {
+                    input.downmix_to_first_channel();
+                }
+                if let Some(outputs) = outputs.as_mut() {
+                    outputs.reset_with_buffer_size(samples);
+                }
+                let audio_outputs = match outputs.as_mut() {
+                    Some(outputs) => outputs.iter_channels_mut(),
+                    None => output.iter_channels_mut(),
+                };
                 let ports = livi::EmptyPortConnections::new()
                     .with_atom_sequence_inputs(
                         std::iter::once(midi_input).take(port_counts.atom_sequence_inputs),
                     )
+                    .with_atom_sequence_outputs(
+                        std::iter::once(midi_output).take(port_counts.atom_sequence_outputs),
+                    )
                     .with_audio_inputs(input.iter_channels().take(port_counts.audio_inputs))
-                    .with_audio_outputs(output.iter_channels_mut().take(port_counts.audio_outputs));
+                    .with_audio_outputs(audio_outputs.take(port_counts.audio_outputs));
                 unsafe {
                     instance
                         .run(samples, ports)
-                        .map_err(PluginProcessError::Livi)
+                        .map_err(PluginProcessError::Livi)?;
+                }
+                match (port_counts.audio_outputs, outputs.as_ref()) {
+                    (0, _) => output.copy_from_at(input, 0),
+                    (1, _) => output.copy_first_channel_to_all(),
+                    (_, Some(outputs)) => output.fold_channels_from(outputs),
+                    _ => (),
                 }
+                Ok(())
             }
         }
     }
 }
 
+/// The measured processing time of a plugin.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PluginCost {
+    /// The average time to process a block.
+    pub average: Duration,
+    /// The maximum time to process a block.
+    pub max: Duration,
+    /// The time available to process a block in realtime.
+    pub budget: Duration,
+}
+
+impl PluginCost {
+    /// The average processing time as a fraction of the budget.
+    pub fn budget_fraction(&self) -> f64 {
+        self.average.as_secs_f64() / self.budget.as_secs_f64()
+    }
+}
+
+impl PluginInstance {
+    /// Measure the cost of running the plugin for `blocks` blocks of `samples`
+    /// frames against silence and a synthetic midi pattern.
+    ///
+    /// This runs the plugin directly and should only be used on instances that
+    /// are not part of a live processor.
+    pub fn measure_cost(
+        &mut self,
+        features: &livi::Features,
+        sample_rate: f64,
+        samples: usize,
+        blocks: usize,
+    ) -> Result<PluginCost, PluginProcessError> {
+        let midi_urid = features.midi_urid();
+        let mut midi = LV2AtomSequence::new(features, 1024 /*1 KiB*/);
+        let mut midi_output = LV2AtomSequence::new(features, 64 * 1024 /*64 KiB*/);
+        let mut input = AudioBuffer::with_stereo(samples);
+        let mut output = AudioBuffer::with_stereo(samples);
+        let mut total = Duration::ZERO;
+        let mut max = Duration::ZERO;
+        for block in 0..blocks {
+            midi.clear();
+            let msg = if block % 2 == 0 {
+                wmidi::MidiMessage::NoteOn(wmidi::Channel::Ch1, wmidi::Note::C4, wmidi::U7::MAX)
+            } else {
+                wmidi::MidiMessage::NoteOff(wmidi::Channel::Ch1, wmidi::Note::C4, wmidi::U7::MIN)
+            };
+            let mut data = [0u8; 3];
+            msg.copy_to_slice(&mut data).unwrap();
+            midi.push_midi_event::<3>(0, midi_urid, &data).unwrap();
+            output.reset();
+            let start = Instant::now();
+            self.process(samples, &midi, &mut midi_output, &mut input, &mut output)?;
+            let elapsed = start.elapsed();
+            total += elapsed;
+            max = max.max(elapsed);
+        }
+        Ok(PluginCost {
+            average: total / blocks.max(1) as u32,
+            max,
+            budget: Duration::from_secs_f64(samples as f64 / sample_rate),
+        })
+    }
+}
+
+/// The length of the fade out when a gated sample without an envelope is
+/// stopped by a note off. This avoids a click from cutting the sample off.
+const GATE_FADE: Duration = Duration::from_millis(5);
+
+/// The longest crossfade at the seam of a looping sample. This avoids a click
+/// when the loop end does not line up with the loop start.
+const LOOP_CROSSFADE: Duration = Duration::from_millis(10);
+
+/// How a `SampleTrigger` responds to note offs.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum TriggerMode {
+    /// The sample plays to the end. Note offs are ignored unless the sample
+    /// has an envelope or loops.
+    #[default]
+    OneShot,
+    /// A note off for the note that started the sample, or a note on with a
+    /// velocity of 0, stops the sample with a short fade out.
+    Gate,
+}
+
+/// How the velocity of a note on scales the level of a `SampleTrigger`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum VelocityCurve {
+    /// The level is proportional to the velocity.
+    #[default]
+    Linear,
+    /// The level falls off by a fixed number of decibels per velocity step so
+    /// soft notes are much quieter than with `Linear`.
+    Exponential,
+}
+
+impl VelocityCurve {
+    /// The level of the softest note relative to the loudest note for the
+    /// `Exponential` curve.
+    const EXPONENTIAL_RANGE_DB: f32 = 48.0;
+
+    /// Get the gain for a note on with `velocity`.
+    pub fn gain(self, velocity: wmidi::U7) -> f32 {
+        let velocity = u8::from(velocity) as f32 / 127.0;
+        match self {
+            VelocityCurve::Linear => velocity,
+            VelocityCurve::Exponential if velocity > 0.0 => {
+                10f32.powf((velocity - 1.0) * VelocityCurve::EXPONENTIAL_RANGE_DB / 20.0)
+            }
+            VelocityCurve::Exponential => 0.0,
+        }
+    }
+}
+
+/// An attack, decay, sustain and release envelope that shapes the level of a
+/// triggered sample.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Envelope {
+    /// The milliseconds to rise from silence to full level.
+    pub attack_ms: f32,
+    /// The milliseconds to fall from full level to the sustain level.
+    pub decay_ms: f32,
+    /// The level, from 0.0 to 1.0, that is held until the note is released.
+    pub sustain: f32,
+    /// The milliseconds to fall to silence once the note is released.
+    pub release_ms: f32,
+}
+
+impl Default for Envelope {
+    /// An envelope that leaves the sample unchanged until it is released.
+    fn default() -> Envelope {
+        Envelope {
+            attack_ms: 0.0,
+            decay_ms: 0.0,
+            sustain: 1.0,
+            release_ms: GATE_FADE.as_secs_f32() * 1000.0,
+        }
+    }
+}
+
+impl Envelope {
+    /// The port indices of the envelope parameters for
+    /// `SampleTrigger::set_control_input`.
+    pub const ATTACK_PORT: usize = 0;
+    pub const DECAY_PORT: usize = 1;
+    pub const SUSTAIN_PORT: usize = 2;
+    pub const RELEASE_PORT: usize = 3;
+
+    /// Get the level `age` frames after the sample was triggered if it has not
+    /// been released.
+    fn level(&self, age: usize, sample_rate: f64) -> f32 {
+        let attack = ms_to_frames(self.attack_ms, sample_rate);
+        let decay = ms_to_frames(self.decay_ms, sample_rate);
+        if age < attack {
+            age as f32 / attack as f32
+        } else if age < attack + decay {
+            let t = (age - attack) as f32 / decay as f32;
+            1.0 - (1.0 - self.sustain) * t
+        } else {
+            self.sustain
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct SampleTrigger {
     sample: Arc<AudioBuffer>,
-    index: Option<usize>,
+    sample_rate: f64,
+    mode: TriggerMode,
+    /// The envelope applied to the sample, if any.
+    envelope: Option<Envelope>,
+    velocity_curve: VelocityCurve,
+    /// The gain from the velocity of the note that started the sample.
+    velocity_gain: f32,
+    /// The note that plays the sample at its original pitch. If `None`, every
+    /// note plays the sample at its original pitch.
+    root_note: Option<wmidi::Note>,
+    /// If true, the sample repeats between the loop points until it is
+    /// released.
+    looping: bool,
+    /// The first frame of the loop.
+    loop_start: usize,
+    /// The frame after the last frame of the loop. If `None`, the loop ends at
+    /// the end of the sample.
+    loop_end: Option<usize>,
+    /// The frames of the sample to advance for each output frame.
+    rate: f64,
+    /// The position in the sample, in frames, if the sample is playing.
+    position: Option<f64>,
+    /// The number of frames since the sample was triggered.
+    age: usize,
+    /// The note that started the sample if it was started by a note.
+    note: Option<wmidi::Note>,
+    /// The age and level when the sample was released, if it was released.
+    released: Option<(usize, f32)>,
 }
 
 impl SampleTrigger {
-    /// Create a sample trigger from an audio buffer.
-    pub fn new(sample: Arc<AudioBuffer>) -> SampleTrigger {
+    /// Create a sample trigger from an audio buffer that is played at
+    /// `sample_rate`.
+    pub fn new(sample: Arc<AudioBuffer>, sample_rate: f64) -> SampleTrigger {
         SampleTrigger {
             sample,
-            index: None,
+            sample_rate,
+            mode: TriggerMode::default(),
+            envelope: None,
+            velocity_curve: VelocityCurve::default(),
+            velocity_gain: 1.0,
+            root_note: None,
+            looping: false,
+            loop_start: 0,
+            loop_end: None,
+            rate: 1.0,
+            position: None,
+            age: 0,
+            note: None,
+            released: None,
+        }
+    }
+
+    /// Create a sample trigger from a wave path. The sample is resampled to
+    /// `sample_rate` if the file has a different rate.
+    pub fn from_wav(p: &Path, sample_rate: f64) -> Result<SampleTrigger, WavError> {
+        let sample = AudioBuffer::with_wav(p)?;
+        Ok(SampleTrigger::with_sample_rate(sample, sample_rate))
+    }
+
+    /// Create a sample trigger from the contents of a wav file that is
+    /// already in memory.
+    pub fn from_wav_bytes(bytes: &[u8], sample_rate: f64) -> Result<SampleTrigger, WavError> {
+        let sample = AudioBuffer::from_wav_bytes(bytes)?;
+        Ok(SampleTrigger::with_sample_rate(sample, sample_rate))
+    }
+
+    /// Create a sample trigger from a wave path if it exists or from
+    /// `default_wav`, one of the sounds in `crate::resources`, otherwise.
+    pub fn from_wav_or_default(p: &Path, sample_rate: f64, default_wav: &[u8]) -> SampleTrigger {
+        if p.exists() {
+            match SampleTrigger::from_wav(p, sample_rate) {
+                Ok(s) => return s,
+                Err(err) => warn!("Failed to load {:?}, using the default sound: {}", p, err),
+            }
+        }
+        SampleTrigger::from_wav_bytes(default_wav, sample_rate).expect("default sounds are valid")
+    }
+
+    /// Create a sample trigger that plays `sample` at `sample_rate`,
+    /// resampling it if it was loaded at a different rate.
+    fn with_sample_rate(mut sample: AudioBuffer, sample_rate: f64) -> SampleTrigger {
+        match sample.sample_rate() {
+            Some(rate) if rate != sample_rate => sample = sample.resampled(rate, sample_rate),
+            _ => (),
         }
+        SampleTrigger::new(Arc::new(sample), sample_rate)
+    }
+
+    /// Set how the sample responds to note offs.
+    pub fn with_mode(mut self, mode: TriggerMode) -> SampleTrigger {
+        self.mode = mode;
+        self
+    }
+
+    /// Get how the sample responds to note offs.
+    pub fn mode(&self) -> TriggerMode {
+        self.mode
+    }
+
+    /// Set the envelope that shapes each triggered sample. If `None`, the
+    /// sample is played at full level. A note off for the note that started
+    /// the sample starts the release of the envelope in either mode.
+    pub fn with_envelope(mut self, envelope: Option<Envelope>) -> SampleTrigger {
+        self.envelope = envelope;
+        self
+    }
+
+    /// Get the envelope that shapes each triggered sample.
+    pub fn envelope(&self) -> Option<Envelope> {
+        self.envelope
+    }
+
+    /// Set how the velocity of a note on scales the level of the sample.
+    pub fn with_velocity_curve(mut self, velocity_curve: VelocityCurve) -> SampleTrigger {
+        self.velocity_curve = velocity_curve;
+        self
     }
 
-    /// Create a sample trigger from a wave path.
-    pub fn from_wav(p: &Path) -> SampleTrigger {
-        let sample = Arc::new(AudioBuffer::with_wav(p));
-        SampleTrigger::new(sample)
+    /// Get how the velocity of a note on scales the level of the sample.
+    pub fn velocity_curve(&self) -> VelocityCurve {
+        self.velocity_curve
     }
 
-    /// Start triggering the sample as opposed to waiting for a midi note on event.
+    /// Set the note that plays the sample at its original pitch. Other notes
+    /// play the sample faster or slower to shift its pitch by the difference
+    /// in semitones. If `None`, every note plays the sample at its original
+    /// pitch.
+    pub fn with_root_note(mut self, root_note: Option<wmidi::Note>) -> SampleTrigger {
+        self.root_note = root_note;
+        self
+    }
+
+    /// Get the note that plays the sample at its original pitch.
+    pub fn root_note(&self) -> Option<wmidi::Note> {
+        self.root_note
+    }
+
+    /// Set if the sample repeats between the loop points until it is
+    /// released. A note off for the note that started a looping sample starts
+    /// its release in either mode.
+    pub fn with_looping(mut self, looping: bool) -> SampleTrigger {
+        self.looping = looping;
+        self
+    }
+
+    /// Returns true if the sample repeats between the loop points.
+    pub fn is_looping(&self) -> bool {
+        self.looping
+    }
+
+    /// Set the frames the loop starts at and ends before. If `end` is `None`,
+    /// the loop ends at the end of the sample. Points past the end of the
+    /// sample are clamped to the end of the sample.
+    pub fn with_loop_points(mut self, start: usize, end: Option<usize>) -> SampleTrigger {
+        self.loop_start = start;
+        self.loop_end = end;
+        self
+    }
+
+    /// Get the frames the loop starts at and ends before, clamped to the
+    /// length of the sample.
+    pub fn loop_points(&self) -> (usize, usize) {
+        let len = self.sample.buffer_size();
+        let end = self.loop_end.unwrap_or(len).min(len);
+        (self.loop_start.min(end), end)
+    }
+
+    /// Set an envelope parameter by its port index in `Envelope`. Setting any
+    /// parameter adds the default envelope if there is none. Returns the value
+    /// that was set or `None` if the port does not exist.
+    pub fn set_control_input(&mut self, port_index: usize, value: f32) -> Option<f32> {
+        let envelope = self.envelope.get_or_insert_with(Envelope::default);
+        let (param, value) = match port_index {
+            Envelope::ATTACK_PORT => (&mut envelope.attack_ms, value.max(0.0)),
+            Envelope::DECAY_PORT => (&mut envelope.decay_ms, value.max(0.0)),
+            Envelope::SUSTAIN_PORT => (&mut envelope.sustain, value.clamp(0.0, 1.0)),
+            Envelope::RELEASE_PORT => (&mut envelope.release_ms, value.max(0.0)),
+            _ => return None,
+        };
+        *param = value;
+        Some(value)
+    }
+
+    /// Start triggering the sample at full level and its original pitch as
+    /// opposed to waiting for a midi note on event.
     pub fn start(&mut self) {
-        self.index = Some(0);
+        self.position = Some(0.0);
+        self.rate = 1.0;
+        self.velocity_gain = 1.0;
+        self.age = 0;
+        self.note = None;
+        self.released = None;
     }
 
     /// Returns true if the sample is active or false if it has not been started or is done.
     pub fn is_active(&self) -> bool {
-        self.index.is_some()
+        self.position.is_some()
     }
 
-    /// Processes the sample triggering.
+    /// Processes the sample triggering for the first `samples` frames of
+    /// `output`. The frames are replaced by the sample and silenced while the
+    /// sample is not playing. The rest of `output` is left untouched. Each
+    /// output channel plays the matching channel of the sample. If the sample
+    /// has fewer channels, its last channel is used for the remaining
+    /// channels.
     pub fn process(
         &mut self,
+        samples: usize,
         midi_input: &LV2AtomSequence,
         output: &mut AudioBuffer,
     ) -> Result<(), PluginProcessError> {
-        {
-            let output = output.iter_channels_mut().next().unwrap();
-            let mut midi = midi_input.iter().peekable();
-            let sample = self.sample.iter_channels().next().unwrap();
-            for (frame, output) in output.iter_mut().enumerate() {
-                if midi
-                    .peek()
-                    .map(|m| m.event.time_in_frames as usize >= frame)
-                    .unwrap_or(false)
-                {
-                    let data = midi.next().unwrap().data;
-                    match wmidi::MidiMessage::try_from(data) {
-                        Ok(wmidi::MidiMessage::NoteOn(_, _, v)) if u8::from(v) > 0 => {
-                            self.index = Some(0)
+        for dst in output.iter_channels_mut() {
+            for v in dst[..samples].iter_mut() {
+                *v = crate::denormals::RESET_VALUE;
+            }
+        }
+        let sample_channels = self.sample.channels();
+        let mut midi = midi_input.iter().peekable();
+        for frame in 0..samples {
+            while let Some(event) = midi.next_if(|m| m.event.time_in_frames as usize <= frame) {
+                match wmidi::MidiMessage::try_from(event.data) {
+                    Ok(wmidi::MidiMessage::NoteOn(_, note, v)) if u8::from(v) > 0 => {
+                        self.start();
+                        self.note = Some(note);
+                        self.velocity_gain = self.velocity_curve.gain(v);
+                        if let Some(root_note) = self.root_note {
+                            let semitones = u8::from(note) as f64 - u8::from(root_note) as f64;
+                            self.rate = 2f64.powf(semitones / 12.0);
                         }
-                        _ => (),
                     }
+                    Ok(wmidi::MidiMessage::NoteOn(_, note, _))
+                    | Ok(wmidi::MidiMessage::NoteOff(_, note, _)) => self.release(note),
+                    _ => (),
                 }
-                if let Some(index) = self.index {
-                    match sample.get(index) {
-                        Some(s) => {
-                            self.index = Some(index + 1);
-                            *output = *s;
+            }
+            if let Some(position) = self.position {
+                let loop_range = self.loop_range();
+                let position = match loop_range {
+                    // The loop may be shorter than the distance moved in a
+                    // frame, so wrap with the remainder instead of subtracting
+                    // the loop length once.
+                    Some((start, end)) if position >= end as f64 => {
+                        start as f64 + (position - start as f64) % (end - start) as f64
+                    }
+                    _ => position,
+                };
+                match self
+                    .gain()
+                    .filter(|_| (position as usize) < self.sample.buffer_size())
+                {
+                    Some(gain) => {
+                        self.position = Some(position + self.rate);
+                        self.age += 1;
+                        let gain = gain * self.velocity_gain;
+                        for (idx, dst) in output.iter_channels_mut().enumerate() {
+                            let src = self
+                                .sample
+                                .iter_channels()
+                                .nth(idx.min(sample_channels - 1))
+                                .unwrap();
+                            dst[frame] = self.read(src, position, loop_range) * gain;
                         }
-                        None => self.index = None,
+                    }
+                    None => {
+                        self.position = None;
+                        self.released = None;
                     }
                 }
             }
         }
-        output.copy_first_channel_to_all();
         Ok(())
     }
+
+    /// Get the start and end of the loop if the sample is looping and the
+    /// loop is not empty.
+    fn loop_range(&self) -> Option<(usize, usize)> {
+        if !self.looping {
+            return None;
+        }
+        let (start, end) = self.loop_points();
+        Some((start, end)).filter(|_| start < end)
+    }
+
+    /// Read `channel` at the fractional frame `position`. Within the crossfade
+    /// before the loop end, the frames before the loop start are faded in so
+    /// that the loop end flows into the loop start. The crossfade is limited
+    /// to half the loop and to the frames that come before the loop start.
+    fn read(&self, channel: &[f32], position: f64, loop_range: Option<(usize, usize)>) -> f32 {
+        let (start, end) = match loop_range {
+            Some(range) => range,
+            None => return interpolate(channel, position, None),
+        };
+        let value = interpolate(channel, position, Some((start, end)));
+        let crossfade = ms_to_frames(LOOP_CROSSFADE.as_secs_f32() * 1000.0, self.sample_rate)
+            .min((end - start) / 2)
+            .min(start);
+        let crossfade_start = (end - crossfade) as f64;
+        if crossfade == 0 || position < crossfade_start {
+            return value;
+        }
+        let t = ((position - crossfade_start) / crossfade as f64) as f32;
+        let before_start = interpolate(channel, position - (end - start) as f64, None);
+        value * (1.0 - t) + before_start * t
+    }
+
+    /// Get the gain for the current frame or `None` if the release has
+    /// finished.
+    fn gain(&self) -> Option<f32> {
+        let (released_age, released_level) = match self.released {
+            Some(released) => released,
+            None => {
+                return Some(match self.envelope.as_ref() {
+                    Some(envelope) => envelope.level(self.age, self.sample_rate),
+                    None => 1.0,
+                })
+            }
+        };
+        let release = match self.envelope.as_ref() {
+            Some(envelope) => ms_to_frames(envelope.release_ms, self.sample_rate),
+            None => ms_to_frames(GATE_FADE.as_secs_f32() * 1000.0, self.sample_rate),
+        };
+        let elapsed = self.age - released_age;
+        if elapsed >= release {
+            return None;
+        }
+        Some(released_level * (release - elapsed) as f32 / release as f32)
+    }
+
+    /// Start the release if the sample was started by `note` and is gated,
+    /// has an envelope or loops.
+    fn release(&mut self, note: wmidi::Note) {
+        if (self.mode == TriggerMode::Gate || self.envelope.is_some() || self.looping)
+            && self.position.is_some()
+            && self.note == Some(note)
+            && self.released.is_none()
+        {
+            self.released = self.gain().map(|level| (self.age, level));
+        }
+    }
+}
+
+/// Read `channel` at the fractional frame `position` by interpolating between
+/// the two closest frames. If `loop_range` is set, the frame after the last
+/// frame of the loop is the loop start.
+fn interpolate(channel: &[f32], position: f64, loop_range: Option<(usize, usize)>) -> f32 {
+    let index = position as usize;
+    let next = match loop_range {
+        Some((start, end)) if index + 1 >= end => start,
+        _ => (index + 1).min(channel.len() - 1),
+    };
+    let frac = (position - index as f64) as f32;
+    channel[index] + (channel[next] - channel[index]) * frac
+}
+
+/// Convert a number of milliseconds to a number of frames.
+fn ms_to_frames(ms: f32, sample_rate: f64) -> usize {
+    (ms as f64 * sample_rate / 1000.0) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f64 = 44100.0;
+
+    /// A value that processing never writes.
+    const UNTOUCHED: f32 = 7.0;
+
+    fn features() -> Arc<livi::Features> {
+        livi::FeaturesBuilder {
+            min_block_length: 1,
+            max_block_length: 4096,
+        }
+        .build(&livi::World::with_plugin_predicate(|_| false))
+    }
+
+    /// Create a sequence with each midi message at its frame.
+    fn midi(features: &livi::Features, events: &[(i64, wmidi::MidiMessage)]) -> LV2AtomSequence {
+        let mut sequence = LV2AtomSequence::new(features, 1024);
+        for (frame, message) in events {
+            let mut data = [0u8; 3];
+            let size = message.copy_to_slice(&mut data).unwrap();
+            sequence
+                .push_midi_event::<3>(*frame, features.midi_urid(), &data[..size])
+                .unwrap();
+        }
+        sequence
+    }
+
+    /// Create a mono sample whose value at each frame is the frame index.
+    fn ramp(frames: usize) -> Arc<AudioBuffer> {
+        Arc::new(AudioBuffer::from_channels(&[(0..frames)
+            .map(|i| i as f32)
+            .collect()]))
+    }
+
+    /// Create a mono sample that is at full level for `frames` frames.
+    fn full_level(frames: usize) -> Arc<AudioBuffer> {
+        Arc::new(AudioBuffer::from_channels(&[vec![1.0; frames]]))
+    }
+
+    fn untouched_output(buffer_size: usize) -> AudioBuffer {
+        let mut output = AudioBuffer::with_stereo(buffer_size);
+        for channel in output.iter_channels_mut() {
+            channel.fill(UNTOUCHED);
+        }
+        output
+    }
+
+    #[test]
+    fn sample_trigger_only_writes_the_processed_frames() {
+        let features = features();
+        let empty = midi(&features, &[]);
+        let mut trigger = SampleTrigger::new(ramp(4096), SAMPLE_RATE);
+        trigger.start();
+        let mut played = 0;
+        for samples in [64, 480, 1024, 1, 1024, 64] {
+            let mut output = untouched_output(1024);
+            trigger.process(samples, &empty, &mut output).unwrap();
+            let expected: Vec<f32> = (played..played + samples).map(|i| i as f32).collect();
+            for channel in output.iter_channels() {
+                assert_eq!(&channel[..samples], expected.as_slice());
+                assert!(channel[samples..].iter().all(|v| *v == UNTOUCHED));
+            }
+            played += samples;
+            assert_eq!(trigger.position, Some(played as f64));
+        }
+    }
+
+    #[test]
+    fn sample_trigger_silences_the_processed_frames_when_done() {
+        let features = features();
+        let empty = midi(&features, &[]);
+        let mut trigger = SampleTrigger::new(ramp(100), SAMPLE_RATE);
+        trigger.start();
+        let mut output = untouched_output(256);
+        trigger.process(128, &empty, &mut output).unwrap();
+        assert!(!trigger.is_active());
+        for channel in output.iter_channels() {
+            assert_eq!(channel[99], 99.0);
+            assert!(channel[100..128].iter().all(|v| v.abs() < 1e-6));
+            assert!(channel[128..].iter().all(|v| *v == UNTOUCHED));
+        }
+    }
+
+    #[test]
+    fn sample_trigger_plays_each_channel_of_the_sample() {
+        let features = features();
+        let empty = midi(&features, &[]);
+        let sample = AudioBuffer::from_channels(&[vec![0.25; 64], vec![-0.5; 64]]);
+        let mut trigger = SampleTrigger::new(Arc::new(sample), SAMPLE_RATE);
+        trigger.start();
+        let mut output = AudioBuffer::new(3, 32);
+        trigger.process(32, &empty, &mut output).unwrap();
+        let channels: Vec<&[f32]> = output.iter_channels().collect();
+        assert_eq!(
+            channels,
+            vec![&[0.25; 32][..], &[-0.5; 32][..], &[-0.5; 32][..]]
+        );
+    }
+
+    #[test]
+    fn sample_trigger_falls_back_to_the_default_sound() {
+        let default =
+            SampleTrigger::from_wav_bytes(crate::resources::BEEP_WAV, SAMPLE_RATE).unwrap();
+        let dir = std::env::temp_dir();
+        let missing = dir.join(format!("mini-leebee-{}-missing.wav", std::process::id()));
+        let corrupt = dir.join(format!("mini-leebee-{}-corrupt.wav", std::process::id()));
+        std::fs::write(&corrupt, b"not a wav file").unwrap();
+        for path in [&missing, &corrupt] {
+            let trigger =
+                SampleTrigger::from_wav_or_default(path, SAMPLE_RATE, crate::resources::BEEP_WAV);
+            assert_eq!(trigger.sample.buffer_size(), default.sample.buffer_size());
+            assert!(trigger.sample.buffer_size() > 0);
+        }
+        std::fs::remove_file(&corrupt).unwrap();
+    }
+
+    /// Process `samples` frames of `trigger` in a single block with the midi
+    /// `events` and return the first output channel.
+    fn play(
+        trigger: &mut SampleTrigger,
+        samples: usize,
+        events: &[(i64, wmidi::MidiMessage)],
+    ) -> Vec<f32> {
+        let features = features();
+        let mut output = AudioBuffer::with_stereo(samples);
+        trigger
+            .process(samples, &midi(&features, events), &mut output)
+            .unwrap();
+        let channel = output.iter_channels().next().unwrap().to_vec();
+        channel
+    }
+
+    fn note_on(note: wmidi::Note, velocity: u8) -> wmidi::MidiMessage<'static> {
+        wmidi::MidiMessage::NoteOn(
+            wmidi::Channel::Ch1,
+            note,
+            wmidi::U7::try_from(velocity).unwrap(),
+        )
+    }
+
+    fn note_off(note: wmidi::Note) -> wmidi::MidiMessage<'static> {
+        wmidi::MidiMessage::NoteOff(wmidi::Channel::Ch1, note, wmidi::U7::MIN)
+    }
+
+    fn assert_close(actual: &[f32], expected: impl IntoIterator<Item = f32>) {
+        let expected: Vec<f32> = expected.into_iter().collect();
+        assert_eq!(actual.len(), expected.len());
+        for (frame, (a, b)) in actual.iter().zip(expected.iter()).enumerate() {
+            assert!((a - b).abs() < 1e-4, "frame {frame}: {a} != {b}");
+        }
+    }
+
+    #[test]
+    fn gated_sample_fades_out_on_note_off() {
+        let fade = ms_to_frames(GATE_FADE.as_secs_f32() * 1000.0, SAMPLE_RATE);
+        let release_messages = [note_off(wmidi::Note::C4), note_on(wmidi::Note::C4, 0)];
+        for release in release_messages {
+            let mut trigger =
+                SampleTrigger::new(full_level(4096), SAMPLE_RATE).with_mode(TriggerMode::Gate);
+            let output = play(
+                &mut trigger,
+                2048,
+                &[(0, note_on(wmidi::Note::C4, 127)), (1000, release)],
+            );
+            assert_close(&output[..1000], std::iter::repeat_n(1.0, 1000));
+            assert_close(
+                &output[1000..1000 + fade],
+                (0..fade).map(|i| (fade - i) as f32 / fade as f32),
+            );
+            assert_close(
+                &output[1000 + fade..],
+                std::iter::repeat_n(0.0, 1048 - fade),
+            );
+            assert!(!trigger.is_active());
+        }
+    }
+
+    #[test]
+    fn gated_sample_ignores_note_offs_of_other_notes() {
+        let mut trigger =
+            SampleTrigger::new(full_level(4096), SAMPLE_RATE).with_mode(TriggerMode::Gate);
+        let output = play(
+            &mut trigger,
+            2048,
+            &[
+                (0, note_on(wmidi::Note::C4, 127)),
+                (100, note_off(wmidi::Note::D4)),
+            ],
+        );
+        assert_close(&output, std::iter::repeat_n(1.0, 2048));
+        assert!(trigger.is_active());
+    }
+
+    #[test]
+    fn one_shot_sample_plays_through_note_offs() {
+        let mut trigger = SampleTrigger::new(ramp(1000), SAMPLE_RATE);
+        assert_eq!(trigger.mode(), TriggerMode::OneShot);
+        let output = play(
+            &mut trigger,
+            1024,
+            &[
+                (0, note_on(wmidi::Note::C4, 127)),
+                (10, note_off(wmidi::Note::C4)),
+            ],
+        );
+        assert_close(
+            &output,
+            (0..1000)
+                .map(|i| i as f32)
+                .chain(std::iter::repeat_n(0.0, 24)),
+        );
+    }
+
+    #[test]
+    fn envelope_shapes_the_level_of_the_sample() {
+        // One frame per millisecond.
+        let sample_rate = 1000.0;
+        let envelope = Envelope {
+            attack_ms: 10.0,
+            decay_ms: 20.0,
+            sustain: 0.5,
+            release_ms: 40.0,
+        };
+        let mut trigger =
+            SampleTrigger::new(full_level(1000), sample_rate).with_envelope(Some(envelope));
+        let output = play(
+            &mut trigger,
+            200,
+            &[
+                (0, note_on(wmidi::Note::C4, 127)),
+                (100, note_off(wmidi::Note::C4)),
+            ],
+        );
+        let expected = (0..200).map(|frame| match frame {
+            0..=9 => frame as f32 / 10.0,
+            10..=29 => 1.0 - 0.5 * (frame - 10) as f32 / 20.0,
+            30..=99 => 0.5,
+            100..=139 => 0.5 * (140 - frame) as f32 / 40.0,
+            _ => 0.0,
+        });
+        assert_close(&output, expected);
+        assert!(!trigger.is_active());
+    }
+
+    #[test]
+    fn release_starts_from_the_level_at_the_note_off() {
+        let sample_rate = 1000.0;
+        let envelope = Envelope {
+            attack_ms: 10.0,
+            decay_ms: 0.0,
+            sustain: 1.0,
+            release_ms: 10.0,
+        };
+        let mut trigger =
+            SampleTrigger::new(full_level(1000), sample_rate).with_envelope(Some(envelope));
+        let output = play(
+            &mut trigger,
+            20,
+            &[
+                (0, note_on(wmidi::Note::C4, 127)),
+                (5, note_off(wmidi::Note::C4)),
+            ],
+        );
+        let expected = (0..20).map(|frame| match frame {
+            0..=4 => frame as f32 / 10.0,
+            5..=14 => 0.5 * (15 - frame) as f32 / 10.0,
+            _ => 0.0,
+        });
+        assert_close(&output, expected);
+    }
+
+    #[test]
+    fn envelope_parameters_are_set_by_port() {
+        let mut trigger = SampleTrigger::new(full_level(10), SAMPLE_RATE);
+        assert_eq!(trigger.envelope(), None);
+        assert_eq!(
+            trigger.set_control_input(Envelope::SUSTAIN_PORT, 2.0),
+            Some(1.0)
+        );
+        assert_eq!(trigger.envelope(), Some(Envelope::default()));
+        assert_eq!(
+            trigger.set_control_input(Envelope::ATTACK_PORT, -5.0),
+            Some(0.0)
+        );
+        assert_eq!(
+            trigger.set_control_input(Envelope::DECAY_PORT, 15.0),
+            Some(15.0)
+        );
+        assert_eq!(
+            trigger.set_control_input(Envelope::RELEASE_PORT, 250.0),
+            Some(250.0)
+        );
+        assert_eq!(trigger.set_control_input(4, 1.0), None);
+        assert_eq!(
+            trigger.envelope(),
+            Some(Envelope {
+                attack_ms: 0.0,
+                decay_ms: 15.0,
+                sustain: 1.0,
+                release_ms: 250.0,
+            })
+        );
+    }
+
+    /// Get the level of `trigger` right after a note on with `velocity`.
+    fn level_for_velocity(mut trigger: SampleTrigger, velocity: u8) -> f32 {
+        play(&mut trigger, 1, &[(0, note_on(wmidi::Note::C4, velocity))])[0]
+    }
+
+    #[test]
+    fn velocity_scales_the_level_linearly() {
+        let trigger = SampleTrigger::new(full_level(100), SAMPLE_RATE);
+        assert_eq!(trigger.velocity_curve(), VelocityCurve::Linear);
+        let loud = level_for_velocity(trigger.clone(), 127);
+        let soft = level_for_velocity(trigger, 64);
+        assert!((loud - 1.0).abs() < 1e-6);
+        assert!((soft / loud - 0.5).abs() < 0.01, "{soft} / {loud}");
+    }
+
+    #[test]
+    fn exponential_velocity_is_quieter_for_soft_notes() {
+        let trigger = SampleTrigger::new(full_level(100), SAMPLE_RATE)
+            .with_velocity_curve(VelocityCurve::Exponential);
+        assert!((level_for_velocity(trigger.clone(), 127) - 1.0).abs() < 1e-6);
+        let soft = level_for_velocity(trigger.clone(), 64);
+        assert!(soft > 0.0 && soft < 0.1, "{soft}");
+        let softest = level_for_velocity(trigger, 1);
+        // The softest note is about 48dB below the loudest note.
+        assert!((20.0 * softest.log10() + 48.0).abs() < 0.5, "{softest}");
+    }
+
+    #[test]
+    fn velocity_is_captured_when_the_sample_is_triggered() {
+        let mut trigger = SampleTrigger::new(full_level(100), SAMPLE_RATE);
+        let output = play(
+            &mut trigger,
+            30,
+            &[
+                (0, note_on(wmidi::Note::C4, 64)),
+                (10, note_off(wmidi::Note::C4)),
+                (20, note_on(wmidi::Note::C4, 127)),
+            ],
+        );
+        let soft = VelocityCurve::Linear.gain(wmidi::U7::try_from(64).unwrap());
+        assert_close(
+            &output,
+            std::iter::repeat_n(soft, 20).chain(std::iter::repeat_n(1.0, 10)),
+        );
+    }
+
+    /// Count the times `signal` crosses zero on the way up.
+    fn upward_zero_crossings(signal: &[f32]) -> usize {
+        signal
+            .windows(2)
+            .filter(|w| w[0] < 0.0 && w[1] >= 0.0)
+            .count()
+    }
+
+    /// Trigger a looping second of a 1kHz tone with a root note of C4 by
+    /// `note` and get the frequency of the output.
+    fn frequency_of_tone_for(note: wmidi::Note) -> usize {
+        let sample_rate = 48000.0;
+        let tone: Vec<f32> = (0..48000)
+            .map(|i| (2.0 * std::f64::consts::PI * 1000.0 * i as f64 / sample_rate).sin() as f32)
+            .collect();
+        let mut trigger =
+            SampleTrigger::new(Arc::new(AudioBuffer::from_channels(&[tone])), sample_rate)
+                .with_root_note(Some(wmidi::Note::C4))
+                .with_looping(true);
+        let output = play(&mut trigger, 48000, &[(0, note_on(note, 127))]);
+        upward_zero_crossings(&output)
+    }
+
+    #[test]
+    fn root_note_plays_the_original_pitch() {
+        assert!(frequency_of_tone_for(wmidi::Note::C4).abs_diff(1000) <= 1);
+    }
+
+    #[test]
+    fn octave_up_doubles_the_pitch() {
+        assert!(frequency_of_tone_for(wmidi::Note::C5).abs_diff(2000) <= 1);
+        assert!(frequency_of_tone_for(wmidi::Note::C3).abs_diff(500) <= 1);
+        // A fifth up is 2^(7/12) times the frequency.
+        assert!(frequency_of_tone_for(wmidi::Note::G4).abs_diff(1498) <= 1);
+    }
+
+    #[test]
+    fn fractional_positions_are_interpolated() {
+        let mut trigger =
+            SampleTrigger::new(ramp(100), SAMPLE_RATE).with_root_note(Some(wmidi::Note::C5));
+        // An octave down plays at half speed.
+        let output = play(&mut trigger, 8, &[(0, note_on(wmidi::Note::C4, 127))]);
+        assert_close(&output, (0..8).map(|i| i as f32 / 2.0));
+    }
+
+    #[test]
+    fn without_a_root_note_every_note_plays_the_original_pitch() {
+        let mut trigger = SampleTrigger::new(ramp(100), SAMPLE_RATE);
+        let output = play(&mut trigger, 8, &[(0, note_on(wmidi::Note::C6, 127))]);
+        assert_close(&output, (0..8).map(|i| i as f32));
+    }
+
+    #[test]
+    fn loop_shorter_than_the_block_wraps_within_the_block() {
+        let mut trigger = SampleTrigger::new(ramp(100), SAMPLE_RATE)
+            .with_looping(true)
+            .with_loop_points(0, Some(10));
+        let output = play(&mut trigger, 64, &[(0, note_on(wmidi::Note::C4, 127))]);
+        assert_close(&output, (0..64).map(|i| (i % 10) as f32));
+        assert!(trigger.is_active());
+    }
+
+    #[test]
+    fn loop_seam_is_crossfaded() {
+        let mut trigger = SampleTrigger::new(ramp(100), SAMPLE_RATE)
+            .with_looping(true)
+            .with_loop_points(20, Some(30));
+        let output = play(&mut trigger, 100, &[(0, note_on(wmidi::Note::C4, 127))]);
+        // The crossfade is limited to half of the loop. Over the last 5 frames
+        // of the loop, the frames before the loop start fade in so the loop
+        // end ramps back down to the loop start.
+        let expected = (0..100).map(|frame| {
+            let position = if frame < 30 {
+                frame
+            } else {
+                20 + (frame - 20) % 10
+            };
+            if position > 25 {
+                50.0 - position as f32
+            } else {
+                position as f32
+            }
+        });
+        assert_close(&output, expected);
+        assert!(output.windows(2).all(|w| (w[1] - w[0]).abs() < 1.0 + 1e-4));
+    }
+
+    #[test]
+    fn looping_sample_stops_after_the_note_off() {
+        let fade = ms_to_frames(GATE_FADE.as_secs_f32() * 1000.0, SAMPLE_RATE);
+        let mut trigger = SampleTrigger::new(full_level(10), SAMPLE_RATE).with_looping(true);
+        let output = play(
+            &mut trigger,
+            1000,
+            &[
+                (0, note_on(wmidi::Note::C4, 127)),
+                (500, note_off(wmidi::Note::C4)),
+            ],
+        );
+        assert_close(
+            &output,
+            std::iter::repeat_n(1.0, 500)
+                .chain((0..fade).map(|i| (fade - i) as f32 / fade as f32))
+                .chain(std::iter::repeat_n(0.0, 500 - fade)),
+        );
+        assert!(!trigger.is_active());
+    }
+
+    #[test]
+    fn loop_points_are_clamped_to_the_sample() {
+        let trigger = SampleTrigger::new(ramp(100), SAMPLE_RATE);
+        assert_eq!(trigger.loop_points(), (0, 100));
+        assert_eq!(
+            trigger
+                .clone()
+                .with_loop_points(50, Some(1000))
+                .loop_points(),
+            (50, 100)
+        );
+        assert_eq!(
+            trigger.with_loop_points(500, None).loop_points(),
+            (100, 100)
+        );
+    }
 }
@@ -0,0 +1,52 @@
+use std::{
+    sync::{Arc, OnceLock},
+    time::Instant,
+};
+
+use log::*;
+
+/// The lv2 plugins that are installed. Scanning the lv2 path may take several
+/// seconds when many plugins are installed, so it runs on a background thread
+/// and the plugins become available once it finishes.
+#[derive(Clone, Debug, Default)]
+pub struct PluginScan {
+    world: Arc<OnceLock<Arc<livi::World>>>,
+}
+
+impl PluginScan {
+    /// Start scanning the lv2 path on a background thread.
+    pub fn start() -> PluginScan {
+        let scan = PluginScan::default();
+        let world = scan.world.clone();
+        std::thread::spawn(move || {
+            let start = Instant::now();
+            let livi = livi::World::new();
+            info!(
+                "Found {} lv2 plugins in {:?}.",
+                livi.iter_plugins().count(),
+                start.elapsed()
+            );
+            // The world is only ever set here.
+            let _ = world.set(Arc::new(livi));
+        });
+        scan
+    }
+
+    /// Create a scan that has already finished with the plugins of `world`.
+    pub fn with_world(world: livi::World) -> PluginScan {
+        let scan = PluginScan::default();
+        let _ = scan.world.set(Arc::new(world));
+        scan
+    }
+
+    /// Get the world with the installed plugins, or `None` if the scan has
+    /// not finished.
+    pub fn world(&self) -> Option<&Arc<livi::World>> {
+        self.world.get()
+    }
+
+    /// Returns true if the scan has finished.
+    pub fn is_ready(&self) -> bool {
+        self.world.get().is_some()
+    }
+}
@@ -0,0 +1,92 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use ringbuf::{HeapConsumer, HeapProducer, HeapRb};
+
+use crate::audio_buffer::AudioBuffer;
+
+/// Streams stereo audio from the processing thread to a consumer on another
+/// thread without blocking or allocating.
+pub struct MasterRecorder {
+    producer: HeapProducer<f32>,
+    frames_written: u64,
+    overruns: u64,
+    frames_since_stats: usize,
+    /// Set once the processor has stopped recording.
+    finished: Arc<AtomicBool>,
+}
+
+impl MasterRecorder {
+    /// Create a new recorder that can buffer `capacity` frames. The returned
+    /// consumer receives interleaved stereo samples.
+    pub fn new(capacity: usize) -> (MasterRecorder, HeapConsumer<f32>) {
+        let (producer, consumer) = HeapRb::new(capacity * 2).split();
+        let recorder = MasterRecorder {
+            producer,
+            frames_written: 0,
+            overruns: 0,
+            frames_since_stats: 0,
+            finished: Arc::new(AtomicBool::new(false)),
+        };
+        (recorder, consumer)
+    }
+
+    /// Push the first `samples` frames of `audio`. Frames that do not fit in
+    /// the buffer are dropped and counted as overruns.
+    pub fn record(&mut self, samples: usize, audio: &AudioBuffer) {
+        let mut channels = audio.iter_channels();
+        let left = match channels.next() {
+            Some(c) => c,
+            None => return,
+        };
+        let right = channels.next().unwrap_or(left);
+        for frame in 0..samples {
+            if self.producer.free_len() < 2 {
+                self.overruns += (samples - frame) as u64;
+                break;
+            }
+            let _ = self.producer.push(left[frame]);
+            let _ = self.producer.push(right[frame]);
+            self.frames_written += 1;
+        }
+        self.frames_since_stats += samples;
+    }
+
+    /// Returns the number of frames written and dropped if at least `interval`
+    /// frames have been recorded since the last time stats were returned.
+    pub fn stats_due(&mut self, interval: usize) -> Option<(u64, u64)> {
+        if self.frames_since_stats < interval {
+            return None;
+        }
+        self.frames_since_stats = 0;
+        Some(self.stats())
+    }
+
+    /// Returns the number of frames written and dropped.
+    pub fn stats(&self) -> (u64, u64) {
+        (self.frames_written, self.overruns)
+    }
+
+    /// Get the flag that is set by `finish`. Once it is set, no more frames
+    /// are pushed and the consumer can be drained for the last time.
+    pub fn finished_flag(&self) -> Arc<AtomicBool> {
+        self.finished.clone()
+    }
+
+    /// Mark the recording as finished. This should be called by the processor
+    /// once it stops recording.
+    pub fn finish(&self) {
+        self.finished.store(true, Ordering::Release);
+    }
+}
+
+impl std::fmt::Debug for MasterRecorder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MasterRecorder")
+            .field("frames_written", &self.frames_written)
+            .field("overruns", &self.overruns)
+            .finish()
+    }
+}
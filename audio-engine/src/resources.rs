@@ -0,0 +1,5 @@
+/// The default metronome click.
+pub const CLICK_WAV: &[u8] = include_bytes!("../../resources/click.wav");
+
+/// The default sound played to acknowledge an action.
+pub const BEEP_WAV: &[u8] = include_bytes!("../../resources/beep.wav");
@@ -1,7 +1,25 @@
 use livi::event::LV2AtomSequence;
 use log::*;
 
-use crate::{audio_buffer::AudioBuffer, plugin::PluginInstance};
+use crate::{
+    audio_buffer::AudioBuffer,
+    clip::{self, Clip},
+    freeze::FrozenAudio,
+    metronome::SampleTimeInfo,
+    midi_output::MidiOutput,
+    plugin::{PluginInstance, PluginProcessError},
+};
+
+/// The id of the master track that the mix is run through. Ids of other
+/// tracks are positive.
+pub const MASTER_TRACK_ID: i32 = -2;
+
+/// The most notes that may be triggered on a track at once.
+const MAX_TRIGGERED_NOTES: usize = 16;
+
+/// The number of consecutive blocks in which a plugin may fail before the
+/// track is disabled. Some plugins only fail on the first block.
+const MAX_FAILED_BLOCKS: usize = 2;
 
 /// A single audio chain.
 #[derive(Debug)]
@@ -12,6 +30,32 @@ pub struct Track {
 
     id: i32,
     plugins: Vec<PluginInstance>,
+    clip: Option<Clip>,
+    /// The clip to switch to at the next loop boundary of `clip`.
+    next_clip: Option<Clip>,
+    /// The clip that was replaced by `next_clip`. It is held until it is
+    /// taken so that it is not dropped while processing.
+    finished_clip: Option<Clip>,
+    /// The audio to play instead of running the plugins if the track is
+    /// frozen.
+    frozen: Option<FrozenAudio>,
+    /// If true, all notes off is sent to the plugins the next time the track
+    /// is processed.
+    notes_off_pending: bool,
+    /// The notes that are sounding after transposition as a bitset of pitches
+    /// for each channel.
+    held_notes: [u128; 16],
+    /// If true, the held notes are released the next time midi is
+    /// transposed.
+    release_held_notes: bool,
+    /// The notes started by `trigger_note` that have not been released. This
+    /// is never grown.
+    triggered_notes: Vec<TriggeredNote>,
+    /// The number of consecutive blocks in which a plugin failed.
+    failed_blocks: usize,
+    /// The index of the plugin and the error that disabled the track if it
+    /// has not been taken with `take_plugin_error`.
+    plugin_error: Option<(usize, PluginProcessError)>,
     audio_input: AudioBuffer,
     audio_output: AudioBuffer,
 }
@@ -23,8 +67,27 @@ pub struct TrackProperties {
     pub disabled: bool,
     /// The volume multiplier.
     pub volume: f32,
-    /// If true, the track is armed.
+    /// If true, the track is armed and its input is recorded.
     pub armed: bool,
+    /// If true, live midi input is sent to the plugins of the track.
+    pub monitoring: bool,
+    /// The midi input that live input is taken from. If `None`, the inputs that
+    /// no track is routed to are used.
+    pub midi_source: Option<usize>,
+    /// The midi channel, from 0 to 15, of the live input that is sent to the
+    /// track. If `None`, all channels are sent. Messages that are not
+    /// channel messages are always sent.
+    pub midi_channel: Option<u8>,
+    /// The number of semitones, from -24 to 24, to shift notes by before they
+    /// reach the plugins.
+    pub transpose: i8,
+    /// If true, the midi that the plugins produce is sent to the midi output.
+    pub send_midi: bool,
+    /// If true, the track is soloed. When any track is soloed, only soloed
+    /// tracks are mixed.
+    pub solo: bool,
+    /// The stereo panning between -1.0 (left) and 1.0 (right).
+    pub pan: f32,
 }
 
 impl Default for TrackProperties {
@@ -33,10 +96,29 @@ impl Default for TrackProperties {
             disabled: false,
             volume: 0.5,
             armed: false,
+            monitoring: false,
+            midi_source: None,
+            midi_channel: None,
+            transpose: 0,
+            send_midi: false,
+            solo: false,
+            pan: 0.0,
         }
     }
 }
 
+/// A note started by `Track::trigger_note`.
+#[derive(Clone, Debug)]
+struct TriggeredNote {
+    /// The note on to send at the start of the next block, if it has not been
+    /// sent yet.
+    note_on: Option<wmidi::MidiMessage<'static>>,
+    /// The note off that releases the note.
+    note_off: wmidi::MidiMessage<'static>,
+    /// The number of frames until the note off is sent.
+    remaining: usize,
+}
+
 impl Track {
     /// Create a new track.
     pub fn new(id: i32, buffer_size: usize) -> Track {
@@ -44,6 +126,16 @@ impl Track {
             properties: TrackProperties::default(),
             id,
             plugins: Vec::with_capacity(16),
+            clip: None,
+            next_clip: None,
+            finished_clip: None,
+            frozen: None,
+            notes_off_pending: false,
+            held_notes: [0; 16],
+            release_held_notes: false,
+            triggered_notes: Vec::with_capacity(MAX_TRIGGERED_NOTES),
+            failed_blocks: 0,
+            plugin_error: None,
             audio_input: AudioBuffer::with_stereo(buffer_size),
             audio_output: AudioBuffer::with_stereo(buffer_size),
         }
@@ -54,11 +146,147 @@ impl Track {
         self.id
     }
 
+    /// Resize the buffers to hold `buffer_size` frames. This allocates and
+    /// should not be called while processing.
+    pub fn set_buffer_size(&mut self, buffer_size: usize) {
+        self.audio_input.reset_with_buffer_size(buffer_size);
+        self.audio_output.reset_with_buffer_size(buffer_size);
+        for plugin in self.plugins.iter_mut() {
+            plugin.set_buffer_size(buffer_size);
+        }
+    }
+
+    /// Take the index of the plugin and the error that disabled the track. This
+    /// returns `Some` once each time the track is disabled.
+    pub fn take_plugin_error(&mut self) -> Option<(usize, PluginProcessError)> {
+        self.plugin_error.take()
+    }
+
+    /// Run the plugins of a track that was disabled by a plugin failure
+    /// again.
+    pub fn enable(&mut self) {
+        self.properties.disabled = false;
+        self.failed_blocks = 0;
+        self.plugin_error = None;
+    }
+
+    /// Send all notes off and all sound off to the plugins the next time the
+    /// track is processed.
+    pub fn request_notes_off(&mut self) {
+        self.notes_off_pending = true;
+        self.triggered_notes.clear();
+    }
+
+    /// Returns true if all notes off was requested since the last call.
+    pub fn take_notes_off_request(&mut self) -> bool {
+        std::mem::take(&mut self.notes_off_pending)
+    }
+
+    /// Set the number of semitones to shift notes by. Notes that are held
+    /// are released at their old pitch so that they do not get stuck.
+    pub fn set_transpose(&mut self, transpose: i8) {
+        if self.properties.transpose != transpose {
+            self.properties.transpose = transpose;
+            self.release_held_notes = true;
+        }
+    }
+
+    /// Write the events of `input` into `seq` with notes shifted by the
+    /// transpose of the track. Returns false if `input` may be used as is, in
+    /// which case `seq` is left untouched.
+    pub fn transpose_midi(
+        &mut self,
+        input: &LV2AtomSequence,
+        midi_urid: lv2_raw::LV2Urid,
+        seq: &mut LV2AtomSequence,
+    ) -> bool {
+        let transpose = self.properties.transpose;
+        if transpose == 0 && !self.release_held_notes {
+            for event in input.iter() {
+                if let Ok(message) = wmidi::MidiMessage::try_from(event.data) {
+                    update_held_notes(&mut self.held_notes, &message);
+                }
+            }
+            return false;
+        }
+        seq.clear();
+        if std::mem::take(&mut self.release_held_notes) {
+            for (channel, held) in self.held_notes.iter_mut().enumerate() {
+                for pitch in 0..128u8 {
+                    if *held & (1 << pitch) == 0 {
+                        continue;
+                    }
+                    let message = wmidi::MidiMessage::NoteOff(
+                        wmidi::Channel::from_index(channel as u8).unwrap(),
+                        wmidi::Note::from_u8_lossy(pitch),
+                        wmidi::Velocity::MIN,
+                    );
+                    if let Err(err) = clip::push_message(seq, midi_urid, 0, &message) {
+                        warn!("Dropping note off: {:?}", err);
+                    }
+                }
+                *held = 0;
+            }
+        }
+        for event in input.iter() {
+            let frame = event.event.time_in_frames;
+            let res = match wmidi::MidiMessage::try_from(event.data) {
+                Ok(wmidi::MidiMessage::NoteOn(channel, note, velocity)) => {
+                    let message = wmidi::MidiMessage::NoteOn(
+                        channel,
+                        transpose_note(note, transpose),
+                        velocity,
+                    );
+                    update_held_notes(&mut self.held_notes, &message);
+                    clip::push_message(seq, midi_urid, frame as usize, &message)
+                }
+                Ok(wmidi::MidiMessage::NoteOff(channel, note, velocity)) => {
+                    let message = wmidi::MidiMessage::NoteOff(
+                        channel,
+                        transpose_note(note, transpose),
+                        velocity,
+                    );
+                    update_held_notes(&mut self.held_notes, &message);
+                    clip::push_message(seq, midi_urid, frame as usize, &message)
+                }
+                _ => seq.push_midi_event::<4>(frame, midi_urid, event.data),
+            };
+            if let Err(err) = res {
+                warn!("Dropping midi message: {:?}", err);
+            }
+        }
+        true
+    }
+
     /// Push a new plugin.
     pub fn push_plugin(&mut self, plugin: PluginInstance) {
         self.plugins.push(plugin);
     }
 
+    /// Insert a plugin at `index`, or at the end if `index` is past the end.
+    pub fn insert_plugin(&mut self, index: usize, plugin: PluginInstance) {
+        self.plugins.insert(index.min(self.plugins.len()), plugin);
+    }
+
+    /// Move the plugin at index `from` to index `to`. Nothing is moved if
+    /// either index is out of range.
+    pub fn move_plugin(&mut self, from: usize, to: usize) {
+        if from < self.plugins.len() && to < self.plugins.len() {
+            let plugin = self.plugins.remove(from);
+            self.plugins.insert(to, plugin);
+        }
+    }
+
+    /// Get a plugin by its index.
+    pub fn plugin(&self, index: usize) -> Option<&PluginInstance> {
+        self.plugins.get(index)
+    }
+
+    /// Get a plugin by its index.
+    pub fn plugin_mut(&mut self, index: usize) -> Option<&mut PluginInstance> {
+        self.plugins.get_mut(index)
+    }
+
     /// Remove a plugin.
     pub fn remove_plugin(&mut self, index: usize) -> Option<PluginInstance> {
         if index < self.plugins.len() {
@@ -68,22 +296,328 @@ impl Track {
         }
     }
 
-    /// Run processing for the track.
-    pub fn process(&mut self, samples: usize, midi_input: &LV2AtomSequence) -> &AudioBuffer {
+    /// Get the clip that is being played back.
+    pub fn clip(&self) -> Option<&Clip> {
+        self.clip.as_ref()
+    }
+
+    /// Set the audio to play instead of running the plugins and return the
+    /// previous audio. The plugins are kept so that the track may be unfrozen
+    /// by setting `None`.
+    pub fn set_frozen(&mut self, frozen: Option<FrozenAudio>) -> Option<FrozenAudio> {
+        std::mem::replace(&mut self.frozen, frozen)
+    }
+
+    /// Returns true if the track is frozen.
+    pub fn is_frozen(&self) -> bool {
+        self.frozen.is_some()
+    }
+
+    /// Set the clip to play back. Returns the previous clip and the clip that
+    /// was queued to play next.
+    pub fn set_clip(&mut self, clip: Clip) -> (Option<Clip>, Option<Clip>) {
+        (self.clip.replace(clip), self.next_clip.take())
+    }
+
+    /// Set the clip to play back starting from the next loop boundary of the
+    /// current clip. If there is no current clip, then it is used immediately.
+    /// Returns the clip that was previously queued.
+    pub fn queue_clip(&mut self, clip: Clip) -> Option<Clip> {
+        if self.clip.is_some() {
+            self.next_clip.replace(clip)
+        } else {
+            self.clip = Some(clip);
+            None
+        }
+    }
+
+    /// Take the clip that was replaced by the queued clip during
+    /// `merge_clip_events`.
+    pub fn take_finished_clip(&mut self) -> Option<Clip> {
+        self.finished_clip.take()
+    }
+
+    /// Play `note` at the start of the next block and release it after
+    /// `frames` frames. Returns false if too many notes are already
+    /// triggered.
+    pub fn trigger_note(
+        &mut self,
+        note: wmidi::Note,
+        velocity: wmidi::Velocity,
+        frames: usize,
+    ) -> bool {
+        if self.triggered_notes.len() == self.triggered_notes.capacity() {
+            return false;
+        }
+        self.triggered_notes.push(TriggeredNote {
+            note_on: Some(wmidi::MidiMessage::NoteOn(
+                wmidi::Channel::Ch1,
+                note,
+                velocity,
+            )),
+            note_off: wmidi::MidiMessage::NoteOff(wmidi::Channel::Ch1, note, wmidi::Velocity::MIN),
+            remaining: frames,
+        });
+        true
+    }
+
+    /// Write the triggered note ons and note offs that fall within the next
+    /// `samples` frames and the events of `input` into `seq`. Returns false if
+    /// no notes are triggered, in which case `seq` is left untouched.
+    pub fn merge_triggered_notes(
+        &mut self,
+        samples: usize,
+        input: &LV2AtomSequence,
+        midi_urid: lv2_raw::LV2Urid,
+        seq: &mut LV2AtomSequence,
+    ) -> bool {
+        if self.triggered_notes.is_empty() {
+            return false;
+        }
+        self.triggered_notes.sort_unstable_by_key(|n| n.remaining);
+        let note_ons = self
+            .triggered_notes
+            .iter()
+            .filter_map(|n| n.note_on.as_ref().map(|m| (0, m)));
+        let note_offs = self
+            .triggered_notes
+            .iter()
+            .filter(|n| n.remaining < samples)
+            .map(|n| (n.remaining, &n.note_off));
+        clip::merge_into(seq, midi_urid, input, note_ons.chain(note_offs));
+        self.triggered_notes.retain_mut(|n| {
+            n.note_on = None;
+            match n.remaining.checked_sub(samples) {
+                Some(remaining) => {
+                    n.remaining = remaining;
+                    true
+                }
+                None => false,
+            }
+        });
+        true
+    }
+
+    /// Write the clip events within the block described by `time_info` and the
+    /// events of `input` into `seq`. Returns false if the track has no clip, in
+    /// which case `seq` is left untouched.
+    pub fn merge_clip_events(
+        &mut self,
+        time_info: &[SampleTimeInfo],
+        input: &LV2AtomSequence,
+        midi_urid: lv2_raw::LV2Urid,
+        seq: &mut LV2AtomSequence,
+    ) -> bool {
+        let current = match self.clip.as_mut() {
+            Some(c) => c,
+            None => return false,
+        };
+        let start = time_info.first().map(|t| t.to_beats()).unwrap_or(0.0);
+        let end = time_info.last().map(|t| t.to_beats()).unwrap_or(0.0);
+        let boundary = current.next_loop_start(start);
+        match self.next_clip.as_mut() {
+            // The switch waits while the previous finished clip has not been
+            // taken so that it is never dropped here.
+            Some(next) if end >= boundary && self.finished_clip.is_none() => {
+                current.schedule(time_info, f64::NEG_INFINITY, boundary);
+                next.schedule(time_info, boundary, f64::INFINITY);
+                clip::merge_into(
+                    seq,
+                    midi_urid,
+                    input,
+                    current.iter_scheduled().chain(next.iter_scheduled()),
+                );
+                self.finished_clip = std::mem::replace(&mut self.clip, self.next_clip.take());
+            }
+            _ => {
+                current.schedule(time_info, f64::NEG_INFINITY, f64::INFINITY);
+                clip::merge_into(seq, midi_urid, input, current.iter_scheduled());
+            }
+        }
+        true
+    }
+
+    /// Play the frozen audio for the block described by `time_info`. Silence
+    /// is produced if the track is not frozen or if not `playing`.
+    pub fn process_frozen(
+        &mut self,
+        samples: usize,
+        time_info: &[SampleTimeInfo],
+        playing: bool,
+    ) -> &AudioBuffer {
+        self.audio_output.reset_with_buffer_size(samples);
+        if let Some(frozen) = self.frozen.as_ref().filter(|_| playing) {
+            frozen.process(samples, time_info, &mut self.audio_output);
+        }
+        &self.audio_output
+    }
+
+    /// Run processing for the track. If `send_midi` is set, the midi that the
+    /// plugins produce is added to `midi_output`.
+    pub fn process(
+        &mut self,
+        samples: usize,
+        midi_input: &LV2AtomSequence,
+        midi_output: &mut MidiOutput,
+    ) -> &AudioBuffer {
+        self.audio_output.reset_with_buffer_size(samples);
+        self.run_plugins(samples, midi_input, midi_output)
+    }
+
+    /// Run processing for the track with `input` fed into the first plugin.
+    /// If the track has no plugins, then `input` is returned unchanged.
+    pub fn process_with_input(
+        &mut self,
+        samples: usize,
+        input: &AudioBuffer,
+        midi_input: &LV2AtomSequence,
+        midi_output: &mut MidiOutput,
+    ) -> &AudioBuffer {
         self.audio_output.reset_with_buffer_size(samples);
+        self.audio_output.mix_from(input, 1.0);
+        self.run_plugins(samples, midi_input, midi_output)
+    }
+
+    /// Run the plugins in order starting from the audio in `audio_output`. The
+    /// midi output of a plugin replaces the midi input of the plugins after
+    /// it so that midi effects may be placed before instruments.
+    ///
+    /// If a plugin fails in `MAX_FAILED_BLOCKS` consecutive blocks, the track
+    /// is disabled and the first failure is kept for `take_plugin_error`.
+    fn run_plugins(
+        &mut self,
+        samples: usize,
+        midi_input: &LV2AtomSequence,
+        midi_output: &mut MidiOutput,
+    ) -> &AudioBuffer {
         self.audio_input.reset_with_buffer_size(samples);
-        for plugin in self.plugins.iter_mut() {
+        let mut chained = false;
+        let mut failure = None;
+        for (plugin_index, plugin) in self.plugins.iter_mut().enumerate() {
             std::mem::swap(&mut self.audio_input, &mut self.audio_output);
+            let (chained_input, plugin_output) = midi_output.plugin_buffers();
+            let input = if chained { chained_input } else { midi_input };
             if let Err(err) = plugin.process(
                 samples,
-                midi_input,
-                &self.audio_input,
+                input,
+                plugin_output,
+                &mut self.audio_input,
                 &mut self.audio_output,
             ) {
-                error!("Disabing due to plugin failure: {:?} {:?}", plugin, err);
-                self.properties.disabled = true;
+                failure.get_or_insert((plugin_index, err));
+            }
+            if self.properties.send_midi {
+                midi_output.collect_plugin_output();
+            }
+            if plugin.has_midi_output() {
+                midi_output.chain_plugin_output();
+                chained = true;
+            }
+        }
+        match failure {
+            None => self.failed_blocks = 0,
+            Some(failure) => {
+                self.failed_blocks += 1;
+                if self.failed_blocks >= MAX_FAILED_BLOCKS {
+                    self.properties.disabled = true;
+                    self.plugin_error = Some(failure);
+                }
             }
         }
         &self.audio_output
     }
 }
+
+/// Shift `note` by `transpose` semitones, clamping to the range of midi notes.
+fn transpose_note(note: wmidi::Note, transpose: i8) -> wmidi::Note {
+    let pitch = (u8::from(note) as i16 + transpose as i16).clamp(0, 127);
+    wmidi::Note::from_u8_lossy(pitch as u8)
+}
+
+/// Update the bitset of held pitches for each channel with `message`.
+fn update_held_notes(held_notes: &mut [u128; 16], message: &wmidi::MidiMessage) {
+    match message {
+        wmidi::MidiMessage::NoteOn(channel, note, velocity) if u8::from(*velocity) > 0 => {
+            held_notes[channel.index() as usize] |= 1 << u8::from(*note);
+        }
+        wmidi::MidiMessage::NoteOn(channel, note, _)
+        | wmidi::MidiMessage::NoteOff(channel, note, _) => {
+            held_notes[channel.index() as usize] &= !(1 << u8::from(*note));
+        }
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{gain::Gain, plugin::SampleTrigger};
+
+    fn features() -> Arc<livi::Features> {
+        livi::FeaturesBuilder {
+            min_block_length: 1,
+            max_block_length: 128,
+        }
+        .build(&livi::World::with_plugin_predicate(|_| false))
+    }
+
+    /// A midi effect that softens every note on to half velocity.
+    fn soften(message: wmidi::MidiMessage<'static>) -> wmidi::MidiMessage<'static> {
+        match message {
+            wmidi::MidiMessage::NoteOn(channel, note, _) => {
+                wmidi::MidiMessage::NoteOn(channel, note, wmidi::U7::from_u8_lossy(127 / 2))
+            }
+            message => message,
+        }
+    }
+
+    /// Create a sampler that plays a constant 1.0 for each note on, scaled by
+    /// its velocity.
+    fn sampler() -> PluginInstance {
+        let sample = AudioBuffer::from_channels(&[vec![1.0; 256]]);
+        SampleTrigger::new(Arc::new(sample), 44100.0).into()
+    }
+
+    /// Play a note on at frame 0 through `plugins` and return the level of
+    /// the first frame.
+    fn play_note(plugins: Vec<PluginInstance>) -> f32 {
+        let features = features();
+        let mut track = Track::new(1, 128);
+        for plugin in plugins {
+            track.push_plugin(plugin);
+        }
+        let mut midi = LV2AtomSequence::new(&features, 1024);
+        midi.push_midi_event::<3>(0, features.midi_urid(), &[0x90, 60, 127])
+            .unwrap();
+        let mut midi_output = MidiOutput::new(&features, 16);
+        let output = track.process(128, &midi, &mut midi_output);
+        let level = output.iter_channels().next().unwrap()[0];
+        level
+    }
+
+    #[test]
+    fn midi_output_feeds_the_next_plugins() {
+        let softened = 63.0 / 127.0;
+        assert_eq!(play_note(vec![sampler()]), 1.0);
+        assert_eq!(
+            play_note(vec![PluginInstance::MidiEffect(soften), sampler()]),
+            softened
+        );
+        // Plugins without midi outputs pass the chained midi through.
+        assert_eq!(
+            play_note(vec![
+                PluginInstance::MidiEffect(soften),
+                Gain::default().into(),
+                sampler()
+            ]),
+            softened
+        );
+        // Effects only change the midi of the plugins after them.
+        assert_eq!(
+            play_note(vec![sampler(), PluginInstance::MidiEffect(soften)]),
+            1.0
+        );
+    }
+}
@@ -0,0 +1,40 @@
+/// The playback state of the engine.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Transport {
+    /// Time does not advance.
+    #[default]
+    Stopped,
+    /// Time advances towards the start while the metronome counts in. Midi
+    /// input is discarded.
+    CountingIn,
+    /// Time advances.
+    Playing,
+}
+
+impl Transport {
+    /// Returns true if the transport is playing.
+    pub fn is_playing(self) -> bool {
+        matches!(self, Transport::CountingIn | Transport::Playing)
+    }
+
+    /// Returns true if the transport is counting in.
+    pub fn is_counting_in(self) -> bool {
+        matches!(self, Transport::CountingIn)
+    }
+}
+
+/// The state of a transport that is shared with other applications, such as
+/// the JACK transport.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SharedTransport {
+    /// True if the shared transport is rolling.
+    pub playing: bool,
+    /// The position in beats from the start at the first frame of the block.
+    /// If `None`, the shared transport has no musical position and the
+    /// position is derived from `frame` and the current tempo.
+    pub beats: Option<f64>,
+    /// The frame of the shared transport at the first frame of the block.
+    pub frame: u64,
+    /// The tempo published by the shared transport.
+    pub beats_per_minute: Option<f64>,
+}
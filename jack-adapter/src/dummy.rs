@@ -0,0 +1,247 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use audio_engine::{plugin_scan::PluginScan, Communicator, ProcessorConfig};
+use log::*;
+
+use crate::AudioAdapter;
+
+/// Runs the audio engine on a thread paced like an audio device without
+/// connecting to any devices. The output is discarded and there is no audio
+/// or midi input. This is useful for development and for machines without
+/// JACK.
+pub struct DummyAdapter {
+    pub audio_engine: Communicator,
+    /// The names of the midi inputs. Nothing is received on them.
+    midi_input_names: Vec<String>,
+    sample_rate: f64,
+    buffer_size: usize,
+    /// Signals the processing thread to stop.
+    stop: Arc<AtomicBool>,
+    /// The thread running the processor.
+    thread: Option<std::thread::JoinHandle<()>>,
+    /// The bits of the last cpu load percentage as an `f32`.
+    cpu_load: Arc<AtomicU32>,
+    /// The number of blocks that were processed too late.
+    xruns: Arc<AtomicU64>,
+    /// The configuration used to create the processor.
+    config: ProcessorConfig,
+}
+
+impl DummyAdapter {
+    /// Create a new audio engine that processes `buffer_size` frames at a
+    /// time at `sample_rate`. The lv2 plugins are scanned in the background.
+    pub fn new(config: &ProcessorConfig, sample_rate: f64, buffer_size: usize) -> DummyAdapter {
+        DummyAdapter::with_plugins(config, sample_rate, buffer_size, PluginScan::start())
+    }
+
+    /// Create a new audio engine that uses the plugins of an existing scan.
+    pub fn with_plugins(
+        config: &ProcessorConfig,
+        sample_rate: f64,
+        buffer_size: usize,
+        plugins: PluginScan,
+    ) -> DummyAdapter {
+        let (mut processor, communicator) =
+            audio_engine::Processor::new(sample_rate, buffer_size, config, plugins);
+        let stop = Arc::new(AtomicBool::new(false));
+        let cpu_load = Arc::new(AtomicU32::new(0f32.to_bits()));
+        let xruns = Arc::new(AtomicU64::new(0));
+        let period = Duration::from_secs_f64(buffer_size as f64 / sample_rate);
+        let thread = {
+            let stop = stop.clone();
+            let cpu_load = cpu_load.clone();
+            let xruns = xruns.clone();
+            std::thread::spawn(move || {
+                audio_engine::denormals::flush_denormals_to_zero();
+                let silence = vec![0f32; buffer_size];
+                let input = [silence.as_slice(), silence.as_slice()];
+                let mut deadline = Instant::now() + period;
+                while !stop.load(Ordering::Relaxed) {
+                    let start = Instant::now();
+                    processor.process(buffer_size, std::iter::empty(), &input);
+                    let load = start.elapsed().as_secs_f32() / period.as_secs_f32() * 100.0;
+                    cpu_load.store(load.to_bits(), Ordering::Relaxed);
+                    let now = Instant::now();
+                    if now > deadline {
+                        xruns.fetch_add(1, Ordering::Relaxed);
+                        deadline = now;
+                    } else {
+                        std::thread::sleep(deadline - now);
+                    }
+                    deadline += period;
+                }
+            })
+        };
+        info!(
+            "Started dummy audio backend at {} Hz with {} frames per block.",
+            sample_rate, buffer_size
+        );
+        DummyAdapter {
+            audio_engine: communicator,
+            midi_input_names: (0..config.midi_inputs)
+                .map(|idx| format!("dummy:midi_in_{}", idx + 1))
+                .collect(),
+            sample_rate,
+            buffer_size,
+            stop,
+            thread: Some(thread),
+            cpu_load,
+            xruns,
+            config: config.clone(),
+        }
+    }
+}
+
+impl AudioAdapter for DummyAdapter {
+    fn audio_engine(&self) -> &Communicator {
+        &self.audio_engine
+    }
+
+    fn is_shut_down(&self) -> bool {
+        false
+    }
+
+    fn reconnect(&mut self) -> Result<(), jack::Error> {
+        *self = DummyAdapter::with_plugins(
+            &self.config,
+            self.sample_rate,
+            self.buffer_size,
+            self.audio_engine.plugins.clone(),
+        );
+        Ok(())
+    }
+
+    /// There are no other clients to publish the tempo to, so this does
+    /// nothing.
+    fn set_timebase_master(&mut self, _: bool) -> Result<(), jack::Error> {
+        Ok(())
+    }
+
+    fn set_timebase_beats_per_minute(&self, _: f32) {}
+
+    /// There is no shared transport, so this does nothing.
+    fn start_transport(&self) -> Result<(), jack::Error> {
+        Ok(())
+    }
+
+    /// There is no shared transport, so this does nothing.
+    fn stop_transport(&self) -> Result<(), jack::Error> {
+        Ok(())
+    }
+
+    fn physical_audio_outputs(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    fn connect_audio_outputs(&self, _: &[String]) {}
+
+    fn midi_input_names(&self) -> &[String] {
+        &self.midi_input_names
+    }
+
+    fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    fn sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    fn cpu_load(&self) -> f32 {
+        f32::from_bits(self.cpu_load.load(Ordering::Relaxed))
+    }
+
+    fn xruns(&self) -> u64 {
+        self.xruns.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for DummyAdapter {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("The dummy audio thread panicked.");
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for DummyAdapter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DummyAdapter")
+            .field("audio_engine", &self.audio_engine)
+            .field("sample_rate", &self.sample_rate)
+            .field("buffer_size", &self.buffer_size)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use audio_engine::commands::Notifications;
+
+    use super::*;
+
+    fn adapter(sample_rate: f64, buffer_size: usize) -> DummyAdapter {
+        let config = ProcessorConfig {
+            midi_inputs: 2,
+            ..ProcessorConfig::default()
+        };
+        DummyAdapter::with_plugins(&config, sample_rate, buffer_size, PluginScan::default())
+    }
+
+    /// Count the meter notifications, one per processed block, until `end`.
+    fn count_blocks(adapter: &DummyAdapter, end: Instant) -> usize {
+        let mut blocks = 0;
+        while Instant::now() < end {
+            blocks += adapter
+                .audio_engine
+                .notifications
+                .try_iter()
+                .filter(|n| matches!(n, Notifications::Meters { .. }))
+                .count();
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        blocks
+    }
+
+    #[test]
+    fn reports_its_configuration() {
+        let adapter = adapter(48000.0, 256);
+        assert_eq!(adapter.sample_rate(), 48000.0);
+        assert_eq!(adapter.buffer_size(), 256);
+        assert_eq!(
+            adapter.midi_input_names(),
+            &["dummy:midi_in_1", "dummy:midi_in_2"]
+        );
+        assert!(adapter.physical_audio_outputs().is_empty());
+        assert!(!adapter.is_shut_down());
+    }
+
+    #[test]
+    fn blocks_are_paced_like_an_audio_device() {
+        // 10ms per block.
+        let adapter = adapter(1000.0, 10);
+        let blocks = count_blocks(&adapter, Instant::now() + Duration::from_millis(200));
+        assert!(blocks > 0);
+        assert!(blocks <= 22, "processed {blocks} blocks in 200ms");
+    }
+
+    #[test]
+    fn reconnecting_restarts_the_processing() {
+        let mut adapter = adapter(1000.0, 10);
+        adapter.reconnect().unwrap();
+        assert_eq!(adapter.sample_rate(), 1000.0);
+        assert_eq!(adapter.buffer_size(), 10);
+        assert_eq!(adapter.midi_input_names().len(), 2);
+        let blocks = count_blocks(&adapter, Instant::now() + Duration::from_millis(100));
+        assert!(blocks > 0);
+    }
+}
@@ -1,23 +1,114 @@
-use audio_engine::Communicator;
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    Arc,
+};
+
+use audio_engine::{plugin_scan::PluginScan, Communicator, ProcessorConfig};
 use log::*;
+use notifications::NotificationHandler;
 use ports::Ports;
 use processor::Processor;
+use timebase::Timebase;
 
+pub mod dummy;
+pub mod notifications;
+pub mod optional;
 pub mod ports;
 pub mod processor;
+pub mod timebase;
+
+pub use dummy::DummyAdapter;
+pub use optional::OptionalJackAdapter;
+
+/// A backend that runs the audio engine and connects it to audio and midi
+/// devices.
+pub trait AudioAdapter: std::fmt::Debug {
+    /// Get the communicator for sending commands to and receiving
+    /// notifications from the audio engine.
+    fn audio_engine(&self) -> &Communicator;
+
+    /// Returns true if the backend has stopped processing audio. No audio is
+    /// processed until `reconnect` succeeds.
+    fn is_shut_down(&self) -> bool;
+
+    /// Start again with a fresh processor. The new processor has no tracks so
+    /// the caller must add them again through `audio_engine`.
+    fn reconnect(&mut self) -> Result<(), jack::Error>;
+
+    /// Set whether the tempo set with `set_timebase_beats_per_minute` is
+    /// published to other clients.
+    fn set_timebase_master(&mut self, enabled: bool) -> Result<(), jack::Error>;
+
+    /// Set the tempo that is published while acting as the timebase master.
+    fn set_timebase_beats_per_minute(&self, beats_per_minute: f32);
+
+    /// Start rolling the shared transport.
+    fn start_transport(&self) -> Result<(), jack::Error>;
+
+    /// Stop the shared transport.
+    fn stop_transport(&self) -> Result<(), jack::Error>;
+
+    /// Get the names of the physical ports that audio can be played through.
+    fn physical_audio_outputs(&self) -> Vec<String>;
+
+    /// Connect the audio outputs to `dsts`, replacing any existing audio
+    /// output connections.
+    fn connect_audio_outputs(&self, dsts: &[String]);
+
+    /// Get the names of the midi inputs. The index of a name is the index of
+    /// the input used by `Command::SetTrackMidiSource`.
+    fn midi_input_names(&self) -> &[String];
+
+    /// Get the buffer size.
+    fn buffer_size(&self) -> usize;
+
+    /// Get the sample rate.
+    fn sample_rate(&self) -> f64;
+
+    /// Get the cpu load as a percentage.
+    fn cpu_load(&self) -> f32;
+
+    /// Get the number of xruns since the processor was created.
+    fn xruns(&self) -> u64;
+}
 
 /// Manages audio and midi processing.
 pub struct JackAdapter {
     pub audio_engine: Communicator,
     /// The underlying JACK client.
-    client: jack::AsyncClient<(), Processor>,
+    client: jack::AsyncClient<NotificationHandler, Processor>,
+    /// The tempo published while acting as the JACK timebase master. This is
+    /// declared after `client` so that it outlives the client's timebase
+    /// callback.
+    timebase: Arc<Timebase>,
+    /// True if the client is registered as the JACK timebase master.
+    timebase_master: bool,
     /// The function to call to automatically connect ports.
     auto_connect_fn: Box<dyn Send + Sync + Fn(&jack::Client)>,
+    /// The full names of the audio output ports.
+    audio_out_names: Vec<String>,
+    /// The full names of the midi input ports.
+    midi_input_names: Vec<String>,
+    /// Set once the JACK server has shut down the client.
+    shutdown: Arc<AtomicBool>,
+    /// The number of xruns that have occurred.
+    xruns: Arc<AtomicU64>,
+    /// The configuration used to create the processor.
+    config: ProcessorConfig,
 }
 
 impl JackAdapter {
-    /// Create a new audio engine.
-    pub fn new() -> Result<JackAdapter, jack::Error> {
+    /// Create a new audio engine. The lv2 plugins are scanned in the
+    /// background.
+    pub fn new(config: &ProcessorConfig) -> Result<JackAdapter, jack::Error> {
+        JackAdapter::with_plugins(config, PluginScan::start())
+    }
+
+    /// Create a new audio engine that uses the plugins of an existing scan.
+    pub fn with_plugins(
+        config: &ProcessorConfig,
+        plugins: PluginScan,
+    ) -> Result<JackAdapter, jack::Error> {
         let (client, status) =
             jack::Client::new("mini-leebee", jack::ClientOptions::NO_START_SERVER)?;
         info!(
@@ -25,46 +116,168 @@ impl JackAdapter {
             client.name(),
             status
         );
-        // JACK on pipewire may sometimes increase the buffer size. To combat
-        // this, we artifically increase the buffer size.
-        let buffer_size = client.buffer_size() as usize * 4;
+        // The processor is resized by the buffer size callback if the buffer
+        // size changes.
+        let buffer_size = client.buffer_size() as usize;
         let sample_rate = client.sample_rate() as f64;
-        let ports = Ports::new(&client)?;
+        let ports = Ports::new(&client, config.midi_inputs)?;
         let auto_connect_fn = ports.auto_connect_fn();
-        let (processor, communicator) = Processor::new(ports, sample_rate, buffer_size);
-        let client = client.activate_async((), processor)?;
+        let audio_out_names = ports.audio_out_names();
+        let midi_input_names = ports.midi_input_names();
+        let (processor, communicator) =
+            Processor::new(ports, sample_rate, buffer_size, config, plugins);
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let xruns = Arc::new(AtomicU64::new(0));
+        let notification_handler = NotificationHandler::new(shutdown.clone(), xruns.clone());
+        let client = client.activate_async(notification_handler, processor)?;
         Ok(JackAdapter {
             audio_engine: communicator,
             client,
+            timebase: Arc::new(Timebase::new(120.0)),
+            timebase_master: false,
             auto_connect_fn,
+            audio_out_names,
+            midi_input_names,
+            shutdown,
+            xruns,
+            config: config.clone(),
         })
     }
 
+    /// Returns true if the client is the JACK timebase master.
+    pub fn is_timebase_master(&self) -> bool {
+        self.timebase_master
+    }
+
     /// Automatically connect io ports.
     pub fn auto_connect(&self) {
         (self.auto_connect_fn)(self.client.as_client());
     }
 
+    /// Iterate over all notifications.
+    pub fn notifications(
+        &self,
+    ) -> impl '_ + Iterator<Item = audio_engine::commands::Notifications> {
+        self.audio_engine.notifications.try_iter()
+    }
+}
+
+impl AudioAdapter for JackAdapter {
+    fn audio_engine(&self) -> &Communicator {
+        &self.audio_engine
+    }
+
+    /// Returns true if the JACK server has shut down the client. No audio is
+    /// processed until `reconnect` succeeds.
+    fn is_shut_down(&self) -> bool {
+        self.shutdown.load(Ordering::Relaxed)
+    }
+
+    /// Create a new JACK client with a fresh processor and automatically
+    /// connect its ports. This is used to recover after the JACK server has
+    /// restarted.
+    ///
+    /// The new processor has no tracks so the caller must add them again
+    /// through `audio_engine`. Plugins must be instantiated with the new
+    /// `audio_engine.lv2_features` since the buffer size may have changed. The
+    /// plugins are not scanned again. The old client is closed once the new
+    /// one is active.
+    fn reconnect(&mut self) -> Result<(), jack::Error> {
+        let adapter = JackAdapter::with_plugins(&self.config, self.audio_engine.plugins.clone())?;
+        *self = adapter;
+        self.auto_connect();
+        Ok(())
+    }
+
+    /// Set whether the client acts as the JACK timebase master and publishes
+    /// the tempo set with `set_timebase_beats_per_minute`. This fails if
+    /// another client is already the timebase master.
+    fn set_timebase_master(&mut self, enabled: bool) -> Result<(), jack::Error> {
+        if enabled == self.timebase_master {
+            return Ok(());
+        }
+        let client = self.client.as_client();
+        if enabled {
+            // Safety: `self.timebase` is dropped after `self.client`, which
+            // ends the registration.
+            unsafe { Timebase::register(client, &self.timebase)? };
+            info!("Registered as the JACK timebase master.");
+        } else {
+            Timebase::release(client)?;
+            info!("Released the JACK timebase.");
+        }
+        self.timebase_master = enabled;
+        Ok(())
+    }
+
+    /// Set the tempo that is published while acting as the JACK timebase
+    /// master.
+    fn set_timebase_beats_per_minute(&self, beats_per_minute: f32) {
+        self.timebase.set_beats_per_minute(beats_per_minute);
+    }
+
+    /// Start rolling the JACK transport.
+    fn start_transport(&self) -> Result<(), jack::Error> {
+        self.client.as_client().transport().start()
+    }
+
+    /// Stop the JACK transport.
+    fn stop_transport(&self) -> Result<(), jack::Error> {
+        self.client.as_client().transport().stop()
+    }
+
+    /// Get the names of the physical ports that audio can be played through.
+    fn physical_audio_outputs(&self) -> Vec<String> {
+        self.client.as_client().ports(
+            None,
+            Some(jack::jack_sys::FLOAT_MONO_AUDIO),
+            jack::PortFlags::IS_PHYSICAL | jack::PortFlags::IS_INPUT,
+        )
+    }
+
+    /// Connect the audio outputs to `dsts`, replacing any existing audio output
+    /// connections.
+    fn connect_audio_outputs(&self, dsts: &[String]) {
+        let client = self.client.as_client();
+        for src in self.audio_out_names.iter() {
+            if let Some(port) = client.port_by_name(src) {
+                if let Err(err) = client.disconnect(&port) {
+                    warn!("Failed to disconnect audio port {}: {:?}", src, err);
+                }
+            }
+        }
+        for (src, dst) in self.audio_out_names.iter().zip(dsts.iter()) {
+            match client.connect_ports_by_name(src, dst) {
+                Ok(()) => info!("Connected audio port {} to {}.", src, dst),
+                Err(err) => warn!("Failed to connect audio port {} to {}: {:?}", src, dst, err),
+            }
+        }
+    }
+
+    /// Get the full names of the midi input ports. The index of a name is the
+    /// index of the input used by `Command::SetTrackMidiSource`.
+    fn midi_input_names(&self) -> &[String] {
+        &self.midi_input_names
+    }
+
     /// Get the buffer size.
-    pub fn buffer_size(&self) -> usize {
+    fn buffer_size(&self) -> usize {
         self.client.as_client().buffer_size() as usize
     }
 
     /// Get the sample rate.
-    pub fn sample_rate(&self) -> f64 {
+    fn sample_rate(&self) -> f64 {
         self.client.as_client().sample_rate() as f64
     }
 
     /// Get the cpu load.
-    pub fn cpu_load(&self) -> f32 {
+    fn cpu_load(&self) -> f32 {
         self.client.as_client().cpu_load()
     }
 
-    /// Iterate over all notifications.
-    pub fn notifications(
-        &self,
-    ) -> impl '_ + Iterator<Item = audio_engine::commands::Notifications> {
-        self.audio_engine.notifications.try_iter()
+    /// Get the number of xruns since the client was created.
+    fn xruns(&self) -> u64 {
+        self.xruns.load(Ordering::Relaxed)
     }
 }
 
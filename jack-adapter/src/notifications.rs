@@ -0,0 +1,33 @@
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    Arc,
+};
+
+/// Implements the `jack::NotificationHandler` trait.
+#[derive(Debug)]
+pub struct NotificationHandler {
+    /// Set once the JACK server has shut down the client.
+    shutdown: Arc<AtomicBool>,
+    /// The number of xruns that have occurred.
+    xruns: Arc<AtomicU64>,
+}
+
+impl NotificationHandler {
+    /// Create a new notification handler that sets `shutdown` when the client
+    /// is shut down and counts xruns in `xruns`.
+    pub fn new(shutdown: Arc<AtomicBool>, xruns: Arc<AtomicU64>) -> NotificationHandler {
+        NotificationHandler { shutdown, xruns }
+    }
+}
+
+impl jack::NotificationHandler for NotificationHandler {
+    fn shutdown(&mut self, _: jack::ClientStatus, _: &str) {
+        // This may run in a restricted context so only a flag is set.
+        self.shutdown.store(true, Ordering::Relaxed);
+    }
+
+    fn xrun(&mut self, _: &jack::Client) -> jack::Control {
+        self.xruns.fetch_add(1, Ordering::Relaxed);
+        jack::Control::Continue
+    }
+}
@@ -0,0 +1,174 @@
+use audio_engine::{plugin_scan::PluginScan, Communicator, ProcessorConfig};
+use log::*;
+
+use crate::{AudioAdapter, JackAdapter};
+
+/// A `JackAdapter` that starts disconnected if the JACK server is not running.
+/// While disconnected no audio is processed and `reconnect` tries to connect
+/// to the JACK server.
+pub struct OptionalJackAdapter {
+    jack: Result<JackAdapter, Disconnected>,
+}
+
+/// The audio engine of an `OptionalJackAdapter` that has never connected.
+struct Disconnected {
+    audio_engine: Communicator,
+    /// The processor is never run. It is kept so that the channels of
+    /// `audio_engine` stay open.
+    _processor: audio_engine::Processor,
+    sample_rate: f64,
+    buffer_size: usize,
+    /// The configuration used to create the processor.
+    config: ProcessorConfig,
+}
+
+impl OptionalJackAdapter {
+    /// Connect to the JACK server and automatically connect the ports. If the
+    /// JACK server is not running, the adapter starts disconnected with an
+    /// audio engine at `sample_rate` and `buffer_size` that does not process
+    /// any audio. The lv2 plugins are scanned in the background.
+    pub fn new(config: &ProcessorConfig, sample_rate: f64, buffer_size: usize) -> Self {
+        let plugins = PluginScan::start();
+        let jack = match JackAdapter::with_plugins(config, plugins.clone()) {
+            Ok(jack) => {
+                jack.auto_connect();
+                Ok(jack)
+            }
+            Err(err) => {
+                error!("Failed to connect to JACK, starting disconnected: {}", err);
+                let (processor, audio_engine) =
+                    audio_engine::Processor::new(sample_rate, buffer_size, config, plugins);
+                Err(Disconnected {
+                    audio_engine,
+                    _processor: processor,
+                    sample_rate,
+                    buffer_size,
+                    config: config.clone(),
+                })
+            }
+        };
+        OptionalJackAdapter { jack }
+    }
+}
+
+impl AudioAdapter for OptionalJackAdapter {
+    fn audio_engine(&self) -> &Communicator {
+        match &self.jack {
+            Ok(jack) => jack.audio_engine(),
+            Err(disconnected) => &disconnected.audio_engine,
+        }
+    }
+
+    /// Returns true if the adapter never connected or if the JACK server has
+    /// shut down the client.
+    fn is_shut_down(&self) -> bool {
+        match &self.jack {
+            Ok(jack) => jack.is_shut_down(),
+            Err(_) => true,
+        }
+    }
+
+    /// Connect to the JACK server. See `JackAdapter::reconnect`.
+    fn reconnect(&mut self) -> Result<(), jack::Error> {
+        match &mut self.jack {
+            Ok(jack) => jack.reconnect(),
+            Err(disconnected) => {
+                let jack = JackAdapter::with_plugins(
+                    &disconnected.config,
+                    disconnected.audio_engine.plugins.clone(),
+                )?;
+                jack.auto_connect();
+                self.jack = Ok(jack);
+                Ok(())
+            }
+        }
+    }
+
+    fn set_timebase_master(&mut self, enabled: bool) -> Result<(), jack::Error> {
+        match &mut self.jack {
+            Ok(jack) => jack.set_timebase_master(enabled),
+            Err(_) => Err(jack::Error::ClientIsNoLongerAlive),
+        }
+    }
+
+    fn set_timebase_beats_per_minute(&self, beats_per_minute: f32) {
+        if let Ok(jack) = &self.jack {
+            jack.set_timebase_beats_per_minute(beats_per_minute);
+        }
+    }
+
+    fn start_transport(&self) -> Result<(), jack::Error> {
+        match &self.jack {
+            Ok(jack) => jack.start_transport(),
+            Err(_) => Err(jack::Error::ClientIsNoLongerAlive),
+        }
+    }
+
+    fn stop_transport(&self) -> Result<(), jack::Error> {
+        match &self.jack {
+            Ok(jack) => jack.stop_transport(),
+            Err(_) => Err(jack::Error::ClientIsNoLongerAlive),
+        }
+    }
+
+    fn physical_audio_outputs(&self) -> Vec<String> {
+        match &self.jack {
+            Ok(jack) => jack.physical_audio_outputs(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    fn connect_audio_outputs(&self, dsts: &[String]) {
+        match &self.jack {
+            Ok(jack) => jack.connect_audio_outputs(dsts),
+            Err(_) => warn!("Not connecting audio outputs while disconnected from JACK."),
+        }
+    }
+
+    fn midi_input_names(&self) -> &[String] {
+        match &self.jack {
+            Ok(jack) => jack.midi_input_names(),
+            Err(_) => &[],
+        }
+    }
+
+    fn buffer_size(&self) -> usize {
+        match &self.jack {
+            Ok(jack) => jack.buffer_size(),
+            Err(disconnected) => disconnected.buffer_size,
+        }
+    }
+
+    fn sample_rate(&self) -> f64 {
+        match &self.jack {
+            Ok(jack) => jack.sample_rate(),
+            Err(disconnected) => disconnected.sample_rate,
+        }
+    }
+
+    fn cpu_load(&self) -> f32 {
+        match &self.jack {
+            Ok(jack) => jack.cpu_load(),
+            Err(_) => 0.0,
+        }
+    }
+
+    fn xruns(&self) -> u64 {
+        match &self.jack {
+            Ok(jack) => jack.xruns(),
+            Err(_) => 0,
+        }
+    }
+}
+
+impl std::fmt::Debug for OptionalJackAdapter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.jack {
+            Ok(jack) => jack.fmt(f),
+            Err(disconnected) => f
+                .debug_struct("OptionalJackAdapter")
+                .field("audio_engine", &disconnected.audio_engine)
+                .finish(),
+        }
+    }
+}
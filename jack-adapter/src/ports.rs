@@ -7,22 +7,50 @@ use audio_engine::audio_buffer::AudioBuffer;
 pub struct Ports {
     /// The audio outputs.
     pub audio_out: [jack::Port<jack::AudioOut>; 2],
-    /// The midi input.
-    pub midi_input: jack::Port<jack::MidiIn>,
+    /// The audio inputs.
+    pub audio_in: [jack::Port<jack::AudioIn>; 2],
+    /// The midi inputs. There is always at least one.
+    pub midi_inputs: Vec<jack::Port<jack::MidiIn>>,
+    /// The midi output.
+    pub midi_output: jack::Port<jack::MidiOut>,
 }
 
 impl Ports {
-    /// Create a new set of ports.
-    pub fn new(client: &jack::Client) -> Result<Ports, jack::Error> {
+    /// Create a new set of ports with `midi_inputs` midi input ports. At least
+    /// one midi input port is created.
+    pub fn new(client: &jack::Client, midi_inputs: usize) -> Result<Ports, jack::Error> {
+        let midi_inputs = (0..midi_inputs.max(1))
+            .map(|idx| match idx {
+                0 => client.register_port("midi_in", jack::MidiIn),
+                idx => client.register_port(&format!("midi_in_{}", idx + 1), jack::MidiIn),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
         Ok(Ports {
             audio_out: [
                 client.register_port("audio_out_l", jack::AudioOut)?,
                 client.register_port("audio_out_r", jack::AudioOut)?,
             ],
-            midi_input: client.register_port("midi_in", jack::MidiIn)?,
+            audio_in: [
+                client.register_port("audio_in_l", jack::AudioIn)?,
+                client.register_port("audio_in_r", jack::AudioIn)?,
+            ],
+            midi_inputs,
+            midi_output: client.register_port("midi_out", jack::MidiOut)?,
         })
     }
 
+    /// Iterate over the events of all midi inputs as `(input, frame, data)`.
+    /// The events of each input are sorted by frame.
+    pub fn midi_in<'a>(
+        &'a self,
+        ps: &'a jack::ProcessScope,
+    ) -> impl 'a + Iterator<Item = (usize, u32, &'a [u8])> {
+        self.midi_inputs
+            .iter()
+            .enumerate()
+            .flat_map(move |(idx, port)| port.iter(ps).map(move |raw| (idx, raw.time, raw.bytes)))
+    }
+
     /// Mix the contents of src into the output audio.
     pub fn copy_audio_out(&mut self, ps: &jack::ProcessScope, src: &AudioBuffer) {
         for (src, dst) in src.iter_channels().zip(self.audio_out.iter_mut()) {
@@ -32,14 +60,59 @@ impl Ports {
         }
     }
 
-    /// Automatically connect the ports to physical ports.
+    /// Write `events` to the midi output. Returns the number of events that
+    /// did not fit.
+    pub fn write_midi_out(
+        &mut self,
+        ps: &jack::ProcessScope,
+        events: &[audio_engine::midi_output::MidiOutputEvent],
+    ) -> u64 {
+        let mut writer = self.midi_output.writer(ps);
+        let mut dropped = 0;
+        for event in events {
+            let raw = jack::RawMidi {
+                time: event.frame,
+                bytes: event.data(),
+            };
+            if writer.write(&raw).is_err() {
+                dropped += 1;
+            }
+        }
+        dropped
+    }
+
+    /// Get the contents of the audio inputs.
+    pub fn audio_in<'a>(&'a self, ps: &'a jack::ProcessScope) -> [&'a [f32]; 2] {
+        [self.audio_in[0].as_slice(ps), self.audio_in[1].as_slice(ps)]
+    }
+
+    /// Get the full names of the audio output ports.
+    pub fn audio_out_names(&self) -> Vec<String> {
+        self.audio_out
+            .iter()
+            .map(|port| port.name().unwrap())
+            .collect()
+    }
+
+    /// Get the full names of the midi input ports.
+    pub fn midi_input_names(&self) -> Vec<String> {
+        self.midi_inputs
+            .iter()
+            .map(|port| port.name().unwrap())
+            .collect()
+    }
+
+    /// Automatically connect the ports to physical ports. Physical midi
+    /// devices are only connected to the first midi input so that the others
+    /// may be connected to specific devices by the user.
     pub fn auto_connect_fn(&self) -> Box<dyn Send + Sync + Fn(&jack::Client)> {
-        let audio_outputs: Vec<_> = self
-            .audio_out
+        let audio_outputs = self.audio_out_names();
+        let audio_inputs: Vec<String> = self
+            .audio_in
             .iter()
             .map(|port| port.name().unwrap())
             .collect();
-        let midi_input = self.midi_input.name().unwrap();
+        let midi_input = self.midi_inputs[0].name().unwrap();
         Box::new(move |client: &jack::Client| {
             let srcs = audio_outputs.iter();
             let dsts = client.ports(
@@ -54,6 +127,18 @@ impl Ports {
                 };
             }
 
+            let srcs = client.ports(
+                None,
+                Some(jack::jack_sys::FLOAT_MONO_AUDIO),
+                jack::PortFlags::IS_PHYSICAL | jack::PortFlags::IS_OUTPUT,
+            );
+            for (src, dst) in srcs.iter().zip(audio_inputs.iter()) {
+                match client.connect_ports_by_name(src, dst) {
+                    Ok(()) => info!("Connected audio port {} to {}.", src, dst),
+                    Err(err) => warn!("Failed to connect audio port {} to {}: {:?}", src, dst, err),
+                };
+            }
+
             let srcs = client.ports(
                 None,
                 Some(jack::jack_sys::RAW_MIDI_TYPE),
@@ -1,4 +1,7 @@
-use audio_engine::Communicator;
+use audio_engine::{
+    plugin_scan::PluginScan, transport::SharedTransport, Communicator, ProcessorConfig,
+};
+use log::*;
 
 use crate::ports::Ports;
 
@@ -13,26 +16,66 @@ pub struct Processor {
 
 impl Processor {
     /// Create a new processor.
-    pub fn new(ports: Ports, sample_rate: f64, buffer_size: usize) -> (Processor, Communicator) {
-        let (inner, communicator) = audio_engine::Processor::new(sample_rate, buffer_size);
+    pub fn new(
+        ports: Ports,
+        sample_rate: f64,
+        buffer_size: usize,
+        config: &ProcessorConfig,
+        plugins: PluginScan,
+    ) -> (Processor, Communicator) {
+        let (inner, communicator) =
+            audio_engine::Processor::new(sample_rate, buffer_size, config, plugins);
         let processor = Processor { inner, ports };
         (processor, communicator)
     }
 }
 
 impl jack::ProcessHandler for Processor {
-    fn process(&mut self, _: &jack::Client, ps: &jack::ProcessScope) -> jack::Control {
+    fn buffer_size(&mut self, _: &jack::Client, size: jack::Frames) -> jack::Control {
+        // JACK does not run `process` while this is called and allows
+        // allocating here.
+        info!("JACK buffer size set to {}.", size);
+        self.inner.set_buffer_size(size as usize);
+        jack::Control::Continue
+    }
+
+    fn process(&mut self, client: &jack::Client, ps: &jack::ProcessScope) -> jack::Control {
+        // JACK may run the callback on a new thread, so this is set on every
+        // cycle. It is cheap compared to the rest of the processing.
+        audio_engine::denormals::flush_denormals_to_zero();
         let samples = ps.n_frames() as usize;
-        self.ports.copy_audio_out(
-            ps,
-            self.inner.process(
-                samples,
-                self.ports
-                    .midi_input
-                    .iter(ps)
-                    .map(|raw| (raw.time, raw.bytes)),
-            ),
-        );
+        if self.inner.follows_transport() {
+            // Querying from the process thread returns the position of the
+            // first frame of this cycle.
+            if let Ok(transport) = client.transport().query() {
+                self.inner.follow_transport(shared_transport(&transport));
+            }
+        }
+        let audio_out =
+            self.inner
+                .process(samples, self.ports.midi_in(ps), &self.ports.audio_in(ps));
+        self.ports.copy_audio_out(ps, audio_out);
+        let dropped = self.ports.write_midi_out(ps, self.inner.midi_output());
+        if dropped > 0 {
+            self.inner.add_dropped_midi_output(dropped);
+        }
         jack::Control::Continue
     }
 }
+
+/// Convert the JACK transport to the shared transport followed by the audio
+/// engine. Bars with a different number of beats than the metronome are
+/// counted as the same number of metronome beats.
+fn shared_transport(transport: &jack::TransportStatePosition) -> SharedTransport {
+    let bbt = transport.pos.bbt();
+    SharedTransport {
+        playing: transport.state == jack::TransportState::Rolling,
+        beats: bbt.map(|bbt| {
+            (bbt.bar - 1) as f64 * bbt.sig_num as f64
+                + (bbt.beat - 1) as f64
+                + bbt.tick as f64 / bbt.ticks_per_beat
+        }),
+        frame: transport.pos.frame() as u64,
+        beats_per_minute: bbt.map(|bbt| bbt.bpm),
+    }
+}
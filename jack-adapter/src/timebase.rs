@@ -0,0 +1,98 @@
+use std::{
+    ffi::c_void,
+    os::raw::c_int,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use jack::jack_sys;
+
+/// The number of beats in each bar that is published to the JACK transport.
+const BEATS_PER_BAR: f64 = audio_engine::metronome::BEATS_PER_MEASURE;
+
+/// The note value of a beat that is published to the JACK transport.
+const BEAT_TYPE: f32 = 4.0;
+
+/// The resolution of the ticks that are published to the JACK transport.
+const TICKS_PER_BEAT: f64 = 1920.0;
+
+/// The tempo published to the JACK transport while acting as the timebase
+/// master. It is shared with the JACK timebase callback.
+#[derive(Debug)]
+pub struct Timebase {
+    /// The bits of the tempo as an `f32`.
+    beats_per_minute: AtomicU32,
+}
+
+impl Timebase {
+    /// Create a new timebase with the given tempo.
+    pub fn new(beats_per_minute: f32) -> Timebase {
+        Timebase {
+            beats_per_minute: AtomicU32::new(beats_per_minute.to_bits()),
+        }
+    }
+
+    /// Set the published tempo.
+    pub fn set_beats_per_minute(&self, beats_per_minute: f32) {
+        self.beats_per_minute
+            .store(beats_per_minute.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Get the published tempo.
+    pub fn beats_per_minute(&self) -> f32 {
+        f32::from_bits(self.beats_per_minute.load(Ordering::Relaxed))
+    }
+
+    /// Register `timebase` as the JACK timebase master of `client`. This fails
+    /// if another client is already the timebase master.
+    ///
+    /// # Safety
+    /// `timebase` must outlive the registration. The registration ends when
+    /// `release` is called or when the client is closed.
+    pub unsafe fn register(client: &jack::Client, timebase: &Timebase) -> Result<(), jack::Error> {
+        let res = jack_sys::jack_set_timebase_callback(
+            client.raw(),
+            1,
+            Some(timebase_callback),
+            timebase as *const Timebase as *mut c_void,
+        );
+        match res {
+            0 => Ok(()),
+            _ => Err(jack::Error::CallbackRegistrationError),
+        }
+    }
+
+    /// Stop being the JACK timebase master of `client`.
+    pub fn release(client: &jack::Client) -> Result<(), jack::Error> {
+        let res = unsafe { jack_sys::jack_release_timebase(client.raw()) };
+        match res {
+            0 => Ok(()),
+            _ => Err(jack::Error::CallbackDeregistrationError),
+        }
+    }
+}
+
+/// Fill in the bar, beat and tick of `pos` from its frame and the tempo of the
+/// `Timebase` pointed to by `arg`.
+unsafe extern "C" fn timebase_callback(
+    _: jack_sys::jack_transport_state_t,
+    _: jack_sys::jack_nframes_t,
+    pos: *mut jack_sys::jack_position_t,
+    _: c_int,
+    arg: *mut c_void,
+) {
+    let timebase = &*(arg as *const Timebase);
+    let pos = &mut *pos;
+    let beats_per_minute = timebase.beats_per_minute() as f64;
+    let beats = pos.frame as f64 / pos.frame_rate as f64 * beats_per_minute / 60.0;
+    let whole_beats = beats.floor();
+    let bars = (whole_beats / BEATS_PER_BAR).floor();
+    pos.valid |= jack_sys::JackPositionBBT;
+    pos.bar = bars as i32 + 1;
+    pos.beat = (whole_beats - bars * BEATS_PER_BAR) as i32 + 1;
+    pos.tick = ((beats - whole_beats) * TICKS_PER_BEAT) as i32;
+    pos.bar_start_tick = bars * BEATS_PER_BAR * TICKS_PER_BEAT;
+    pos.beats_per_bar = BEATS_PER_BAR as f32;
+    pos.beat_type = BEAT_TYPE;
+    pos.ticks_per_beat = TICKS_PER_BEAT;
+    pos.beats_per_minute = beats_per_minute;
+}
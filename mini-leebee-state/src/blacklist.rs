@@ -0,0 +1,119 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::Error;
+
+/// The number of times a plugin may fail to instantiate before it is added to
+/// the blacklist.
+const MAX_INSTANTIATE_FAILURES: u32 = 2;
+
+/// Plugins that are hidden from the plugin list. The blacklist is saved to a
+/// file whenever it changes so that it survives restarts.
+#[derive(Debug, Default)]
+pub struct Blacklist {
+    /// The file the blacklist is saved to. If `None`, the blacklist is not
+    /// saved.
+    path: Option<PathBuf>,
+    /// The blacklisted plugins by plugin id.
+    entries: BTreeMap<String, BlacklistEntry>,
+    /// The number of times each plugin failed to instantiate since the
+    /// program started.
+    failures: HashMap<String, u32>,
+}
+
+/// A blacklisted plugin.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct BlacklistEntry {
+    /// Why the plugin was blacklisted. This is the error for plugins that were
+    /// blacklisted automatically.
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+/// The blacklist as it is saved to disk.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct BlacklistFile {
+    #[serde(default)]
+    plugins: BTreeMap<String, BlacklistEntry>,
+}
+
+impl Blacklist {
+    /// Load the blacklist from `path`. If the file does not exist, the
+    /// blacklist is empty and is created once a plugin is added.
+    pub fn load(path: &Path) -> Result<Blacklist, Error> {
+        let entries = match std::fs::read_to_string(path) {
+            Ok(contents) => {
+                toml::from_str::<BlacklistFile>(&contents)
+                    .map_err(|err| Error::file(path, err))?
+                    .plugins
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(err) => return Err(Error::file(path, err)),
+        };
+        Ok(Blacklist {
+            path: Some(path.to_path_buf()),
+            entries,
+            failures: HashMap::new(),
+        })
+    }
+
+    /// Returns true if the plugin is blacklisted.
+    pub fn contains(&self, plugin_id: &str) -> bool {
+        self.entries.contains_key(plugin_id)
+    }
+
+    /// Iterate over the blacklisted plugin ids and their entries.
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &BlacklistEntry)> {
+        self.entries.iter()
+    }
+
+    /// Add a plugin to the blacklist and save it.
+    pub fn insert(&mut self, plugin_id: &str, reason: Option<String>) -> Result<(), Error> {
+        self.entries
+            .insert(plugin_id.to_string(), BlacklistEntry { reason });
+        self.save()
+    }
+
+    /// Remove a plugin from the blacklist and save it. Returns false if the
+    /// plugin was not blacklisted.
+    pub fn remove(&mut self, plugin_id: &str) -> Result<bool, Error> {
+        self.failures.remove(plugin_id);
+        if self.entries.remove(plugin_id).is_none() {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    /// Record that a plugin failed to instantiate. Returns true if the plugin
+    /// was added to the blacklist because it failed too many times.
+    pub fn record_failure(&mut self, plugin_id: &str, err: &Error) -> Result<bool, Error> {
+        let failures = self.failures.entry(plugin_id.to_string()).or_default();
+        *failures += 1;
+        if *failures < MAX_INSTANTIATE_FAILURES || self.contains(plugin_id) {
+            return Ok(false);
+        }
+        self.insert(plugin_id, Some(err.to_string()))?;
+        Ok(true)
+    }
+
+    /// Save the blacklist to its file.
+    fn save(&self) -> Result<(), Error> {
+        let path = match self.path.as_ref() {
+            Some(p) => p,
+            None => return Ok(()),
+        };
+        let file = BlacklistFile {
+            plugins: self.entries.clone(),
+        };
+        let contents = toml::to_string(&file).map_err(|err| Error::file(path, err))?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|err| Error::file(dir, err))?;
+        }
+        std::fs::write(path, contents).map_err(|err| Error::file(path, err))
+    }
+}
@@ -0,0 +1,163 @@
+use std::path::{Path, PathBuf};
+
+use audio_engine::drum_kit::{DrumKit, DrumPad};
+use serde::Deserialize;
+
+use crate::Error;
+
+/// A drum kit file. Each pad plays a wave file on a midi note:
+///
+/// ```toml
+/// [[pads]]
+/// note = 36
+/// sample = "kick.wav"
+///
+/// [[pads]]
+/// note = 38
+/// sample = "snare.wav"
+/// gain = 0.8
+/// ```
+///
+/// Relative sample paths are relative to the directory of the kit file.
+#[derive(Debug, Deserialize)]
+struct DrumKitFile {
+    #[serde(default)]
+    pads: Vec<DrumKitFilePad>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DrumKitFilePad {
+    /// The midi note number that plays the sample.
+    note: u8,
+    /// The wave file to play.
+    sample: PathBuf,
+    /// The gain applied to the sample.
+    #[serde(default = "default_gain")]
+    gain: f32,
+}
+
+fn default_gain() -> f32 {
+    1.0
+}
+
+/// Load the kit file at `path` and the samples it lists. The samples are
+/// resampled to `sample_rate`.
+pub fn load(path: &Path, sample_rate: f64) -> Result<DrumKit, Error> {
+    let contents = std::fs::read_to_string(path).map_err(|err| Error::file(path, err))?;
+    let file: DrumKitFile = toml::from_str(&contents).map_err(|err| Error::file(path, err))?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut pads = Vec::with_capacity(file.pads.len());
+    for pad in file.pads {
+        let note = wmidi::Note::try_from(pad.note)
+            .map_err(|_| Error::file(path, format!("{} is not a valid midi note", pad.note)))?;
+        let sample = dir.join(&pad.sample);
+        let pad = DrumPad::from_wav(&sample, sample_rate, pad.gain)
+            .map_err(|err| Error::file(&sample, err))?;
+        pads.push((note, pad));
+    }
+    Ok(DrumKit::new(pads))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Create an empty directory for a test.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mini-leebee-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Write a mono WAV file with `frames` frames at `sample_rate`.
+    fn write_wav(path: &Path, frames: usize, sample_rate: u32) {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(path, spec).unwrap();
+        for _ in 0..frames {
+            writer.write_sample(i16::MAX / 2).unwrap();
+        }
+        writer.finalize().unwrap();
+    }
+
+    /// Load a kit file with `contents` from `dir`.
+    fn load_kit(dir: &Path, contents: &str) -> Result<DrumKit, Error> {
+        let path = dir.join("kit.toml");
+        std::fs::write(&path, contents).unwrap();
+        load(&path, 44100.0)
+    }
+
+    #[test]
+    fn samples_are_relative_to_the_kit_file() {
+        let dir = test_dir("kit");
+        std::fs::create_dir(dir.join("samples")).unwrap();
+        write_wav(&dir.join("samples/kick.wav"), 100, 44100);
+        write_wav(&dir.join("snare.wav"), 200, 44100);
+        let kit = load_kit(
+            &dir,
+            r#"
+            [[pads]]
+            note = 36
+            sample = "samples/kick.wav"
+
+            [[pads]]
+            note = 38
+            sample = "snare.wav"
+            gain = 0.8
+            "#,
+        )
+        .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let kick = kit.pad(wmidi::Note::C2).unwrap();
+        assert_eq!(kick.gain, 1.0);
+        assert_eq!(kick.sample.buffer_size(), 100);
+        let snare = kit.pad(wmidi::Note::D2).unwrap();
+        assert_eq!(snare.gain, 0.8);
+        assert_eq!(snare.sample.buffer_size(), 200);
+        assert_eq!(kit.iter_pads().count(), 2);
+    }
+
+    #[test]
+    fn samples_are_resampled_to_the_sample_rate() {
+        let dir = test_dir("kit-resampled");
+        write_wav(&dir.join("kick.wav"), 22050, 22050);
+        let kit = load_kit(&dir, "[[pads]]\nnote = 36\nsample = \"kick.wav\"\n").unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let frames = kit.pad(wmidi::Note::C2).unwrap().sample.buffer_size();
+        assert!(frames.abs_diff(44100) <= 2, "{frames}");
+    }
+
+    #[test]
+    fn empty_kit_has_no_pads() {
+        let dir = test_dir("kit-empty");
+        let kit = load_kit(&dir, "").unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(kit.iter_pads().count(), 0);
+    }
+
+    #[test]
+    fn invalid_kits_are_errors() {
+        let dir = test_dir("kit-invalid");
+        write_wav(&dir.join("kick.wav"), 100, 44100);
+        let invalid = [
+            // Midi notes only go up to 127.
+            "[[pads]]\nnote = 128\nsample = \"kick.wav\"\n",
+            "[[pads]]\nnote = 36\nsample = \"missing.wav\"\n",
+            "[[pads]]\nnote = 36\n",
+            "pads = 3",
+        ];
+        for contents in invalid {
+            assert!(load_kit(&dir, contents).is_err(), "{contents}");
+        }
+        assert!(matches!(
+            load(&dir.join("missing.toml"), 44100.0),
+            Err(Error::File { .. })
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
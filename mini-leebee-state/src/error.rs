@@ -0,0 +1,140 @@
+use std::path::PathBuf;
+
+/// An error from `State`.
+#[derive(Debug)]
+pub enum Error {
+    /// There is no track with the id.
+    TrackNotFound(i32),
+    /// There is no plugin with the id.
+    PluginNotFound(String),
+    /// The installed plugins are still being scanned.
+    ScanningPlugins,
+    /// The track has no plugin at the index.
+    PluginIndexOutOfRange { track_id: i32, plugin_index: usize },
+    /// A plugin could not be instantiated.
+    PluginInstantiation {
+        name: String,
+        source: livi::error::InstantiateError,
+    },
+    /// A plugin has a port that the audio engine can not connect.
+    UnsupportedPlugin {
+        name: String,
+        port: audio_engine::plugin::UnsupportedPort,
+    },
+    /// A plugin failed to run.
+    PluginProcess {
+        name: String,
+        source: audio_engine::plugin::PluginProcessError,
+    },
+    /// The audio engine is not accepting commands. This happens if the JACK
+    /// server has shut down or the engine has stopped processing commands.
+    EngineUnavailable,
+    /// Connecting to the JACK server failed.
+    Jack(jack::Error),
+    /// A file could not be read or written.
+    File {
+        path: PathBuf,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    /// The request is not valid in the current state.
+    InvalidRequest(String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::TrackNotFound(track_id) => write!(f, "track {track_id} not found"),
+            Error::PluginNotFound(plugin_id) => write!(f, "plugin {plugin_id} not found"),
+            Error::ScanningPlugins => write!(f, "plugins are still being scanned"),
+            Error::PluginIndexOutOfRange {
+                track_id,
+                plugin_index,
+            } => write!(
+                f,
+                "track {track_id} does not have a plugin at index {plugin_index}"
+            ),
+            Error::PluginInstantiation { name, source } => {
+                write!(f, "failed to instantiate plugin {name}: {source}")
+            }
+            Error::UnsupportedPlugin { name, port } => {
+                write!(f, "plugin {name} is not supported: {port}")
+            }
+            Error::PluginProcess { name, source } => {
+                write!(f, "failed to run plugin {name}: {source}")
+            }
+            Error::EngineUnavailable => write!(f, "the audio engine is not available"),
+            Error::Jack(err) => write!(f, "JACK error: {err}"),
+            Error::File { path, source } => write!(f, "{path:?}: {source}"),
+            Error::InvalidRequest(msg) => write!(f, "{msg}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::PluginInstantiation { source, .. } => Some(source),
+            Error::PluginProcess { source, .. } => Some(source),
+            Error::Jack(err) => Some(err),
+            Error::File { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+/// An error from creating a performance profile with `State::pprof_report`.
+#[derive(Debug)]
+pub enum ProfileError {
+    /// Another profile is still in progress.
+    InProgress,
+    /// The profiler could not be started or the report could not be built.
+    Pprof(pprof::Error),
+}
+
+impl std::fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProfileError::InProgress => write!(f, "a profile is already in progress"),
+            ProfileError::Pprof(err) => write!(f, "profiling failed: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for ProfileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ProfileError::InProgress => None,
+            ProfileError::Pprof(err) => Some(err),
+        }
+    }
+}
+
+impl From<pprof::Error> for ProfileError {
+    fn from(err: pprof::Error) -> ProfileError {
+        ProfileError::Pprof(err)
+    }
+}
+
+impl From<Error> for String {
+    fn from(err: Error) -> String {
+        err.to_string()
+    }
+}
+
+impl Error {
+    /// Create an error for the file at `path`.
+    pub(crate) fn file(
+        path: impl Into<PathBuf>,
+        source: impl Into<Box<dyn std::error::Error + Send + Sync>>,
+    ) -> Error {
+        Error::File {
+            path: path.into(),
+            source: source.into(),
+        }
+    }
+
+    /// Create an error for a request that is not valid in the current state.
+    pub(crate) fn invalid(msg: impl Into<String>) -> Error {
+        Error::InvalidRequest(msg.into())
+    }
+}
@@ -0,0 +1,195 @@
+use crate::{Track, TrackPlugin, TrackProperties};
+
+/// The maximum number of edits that can be undone.
+const HISTORY_LIMIT: usize = 100;
+
+/// An arrangement edit that can be undone and redone.
+#[derive(Clone, Debug)]
+pub enum Edit {
+    /// Tracks were added at their positions in the track list, sorted by
+    /// position.
+    AddTracks(Vec<(usize, Track)>),
+    /// Tracks were deleted from their positions in the track list, sorted by
+    /// position.
+    DeleteTracks(Vec<(usize, Track)>),
+    /// A plugin was added to a track.
+    AddPlugin {
+        track_id: i32,
+        plugin_index: usize,
+        plugin: TrackPlugin,
+    },
+    /// A plugin was removed from a track.
+    RemovePlugin {
+        track_id: i32,
+        plugin_index: usize,
+        plugin: TrackPlugin,
+    },
+    /// A plugin was moved within a track.
+    MovePlugin {
+        track_id: i32,
+        from: usize,
+        to: usize,
+    },
+    /// A track was renamed.
+    RenameTrack {
+        track_id: i32,
+        before: String,
+        after: String,
+    },
+    /// The properties of a track were changed.
+    SetTrackProperties {
+        track_id: i32,
+        before: TrackProperties,
+        after: TrackProperties,
+    },
+}
+
+impl Edit {
+    /// Get the edit that reverts this edit.
+    pub fn inverse(self) -> Edit {
+        match self {
+            Edit::AddTracks(tracks) => Edit::DeleteTracks(tracks),
+            Edit::DeleteTracks(tracks) => Edit::AddTracks(tracks),
+            Edit::AddPlugin {
+                track_id,
+                plugin_index,
+                plugin,
+            } => Edit::RemovePlugin {
+                track_id,
+                plugin_index,
+                plugin,
+            },
+            Edit::RemovePlugin {
+                track_id,
+                plugin_index,
+                plugin,
+            } => Edit::AddPlugin {
+                track_id,
+                plugin_index,
+                plugin,
+            },
+            Edit::MovePlugin { track_id, from, to } => Edit::MovePlugin {
+                track_id,
+                from: to,
+                to: from,
+            },
+            Edit::RenameTrack {
+                track_id,
+                before,
+                after,
+            } => Edit::RenameTrack {
+                track_id,
+                before: after,
+                after: before,
+            },
+            Edit::SetTrackProperties {
+                track_id,
+                before,
+                after,
+            } => Edit::SetTrackProperties {
+                track_id,
+                before: after,
+                after: before,
+            },
+        }
+    }
+}
+
+/// The edits that can be undone and redone.
+#[derive(Debug, Default)]
+pub struct History {
+    undo: Vec<Edit>,
+    redo: Vec<Edit>,
+    /// Set while an edit is undone or redone so that the changes it makes are
+    /// not recorded as new edits.
+    pub replaying: bool,
+}
+
+impl History {
+    /// Record a new edit. Edits that were undone can no longer be redone.
+    /// Consecutive property changes or renames of the same track are merged
+    /// into a single edit so that dragging a slider or typing a name is
+    /// undone in one step.
+    pub fn record(&mut self, edit: Edit) {
+        if self.replaying {
+            return;
+        }
+        self.redo.clear();
+        let merged = match (self.undo.last_mut(), &edit) {
+            (
+                Some(Edit::SetTrackProperties {
+                    track_id: last_track_id,
+                    after: last_after,
+                    ..
+                }),
+                Edit::SetTrackProperties {
+                    track_id, after, ..
+                },
+            ) if last_track_id == track_id => {
+                *last_after = *after;
+                true
+            }
+            (
+                Some(Edit::RenameTrack {
+                    track_id: last_track_id,
+                    after: last_after,
+                    ..
+                }),
+                Edit::RenameTrack {
+                    track_id, after, ..
+                },
+            ) if last_track_id == track_id => {
+                last_after.clone_from(after);
+                true
+            }
+            _ => false,
+        };
+        if !merged {
+            self.push(edit);
+        }
+    }
+
+    /// Add a new edit, dropping the oldest edit if there are too many.
+    fn push(&mut self, edit: Edit) {
+        if self.undo.len() == HISTORY_LIMIT {
+            self.undo.remove(0);
+        }
+        self.undo.push(edit);
+    }
+
+    /// Take the edit to undo.
+    pub fn take_undo(&mut self) -> Option<Edit> {
+        self.undo.pop()
+    }
+
+    /// Take the edit to redo.
+    pub fn take_redo(&mut self) -> Option<Edit> {
+        self.redo.pop()
+    }
+
+    /// Add an edit that was undone so that it may be redone.
+    pub fn push_redo(&mut self, edit: Edit) {
+        self.redo.push(edit);
+    }
+
+    /// Add an edit that was redone so that it may be undone again.
+    pub fn push_undo(&mut self, edit: Edit) {
+        self.undo.push(edit);
+    }
+
+    /// Returns true if there is an edit to undo.
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    /// Returns true if there is an edit to redo.
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// Forget all edits.
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+}
@@ -1,13 +1,136 @@
-use std::{collections::HashSet, path::Path};
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
 
-use audio_engine::{commands::Command, metronome::SampleTimeInfo};
+use audio_engine::{
+    clip::{Clip, MidiEvent},
+    commands::Command,
+    drum_kit::DrumKit,
+    gain::Gain,
+    lv2_state::{Lv2State, Lv2StateHandle},
+    metronome::SampleTimeInfo,
+    plugin::{Envelope, PluginCost, PluginInstance, SampleTrigger, TriggerMode},
+};
+use log::*;
+
+pub use audio_engine::track::MASTER_TRACK_ID;
+pub use blacklist::BlacklistEntry;
+pub use error::{Error, ProfileError};
+
+mod blacklist;
+mod drum_kit;
+mod error;
+mod history;
+mod project;
+
+/// The number of taps to average the tempo over.
+const TAP_TEMPO_TAPS: usize = 4;
+
+/// The maximum number of midi events in a single recording.
+const RECORDING_CAPACITY: usize = 16 * 1024;
+
+/// The maximum number of seconds of audio in a single recording.
+const AUDIO_RECORDING_SECONDS: usize = 5 * 60;
+
+/// The number of seconds of output that may be buffered while recording the
+/// output to disk.
+const MASTER_RECORDING_BUFFER_SECONDS: usize = 2;
+
+/// How long to wait for the audio engine to stop recording the output.
+const MASTER_RECORDING_STOP_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long a clip is reported by `State::recent_clip`.
+const CLIP_HOLD: Duration = Duration::from_secs(1);
+
+/// How fast meters fall, in decibels per second.
+const METER_DECAY_DB_PER_SECOND: f32 = 24.0;
+
+/// How long the peak of a meter is held before it falls to the level.
+const METER_PEAK_HOLD: Duration = Duration::from_secs(1);
+
+/// Meters below this many decibels read as silence.
+pub const METER_FLOOR_DB: f32 = -60.0;
+
+/// The number of ticks per beat in exported midi files.
+const MIDI_FILE_TICKS_PER_BEAT: u16 = 480;
+
+/// The id of the built in drum kit plugin.
+pub const DRUM_KIT_PLUGIN_ID: &str = "internal:drum-kit";
+
+/// The id of the built in plugin that plays a sample recorded with
+/// `State::record_sample`.
+pub const SAMPLER_PLUGIN_ID: &str = "internal:sampler";
+
+/// The id of the built in plugin that adjusts the level, polarity and
+/// balance.
+pub const GAIN_PLUGIN_ID: &str = "internal:gain";
+
+/// Recorded samples start at the first frame louder than this, about -40 dB.
+const SAMPLE_SILENCE_THRESHOLD: f32 = 0.01;
+
+/// Taps further apart than this start a new tempo.
+const TAP_TEMPO_TIMEOUT: Duration = Duration::from_secs(2);
 
 /// Implements the MiniLeebee gRPC service.
 #[derive(Debug)]
 pub struct State {
-    jack_adapter: jack_adapter::JackAdapter,
+    /// The backend that runs the audio engine.
+    adapter: Box<dyn jack_adapter::AudioAdapter>,
     state: InnerState,
     ok_sound: audio_engine::plugin::SampleTrigger,
+    /// Measured plugin costs keyed by plugin id and block size.
+    plugin_costs: Arc<Mutex<HashMap<(String, usize), PluginCost>>>,
+    /// The recording of the output, if the output is being recorded.
+    master_recording: Option<MasterRecording>,
+    /// The directory that sounds and samples are loaded from.
+    resources_dir: PathBuf,
+    /// Set while an autosave is being written.
+    autosaving: Arc<AtomicBool>,
+    /// Set while a bounce is being rendered.
+    bouncing: Arc<AtomicBool>,
+    /// Set while a performance profile is being taken.
+    profiling: Arc<AtomicBool>,
+    /// The arrangement edits that can be undone and redone.
+    history: history::History,
+    /// The plugins that are hidden from `get_plugins`.
+    blacklist: Mutex<blacklist::Blacklist>,
+}
+
+/// Configuration for creating a `State`.
+#[derive(Clone, Debug)]
+pub struct StateConfig {
+    /// The directory that sounds and samples are loaded from. Sounds that are
+    /// not in the directory use the built in defaults.
+    pub resources_dir: PathBuf,
+    /// The file the plugin blacklist is saved to. If `None`, the blacklist is
+    /// not saved.
+    pub blacklist_path: Option<PathBuf>,
+}
+
+impl Default for StateConfig {
+    fn default() -> Self {
+        StateConfig {
+            resources_dir: PathBuf::from("resources"),
+            blacklist_path: None,
+        }
+    }
+}
+
+/// A recording of the output that is being written to disk.
+#[derive(Debug)]
+struct MasterRecording {
+    /// Set by the audio engine once it stops pushing frames.
+    finished: Arc<AtomicBool>,
+    /// Signals the writer to finish once the buffer is drained.
+    stop: Arc<AtomicBool>,
+    /// The thread writing the output to disk.
+    writer: std::thread::JoinHandle<Result<(), Error>>,
 }
 
 #[derive(Debug)]
@@ -15,53 +138,316 @@ struct InnerState {
     cpu: String,
     metronome: Metronome,
     tracks: Vec<Track>,
+    /// The track that the mix is run through.
+    master: Track,
+    /// The gain applied to the output.
+    master_volume: f32,
+    /// True if the limiter is enabled on the output.
+    limiter_enabled: bool,
+    /// The time and peak of the most recent clip on the output.
+    last_clip: Option<(Instant, f32)>,
+    /// The meters of the tracks and the output, by track id.
+    meters: HashMap<i32, Meter>,
+    /// The last time the meters were decayed.
+    meters_updated: Instant,
     armed_track: Option<i32>,
+    /// The file the metronome sample was loaded from, if it was changed.
+    metronome_sample: Option<PathBuf>,
+    /// True if midi clock is sent to the midi output.
+    midi_clock: bool,
+    /// The tempo detected from the midi clock on the midi input.
+    external_tempo: Option<f32>,
+    /// True if the midi clock on the midi input stopped sending pulses.
+    external_clock_lost: bool,
+    /// True if playback and the metronome follow the JACK transport.
+    follow_jack_transport: bool,
+    /// True if the tempo is published as the JACK timebase master.
+    jack_timebase_master: bool,
     next_track_id: i32,
     time_info: audio_engine::metronome::SampleTimeInfo,
+    playing: bool,
+    counting_in: bool,
+    recording: bool,
+    recording_mode: RecordingMode,
+    /// The track that a sample is being recorded for.
+    sample_recording: Option<i32>,
+    /// The frames written and dropped while recording the output.
+    master_recording_stats: (u64, u64),
+    /// The times of the most recent tempo taps.
+    taps: Vec<Instant>,
+    /// The control that the next midi controller is mapped to if midi learn
+    /// is in progress.
+    midi_learn: Option<MidiLearnTarget>,
+    /// The mappings from midi controllers to controls.
+    midi_mappings: Vec<MidiMapping>,
+    /// The file the project was last saved to.
+    project_path: Option<PathBuf>,
 }
 
 impl State {
     /// Create a new server.
-    pub fn new(jack_adapter: jack_adapter::JackAdapter) -> State {
-        let mut ok_sound =
-            audio_engine::plugin::SampleTrigger::from_wav(Path::new("resources/beep.wav"));
+    pub fn new(adapter: Box<dyn jack_adapter::AudioAdapter>, config: &StateConfig) -> State {
+        let mut ok_sound = audio_engine::plugin::SampleTrigger::from_wav_or_default(
+            &config.resources_dir.join("beep.wav"),
+            adapter.sample_rate(),
+            audio_engine::resources::BEEP_WAV,
+        );
         ok_sound.start();
+        let blacklist = match config.blacklist_path.as_ref() {
+            Some(path) => blacklist::Blacklist::load(path).unwrap_or_else(|err| {
+                // The file is not overwritten so that it may be fixed by hand.
+                error!("Failed to load the plugin blacklist: {}", err);
+                blacklist::Blacklist::default()
+            }),
+            None => blacklist::Blacklist::default(),
+        };
         State {
-            jack_adapter,
+            adapter,
             state: InnerState {
                 cpu: String::new(),
                 metronome: Metronome {
                     beats_per_minute: 120.0,
                     volume: 0.0,
+                    swing: 0.5,
+                    external_sync: false,
                 },
                 tracks: Vec::new(),
+                master: Track {
+                    id: MASTER_TRACK_ID,
+                    name: "Master".to_string(),
+                    plugins: Vec::new(),
+                    properties: TrackProperties {
+                        armed: false,
+                        monitoring: false,
+                        midi_source: None,
+                        midi_channel: None,
+                        transpose: 0,
+                        send_midi: false,
+                        solo: false,
+                        pan: 0.0,
+                        volume: 0.5,
+                        disabled: false,
+                    },
+                    clip: None,
+                    step_pattern: None,
+                    frozen: None,
+                    plugin_error: None,
+                },
+                master_volume: 1.0,
+                limiter_enabled: true,
+                last_clip: None,
+                meters: HashMap::new(),
+                meters_updated: Instant::now(),
                 armed_track: None,
+                metronome_sample: None,
+                midi_clock: false,
+                external_tempo: None,
+                external_clock_lost: false,
+                follow_jack_transport: false,
+                jack_timebase_master: false,
                 next_track_id: 1,
                 time_info: audio_engine::metronome::SampleTimeInfo {
                     measure: 0,
                     beat: 0,
                     sub_beat: 0.0,
                 },
+                playing: false,
+                counting_in: false,
+                recording: false,
+                recording_mode: RecordingMode::Midi,
+                sample_recording: None,
+                master_recording_stats: (0, 0),
+                taps: Vec::with_capacity(TAP_TEMPO_TAPS),
+                midi_learn: None,
+                midi_mappings: Vec::new(),
+                project_path: None,
             },
             ok_sound,
+            plugin_costs: Arc::new(Mutex::new(HashMap::new())),
+            master_recording: None,
+            resources_dir: config.resources_dir.clone(),
+            autosaving: Arc::new(AtomicBool::new(false)),
+            bouncing: Arc::new(AtomicBool::new(false)),
+            profiling: Arc::new(AtomicBool::new(false)),
+            history: history::History::default(),
+            blacklist: Mutex::new(blacklist),
         }
     }
 
+    /// Resolve a path to a sound or sample. Relative paths are resolved
+    /// against the resources directory.
+    pub fn resolve_resource(&self, path: &Path) -> PathBuf {
+        self.resources_dir.join(path)
+    }
+
     pub fn play_sound(&self) {
-        self.jack_adapter
-            .audio_engine
+        // The sound is only feedback so it may be dropped.
+        let _ = self.send_command(Command::PlaySound(self.ok_sound.clone()));
+    }
+
+    /// Send a command to the audio engine without blocking.
+    fn send_command(&self, command: Command) -> Result<(), Error> {
+        if self.adapter.is_shut_down() {
+            return Err(Error::EngineUnavailable);
+        }
+        self.adapter
+            .audio_engine()
             .commands
-            .send(Command::PlaySound(self.ok_sound.clone()))
-            .unwrap();
+            .send(command)
+            .map_err(|_| Error::EngineUnavailable)
+    }
+
+    /// Send a command to the audio engine and log a warning if it could not be
+    /// sent. This is used for changes that are kept in the state and restored
+    /// on `reconnect`.
+    fn send_command_or_warn(&self, command: Command) {
+        if let Err(err) = self.send_command(command) {
+            warn!("Failed to send command to the audio engine: {}", err);
+        }
+    }
+
+    /// Get the index of the track with `track_id` in `self.state.tracks`.
+    fn track_index(&self, track_id: i32) -> Result<usize, Error> {
+        self.state
+            .tracks
+            .iter()
+            .position(|t| t.id == track_id)
+            .ok_or(Error::TrackNotFound(track_id))
     }
 
     pub fn update(&mut self) {
-        self.state.cpu = format!("CPU: {:.0}%", self.jack_adapter.cpu_load());
-        for notification in self.jack_adapter.audio_engine.notifications.try_iter() {
+        self.state.cpu = format!("CPU: {:.0}%", self.adapter.cpu_load());
+        let now = Instant::now();
+        let elapsed = now - self.state.meters_updated;
+        self.state.meters_updated = now;
+        for meter in self.state.meters.values_mut() {
+            meter.decay(now, elapsed);
+        }
+        let mut controls = Vec::new();
+        let mut samples = Vec::new();
+        let mut recorded_audio = Vec::new();
+        for notification in self.adapter.audio_engine().notifications.try_iter() {
             match notification {
                 audio_engine::commands::Notifications::TimeInfo(time_info) => {
                     self.state.time_info = time_info;
                 }
+                audio_engine::commands::Notifications::CountInFinished => {
+                    self.state.counting_in = false;
+                }
+                audio_engine::commands::Notifications::ExternalTempo(beats_per_minute) => {
+                    self.state.external_tempo = Some(beats_per_minute);
+                    self.state.external_clock_lost = false;
+                }
+                audio_engine::commands::Notifications::ExternalClockLost { beats_per_minute } => {
+                    warn!("Lost the external midi clock, using {beats_per_minute:.1} BPM.");
+                    self.state.external_tempo = Some(beats_per_minute);
+                    self.state.external_clock_lost = true;
+                }
+                audio_engine::commands::Notifications::ExternalTransport { playing } => {
+                    if !playing && self.state.recording {
+                        self.send_command_or_warn(Command::StopRecording);
+                        self.state.recording = false;
+                    }
+                    self.state.playing = playing;
+                    self.state.counting_in = false;
+                }
+                audio_engine::commands::Notifications::MidiCc { cc, channel, value } => {
+                    controls.push((cc, channel, value))
+                }
+                audio_engine::commands::Notifications::ClipDetected { peak, .. } => {
+                    self.state.last_clip = Some((Instant::now(), peak))
+                }
+                audio_engine::commands::Notifications::RecordingStats {
+                    frames_written,
+                    overruns,
+                } => self.state.master_recording_stats = (frames_written, overruns),
+                audio_engine::commands::Notifications::PluginError {
+                    track_id,
+                    plugin_index,
+                    error,
+                } => {
+                    let track = if track_id == MASTER_TRACK_ID {
+                        Some(&mut self.state.master)
+                    } else {
+                        self.state.tracks.iter_mut().find(|t| t.id == track_id)
+                    };
+                    match track {
+                        Some(t) => {
+                            let err = Error::PluginProcess {
+                                name: t
+                                    .plugins
+                                    .get(plugin_index)
+                                    .map(|p| p.plugin_id.clone())
+                                    .unwrap_or_default(),
+                                source: error,
+                            };
+                            error!("Disabled track {track_id}: {err}");
+                            t.properties.disabled = true;
+                            t.plugin_error = Some((plugin_index, err.to_string()));
+                        }
+                        None => warn!("Plugin error for missing track {track_id}."),
+                    }
+                }
+                audio_engine::commands::Notifications::Disposed(disposed) => drop(disposed),
+                audio_engine::commands::Notifications::Meters {
+                    track_levels,
+                    master,
+                } => {
+                    let levels = track_levels
+                        .iter()
+                        .copied()
+                        .chain(std::iter::once((MASTER_TRACK_ID, master)));
+                    for (track_id, level) in levels {
+                        let peak = level.peak[0].max(level.peak[1]);
+                        self.state
+                            .meters
+                            .entry(track_id)
+                            .or_default()
+                            .feed(now, peak);
+                    }
+                }
+                audio_engine::commands::Notifications::RecordedAudio(track_id, channels) => {
+                    match self.state.tracks.iter().find(|t| t.id == track_id) {
+                        Some(_) if channels.iter().any(|c| !c.is_empty()) => {
+                            recorded_audio.push((track_id, channels));
+                        }
+                        Some(_) => info!("No audio was recorded for track {track_id}."),
+                        None => warn!("Recorded audio for missing track {track_id}."),
+                    }
+                }
+                audio_engine::commands::Notifications::RecordedSample(track_id, channels) => {
+                    self.state.sample_recording = None;
+                    samples.push((track_id, channels));
+                }
+                audio_engine::commands::Notifications::RecordedClip(track_id, events) => {
+                    match self.state.tracks.iter().position(|t| t.id == track_id) {
+                        Some(index) if !events.is_empty() => {
+                            self.send_command_or_warn(Command::SetTrackClip(
+                                track_id,
+                                Clip::new(events.clone()),
+                            ));
+                            let t = &mut self.state.tracks[index];
+                            t.clip = Some(events);
+                            t.step_pattern = None;
+                        }
+                        Some(_) => info!("Nothing was recorded for track {track_id}."),
+                        None => warn!("Recorded clip for missing track {track_id}."),
+                    }
+                }
+            }
+        }
+        for (cc, channel, value) in controls {
+            self.handle_midi_cc(cc, channel, value);
+        }
+        for (track_id, channels) in recorded_audio {
+            let sample = audio_engine::audio_buffer::AudioBuffer::from_channels(&channels);
+            if let Err(err) = self.push_sample_plugin(track_id, sample) {
+                error!("Failed to add the audio recorded for track {track_id}: {err}");
+            }
+        }
+        for (track_id, channels) in samples {
+            if let Err(err) = self.add_recorded_sample(track_id, channels) {
+                error!("Failed to add the sample recorded for track {track_id}: {err}");
             }
         }
     }
@@ -71,129 +457,2294 @@ impl State {
         &self.state.cpu
     }
 
+    /// Get the number of xruns since connecting to JACK.
+    pub fn xruns(&self) -> u64 {
+        self.adapter.xruns()
+    }
+
+    /// Get the sample rate of the audio engine.
+    pub fn sample_rate(&self) -> f64 {
+        self.adapter.sample_rate()
+    }
+
+    /// Get the buffer size of the audio engine.
+    pub fn buffer_size(&self) -> usize {
+        self.adapter.buffer_size()
+    }
+
+    /// Get the names of the physical ports that audio can be played through.
+    pub fn physical_audio_outputs(&self) -> Vec<String> {
+        self.adapter.physical_audio_outputs()
+    }
+
+    /// Connect the audio outputs to the physical ports in `dsts`.
+    pub fn connect_audio_outputs(&self, dsts: &[String]) {
+        self.adapter.connect_audio_outputs(dsts);
+    }
+
+    /// Get the counters describing the health of the audio engine.
+    pub fn engine_stats(&self) -> &audio_engine::notifier::EngineStats {
+        &self.adapter.audio_engine().stats
+    }
+
+    /// Set the minimum interval between time info updates.
+    pub fn set_time_info_interval(&self, interval: Duration) {
+        self.send_command_or_warn(Command::SetTimeInfoInterval(interval));
+    }
+
+    /// Set the minimum interval between meter updates.
+    pub fn set_meter_interval(&self, interval: Duration) {
+        self.send_command_or_warn(Command::SetMeterInterval(interval));
+    }
+
     /// Get the current time info.
     pub fn time_info(&self) -> SampleTimeInfo {
         self.state.time_info
     }
 
-    /// Get the plugins.
-    pub fn get_plugins(&self) -> Vec<Plugin> {
-        let plugins = self
-            .jack_adapter
-            .audio_engine
-            .livi
-            .iter_plugins()
-            .map(|p| Plugin {
-                id: id_for_plugin(&p),
-                name: p.name(),
-                class: if p.is_instrument() {
-                    PluginClass::Instrument
-                } else {
-                    PluginClass::Effect
-                },
-            })
-            .collect();
-        plugins
+    /// Start playback.
+    pub fn play(&mut self) {
+        self.send_command_or_warn(Command::TransportPlay);
+        if self.state.follow_jack_transport {
+            if let Err(err) = self.adapter.start_transport() {
+                warn!("Failed to start the JACK transport: {}", err);
+            }
+        }
+        self.state.playing = true;
     }
 
-    /// Set the metronome parameters.
-    pub fn set_metronome(&mut self, metronome: Metronome) {
-        self.jack_adapter
-            .audio_engine
-            .commands
-            .send(Command::SetMetronome {
-                volume: metronome.volume,
-                beats_per_minute: metronome.beats_per_minute,
-            })
-            .unwrap();
-        self.state.metronome = metronome;
+    /// Start recording the input of the armed track. Depending on the
+    /// recording mode, either midi or audio is captured while playback is in
+    /// progress until `stop` is called.
+    pub fn start_recording(&mut self) -> Result<(), Error> {
+        let track_id = match self.state.armed_track {
+            Some(id) => id,
+            None => return Err(Error::invalid("no track is armed for recording")),
+        };
+        let command = match self.state.recording_mode {
+            RecordingMode::Midi => {
+                Command::StartRecording(track_id, Vec::with_capacity(RECORDING_CAPACITY))
+            }
+            RecordingMode::Audio => {
+                let frames = AUDIO_RECORDING_SECONDS * self.adapter.sample_rate() as usize;
+                Command::StartAudioRecording(
+                    track_id,
+                    vec![Vec::with_capacity(frames), Vec::with_capacity(frames)],
+                )
+            }
+        };
+        self.send_command(command)?;
+        self.state.recording = true;
+        Ok(())
     }
 
-    /// Set a track to be armed.
-    pub fn set_armed(&mut self, track_id: Option<i32>) {
-        self.state.armed_track = track_id;
-        let track_id = track_id.unwrap_or(-1);
-        self.jack_adapter
-            .audio_engine
-            .commands
-            .send(Command::ArmTrack(track_id))
-            .unwrap();
+    /// Start recording the audio input into a new sample for a track. The
+    /// input is captured whether or not the transport is playing, for up to
+    /// `max_seconds` or until `stop_sample_recording` is called. The sample is
+    /// then trimmed of leading silence and added to the end of the track as a
+    /// sampler plugin that plays the sample chromatically from C4.
+    pub fn record_sample(&mut self, track_id: i32, max_seconds: f32) -> Result<(), Error> {
+        self.track_index(track_id)?;
+        if self.state.sample_recording.is_some() {
+            return Err(Error::invalid("a sample is already being recorded"));
+        }
+        if !max_seconds.is_finite() || max_seconds <= 0.0 {
+            return Err(Error::invalid(format!(
+                "{max_seconds} is not a valid recording length"
+            )));
+        }
+        let frames = (max_seconds as f64 * self.adapter.sample_rate()).ceil() as usize;
+        self.send_command(Command::StartSampleRecording(
+            track_id,
+            vec![Vec::with_capacity(frames), Vec::with_capacity(frames)],
+        ))?;
+        self.state.sample_recording = Some(track_id);
+        Ok(())
     }
 
-    /// Add a plugin to a track.
-    pub fn add_plugin_to_track(&mut self, track_id: i32, plugin_id: &str) -> Result<(), String> {
-        let plugin = match self
-            .jack_adapter
-            .audio_engine
-            .livi
-            .iter_plugins()
-            .find(|p| id_for_plugin(p) == plugin_id)
-        {
-            Some(p) => p,
-            None => {
-                return Err(format!("plugin {plugin_id} not found"));
-            }
+    /// Stop recording a sample. The sample is added to its track by `update`
+    /// once the audio engine sends it back.
+    pub fn stop_sample_recording(&mut self) -> Result<(), Error> {
+        if self.state.sample_recording.is_none() {
+            return Err(Error::invalid("no sample is being recorded"));
+        }
+        self.send_command(Command::StopSampleRecording)
+    }
+
+    /// Get the track that a sample is being recorded for.
+    pub fn sample_recording_track(&self) -> Option<i32> {
+        self.state.sample_recording
+    }
+
+    /// Trim the leading silence from a recorded sample and add it to the end
+    /// of a track as a sampler plugin.
+    fn add_recorded_sample(
+        &mut self,
+        track_id: i32,
+        mut channels: Vec<Vec<f32>>,
+    ) -> Result<(), Error> {
+        let start = channels
+            .iter()
+            .filter_map(|c| c.iter().position(|v| v.abs() > SAMPLE_SILENCE_THRESHOLD))
+            .min();
+        let start = match start {
+            Some(start) => start,
+            None => return Err(Error::invalid("the recorded sample is silent")),
         };
-        let track = match self.state.tracks.iter_mut().find(|t| t.id == track_id) {
-            Some(t) => t,
-            None => {
-                return Err(format!("track {track_id} not found"));
-            }
+        for channel in channels.iter_mut() {
+            channel.drain(..start);
+        }
+        let sample = audio_engine::audio_buffer::AudioBuffer::from_channels(&channels);
+        self.push_sample_plugin(track_id, sample)
+    }
+
+    /// Add `sample` to the end of a track as a sampler plugin.
+    fn push_sample_plugin(
+        &mut self,
+        track_id: i32,
+        sample: audio_engine::audio_buffer::AudioBuffer,
+    ) -> Result<(), Error> {
+        self.push_plugin(
+            track_id,
+            TrackPlugin {
+                plugin_id: SAMPLER_PLUGIN_ID.to_string(),
+                param_values: HashMap::new(),
+                drum_kit: None,
+                sample: Some(Arc::new(sample)),
+                missing: false,
+                lv2_state: None,
+                lv2_state_handle: None,
+            },
+        )
+    }
+
+    /// Start recording the output to a 32 bit float WAV file at `path`. The
+    /// output is written to disk on a separate thread.
+    pub fn start_master_recording(&mut self, path: &Path) -> Result<(), Error> {
+        if self.master_recording.is_some() {
+            return Err(Error::invalid("the output is already being recorded"));
+        }
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: self.adapter.sample_rate() as u32,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
         };
-        let instance_or_err = unsafe {
-            plugin.instantiate(
-                self.jack_adapter.audio_engine.lv2_features.clone(),
-                self.jack_adapter.sample_rate(),
-            )
+        let mut wav = hound::WavWriter::create(path, spec).map_err(|err| Error::file(path, err))?;
+        let capacity = MASTER_RECORDING_BUFFER_SECONDS * spec.sample_rate as usize;
+        let (recorder, mut consumer) = audio_engine::recorder::MasterRecorder::new(capacity);
+        let finished = recorder.finished_flag();
+        let writer_finished = finished.clone();
+        let stop = Arc::new(AtomicBool::new(false));
+        let writer_stop = stop.clone();
+        let writer_path = path.to_path_buf();
+        let writer = std::thread::spawn(move || {
+            let mut buffer = vec![0f32; capacity];
+            loop {
+                // Frames pushed before the flags were set are drained before
+                // stopping.
+                let stopping =
+                    writer_finished.load(Ordering::Acquire) || writer_stop.load(Ordering::Acquire);
+                let len = consumer.pop_slice(&mut buffer);
+                for sample in buffer[..len].iter() {
+                    wav.write_sample(*sample)
+                        .map_err(|err| Error::file(&writer_path, err))?;
+                }
+                if len == 0 {
+                    if stopping {
+                        break;
+                    }
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+            }
+            wav.finalize().map_err(|err| Error::file(&writer_path, err))
+        });
+        if let Err(err) = self.send_command(Command::StartMasterRecord(recorder)) {
+            stop.store(true, Ordering::Release);
+            let _ = writer.join();
+            return Err(err);
+        }
+        self.state.master_recording_stats = (0, 0);
+        self.master_recording = Some(MasterRecording {
+            finished,
+            stop,
+            writer,
+        });
+        Ok(())
+    }
+
+    /// Stop recording the output and wait for the file to be written.
+    pub fn stop_master_recording(&mut self) -> Result<(), Error> {
+        let recording = match self.master_recording.take() {
+            Some(r) => r,
+            None => return Err(Error::invalid("the output is not being recorded")),
         };
-        let instance = match instance_or_err {
-            Ok(i) => i,
+        // The writer finishes once the engine stops pushing frames. It is
+        // stopped anyway if the engine does not respond so that the file is
+        // still finalized.
+        let acknowledged = match self.send_command(Command::StopMasterRecord) {
+            Ok(()) if !self.adapter.is_shut_down() => {
+                let deadline = Instant::now() + MASTER_RECORDING_STOP_TIMEOUT;
+                while !recording.finished.load(Ordering::Acquire) && Instant::now() < deadline {
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+                recording.finished.load(Ordering::Acquire)
+            }
+            Ok(()) => false,
             Err(err) => {
-                return Err(format!(
-                    "failed to instantiate plugin {}: {}",
-                    plugin.name(),
-                    err
-                ))
+                warn!("Failed to send command to the audio engine: {}", err);
+                false
             }
         };
-        self.jack_adapter
-            .audio_engine
-            .commands
-            .send(Command::AddPluginToTrack(track.id, instance.into()))
-            .unwrap();
-        track.plugins.push(TrackPlugin {
-            plugin_id: plugin_id.to_string(),
+        if !acknowledged {
+            warn!("The audio engine did not stop recording the output, the end may be missing.");
+            recording.stop.store(true, Ordering::Release);
+        }
+        match recording.writer.join() {
+            Ok(res) => res,
+            Err(_) => Err(Error::invalid("the output writer panicked")),
+        }
+    }
+
+    /// Returns true if the output is being recorded.
+    pub fn is_master_recording(&self) -> bool {
+        self.master_recording.is_some()
+    }
+
+    /// Get the number of frames written and the number of frames dropped while
+    /// recording the output.
+    pub fn master_recording_stats(&self) -> (u64, u64) {
+        self.state.master_recording_stats
+    }
+
+    /// Render the first `measures` measures of the project to a 32 bit float
+    /// WAV file at `path` without going through JACK. The master plugins,
+    /// master volume and limiter are applied like they are to the live output.
+    /// The metronome is not included.
+    ///
+    /// The tracks and plugins are instantiated again on the calling thread
+    /// and rendered as fast as possible on a background thread. `progress` is
+    /// called with the fraction rendered so far and `callback` is called with
+    /// the result once the file has been written.
+    pub fn bounce(
+        &self,
+        path: &Path,
+        measures: u32,
+        mut progress: Box<dyn Send + FnMut(f32)>,
+        callback: Box<dyn Send + FnOnce(Result<(), Error>)>,
+    ) -> Result<(), Error> {
+        if measures == 0 {
+            return Err(Error::invalid("at least one measure must be bounced"));
+        }
+        let sample_rate = self.adapter.sample_rate();
+        let buffer_size = self.adapter.buffer_size();
+        let config = audio_engine::ProcessorConfig {
+            commands_capacity: self.state.tracks.len() + self.state.master.plugins.len() + 4,
+            ..audio_engine::ProcessorConfig::default()
+        };
+        let (mut processor, communicator) = audio_engine::Processor::with_world(
+            sample_rate,
+            buffer_size,
+            &config,
+            self.adapter.audio_engine().plugins.clone(),
+            self.adapter.audio_engine().lv2_features.clone(),
+        );
+        self.send_tracks(&communicator.commands, buffer_size)?;
+        for command in [
+            Command::SetMetronome {
+                volume: 0.0,
+                beats_per_minute: self.state.metronome.beats_per_minute,
+                swing: self.state.metronome.swing,
+                apply_on_downbeat: false,
+                external_sync: false,
+            },
+            Command::SetMasterVolume(self.state.master_volume),
+            Command::SetLimiter(self.state.limiter_enabled),
+            Command::TransportPlay,
+        ] {
+            communicator
+                .commands
+                .send(command)
+                .map_err(|_| Error::EngineUnavailable)?;
+        }
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: sample_rate as u32,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let mut wav = hound::WavWriter::create(path, spec).map_err(|err| Error::file(path, err))?;
+        let path = path.to_path_buf();
+        let beats = measures as f64 * audio_engine::metronome::BEATS_PER_MEASURE;
+        let seconds = beats * 60.0 / self.state.metronome.beats_per_minute as f64;
+        let frames = (seconds * sample_rate).round() as usize;
+        let bouncing = self.bouncing.clone();
+        bouncing.store(true, Ordering::Release);
+        std::thread::spawn(move || {
+            audio_engine::denormals::flush_denormals_to_zero();
+            let render = || -> Result<(), Error> {
+                let mut rendered = 0;
+                while rendered < frames {
+                    let samples = buffer_size.min(frames - rendered);
+                    let output = processor.process(samples, std::iter::empty(), &[]);
+                    let mut channels = output.iter_channels();
+                    let (left, right) = (channels.next().unwrap(), channels.next().unwrap());
+                    for (l, r) in left.iter().zip(right.iter()) {
+                        wav.write_sample(*l)
+                            .map_err(|err| Error::file(&path, err))?;
+                        wav.write_sample(*r)
+                            .map_err(|err| Error::file(&path, err))?;
+                    }
+                    communicator.notifications.try_iter().for_each(drop);
+                    rendered += samples;
+                    progress(rendered as f32 / frames as f32);
+                }
+                wav.finalize().map_err(|err| Error::file(&path, err))
+            };
+            callback(render());
+            bouncing.store(false, Ordering::Release);
         });
-        self.play_sound();
         Ok(())
     }
 
-    /// Remove a plugin from a track.
-    pub fn remove_plugin_from_track(
-        &mut self,
-        track_id: i32,
-        plugin_index: usize,
-    ) -> Result<(), String> {
-        let track = match self.state.tracks.iter_mut().find(|t| t.id == track_id) {
-            Some(t) => t,
-            None => return Err(format!("track {track_id} not found")),
-        };
-        if plugin_index >= track.plugins.len() {
-            return Err(format!(
-                "track {track_id} does not a plugin at index {plugin_index}"
-            ));
-        }
-        self.jack_adapter
-            .audio_engine
-            .commands
-            .send(Command::DeletePlugin(track_id, plugin_index))
-            .unwrap();
-        track.plugins.remove(plugin_index);
-        self.play_sound();
-        Ok(())
+    /// Instantiate the tracks and master plugins again and send them through
+    /// `commands` to a processor that has no tracks.
+    ///
+    /// Returns the LV2 State handles of the new instances of the track plugins
+    /// followed by those of the master plugins.
+    fn send_tracks(
+        &self,
+        commands: &audio_engine::channel::Sender<Command>,
+        buffer_size: usize,
+    ) -> Result<Vec<Option<Arc<Lv2StateHandle>>>, Error> {
+        let mut handles = Vec::new();
+        for track in self.state.tracks.iter() {
+            let audio_engine_track = self.engine_track(track, buffer_size)?;
+            handles.extend((0..track.plugins.len()).map(|index| {
+                audio_engine_track
+                    .plugin(index)
+                    .and_then(PluginInstance::lv2_state_handle)
+            }));
+            commands
+                .send(Command::AddTrack(Box::new(audio_engine_track)))
+                .map_err(|_| Error::EngineUnavailable)?;
+        }
+        for plugin in self.state.master.plugins.iter() {
+            let instance = self.instantiate_track_plugin(plugin)?;
+            handles.push(instance.lv2_state_handle());
+            commands
+                .send(Command::AddPluginToMaster(instance))
+                .map_err(|_| Error::EngineUnavailable)?;
+        }
+        Ok(handles)
+    }
+
+    /// Create the audio engine track for `track` with new instances of its
+    /// plugins.
+    fn engine_track(
+        &self,
+        track: &Track,
+        buffer_size: usize,
+    ) -> Result<audio_engine::track::Track, Error> {
+        let mut audio_engine_track = audio_engine::track::Track::new(track.id, buffer_size);
+        audio_engine_track.properties.armed = track.properties.armed;
+        audio_engine_track.properties.monitoring = track.properties.monitoring;
+        audio_engine_track.properties.midi_source = track.properties.midi_source;
+        audio_engine_track.properties.midi_channel = track.properties.midi_channel;
+        audio_engine_track.properties.transpose = track.properties.transpose;
+        audio_engine_track.properties.send_midi = track.properties.send_midi;
+        audio_engine_track.properties.solo = track.properties.solo;
+        audio_engine_track.properties.pan = track.properties.pan;
+        audio_engine_track.properties.volume = track.properties.volume;
+        if let Some(events) = track.clip.as_ref() {
+            audio_engine_track.set_clip(Clip::new(events.clone()));
+        }
+        audio_engine_track.set_frozen(track.frozen.clone());
+        for plugin in track.plugins.iter() {
+            audio_engine_track.push_plugin(self.instantiate_track_plugin(plugin)?);
+        }
+        Ok(audio_engine_track)
+    }
+
+    /// Returns true if the JACK server has shut down and no audio is being
+    /// processed. Use `reconnect` to recover.
+    pub fn is_disconnected(&self) -> bool {
+        self.adapter.is_shut_down()
+    }
+
+    /// Connect to the JACK server again and rebuild the audio engine from the
+    /// tracks and plugins. The plugins are restored with their parameters and
+    /// LV2 State. Transport and recording are lost.
+    pub fn reconnect(&mut self) -> Result<(), Error> {
+        if self.master_recording.is_some() {
+            if let Err(err) = self.stop_master_recording() {
+                warn!("Failed to stop recording the output: {}", err);
+            }
+        }
+        let features = self.adapter.audio_engine().lv2_features.clone();
+        for plugin in self.all_plugins_mut() {
+            plugin.lv2_state = plugin.current_lv2_state(&features);
+        }
+        self.adapter.reconnect().map_err(Error::Jack)?;
+        info!("Reconnected to JACK.");
+        self.state.playing = false;
+        self.state.counting_in = false;
+        self.state.recording = false;
+        self.state.sample_recording = None;
+        let handles = self.send_tracks(
+            &self.adapter.audio_engine().commands,
+            self.adapter.buffer_size(),
+        )?;
+        for (plugin, handle) in self.all_plugins_mut().zip(handles) {
+            plugin.lv2_state_handle = handle;
+        }
+        for track in self
+            .state
+            .tracks
+            .iter_mut()
+            .chain(std::iter::once(&mut self.state.master))
+        {
+            track.properties.disabled = false;
+            track.plugin_error = None;
+        }
+        self.send_command(Command::SetMetronome {
+            volume: self.state.metronome.volume,
+            beats_per_minute: self.state.metronome.beats_per_minute,
+            swing: self.state.metronome.swing,
+            apply_on_downbeat: false,
+            external_sync: self.state.metronome.external_sync,
+        })?;
+        self.send_command(Command::SetMasterVolume(self.state.master_volume))?;
+        self.send_command(Command::SetLimiter(self.state.limiter_enabled))?;
+        self.send_command(Command::SetMidiClock(self.state.midi_clock))?;
+        self.send_command(Command::SetFollowTransport(
+            self.state.follow_jack_transport,
+        ))?;
+        self.adapter
+            .set_timebase_beats_per_minute(self.state.metronome.beats_per_minute);
+        if self.state.jack_timebase_master {
+            if let Err(err) = self.adapter.set_timebase_master(true) {
+                warn!("Failed to restore the JACK timebase master: {}", err);
+                self.state.jack_timebase_master = false;
+            }
+        }
+        self.send_command(Command::ArmTrack(self.state.armed_track.unwrap_or(-1)))?;
+        if let Some(path) = self.state.metronome_sample.clone() {
+            if let Err(err) = self.set_metronome_sample(&path) {
+                warn!("Failed to restore the metronome sample: {}", err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Save the tracks, their plugins and clips, the master track, the
+    /// metronome and the midi mappings to `path`. The samples of sampler plugins are saved as WAV
+    /// files in `project::samples_dir`. LV2 plugins are saved with their
+    /// parameters and, if they support the LV2 State extension, their state.
+    /// The file is replaced only once the new project has been written
+    /// completely.
+    pub fn save_project(&mut self, path: &Path) -> Result<(), Error> {
+        let samples = project::samples(&self.state);
+        let project =
+            project::Project::from_state(&self.state, &self.adapter.audio_engine().lv2_features);
+        project::save(path, &project, &samples, self.adapter.sample_rate())?;
+        info!("Saved project to {:?}.", path);
+        self.state.project_path = Some(path.to_path_buf());
+        Ok(())
+    }
+
+    /// Get the file the project was last saved to or loaded from.
+    pub fn project_path(&self) -> Option<&Path> {
+        self.state.project_path.as_deref()
+    }
+
+    /// Save the project to a new file in `dir` on a background thread and
+    /// delete all but the newest `keep` autosaves. Returns false without
+    /// saving if a previous autosave or a bounce is still in progress.
+    pub fn autosave(&self, dir: &Path, keep: usize) -> bool {
+        if self.bouncing.load(Ordering::Acquire) || self.autosaving.swap(true, Ordering::AcqRel) {
+            return false;
+        }
+        let project =
+            project::Project::from_state(&self.state, &self.adapter.audio_engine().lv2_features);
+        let samples = project::samples(&self.state);
+        let sample_rate = self.adapter.sample_rate();
+        let dir = dir.to_path_buf();
+        let autosaving = self.autosaving.clone();
+        std::thread::spawn(move || {
+            let path = project::autosave_path(&dir);
+            let res = std::fs::create_dir_all(&dir)
+                .map_err(|err| Error::file(&dir, err))
+                .and_then(|()| project::save(&path, &project, &samples, sample_rate));
+            match res {
+                Ok(()) => info!("Autosaved project to {:?}.", path),
+                Err(err) => warn!("Failed to autosave the project: {}", err),
+            }
+            let autosaves = project::autosaves(&dir);
+            for old in autosaves.iter().take(autosaves.len().saturating_sub(keep)) {
+                if let Err(err) = std::fs::remove_file(old) {
+                    warn!("Failed to remove old autosave {:?}: {}", old, err);
+                }
+                if let Err(err) = project::remove_samples(old) {
+                    warn!(
+                        "Failed to remove the samples of old autosave {:?}: {}",
+                        old, err
+                    );
+                }
+            }
+            autosaving.store(false, Ordering::Release);
+        });
+        true
+    }
+
+    /// Get the newest autosave in `dir`, if any.
+    pub fn latest_autosave(dir: &Path) -> Option<PathBuf> {
+        project::autosaves(dir).pop()
+    }
+
+    /// Replace the tracks, the master track, the metronome and the midi
+    /// mappings with the project saved at `path`. Playback and recording are
+    /// stopped.
+    ///
+    /// Returns the errors for any plugins that could not be instantiated.
+    /// These plugins are kept as missing placeholders that pass audio through
+    /// so that saving the project again keeps them.
+    pub fn load_project(&mut self, path: &Path) -> Result<Vec<Error>, Error> {
+        // Loading before the scan finishes would replace every plugin with a
+        // missing placeholder.
+        self.livi()?;
+        let project = project::Project::read(path)?;
+        let tracks = project
+            .tracks
+            .iter()
+            .map(project::ProjectTrack::to_track)
+            .collect::<Result<Vec<_>, _>>()?;
+        self.stop();
+        self.delete_tracks(self.state.tracks.iter().map(|t| t.id).collect())?;
+        for plugin_index in (0..self.state.master.plugins.len()).rev() {
+            self.remove_plugin_from_master(plugin_index)?;
+        }
+        self.set_metronome(
+            Metronome {
+                beats_per_minute: project.metronome.beats_per_minute,
+                volume: project.metronome.volume,
+                swing: project.metronome.swing,
+                external_sync: self.state.metronome.external_sync,
+            },
+            false,
+        );
+        self.set_master_volume(project.master_volume);
+        self.set_limiter(project.limiter_enabled);
+        let mut errors = Vec::new();
+        for plugin in project.master_plugins.iter() {
+            self.restore_plugin(path, MASTER_TRACK_ID, plugin, &mut errors)?;
+        }
+        let buffer_size = self.adapter.buffer_size();
+        let mut track_ids = HashMap::new();
+        for (track, project_track) in tracks.into_iter().zip(project.tracks.iter()) {
+            let mut audio_engine_track = audio_engine::track::Track::new(track.id, buffer_size);
+            audio_engine_track.properties.midi_source = track.properties.midi_source;
+            audio_engine_track.properties.midi_channel = track.properties.midi_channel;
+            audio_engine_track.properties.transpose = track.properties.transpose;
+            audio_engine_track.properties.send_midi = track.properties.send_midi;
+            audio_engine_track.properties.solo = track.properties.solo;
+            audio_engine_track.properties.pan = track.properties.pan;
+            audio_engine_track.properties.volume = track.properties.volume;
+            if let Some(events) = track.clip.as_ref() {
+                audio_engine_track.set_clip(Clip::new(events.clone()));
+            }
+            self.send_command(Command::AddTrack(Box::new(audio_engine_track)))?;
+            let track_id = track.id;
+            track_ids.insert(project_track.id, track_id);
+            self.state.next_track_id = self.state.next_track_id.max(track_id + 1);
+            self.state.tracks.push(track);
+            for plugin in project_track.plugins.iter() {
+                self.restore_plugin(path, track_id, plugin, &mut errors)?;
+            }
+        }
+        self.restore_midi_mappings(&project.midi_mappings, &track_ids);
+        self.state.project_path = Some(path.to_path_buf());
+        self.history.clear();
+        info!(
+            "Loaded project from {:?} with {} missing plugins.",
+            path,
+            errors.len()
+        );
+        Ok(errors)
+    }
+
+    /// Add a plugin that was saved in the project at `path` to the end of a
+    /// track. If the plugin or its sample can not be loaded, a missing
+    /// placeholder is added instead and the error is added to `errors`.
+    fn restore_plugin(
+        &mut self,
+        path: &Path,
+        track_id: i32,
+        project_plugin: &project::ProjectPlugin,
+        errors: &mut Vec<Error>,
+    ) -> Result<(), Error> {
+        let mut plugin = project_plugin.to_track_plugin();
+        let res = match project_plugin.load_sample(path, self.adapter.sample_rate()) {
+            Ok(sample) => {
+                plugin.sample = sample.map(Arc::new);
+                self.push_plugin(track_id, plugin.clone())
+            }
+            Err(err) => Err(err),
+        };
+        match res {
+            Ok(()) => Ok(()),
+            Err(Error::EngineUnavailable) => Err(Error::EngineUnavailable),
+            Err(err) => {
+                warn!("Plugin {} is missing: {}", plugin.plugin_id, err);
+                errors.push(err);
+                plugin.missing = true;
+                self.push_plugin(track_id, plugin)
+            }
+        }
+    }
+
+    /// Replace the midi mappings with `mappings` from a project. `track_ids`
+    /// maps the saved ids of tracks to the ids of the loaded tracks. Mappings
+    /// of controls that were not loaded are dropped.
+    fn restore_midi_mappings(
+        &mut self,
+        mappings: &[project::ProjectMidiMapping],
+        track_ids: &HashMap<i32, i32>,
+    ) {
+        self.state.midi_learn = None;
+        self.state.midi_mappings.clear();
+        for project_mapping in mappings {
+            let res = match project_mapping.to_midi_mapping(track_ids) {
+                Some(mapping) => self.check_loaded_target(mapping.target).map(|()| mapping),
+                None => Err(Error::invalid("its track was not loaded")),
+            };
+            match res {
+                Ok(mapping) => self.state.midi_mappings.push(mapping),
+                Err(err) => warn!(
+                    "Dropped the mapping of controller {} on channel {}: {}",
+                    project_mapping.cc, project_mapping.channel, err
+                ),
+            }
+        }
+    }
+
+    /// Check that the track and plugin of `target` exist. Parameters are not
+    /// checked so that the mappings of missing placeholders are kept when the
+    /// project is saved again.
+    fn check_loaded_target(&self, target: MidiLearnTarget) -> Result<(), Error> {
+        match target {
+            MidiLearnTarget::MetronomeVolume | MidiLearnTarget::MasterVolume => Ok(()),
+            MidiLearnTarget::TrackVolume(track_id) => self.track_index(track_id).map(|_| ()),
+            MidiLearnTarget::PluginParam {
+                track_id,
+                plugin_index,
+                ..
+            } => {
+                let track = self.track(track_id).ok_or(Error::TrackNotFound(track_id))?;
+                if plugin_index >= track.plugins.len() {
+                    return Err(Error::PluginIndexOutOfRange {
+                        track_id,
+                        plugin_index,
+                    });
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Get the kind of input that is recorded.
+    pub fn recording_mode(&self) -> RecordingMode {
+        self.state.recording_mode
+    }
+
+    /// Set the kind of input that is recorded. This takes effect the next time
+    /// recording starts.
+    pub fn set_recording_mode(&mut self, mode: RecordingMode) {
+        self.state.recording_mode = mode;
+    }
+
+    /// Start recording the armed track and start playback from the start after
+    /// counting in for `measures` measures. Midi input for the armed track is
+    /// discarded during the count in.
+    pub fn start_recording_with_count_in(&mut self, measures: u8) -> Result<(), Error> {
+        self.start_recording()?;
+        self.send_command(Command::TransportCountIn(measures))?;
+        self.state.playing = true;
+        self.state.counting_in = measures > 0;
+        Ok(())
+    }
+
+    /// Returns true if midi is being recorded.
+    pub fn is_recording(&self) -> bool {
+        self.state.recording
+    }
+
+    /// Stop playback. The position is held.
+    pub fn stop(&mut self) {
+        self.send_command_or_warn(Command::TransportStop);
+        if self.state.follow_jack_transport {
+            if let Err(err) = self.adapter.stop_transport() {
+                warn!("Failed to stop the JACK transport: {}", err);
+            }
+        }
+        if self.state.recording {
+            self.send_command_or_warn(Command::StopRecording);
+        }
+        self.state.playing = false;
+        self.state.counting_in = false;
+        self.state.recording = false;
+    }
+
+    /// Send a midi message to the armed track as if it came from the midi
+    /// input. The message is played at the start of the next block.
+    pub fn send_midi(&self, bytes: &[u8]) -> Result<(), Error> {
+        let message = wmidi::MidiMessage::try_from(bytes)
+            .ok()
+            .and_then(wmidi::MidiMessage::drop_unowned_sysex)
+            .ok_or_else(|| Error::invalid(format!("{bytes:?} is not a valid midi message")))?;
+        self.send_command(Command::InjectMidi(message))
+    }
+
+    /// Play `note` on a track for `duration` to audition its instrument. The
+    /// note is released by the engine so it does not get stuck if the caller
+    /// goes away.
+    pub fn trigger_note(
+        &self,
+        track_id: i32,
+        note: u8,
+        velocity: u8,
+        duration: Duration,
+    ) -> Result<(), Error> {
+        let track = &self.state.tracks[self.track_index(track_id)?];
+        let livi = self.livi()?;
+        let has_instrument = track.plugins.iter().any(|plugin| {
+            plugin.plugin_id == DRUM_KIT_PLUGIN_ID
+                || plugin.plugin_id == SAMPLER_PLUGIN_ID
+                || livi
+                    .iter_plugins()
+                    .any(|p| p.is_instrument() && id_for_plugin(&p) == plugin.plugin_id)
+        });
+        if !has_instrument {
+            return Err(Error::invalid(format!(
+                "track {track_id} does not have an instrument"
+            )));
+        }
+        if track.frozen.is_some() {
+            return Err(Error::invalid(format!("track {track_id} is frozen")));
+        }
+        let note = wmidi::Note::try_from(note)
+            .map_err(|_| Error::invalid(format!("{note} is not a valid midi note")))?;
+        let velocity = wmidi::Velocity::try_from(velocity)
+            .map_err(|_| Error::invalid(format!("{velocity} is not a valid velocity")))?;
+        let frames = (duration.as_secs_f64() * self.adapter.sample_rate()).round() as usize;
+        self.send_command(Command::TriggerNote {
+            track_id,
+            note,
+            velocity,
+            frames,
+        })
+    }
+
+    /// Send all notes off and all sound off to every track. This silences
+    /// notes that are stuck because their note off was never received.
+    pub fn midi_panic(&self) -> Result<(), Error> {
+        self.send_command(Command::MidiPanic)
+    }
+
+    /// Start listening for a midi controller to map to `target`. The next
+    /// control change that is received is mapped to `target`, replacing any
+    /// previous mapping of that controller.
+    pub fn start_midi_learn(&mut self, target: MidiLearnTarget) -> Result<(), Error> {
+        match target {
+            MidiLearnTarget::MetronomeVolume | MidiLearnTarget::MasterVolume => (),
+            MidiLearnTarget::TrackVolume(track_id) => {
+                self.track_index(track_id)?;
+            }
+            MidiLearnTarget::PluginParam {
+                track_id,
+                plugin_index,
+                port_index,
+            } => {
+                self.plugin_param(track_id, plugin_index, port_index)?;
+            }
+        }
+        self.state.midi_learn = Some(target);
+        Ok(())
+    }
+
+    /// Stop listening for a midi controller without mapping it.
+    pub fn cancel_midi_learn(&mut self) {
+        self.state.midi_learn = None;
+    }
+
+    /// Get the control that the next midi controller is mapped to if midi
+    /// learn is in progress.
+    pub fn midi_learn_target(&self) -> Option<MidiLearnTarget> {
+        self.state.midi_learn
+    }
+
+    /// Get the mappings from midi controllers to controls.
+    pub fn midi_mappings(&self) -> &[MidiMapping] {
+        &self.state.midi_mappings
+    }
+
+    /// Remove the mapping of controller `cc` on `channel`.
+    pub fn remove_midi_mapping(&mut self, cc: u8, channel: u8) -> Result<(), Error> {
+        let mappings = &mut self.state.midi_mappings;
+        let len = mappings.len();
+        mappings.retain(|m| (m.cc, m.channel) != (cc, channel));
+        if mappings.len() == len {
+            return Err(Error::invalid(format!(
+                "controller {cc} on channel {channel} is not mapped"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Map a control change to the control that midi learn is waiting for, or
+    /// apply it to the control that it is already mapped to.
+    fn handle_midi_cc(&mut self, cc: u8, channel: u8, value: u8) {
+        if let Some(target) = self.state.midi_learn.take() {
+            info!("Mapped controller {cc} on channel {channel} to {target}.");
+            self.state
+                .midi_mappings
+                .retain(|m| (m.cc, m.channel) != (cc, channel));
+            self.state.midi_mappings.push(MidiMapping {
+                cc,
+                channel,
+                target,
+            });
+            return;
+        }
+        let target = match self
+            .state
+            .midi_mappings
+            .iter()
+            .find(|m| (m.cc, m.channel) == (cc, channel))
+        {
+            Some(m) => m.target,
+            None => return,
+        };
+        let fraction = value as f32 / 127.0;
+        let res = match target {
+            MidiLearnTarget::MetronomeVolume => {
+                let mut metronome = self.state.metronome.clone();
+                metronome.volume = fraction;
+                self.set_metronome(metronome, false);
+                Ok(())
+            }
+            MidiLearnTarget::TrackVolume(track_id) => self.set_track_volume(track_id, fraction),
+            MidiLearnTarget::MasterVolume => {
+                self.set_master_volume(fraction);
+                Ok(())
+            }
+            MidiLearnTarget::PluginParam {
+                track_id,
+                plugin_index,
+                port_index,
+            } => self
+                .plugin_param(track_id, plugin_index, port_index)
+                .and_then(|param| {
+                    let min = param.min_value.unwrap_or(param.default_value.min(0.0));
+                    let max = param.max_value.unwrap_or(param.default_value.max(1.0));
+                    self.set_plugin_param(
+                        track_id,
+                        plugin_index,
+                        &port_index.to_string(),
+                        min + fraction * (max - min),
+                    )
+                })
+                .map(|_| ()),
+        };
+        if let Err(err) = res {
+            warn!("Failed to apply controller {cc} on channel {channel} to {target}: {err}");
+        }
+    }
+
+    /// Remove the midi mappings of a plugin that was removed from a track and
+    /// shift the mappings of the plugins after it.
+    fn forget_plugin_mappings(&mut self, track_id: i32, removed_index: usize) {
+        let targets = self
+            .state
+            .midi_mappings
+            .iter_mut()
+            .map(|m| &mut m.target)
+            .chain(self.state.midi_learn.as_mut());
+        for target in targets {
+            if let MidiLearnTarget::PluginParam {
+                track_id: id,
+                plugin_index,
+                ..
+            } = target
+            {
+                if *id == track_id && *plugin_index > removed_index {
+                    *plugin_index -= 1;
+                }
+            }
+        }
+        let removed = |target: &MidiLearnTarget| {
+            matches!(target, MidiLearnTarget::PluginParam { track_id: id, plugin_index, .. }
+                if *id == track_id && *plugin_index == removed_index)
+        };
+        self.state.midi_mappings.retain(|m| !removed(&m.target));
+        if self.state.midi_learn.as_ref().is_some_and(removed) {
+            self.state.midi_learn = None;
+        }
+    }
+
+    /// Returns true if playback is in progress.
+    pub fn is_playing(&self) -> bool {
+        self.state.playing
+    }
+
+    /// Returns true if the metronome is counting in before recording.
+    pub fn is_counting_in(&self) -> bool {
+        self.state.counting_in
+    }
+
+    /// Move the position to the start of `beat` within `measure`. Positions
+    /// before the start are clamped to 0:0.
+    pub fn seek(&self, measure: i16, beat: i16) {
+        self.send_command_or_warn(Command::TransportSeek { measure, beat });
+    }
+
+    /// Returns true if the installed plugins have been scanned.
+    pub fn plugins_ready(&self) -> bool {
+        self.adapter.audio_engine().plugins.is_ready()
+    }
+
+    /// Get the lv2 world with the installed plugins.
+    fn livi(&self) -> Result<&Arc<livi::World>, Error> {
+        self.adapter
+            .audio_engine()
+            .plugins
+            .world()
+            .ok_or(Error::ScanningPlugins)
+    }
+
+    /// Get the plugins. The built in plugins come first followed by the lv2
+    /// plugins. Blacklisted plugins are only included if `include_blacklisted`
+    /// is true. Returns `Error::ScanningPlugins` if the installed plugins have
+    /// not been scanned yet.
+    pub fn get_plugins(&self, include_blacklisted: bool) -> Result<Vec<Plugin>, Error> {
+        let blacklist = self.blacklist.lock().unwrap();
+        let internal_plugins = internal_plugins().into_iter().map(|mut p| {
+            p.blacklisted = blacklist.contains(&p.id);
+            p
+        });
+        let lv2_plugins = self.livi()?.iter_plugins().map(|p| {
+            let id = id_for_plugin(&p);
+            Plugin {
+                blacklisted: blacklist.contains(&id),
+                id,
+                name: p.name(),
+                class: if p.is_instrument() {
+                    PluginClass::Instrument
+                } else {
+                    PluginClass::Effect
+                },
+                params: params_for_plugin(&p),
+                unsupported: audio_engine::plugin::unsupported_port(&p)
+                    .map(|port| port.to_string()),
+            }
+        });
+        let plugins = internal_plugins
+            .chain(lv2_plugins)
+            .filter(|p| include_blacklisted || !p.blacklisted)
+            .collect();
+        Ok(plugins)
+    }
+
+    /// Hide a plugin from `get_plugins`. The blacklist is saved so that the
+    /// plugin stays hidden after a restart. Tracks that already use the plugin
+    /// are not changed.
+    pub fn blacklist_plugin(&mut self, plugin_id: &str) -> Result<(), Error> {
+        if !internal_plugins().iter().any(|p| p.id == plugin_id)
+            && !self
+                .livi()?
+                .iter_plugins()
+                .any(|p| id_for_plugin(&p) == plugin_id)
+        {
+            return Err(Error::PluginNotFound(plugin_id.to_string()));
+        }
+        self.blacklist.get_mut().unwrap().insert(plugin_id, None)
+    }
+
+    /// Remove a plugin from the blacklist so that it is returned by
+    /// `get_plugins` again.
+    pub fn unblacklist_plugin(&mut self, plugin_id: &str) -> Result<(), Error> {
+        if self.blacklist.get_mut().unwrap().remove(plugin_id)? {
+            Ok(())
+        } else {
+            Err(Error::invalid(format!(
+                "plugin {plugin_id} is not blacklisted"
+            )))
+        }
+    }
+
+    /// Returns true if the plugin is on the blacklist.
+    pub fn is_blacklisted(&self, plugin_id: &str) -> bool {
+        self.blacklist.lock().unwrap().contains(plugin_id)
+    }
+
+    /// Get the blacklisted plugin ids and their entries.
+    pub fn blacklisted_plugins(&self) -> Vec<(String, BlacklistEntry)> {
+        self.blacklist
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, entry)| (id.clone(), entry.clone()))
+            .collect()
+    }
+
+    /// Get the control parameters for a plugin.
+    pub fn get_plugin_params(&self, plugin_id: &str) -> Result<Vec<PluginParam>, Error> {
+        if let Some(p) = internal_plugins().into_iter().find(|p| p.id == plugin_id) {
+            return Ok(p.params);
+        }
+        match self
+            .livi()?
+            .iter_plugins()
+            .find(|p| id_for_plugin(p) == plugin_id)
+        {
+            Some(p) => Ok(params_for_plugin(&p)),
+            None => Err(Error::PluginNotFound(plugin_id.to_string())),
+        }
+    }
+
+    /// Get the control parameter at `port_index` of the plugin at
+    /// `plugin_index` on a track.
+    fn plugin_param(
+        &self,
+        track_id: i32,
+        plugin_index: usize,
+        port_index: usize,
+    ) -> Result<PluginParam, Error> {
+        let track = self.track(track_id).ok_or(Error::TrackNotFound(track_id))?;
+        let plugin_id = match track.plugins.get(plugin_index) {
+            Some(p) => &p.plugin_id,
+            None => {
+                return Err(Error::PluginIndexOutOfRange {
+                    track_id,
+                    plugin_index,
+                })
+            }
+        };
+        self.get_plugin_params(plugin_id)?
+            .into_iter()
+            .find(|p| p.index == port_index)
+            .ok_or_else(|| {
+                Error::invalid(format!("plugin {plugin_id} has no parameter {port_index}"))
+            })
+    }
+
+    /// Get the presets for a plugin.
+    pub fn get_plugin_presets(&self, plugin_id: &str) -> Result<Vec<PluginPreset>, Error> {
+        if internal_plugins().iter().any(|p| p.id == plugin_id) {
+            return Ok(Vec::new());
+        }
+        let livi = self.livi()?;
+        match livi.iter_plugins().find(|p| id_for_plugin(p) == plugin_id) {
+            Some(p) => Ok(presets_for_plugin(livi, &p)),
+            None => Err(Error::PluginNotFound(plugin_id.to_string())),
+        }
+    }
+
+    /// Apply all the control values of a preset to a plugin. Returns the
+    /// symbols of the preset ports that could not be applied.
+    pub fn load_plugin_preset(
+        &mut self,
+        track_id: i32,
+        plugin_index: usize,
+        preset_uri: &str,
+    ) -> Result<Vec<String>, Error> {
+        let plugin_id = match self.track(track_id).map(|t| t.plugins.get(plugin_index)) {
+            Some(Some(p)) => p.plugin_id.clone(),
+            Some(None) => {
+                return Err(Error::PluginIndexOutOfRange {
+                    track_id,
+                    plugin_index,
+                })
+            }
+            None => return Err(Error::TrackNotFound(track_id)),
+        };
+        let preset = match self
+            .get_plugin_presets(&plugin_id)?
+            .into_iter()
+            .find(|p| p.uri == preset_uri)
+        {
+            Some(p) => p,
+            None => {
+                return Err(Error::InvalidRequest(format!(
+                    "plugin {plugin_id} has no preset {preset_uri}"
+                )))
+            }
+        };
+        let mut skipped = preset.unsupported;
+        for (symbol, value) in preset.values {
+            if self
+                .set_plugin_param(track_id, plugin_index, &symbol, value)
+                .is_err()
+            {
+                skipped.push(symbol);
+            }
+        }
+        Ok(skipped)
+    }
+
+    /// Set the metronome parameters. If `apply_on_downbeat` is true and
+    /// playback is in progress, the tempo changes at the start of the next
+    /// measure.
+    pub fn set_metronome(&mut self, metronome: Metronome, apply_on_downbeat: bool) {
+        self.send_command_or_warn(Command::SetMetronome {
+            volume: metronome.volume,
+            beats_per_minute: metronome.beats_per_minute,
+            swing: metronome.swing,
+            apply_on_downbeat,
+            external_sync: metronome.external_sync,
+        });
+        if metronome.external_sync != self.state.metronome.external_sync {
+            self.state.external_tempo = None;
+            self.state.external_clock_lost = false;
+        }
+        self.adapter
+            .set_timebase_beats_per_minute(metronome.beats_per_minute);
+        self.state.metronome = metronome;
+    }
+
+    /// Get the tempo detected from the midi clock on the midi input. This is
+    /// `None` if external sync is disabled or no clock has been detected.
+    pub fn external_tempo(&self) -> Option<f32> {
+        self.state.external_tempo
+    }
+
+    /// Returns true if the midi clock that the tempo is synced to stopped
+    /// sending pulses. The last stable tempo is used until it returns.
+    pub fn is_external_clock_lost(&self) -> bool {
+        self.state.external_clock_lost
+    }
+
+    /// Set the sample that the metronome plays on each beat. The running
+    /// metronome is left untouched if the sample could not be loaded.
+    pub fn set_metronome_sample(&mut self, path: &Path) -> Result<(), Error> {
+        let sample =
+            audio_engine::plugin::SampleTrigger::from_wav(path, self.adapter.sample_rate())
+                .map_err(|err| Error::file(path, err))?;
+        self.send_command(Command::SetMetronomeSample(sample))?;
+        self.state.metronome_sample = Some(path.to_path_buf());
+        Ok(())
+    }
+
+    /// Set whether midi clock is sent to the midi output. While enabled, 24
+    /// pulses per beat are sent at the tempo of the metronome along with
+    /// start and stop when playback starts and stops.
+    pub fn set_midi_clock(&mut self, enabled: bool) {
+        self.send_command_or_warn(Command::SetMidiClock(enabled));
+        self.state.midi_clock = enabled;
+    }
+
+    /// Returns true if midi clock is sent to the midi output.
+    pub fn is_midi_clock_enabled(&self) -> bool {
+        self.state.midi_clock
+    }
+
+    /// Set whether playback and the metronome follow the JACK transport. While
+    /// enabled, the JACK transport is started and stopped along with playback
+    /// and the tempo follows the JACK timebase master if there is one.
+    pub fn set_follow_jack_transport(&mut self, enabled: bool) {
+        self.send_command_or_warn(Command::SetFollowTransport(enabled));
+        self.state.follow_jack_transport = enabled;
+    }
+
+    /// Returns true if playback and the metronome follow the JACK transport.
+    pub fn is_following_jack_transport(&self) -> bool {
+        self.state.follow_jack_transport
+    }
+
+    /// Set whether the tempo of the metronome is published to other JACK
+    /// clients as the JACK timebase master. This fails if another client is
+    /// already the timebase master.
+    pub fn set_jack_timebase_master(&mut self, enabled: bool) -> Result<(), Error> {
+        self.adapter
+            .set_timebase_master(enabled)
+            .map_err(Error::Jack)?;
+        self.state.jack_timebase_master = enabled;
+        Ok(())
+    }
+
+    /// Returns true if the tempo is published as the JACK timebase master.
+    pub fn is_jack_timebase_master(&self) -> bool {
+        self.state.jack_timebase_master
+    }
+
+    /// Register a tempo tap. Once there are at least 2 taps, the metronome is
+    /// set to the average tempo of the last few taps and the new beats per
+    /// minute is returned.
+    pub fn tap_tempo(&mut self) -> Option<f32> {
+        let now = Instant::now();
+        let taps = &mut self.state.taps;
+        if taps
+            .last()
+            .map(|t| now.duration_since(*t) > TAP_TEMPO_TIMEOUT)
+            .unwrap_or(false)
+        {
+            taps.clear();
+        }
+        if taps.len() == TAP_TEMPO_TAPS {
+            taps.remove(0);
+        }
+        taps.push(now);
+        if taps.len() < 2 {
+            return None;
+        }
+        let seconds_per_beat = now.duration_since(taps[0]).as_secs_f32() / (taps.len() - 1) as f32;
+        let beats_per_minute = (60.0 / seconds_per_beat).clamp(20.0, 300.0);
+        let mut metronome = self.state.metronome.clone();
+        metronome.beats_per_minute = beats_per_minute;
+        self.set_metronome(metronome, true);
+        Some(beats_per_minute)
+    }
+
+    /// Set a track to be armed. If `track_id` is `None`, no track is armed.
+    pub fn set_armed(&mut self, track_id: Option<i32>) -> Result<(), Error> {
+        if let Some(track_id) = track_id {
+            self.track_index(track_id)?;
+        }
+        self.state.armed_track = track_id;
+        for track in self.state.tracks.iter_mut() {
+            let armed = Some(track.id) == track_id;
+            if armed {
+                track.properties.monitoring = true;
+            } else if track.properties.armed {
+                track.properties.monitoring = false;
+            }
+            track.properties.armed = armed;
+        }
+        self.send_command_or_warn(Command::ArmTrack(track_id.unwrap_or(-1)));
+        Ok(())
+    }
+
+    /// Set whether live midi input is sent to a track. This is independent of
+    /// which track is armed for recording.
+    pub fn set_track_monitoring(&mut self, track_id: i32, monitoring: bool) -> Result<(), Error> {
+        let index = self.track_index(track_id)?;
+        self.send_command(Command::SetTrackMonitoring(track_id, monitoring))?;
+        self.state.tracks[index].properties.monitoring = monitoring;
+        Ok(())
+    }
+
+    /// Get the names of the midi inputs. The index of a name is the source used
+    /// by `set_track_midi_source`.
+    pub fn midi_input_names(&self) -> &[String] {
+        self.adapter.midi_input_names()
+    }
+
+    /// Set the midi input that a track takes live input from. If `source` is
+    /// `None`, the track takes input from the inputs that no track is routed
+    /// to.
+    pub fn set_track_midi_source(
+        &mut self,
+        track_id: i32,
+        source: Option<usize>,
+    ) -> Result<(), Error> {
+        let index = self.track_index(track_id)?;
+        let inputs = self.midi_input_names().len();
+        if let Some(source) = source.filter(|s| *s >= inputs) {
+            return Err(Error::invalid(format!(
+                "midi input {source} does not exist, there are {inputs} midi inputs"
+            )));
+        }
+        self.send_command(Command::SetTrackMidiSource(track_id, source))?;
+        let before = self.state.tracks[index].properties;
+        self.state.tracks[index].properties.midi_source = source;
+        self.record_track_properties(index, before);
+        Ok(())
+    }
+
+    /// Set the midi channel, from 0 to 15, that a track takes live input from.
+    /// If `channel` is `None`, the track takes input from all channels.
+    pub fn set_track_midi_channel(
+        &mut self,
+        track_id: i32,
+        channel: Option<u8>,
+    ) -> Result<(), Error> {
+        let index = self.track_index(track_id)?;
+        if let Some(channel) = channel.filter(|c| *c >= 16) {
+            return Err(Error::invalid(format!(
+                "midi channel must be between 0 and 15 but got {channel}"
+            )));
+        }
+        self.send_command(Command::SetTrackMidiChannel(track_id, channel))?;
+        let before = self.state.tracks[index].properties;
+        self.state.tracks[index].properties.midi_channel = channel;
+        self.record_track_properties(index, before);
+        Ok(())
+    }
+
+    /// Set whether the midi that the plugins of a track produce is sent to the
+    /// midi output.
+    pub fn set_track_send_midi(&mut self, track_id: i32, send_midi: bool) -> Result<(), Error> {
+        let index = self.track_index(track_id)?;
+        self.send_command(Command::SetTrackSendMidi(track_id, send_midi))?;
+        let before = self.state.tracks[index].properties;
+        self.state.tracks[index].properties.send_midi = send_midi;
+        self.record_track_properties(index, before);
+        Ok(())
+    }
+
+    /// Set the number of semitones, from -24 to 24, to shift the notes of a
+    /// track by.
+    pub fn set_track_transpose(&mut self, track_id: i32, transpose: i8) -> Result<(), Error> {
+        let index = self.track_index(track_id)?;
+        if !(-24..=24).contains(&transpose) {
+            return Err(Error::invalid(format!(
+                "transpose must be between -24 and 24 semitones but got {transpose}"
+            )));
+        }
+        self.send_command(Command::SetTrackTranspose(track_id, transpose))?;
+        let before = self.state.tracks[index].properties;
+        self.state.tracks[index].properties.transpose = transpose;
+        self.record_track_properties(index, before);
+        Ok(())
+    }
+
+    /// Set the solo state of a track.
+    pub fn set_track_solo(&mut self, track_id: i32, solo: bool) -> Result<(), Error> {
+        let index = self.track_index(track_id)?;
+        self.send_command(Command::SetTrackSolo(track_id, solo))?;
+        let before = self.state.tracks[index].properties;
+        self.state.tracks[index].properties.solo = solo;
+        self.record_track_properties(index, before);
+        Ok(())
+    }
+
+    /// Set the stereo panning of a track. `pan` is clamped to [-1.0, 1.0].
+    pub fn set_track_pan(&mut self, track_id: i32, pan: f32) -> Result<(), Error> {
+        let index = self.track_index(track_id)?;
+        let pan = pan.clamp(-1.0, 1.0);
+        self.send_command(Command::SetTrackPan(track_id, pan))?;
+        let before = self.state.tracks[index].properties;
+        self.state.tracks[index].properties.pan = pan;
+        self.record_track_properties(index, before);
+        Ok(())
+    }
+
+    /// Set the volume multiplier of a track. Negative values are treated as
+    /// 0.0, which silences the track.
+    pub fn set_track_volume(&mut self, track_id: i32, volume: f32) -> Result<(), Error> {
+        let index = self.track_index(track_id)?;
+        let volume = volume.max(0.0);
+        self.send_command(Command::SetTrackVolume(track_id, volume))?;
+        let before = self.state.tracks[index].properties;
+        self.state.tracks[index].properties.volume = volume;
+        self.record_track_properties(index, before);
+        Ok(())
+    }
+
+    /// Change the properties of a track that are set in `update`. All values
+    /// are validated before any are applied so that an invalid update changes
+    /// nothing. Returns the resulting properties.
+    pub fn update_track_properties(
+        &mut self,
+        track_id: i32,
+        update: TrackPropertiesUpdate,
+    ) -> Result<TrackProperties, Error> {
+        let index = self.track_index(track_id)?;
+        if let Some(volume) = update.volume.filter(|v| !(v.is_finite() && *v >= 0.0)) {
+            return Err(Error::invalid(format!(
+                "volume must be at least 0.0 but got {volume}"
+            )));
+        }
+        if let Some(pan) = update.pan.filter(|p| !(-1.0..=1.0).contains(p)) {
+            return Err(Error::invalid(format!(
+                "pan must be between -1.0 and 1.0 but got {pan}"
+            )));
+        }
+        if let Some(armed) = update.armed {
+            let armed_track = if armed {
+                Some(track_id)
+            } else {
+                self.state.armed_track.filter(|id| *id != track_id)
+            };
+            self.set_armed(armed_track)?;
+        }
+        if let Some(monitoring) = update.monitoring {
+            self.set_track_monitoring(track_id, monitoring)?;
+        }
+        if let Some(solo) = update.solo {
+            self.set_track_solo(track_id, solo)?;
+        }
+        if let Some(pan) = update.pan {
+            self.set_track_pan(track_id, pan)?;
+        }
+        if let Some(volume) = update.volume {
+            self.set_track_volume(track_id, volume)?;
+        }
+        if let Some(name) = update.name {
+            self.rename_track(track_id, name)?;
+        }
+        Ok(self.state.tracks[index].properties)
+    }
+
+    /// Add a plugin to a track. Returns `Error::UnsupportedPlugin` if the
+    /// audio engine can not connect one of the plugin's ports.
+    pub fn add_plugin_to_track(&mut self, track_id: i32, plugin_id: &str) -> Result<(), Error> {
+        if track_id == MASTER_TRACK_ID {
+            return self.add_plugin_to_master(plugin_id);
+        }
+        self.push_plugin(
+            track_id,
+            TrackPlugin {
+                plugin_id: plugin_id.to_string(),
+                param_values: HashMap::new(),
+                drum_kit: None,
+                sample: None,
+                missing: false,
+                lv2_state: None,
+                lv2_state_handle: None,
+            },
+        )?;
+        self.play_sound();
+        Ok(())
+    }
+
+    /// Add a plugin to the end of the master track. The master track
+    /// processes the mix of all tracks, the metronome, and sound effects.
+    pub fn add_plugin_to_master(&mut self, plugin_id: &str) -> Result<(), Error> {
+        self.push_plugin(
+            MASTER_TRACK_ID,
+            TrackPlugin {
+                plugin_id: plugin_id.to_string(),
+                param_values: HashMap::new(),
+                drum_kit: None,
+                sample: None,
+                missing: false,
+                lv2_state: None,
+                lv2_state_handle: None,
+            },
+        )?;
+        self.play_sound();
+        Ok(())
+    }
+
+    /// Instantiate `plugin` and add it to the end of a track or the master
+    /// track.
+    fn push_plugin(&mut self, track_id: i32, plugin: TrackPlugin) -> Result<(), Error> {
+        let plugin_index = match self.track(track_id) {
+            Some(track) => track.plugins.len(),
+            None => return Err(Error::TrackNotFound(track_id)),
+        };
+        self.insert_plugin(track_id, plugin_index, plugin)
+    }
+
+    /// Instantiate `plugin` and insert it at `plugin_index` within a track or
+    /// the master track.
+    fn insert_plugin(
+        &mut self,
+        track_id: i32,
+        plugin_index: usize,
+        mut plugin: TrackPlugin,
+    ) -> Result<(), Error> {
+        let plugins = match self.track(track_id) {
+            Some(track) => track.plugins.len(),
+            None => return Err(Error::TrackNotFound(track_id)),
+        };
+        if plugin_index > plugins {
+            return Err(Error::PluginIndexOutOfRange {
+                track_id,
+                plugin_index,
+            });
+        }
+        let instance = self.instantiate_track_plugin(&plugin)?;
+        plugin.lv2_state_handle = instance.lv2_state_handle();
+        self.send_command(Command::InsertPlugin(track_id, plugin_index, instance))?;
+        self.shift_plugin_mappings(track_id, |index| {
+            if index >= plugin_index {
+                index + 1
+            } else {
+                index
+            }
+        });
+        self.history.record(history::Edit::AddPlugin {
+            track_id,
+            plugin_index,
+            plugin: plugin.clone(),
+        });
+        if let Some(track) = self.track_mut(track_id) {
+            track.plugins.insert(plugin_index, plugin);
+        }
+        Ok(())
+    }
+
+    /// Move a plugin within a track or the master track so that it ends up at
+    /// `to`. The plugins in between shift over by one.
+    pub fn move_plugin(&mut self, track_id: i32, from: usize, to: usize) -> Result<(), Error> {
+        let plugins = match self.track(track_id) {
+            Some(track) => track.plugins.len(),
+            None => return Err(Error::TrackNotFound(track_id)),
+        };
+        for plugin_index in [from, to] {
+            if plugin_index >= plugins {
+                return Err(Error::PluginIndexOutOfRange {
+                    track_id,
+                    plugin_index,
+                });
+            }
+        }
+        if from == to {
+            return Ok(());
+        }
+        self.send_command(Command::MovePlugin { track_id, from, to })?;
+        if let Some(track) = self.track_mut(track_id) {
+            let plugin = track.plugins.remove(from);
+            track.plugins.insert(to, plugin);
+        }
+        self.shift_plugin_mappings(track_id, |index| {
+            if index == from {
+                to
+            } else if from < to && (from + 1..=to).contains(&index) {
+                index - 1
+            } else if to < from && (to..from).contains(&index) {
+                index + 1
+            } else {
+                index
+            }
+        });
+        self.history
+            .record(history::Edit::MovePlugin { track_id, from, to });
+        Ok(())
+    }
+
+    /// Change the plugin index of the midi mappings of a track's plugins with
+    /// `f`.
+    fn shift_plugin_mappings(&mut self, track_id: i32, f: impl Fn(usize) -> usize) {
+        let targets = self
+            .state
+            .midi_mappings
+            .iter_mut()
+            .map(|m| &mut m.target)
+            .chain(self.state.midi_learn.as_mut());
+        for target in targets {
+            if let MidiLearnTarget::PluginParam {
+                track_id: id,
+                plugin_index,
+                ..
+            } = target
+            {
+                if *id == track_id {
+                    *plugin_index = f(*plugin_index);
+                }
+            }
+        }
+    }
+
+    /// Remove a plugin from the master track.
+    pub fn remove_plugin_from_master(&mut self, plugin_index: usize) -> Result<(), Error> {
+        if plugin_index >= self.state.master.plugins.len() {
+            return Err(Error::PluginIndexOutOfRange {
+                track_id: MASTER_TRACK_ID,
+                plugin_index,
+            });
+        }
+        self.send_command(Command::DeletePluginFromMaster(plugin_index))?;
+        let mut plugin = self.state.master.plugins.remove(plugin_index);
+        plugin.lv2_state = plugin.current_lv2_state(&self.adapter.audio_engine().lv2_features);
+        self.history.record(history::Edit::RemovePlugin {
+            track_id: MASTER_TRACK_ID,
+            plugin_index,
+            plugin,
+        });
+        self.forget_plugin_mappings(MASTER_TRACK_ID, plugin_index);
+        self.play_sound();
+        Ok(())
+    }
+
+    /// Create a new instance of the plugin with `plugin_id`. Plugins with ports
+    /// that the audio engine can not connect are rejected before they are
+    /// instantiated. Plugins that fail to instantiate too many times are added
+    /// to the blacklist.
+    fn instantiate_plugin(&self, plugin_id: &str) -> Result<livi::Instance, Error> {
+        let plugin = match self
+            .livi()?
+            .iter_plugins()
+            .find(|p| id_for_plugin(p) == plugin_id)
+        {
+            Some(p) => p,
+            None => {
+                return Err(Error::PluginNotFound(plugin_id.to_string()));
+            }
+        };
+        if let Some(port) = audio_engine::plugin::unsupported_port(&plugin) {
+            return Err(Error::UnsupportedPlugin {
+                name: plugin.name(),
+                port,
+            });
+        }
+        let instance_or_err = unsafe {
+            plugin.instantiate(
+                self.adapter.audio_engine().lv2_features.clone(),
+                self.adapter.sample_rate(),
+            )
+        };
+        let err = match instance_or_err {
+            Ok(instance) => return Ok(instance),
+            Err(source) => Error::PluginInstantiation {
+                name: plugin.name(),
+                source,
+            },
+        };
+        match self
+            .blacklist
+            .lock()
+            .unwrap()
+            .record_failure(plugin_id, &err)
+        {
+            Ok(true) => warn!("Added {} to the plugin blacklist: {}", plugin_id, err),
+            Ok(false) => {}
+            Err(save_err) => error!("Failed to save the plugin blacklist: {}", save_err),
+        }
+        Err(err)
+    }
+
+    /// Instantiate the plugin of a track with its parameter values and LV2
+    /// State, see `TrackPlugin::current_lv2_state`. Missing plugins are
+    /// replaced by a placeholder that passes audio through.
+    fn instantiate_track_plugin(&self, plugin: &TrackPlugin) -> Result<PluginInstance, Error> {
+        if plugin.missing {
+            return Ok(PluginInstance::Missing);
+        }
+        let mut instance: PluginInstance = match plugin.plugin_id.as_str() {
+            DRUM_KIT_PLUGIN_ID => match plugin.drum_kit.as_ref() {
+                Some(path) => drum_kit::load(path, self.adapter.sample_rate())?.into(),
+                None => DrumKit::default().into(),
+            },
+            SAMPLER_PLUGIN_ID => match plugin.sample.as_ref() {
+                Some(sample) => SampleTrigger::new(sample.clone(), self.adapter.sample_rate())
+                    .with_mode(TriggerMode::Gate)
+                    .with_root_note(Some(wmidi::Note::C4))
+                    .into(),
+                None => {
+                    return Err(Error::invalid(
+                        "the sampler plays samples recorded with record_sample",
+                    ))
+                }
+            },
+            GAIN_PLUGIN_ID => Gain::default().into(),
+            _ => {
+                let mut instance = self.instantiate_plugin(&plugin.plugin_id)?;
+                let features = &self.adapter.audio_engine().lv2_features;
+                if let Some(state) = plugin.current_lv2_state(features) {
+                    if let Err(err) =
+                        audio_engine::lv2_state::restore(&mut instance, features, &state)
+                    {
+                        warn!(
+                            "Failed to restore the state of {}: {}",
+                            plugin.plugin_id, err
+                        );
+                    }
+                }
+                PluginInstance::lv2(instance, self.adapter.buffer_size())
+            }
+        };
+        for (port_index, value) in plugin.param_values.iter() {
+            instance.set_control_input(*port_index, *value);
+        }
+        Ok(instance)
+    }
+
+    /// Load the drum kit file at `path` into the drum kit plugin at
+    /// `plugin_index` of a track. The samples are loaded on the calling thread
+    /// and the assembled kit replaces the pads of the running plugin. See
+    /// `drum_kit::load` for the file format.
+    pub fn load_drum_kit(
+        &mut self,
+        track_id: i32,
+        plugin_index: usize,
+        path: &Path,
+    ) -> Result<(), Error> {
+        match self.track(track_id).map(|t| t.plugins.get(plugin_index)) {
+            Some(Some(p)) if p.plugin_id == DRUM_KIT_PLUGIN_ID => {}
+            Some(Some(p)) => {
+                return Err(Error::invalid(format!(
+                    "plugin {} is not a drum kit",
+                    p.plugin_id
+                )))
+            }
+            Some(None) => {
+                return Err(Error::PluginIndexOutOfRange {
+                    track_id,
+                    plugin_index,
+                })
+            }
+            None => return Err(Error::TrackNotFound(track_id)),
+        }
+        let kit = drum_kit::load(path, self.adapter.sample_rate())?;
+        self.send_command(Command::SetDrumKit {
+            track_id,
+            plugin_index,
+            kit: Box::new(kit),
+        })?;
+        if let Some(plugin) = self
+            .track_mut(track_id)
+            .and_then(|t| t.plugins.get_mut(plugin_index))
+        {
+            plugin.drum_kit = Some(path.to_path_buf());
+        }
+        Ok(())
+    }
+
+    /// Get the cost of a plugin at the current buffer size if it has already
+    /// been estimated with `estimate_plugin_cost`.
+    pub fn cached_plugin_cost(&self, plugin_id: &str) -> Option<PluginCost> {
+        let key = (plugin_id.to_string(), self.adapter.buffer_size());
+        self.plugin_costs.lock().unwrap().get(&key).copied()
+    }
+
+    /// Returns true if the cached cost of a plugin is more than `threshold` of
+    /// the time available to process a block. Plugins that have not been
+    /// estimated do not exceed any threshold.
+    pub fn plugin_cost_exceeds(&self, plugin_id: &str, threshold: f64) -> bool {
+        self.cached_plugin_cost(plugin_id)
+            .map(|c| c.budget_fraction() > threshold)
+            .unwrap_or(false)
+    }
+
+    /// Estimate the cost of running a plugin at the current buffer size by
+    /// running a separate instance for `blocks` blocks and call `callback` with
+    /// the result.
+    ///
+    /// The instance is created on the calling thread but measured on a
+    /// background thread and is never added to the live processor. Results are
+    /// cached per plugin and buffer size.
+    pub fn estimate_plugin_cost(
+        &self,
+        plugin_id: &str,
+        blocks: usize,
+        callback: Box<dyn Send + FnOnce(Result<PluginCost, Error>)>,
+    ) {
+        if let Some(cost) = self.cached_plugin_cost(plugin_id) {
+            callback(Ok(cost));
+            return;
+        }
+        let plugin = TrackPlugin {
+            plugin_id: plugin_id.to_string(),
+            param_values: HashMap::new(),
+            drum_kit: None,
+            sample: None,
+            missing: false,
+            lv2_state: None,
+            lv2_state_handle: None,
+        };
+        let mut instance = match self.instantiate_track_plugin(&plugin) {
+            Ok(i) => i,
+            Err(err) => {
+                callback(Err(err));
+                return;
+            }
+        };
+        let key = (plugin_id.to_string(), self.adapter.buffer_size());
+        let features = self.adapter.audio_engine().lv2_features.clone();
+        let sample_rate = self.adapter.sample_rate();
+        let plugin_costs = self.plugin_costs.clone();
+        std::thread::spawn(move || {
+            let result = instance
+                .measure_cost(&features, sample_rate, key.1, blocks)
+                .map_err(|source| Error::PluginProcess {
+                    name: key.0.clone(),
+                    source,
+                });
+            if let Ok(cost) = result {
+                plugin_costs.lock().unwrap().insert(key, cost);
+            }
+            callback(result);
+        });
+    }
+
+    /// Duplicate a track along with its plugins and properties. The new track
+    /// is not armed.
+    ///
+    /// Returns the id of the new track and the errors for any plugins that
+    /// could not be instantiated. Plugins that fail are left out of the new
+    /// track.
+    pub fn duplicate_track(&mut self, track_id: i32) -> Result<(i32, Vec<Error>), Error> {
+        let src = match self.state.tracks.iter().find(|t| t.id == track_id) {
+            Some(t) => t.clone(),
+            None => return Err(Error::TrackNotFound(track_id)),
+        };
+        let new_track_id = self.state.next_track_id;
+        let mut audio_engine_track =
+            audio_engine::track::Track::new(new_track_id, self.adapter.buffer_size());
+        audio_engine_track.properties.midi_source = src.properties.midi_source;
+        audio_engine_track.properties.midi_channel = src.properties.midi_channel;
+        audio_engine_track.properties.transpose = src.properties.transpose;
+        audio_engine_track.properties.send_midi = src.properties.send_midi;
+        audio_engine_track.properties.solo = src.properties.solo;
+        audio_engine_track.properties.pan = src.properties.pan;
+        audio_engine_track.properties.volume = src.properties.volume;
+        if let Some(events) = src.clip.as_ref() {
+            audio_engine_track.set_clip(Clip::new(events.clone()));
+        }
+        let mut track = Track {
+            name: format!("{} (copy)", src.name),
+            id: new_track_id,
+            plugins: Vec::with_capacity(src.plugins.len()),
+            properties: TrackProperties {
+                armed: false,
+                monitoring: false,
+                disabled: false,
+                ..src.properties
+            },
+            clip: src.clip.clone(),
+            step_pattern: src.step_pattern.clone(),
+            frozen: None,
+            plugin_error: None,
+        };
+        let mut errors = Vec::new();
+        for plugin in src.plugins.iter() {
+            match self.instantiate_track_plugin(plugin) {
+                Ok(instance) => {
+                    let mut plugin = plugin.clone();
+                    plugin.lv2_state_handle = instance.lv2_state_handle();
+                    audio_engine_track.push_plugin(instance);
+                    track.plugins.push(plugin);
+                }
+                Err(err) => errors.push(err),
+            }
+        }
+        self.send_command(Command::AddTrack(Box::new(audio_engine_track)))?;
+        self.history.record(history::Edit::AddTracks(vec![(
+            self.state.tracks.len(),
+            track.clone(),
+        )]));
+        self.state.tracks.push(track);
+        self.state.next_track_id += 1;
+        self.play_sound();
+        Ok((new_track_id, errors))
+    }
+
+    /// Freeze a track by rendering a loop of its clip through separate
+    /// instances of its plugins and playing the result instead of running the
+    /// plugins. The plugins are kept so that the track may be unfrozen with
+    /// `unfreeze_track`. Changes to the clip or plugins are not heard until the
+    /// track is unfrozen.
+    pub fn freeze_track(&mut self, track_id: i32) -> Result<(), Error> {
+        let track = match self.state.tracks.iter().find(|t| t.id == track_id) {
+            Some(t) => t,
+            None => return Err(Error::TrackNotFound(track_id)),
+        };
+        if track.frozen.is_some() {
+            return Ok(());
+        }
+        let events = match track.clip.as_ref() {
+            Some(events) => events.clone(),
+            None => {
+                return Err(Error::InvalidRequest(format!(
+                    "track {track_id} has no clip to freeze"
+                )))
+            }
+        };
+        let mut render_track =
+            audio_engine::track::Track::new(track_id, self.adapter.buffer_size());
+        render_track.set_clip(Clip::new(events));
+        for plugin in track.plugins.iter() {
+            render_track.push_plugin(self.instantiate_track_plugin(plugin)?);
+        }
+        let frozen = audio_engine::freeze::FrozenAudio::render(
+            &mut render_track,
+            &self.adapter.audio_engine().lv2_features,
+            self.adapter.sample_rate(),
+            self.state.metronome.beats_per_minute,
+            self.state.metronome.swing,
+        )
+        .unwrap();
+        self.send_command(Command::FreezeTrack(track_id, frozen.clone()))?;
+        let track = self
+            .state
+            .tracks
+            .iter_mut()
+            .find(|t| t.id == track_id)
+            .unwrap();
+        track.frozen = Some(frozen);
+        Ok(())
+    }
+
+    /// Run the plugins of a frozen track again.
+    pub fn unfreeze_track(&mut self, track_id: i32) -> Result<(), Error> {
+        let track = match self.state.tracks.iter_mut().find(|t| t.id == track_id) {
+            Some(t) => t,
+            None => return Err(Error::TrackNotFound(track_id)),
+        };
+        if track.frozen.take().is_some() {
+            self.send_command(Command::UnfreezeTrack(track_id))?;
+        }
+        Ok(())
+    }
+
+    /// Set the value of a plugin parameter. `param` is either the parameter's
+    /// symbol or its port index. The value is clamped to the parameter's range
+    /// and the clamped value is returned.
+    pub fn set_plugin_param(
+        &mut self,
+        track_id: i32,
+        plugin_index: usize,
+        param: &str,
+        value: f32,
+    ) -> Result<f32, Error> {
+        let track = match self.track(track_id) {
+            Some(t) => t,
+            None => return Err(Error::TrackNotFound(track_id)),
+        };
+        let plugin_id = match track.plugins.get(plugin_index) {
+            Some(p) => p.plugin_id.clone(),
+            None => {
+                return Err(Error::PluginIndexOutOfRange {
+                    track_id,
+                    plugin_index,
+                })
+            }
+        };
+        let params = self.get_plugin_params(&plugin_id)?;
+        let param = match params
+            .iter()
+            .find(|p| p.symbol == param || p.index.to_string() == param)
+        {
+            Some(p) => p,
+            None => {
+                return Err(Error::InvalidRequest(format!(
+                    "plugin {plugin_id} has no parameter {param}"
+                )))
+            }
+        };
+        let value = value
+            .max(param.min_value.unwrap_or(f32::NEG_INFINITY))
+            .min(param.max_value.unwrap_or(f32::INFINITY));
+        self.send_command(Command::SetPluginParam {
+            track_id,
+            plugin_index,
+            port_index: param.index,
+            value,
+        })?;
+        if let Some(track_plugin) = self
+            .track_mut(track_id)
+            .and_then(|t| t.plugins.get_mut(plugin_index))
+        {
+            track_plugin.param_values.insert(param.index, value);
+        }
+        Ok(value)
+    }
+
+    /// Multiply the recorded audio of a track by `gain`.
+    pub fn set_track_audio_gain(&mut self, track_id: i32, gain: f32) -> Result<(), Error> {
+        self.edit_track_audio(track_id, |audio| {
+            audio.apply_gain(gain);
+            Ok(())
+        })
+    }
+
+    /// Scale the recorded audio of a track so that its loudest sample is
+    /// `target_peak`.
+    pub fn normalize_track_audio(&mut self, track_id: i32, target_peak: f32) -> Result<(), Error> {
+        self.edit_track_audio(track_id, |audio| {
+            audio.normalize(target_peak);
+            Ok(())
+        })
+    }
+
+    /// Fade the recorded audio of a track in over its first `fade_in` frames
+    /// and out over its last `fade_out` frames.
+    pub fn fade_track_audio(
+        &mut self,
+        track_id: i32,
+        fade_in: usize,
+        fade_out: usize,
+    ) -> Result<(), Error> {
+        self.edit_track_audio(track_id, |audio| {
+            audio.fade_in(fade_in);
+            audio.fade_out(fade_out);
+            Ok(())
+        })
+    }
+
+    /// Keep only the frames in `start..end` of the recorded audio of a track.
+    pub fn trim_track_audio(
+        &mut self,
+        track_id: i32,
+        start: usize,
+        end: usize,
+    ) -> Result<(), Error> {
+        self.edit_track_audio(track_id, |audio| {
+            *audio = match audio.trim(start, end) {
+                Some(a) => a,
+                None => {
+                    return Err(Error::InvalidRequest(format!(
+                        "cannot trim audio to the empty range {start}..{end}"
+                    )))
+                }
+            };
+            Ok(())
+        })
+    }
+
+    /// Apply `edit` to the recorded audio of a track, see
+    /// `Track::recorded_sample`. The sampler plugin that plays it is replaced
+    /// by one that plays the edited audio.
+    fn edit_track_audio(
+        &mut self,
+        track_id: i32,
+        edit: impl FnOnce(&mut audio_engine::audio_buffer::AudioBuffer) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let track = match self.state.tracks.iter().find(|t| t.id == track_id) {
+            Some(t) => t,
+            None => return Err(Error::TrackNotFound(track_id)),
+        };
+        let (plugin_index, sample) = match track.recorded_sample() {
+            Some(s) => s,
+            None => {
+                return Err(Error::InvalidRequest(format!(
+                    "track {track_id} has no recorded audio"
+                )))
+            }
+        };
+        let mut sample = sample.as_ref().clone();
+        edit(&mut sample)?;
+        let mut plugin = track.plugins[plugin_index].clone();
+        plugin.sample = Some(Arc::new(sample));
+        let instance = self.instantiate_track_plugin(&plugin)?;
+        self.send_command(Command::ReplacePlugin {
+            track_id,
+            plugin_index,
+            instance,
+        })?;
+        let index = self.track_index(track_id)?;
+        self.state.tracks[index].plugins[plugin_index] = plugin;
+        Ok(())
+    }
+
+    /// Get the notes of a track's clip.
+    pub fn get_clip_notes(&self, track_id: i32) -> Result<Vec<Note>, Error> {
+        match self.state.tracks.iter().find(|t| t.id == track_id) {
+            Some(t) => Ok(t.notes()),
+            None => Err(Error::TrackNotFound(track_id)),
+        }
+    }
+
+    /// Replace the clip of a track with `notes`. Overlapping notes of the same
+    /// pitch are merged into a single note that keeps the velocity of the
+    /// earliest note.
+    pub fn set_clip_notes(&mut self, track_id: i32, notes: Vec<Note>) -> Result<(), Error> {
+        let index = self.track_index(track_id)?;
+        let events = notes_to_events(notes)?;
+        self.send_command(Command::SetTrackClip(track_id, Clip::new(events.clone())))?;
+        let track = &mut self.state.tracks[index];
+        track.clip = Some(events);
+        track.step_pattern = None;
+        Ok(())
+    }
+
+    /// Write the clip of a track to a standard midi file at `path`.
+    pub fn export_midi_file(&self, track_id: i32, path: &Path) -> Result<(), Error> {
+        let bytes = self.export_midi_bytes(track_id)?;
+        std::fs::write(path, bytes).map_err(|err| Error::file(path, err))
+    }
+
+    /// Encode the clip of a track as a type 0 standard midi file. The tempo is
+    /// set to the current tempo of the metronome.
+    pub fn export_midi_bytes(&self, track_id: i32) -> Result<Vec<u8>, Error> {
+        let track = match self.state.tracks.iter().find(|t| t.id == track_id) {
+            Some(t) => t,
+            None => return Err(Error::TrackNotFound(track_id)),
+        };
+        let (events, length) = match (track.clip.as_ref(), track.clip_length()) {
+            (Some(events), Some(length)) => (events, length),
+            _ => {
+                return Err(Error::InvalidRequest(format!(
+                    "track {track_id} does not have a clip"
+                )))
+            }
+        };
+        let to_ticks = |beat: f64| (beat * MIDI_FILE_TICKS_PER_BEAT as f64).round() as u32;
+        let micros_per_beat = (60_000_000.0 / self.state.metronome.beats_per_minute) as u32;
+        let mut smf_track = vec![midly::TrackEvent {
+            delta: 0.into(),
+            kind: midly::TrackEventKind::Meta(midly::MetaMessage::Tempo(micros_per_beat.into())),
+        }];
+        let mut events: Vec<&MidiEvent> = events.iter().collect();
+        events.sort_by(|a, b| a.beat.total_cmp(&b.beat));
+        let mut tick = 0;
+        for event in events {
+            let mut data = [0u8; 3];
+            let size = match event.message.copy_to_slice(&mut data) {
+                Ok(size) => size,
+                Err(_) => continue,
+            };
+            let (channel, message) = match midly::live::LiveEvent::parse(&data[..size]) {
+                Ok(midly::live::LiveEvent::Midi { channel, message }) => (channel, message),
+                _ => continue,
+            };
+            let event_tick = to_ticks(event.beat);
+            smf_track.push(midly::TrackEvent {
+                delta: (event_tick - tick).into(),
+                kind: midly::TrackEventKind::Midi { channel, message },
+            });
+            tick = event_tick;
+        }
+        smf_track.push(midly::TrackEvent {
+            delta: to_ticks(length).saturating_sub(tick).into(),
+            kind: midly::TrackEventKind::Meta(midly::MetaMessage::EndOfTrack),
+        });
+        let smf = midly::Smf {
+            header: midly::Header::new(
+                midly::Format::SingleTrack,
+                midly::Timing::Metrical(MIDI_FILE_TICKS_PER_BEAT.into()),
+            ),
+            tracks: vec![smf_track],
+        };
+        let mut bytes = Vec::new();
+        smf.write_std(&mut bytes)
+            .map_err(|err| Error::InvalidRequest(format!("failed to encode midi file: {err}")))?;
+        Ok(bytes)
+    }
+
+    /// Replace the clip of a track with the notes of a step pattern. If the
+    /// track is already playing a clip, the pattern starts at the next loop
+    /// boundary.
+    pub fn set_step_pattern(&mut self, track_id: i32, pattern: StepPattern) -> Result<(), Error> {
+        if pattern.rows.len() > STEP_PATTERN_MAX_ROWS {
+            return Err(Error::InvalidRequest(format!(
+                "step patterns support at most {STEP_PATTERN_MAX_ROWS} rows but got {}",
+                pattern.rows.len()
+            )));
+        }
+        let index = self.track_index(track_id)?;
+        let events = notes_to_events(pattern.notes())?;
+        self.send_command(Command::QueueTrackClip(track_id, Clip::new(events.clone())))?;
+        let track = &mut self.state.tracks[index];
+        track.clip = Some(events);
+        track.step_pattern = Some(pattern);
+        Ok(())
+    }
+
+    /// Remove a plugin from a track.
+    pub fn remove_plugin_from_track(
+        &mut self,
+        track_id: i32,
+        plugin_index: usize,
+    ) -> Result<(), Error> {
+        if track_id == MASTER_TRACK_ID {
+            return self.remove_plugin_from_master(plugin_index);
+        }
+        let index = self.track_index(track_id)?;
+        if plugin_index >= self.state.tracks[index].plugins.len() {
+            return Err(Error::PluginIndexOutOfRange {
+                track_id,
+                plugin_index,
+            });
+        }
+        self.send_command(Command::DeletePlugin(track_id, plugin_index))?;
+        let mut plugin = self.state.tracks[index].plugins.remove(plugin_index);
+        plugin.lv2_state = plugin.current_lv2_state(&self.adapter.audio_engine().lv2_features);
+        self.history.record(history::Edit::RemovePlugin {
+            track_id,
+            plugin_index,
+            plugin,
+        });
+        self.forget_plugin_mappings(track_id, plugin_index);
+        self.play_sound();
+        Ok(())
+    }
+
+    /// Get a track by its id. The master track is returned for
+    /// `MASTER_TRACK_ID`.
+    pub fn track(&self, track_id: i32) -> Option<&Track> {
+        if track_id == MASTER_TRACK_ID {
+            Some(&self.state.master)
+        } else {
+            self.state.tracks.iter().find(|t| t.id == track_id)
+        }
+    }
+
+    /// Run the plugins of a track that was disabled by a plugin failure again.
+    /// The master track is addressed with `MASTER_TRACK_ID`.
+    pub fn enable_track(&mut self, track_id: i32) -> Result<(), Error> {
+        if self.track(track_id).is_none() {
+            return Err(Error::TrackNotFound(track_id));
+        }
+        self.send_command(Command::EnableTrack(track_id))?;
+        if let Some(track) = self.track_mut(track_id) {
+            track.properties.disabled = false;
+            track.plugin_error = None;
+        }
+        Ok(())
+    }
+
+    /// Get a track by its id, including the master track.
+    fn track_mut(&mut self, track_id: i32) -> Option<&mut Track> {
+        if track_id == MASTER_TRACK_ID {
+            Some(&mut self.state.master)
+        } else {
+            self.state.tracks.iter_mut().find(|t| t.id == track_id)
+        }
+    }
+
+    /// Iterate over the plugins of the tracks followed by the plugins of the
+    /// master track, in the order that `send_tracks` instantiates them.
+    fn all_plugins_mut(&mut self) -> impl Iterator<Item = &mut TrackPlugin> {
+        self.state
+            .tracks
+            .iter_mut()
+            .chain(std::iter::once(&mut self.state.master))
+            .flat_map(|t| t.plugins.iter_mut())
+    }
+
+    /// Set the gain applied to the output. Negative values are treated as
+    /// 0.0, which silences the output.
+    pub fn set_master_volume(&mut self, volume: f32) {
+        let volume = volume.max(0.0);
+        self.send_command_or_warn(Command::SetMasterVolume(volume));
+        self.state.master_volume = volume;
+    }
+
+    /// Get the gain applied to the output.
+    pub fn master_volume(&self) -> f32 {
+        self.state.master_volume
+    }
+
+    /// Enable or disable the limiter that keeps the output from exceeding
+    /// 1.0.
+    pub fn set_limiter(&mut self, enabled: bool) {
+        self.send_command_or_warn(Command::SetLimiter(enabled));
+        self.state.limiter_enabled = enabled;
+    }
+
+    /// Returns true if the limiter is enabled.
+    pub fn is_limiter_enabled(&self) -> bool {
+        self.state.limiter_enabled
+    }
+
+    /// Get the peak of the output if it clipped within the last second.
+    pub fn recent_clip(&self) -> Option<f32> {
+        self.state
+            .last_clip
+            .filter(|(t, _)| t.elapsed() < CLIP_HOLD)
+            .map(|(_, peak)| peak)
+    }
+
+    /// Get the meter of the track with `track_id`. `MASTER_TRACK_ID` gets
+    /// the meter of the output. Tracks that are not playing read as silence.
+    pub fn meter(&self, track_id: i32) -> Meter {
+        self.state
+            .meters
+            .get(&track_id)
+            .copied()
+            .unwrap_or_default()
     }
 
-    /// Get the tracks.
+    /// Get the master track.
+    pub fn master_track(&self) -> &Track {
+        &self.state.master
+    }
+
+    /// Get the tracks. The master track is not included.
     pub fn iter_tracks(&self) -> impl Iterator<Item = &Track> {
         self.state.tracks.iter()
     }
@@ -204,21 +2755,36 @@ impl State {
     }
 
     /// Create a track.
-    pub fn create_track(&mut self, name: Option<String>) -> Result<i32, String> {
+    pub fn create_track(&mut self, name: Option<String>) -> Result<i32, Error> {
         let track_id = self.state.next_track_id;
         let track = Track {
             name: name.unwrap_or_else(|| format!("Track {track_id}")),
             id: track_id,
             plugins: Vec::new(),
-            properties: TrackProperties { armed: false },
+            properties: TrackProperties {
+                armed: false,
+                monitoring: false,
+                midi_source: None,
+                midi_channel: None,
+                transpose: 0,
+                send_midi: false,
+                solo: false,
+                pan: 0.0,
+                volume: 0.5,
+                disabled: false,
+            },
+            clip: None,
+            step_pattern: None,
+            frozen: None,
+            plugin_error: None,
         };
         let audio_engine_track =
-            audio_engine::track::Track::new(track_id, self.jack_adapter.buffer_size());
-        self.jack_adapter
-            .audio_engine
-            .commands
-            .send(Command::AddTrack(audio_engine_track))
-            .unwrap();
+            audio_engine::track::Track::new(track_id, self.adapter.buffer_size());
+        self.send_command(Command::AddTrack(Box::new(audio_engine_track)))?;
+        self.history.record(history::Edit::AddTracks(vec![(
+            self.state.tracks.len(),
+            track.clone(),
+        )]));
         self.state.tracks.push(track);
         self.state.next_track_id += 1;
         self.play_sound();
@@ -226,46 +2792,248 @@ impl State {
     }
 
     /// Delete tracks.
-    pub fn delete_tracks(
-        &mut self,
-        ids_requested_for_deletion: HashSet<i32>,
-    ) -> Result<(), String> {
+    pub fn delete_tracks(&mut self, ids_requested_for_deletion: HashSet<i32>) -> Result<(), Error> {
         let existing_ids = self.state.tracks.iter().map(|t| t.id);
         let delete_targets: HashSet<i32> = existing_ids
             .filter(|id| ids_requested_for_deletion.contains(id))
             .collect();
         for t in delete_targets.iter() {
-            self.jack_adapter
-                .audio_engine
-                .commands
-                .send(Command::DeleteTrack(*t))
-                .unwrap();
+            self.send_command(Command::DeleteTrack(*t))?;
+        }
+        let features = &self.adapter.audio_engine().lv2_features;
+        let deleted_tracks: Vec<(usize, Track)> = self
+            .state
+            .tracks
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| delete_targets.contains(&t.id))
+            .map(|(position, t)| {
+                let mut track = t.clone();
+                for plugin in track.plugins.iter_mut() {
+                    plugin.lv2_state = plugin.current_lv2_state(features);
+                }
+                (position, track)
+            })
+            .collect();
+        if !deleted_tracks.is_empty() {
+            self.history
+                .record(history::Edit::DeleteTracks(deleted_tracks));
         }
         self.state
             .tracks
             .retain(|t| !delete_targets.contains(&t.id));
+        let deleted = |target: &MidiLearnTarget| {
+            target
+                .track_id()
+                .is_some_and(|id| delete_targets.contains(&id))
+        };
+        self.state.midi_mappings.retain(|m| !deleted(&m.target));
+        if self.state.midi_learn.as_ref().is_some_and(deleted) {
+            self.state.midi_learn = None;
+        }
+        if let Some(armed_track) = self.state.armed_track {
+            if delete_targets.contains(&armed_track) {
+                self.state.armed_track = None;
+                self.send_command_or_warn(Command::ArmTrack(-1));
+            }
+        }
         self.play_sound();
         Ok(())
     }
 
-    /// Create a pprof report and call `callback` with the SVG data.
+    /// Rename a track.
+    pub fn rename_track(&mut self, track_id: i32, name: String) -> Result<(), Error> {
+        let index = self.track_index(track_id)?;
+        let before = std::mem::replace(&mut self.state.tracks[index].name, name.clone());
+        if before != name {
+            self.history.record(history::Edit::RenameTrack {
+                track_id,
+                before,
+                after: name,
+            });
+        }
+        Ok(())
+    }
+
+    /// Record the change of the properties of the track at `index` from
+    /// `before`.
+    fn record_track_properties(&mut self, index: usize, before: TrackProperties) {
+        let track = &self.state.tracks[index];
+        if track.properties == before {
+            return;
+        }
+        self.history.record(history::Edit::SetTrackProperties {
+            track_id: track.id,
+            before,
+            after: track.properties,
+        });
+    }
+
+    /// Returns true if there is an edit that may be undone with `undo`.
+    pub fn can_undo(&self) -> bool {
+        self.history.can_undo()
+    }
+
+    /// Returns true if there is an edit that may be redone with `redo`.
+    pub fn can_redo(&self) -> bool {
+        self.history.can_redo()
+    }
+
+    /// Undo the last arrangement edit. Creating, deleting, renaming and
+    /// changing the properties of tracks and adding, removing and moving
+    /// plugins may be undone.
+    ///
+    /// Plugins that are restored are new instances with the saved parameter
+    /// values. Any other internal state of a removed plugin, like a loaded
+    /// sample, is lost and is not restored by undoing the removal.
+    ///
+    /// Returns false if there was nothing to undo.
+    pub fn undo(&mut self) -> Result<bool, Error> {
+        let edit = match self.history.take_undo() {
+            Some(edit) => edit,
+            None => return Ok(false),
+        };
+        if let Err(err) = self.replay(edit.clone().inverse()) {
+            self.history.push_undo(edit);
+            return Err(err);
+        }
+        self.history.push_redo(edit);
+        Ok(true)
+    }
+
+    /// Redo the last arrangement edit that was undone. Like with `undo`,
+    /// plugins that are added back are new instances with the saved parameter
+    /// values.
+    ///
+    /// Returns false if there was nothing to redo.
+    pub fn redo(&mut self) -> Result<bool, Error> {
+        let edit = match self.history.take_redo() {
+            Some(edit) => edit,
+            None => return Ok(false),
+        };
+        if let Err(err) = self.replay(edit.clone()) {
+            self.history.push_redo(edit);
+            return Err(err);
+        }
+        self.history.push_undo(edit);
+        Ok(true)
+    }
+
+    /// Apply `edit` without recording it.
+    fn replay(&mut self, edit: history::Edit) -> Result<(), Error> {
+        self.history.replaying = true;
+        let res = self.apply_edit(edit);
+        self.history.replaying = false;
+        res
+    }
+
+    /// Apply `edit` through the same paths as the edits made by the user.
+    fn apply_edit(&mut self, edit: history::Edit) -> Result<(), Error> {
+        match edit {
+            history::Edit::AddTracks(tracks) => {
+                for (position, track) in tracks {
+                    self.restore_track(position, track)?;
+                }
+                self.play_sound();
+                Ok(())
+            }
+            history::Edit::DeleteTracks(tracks) => {
+                self.delete_tracks(tracks.into_iter().map(|(_, t)| t.id).collect())
+            }
+            history::Edit::AddPlugin {
+                track_id,
+                plugin_index,
+                plugin,
+            } => {
+                self.insert_plugin(track_id, plugin_index, plugin)?;
+                self.play_sound();
+                Ok(())
+            }
+            history::Edit::RemovePlugin {
+                track_id,
+                plugin_index,
+                ..
+            } => self.remove_plugin_from_track(track_id, plugin_index),
+            history::Edit::MovePlugin { track_id, from, to } => {
+                self.move_plugin(track_id, from, to)
+            }
+            history::Edit::RenameTrack {
+                track_id, after, ..
+            } => self.rename_track(track_id, after),
+            history::Edit::SetTrackProperties {
+                track_id, after, ..
+            } => {
+                self.set_track_midi_source(track_id, after.midi_source)?;
+                self.set_track_midi_channel(track_id, after.midi_channel)?;
+                self.set_track_send_midi(track_id, after.send_midi)?;
+                self.set_track_transpose(track_id, after.transpose)?;
+                self.set_track_solo(track_id, after.solo)?;
+                self.set_track_pan(track_id, after.pan)?;
+                self.set_track_volume(track_id, after.volume)
+            }
+        }
+    }
+
+    /// Add `track` back at `position` in the track list with its id, plugins
+    /// and clip. The track is not armed.
+    fn restore_track(&mut self, position: usize, mut track: Track) -> Result<(), Error> {
+        if self.track_index(track.id).is_ok() {
+            return Err(Error::invalid(format!("track {} already exists", track.id)));
+        }
+        track.properties.armed = false;
+        track.properties.monitoring = false;
+        track.properties.disabled = false;
+        track.plugin_error = None;
+        let audio_engine_track = self.engine_track(&track, self.adapter.buffer_size())?;
+        for (index, plugin) in track.plugins.iter_mut().enumerate() {
+            plugin.lv2_state_handle = audio_engine_track
+                .plugin(index)
+                .and_then(PluginInstance::lv2_state_handle);
+        }
+        self.send_command(Command::AddTrack(Box::new(audio_engine_track)))?;
+        self.state.next_track_id = self.state.next_track_id.max(track.id + 1);
+        let position = position.min(self.state.tracks.len());
+        self.state.tracks.insert(position, track);
+        Ok(())
+    }
+
+    /// Create a pprof report by sampling `frequency_hz` times a second and
+    /// call `callback` with the flamegraph SVG data or the reason it could not
+    /// be created. Only one profile may be taken at a time. If another profile
+    /// is in progress, `callback` is called immediately with
+    /// `ProfileError::InProgress`.
     pub fn pprof_report(
         &self,
         duration: std::time::Duration,
-        callback: Box<dyn Send + FnOnce(Vec<u8>)>,
+        frequency_hz: i32,
+        callback: Box<dyn Send + FnOnce(Result<Vec<u8>, ProfileError>)>,
     ) {
+        if self.profiling.swap(true, Ordering::AcqRel) {
+            callback(Err(ProfileError::InProgress));
+            return;
+        }
+        let profiling = self.profiling.clone();
         std::thread::spawn(move || {
-            let guard = pprof::ProfilerGuardBuilder::default()
-                .frequency(1000)
-                .build()
-                .unwrap();
-            std::thread::sleep(duration);
-            let report = guard.report().build().unwrap();
-            let mut flamegraph_svg = Vec::new();
-            report.flamegraph(&mut flamegraph_svg).unwrap();
-            callback(flamegraph_svg);
+            let profile = || -> Result<Vec<u8>, ProfileError> {
+                let guard = pprof::ProfilerGuardBuilder::default()
+                    .frequency(frequency_hz)
+                    .build()?;
+                std::thread::sleep(duration);
+                let report = guard.report().build()?;
+                let mut flamegraph_svg = Vec::new();
+                report.flamegraph(&mut flamegraph_svg)?;
+                Ok(flamegraph_svg)
+            };
+            let result = profile();
+            profiling.store(false, Ordering::Release);
+            callback(result);
         });
     }
+
+    /// Returns true if a performance profile is being taken.
+    pub fn is_profiling(&self) -> bool {
+        self.profiling.load(Ordering::Acquire)
+    }
 }
 
 /// Get the id for the plugin.
@@ -273,15 +3041,269 @@ fn id_for_plugin(p: &livi::Plugin) -> String {
     format!("lv2:{}", p.uri())
 }
 
+/// Get the built in plugins. They are not blacklisted.
+fn internal_plugins() -> Vec<Plugin> {
+    vec![
+        Plugin {
+            id: DRUM_KIT_PLUGIN_ID.to_string(),
+            name: "Drum Kit".to_string(),
+            class: PluginClass::Instrument,
+            params: Vec::new(),
+            blacklisted: false,
+            unsupported: None,
+        },
+        Plugin {
+            id: SAMPLER_PLUGIN_ID.to_string(),
+            name: "Sampler".to_string(),
+            class: PluginClass::Instrument,
+            params: sampler_params(),
+            blacklisted: false,
+            unsupported: Some("the sampler is added by recording a sample".to_string()),
+        },
+        Plugin {
+            id: GAIN_PLUGIN_ID.to_string(),
+            name: "Gain".to_string(),
+            class: PluginClass::Effect,
+            params: gain_params(),
+            blacklisted: false,
+            unsupported: None,
+        },
+    ]
+}
+
+/// Get the control parameters of the gain plugin.
+fn gain_params() -> Vec<PluginParam> {
+    vec![
+        PluginParam {
+            name: "Gain (dB)".to_string(),
+            symbol: "gain".to_string(),
+            index: Gain::GAIN_DB_PORT,
+            default_value: 0.0,
+            min_value: Some(Gain::MIN_GAIN_DB),
+            max_value: Some(Gain::MAX_GAIN_DB),
+        },
+        PluginParam {
+            name: "Invert polarity".to_string(),
+            symbol: "invert".to_string(),
+            index: Gain::INVERT_PORT,
+            default_value: 0.0,
+            min_value: Some(0.0),
+            max_value: Some(1.0),
+        },
+        PluginParam {
+            name: "Balance".to_string(),
+            symbol: "balance".to_string(),
+            index: Gain::BALANCE_PORT,
+            default_value: 0.0,
+            min_value: Some(-1.0),
+            max_value: Some(1.0),
+        },
+    ]
+}
+
+/// Get the control parameters of the sampler plugin. These are the envelope
+/// ports of `SampleTrigger`.
+fn sampler_params() -> Vec<PluginParam> {
+    let default = Envelope::default();
+    let param = |name: &str, symbol: &str, index, default_value, max_value| PluginParam {
+        name: name.to_string(),
+        symbol: symbol.to_string(),
+        index,
+        default_value,
+        min_value: Some(0.0),
+        max_value: Some(max_value),
+    };
+    vec![
+        param(
+            "Attack (ms)",
+            "attack",
+            Envelope::ATTACK_PORT,
+            default.attack_ms,
+            2000.0,
+        ),
+        param(
+            "Decay (ms)",
+            "decay",
+            Envelope::DECAY_PORT,
+            default.decay_ms,
+            2000.0,
+        ),
+        param(
+            "Sustain",
+            "sustain",
+            Envelope::SUSTAIN_PORT,
+            default.sustain,
+            1.0,
+        ),
+        param(
+            "Release (ms)",
+            "release",
+            Envelope::RELEASE_PORT,
+            default.release_ms,
+            5000.0,
+        ),
+    ]
+}
+
+/// Get the control parameters for the plugin.
+fn params_for_plugin(p: &livi::Plugin) -> Vec<PluginParam> {
+    p.ports_with_type(livi::PortType::ControlInput)
+        .map(|port| PluginParam {
+            symbol: p
+                .raw()
+                .port_by_index(port.index.0)
+                .and_then(|raw| raw.symbol())
+                .and_then(|symbol| symbol.as_str().map(str::to_string))
+                .unwrap_or_default(),
+            name: port.name,
+            index: port.index.0,
+            default_value: port.default_value,
+            min_value: port.min_value,
+            max_value: port.max_value,
+        })
+        .collect()
+}
+
+/// Get the presets for the plugin.
+fn presets_for_plugin(livi: &livi::World, p: &livi::Plugin) -> Vec<PluginPreset> {
+    let world = livi.raw();
+    let preset_class = world.new_uri("http://lv2plug.in/ns/ext/presets#Preset");
+    let label = world.new_uri("http://www.w3.org/2000/01/rdf-schema#label");
+    let port = world.new_uri("http://lv2plug.in/ns/lv2core#port");
+    let symbol = world.new_uri("http://lv2plug.in/ns/lv2core#symbol");
+    let value = world.new_uri("http://lv2plug.in/ns/ext/presets#value");
+    let presets = match p.raw().related(Some(&preset_class)) {
+        Some(presets) => presets,
+        None => return Vec::new(),
+    };
+    let mut ret: Vec<PluginPreset> = presets
+        .iter()
+        .filter_map(|preset| {
+            let uri = preset.as_uri()?.to_string();
+            let _ = world.load_resource(&preset);
+            let name = world
+                .get(Some(&preset), Some(&label), None)
+                .and_then(|n| n.as_str().map(str::to_string))
+                .unwrap_or_else(|| uri.clone());
+            let mut values = Vec::new();
+            let mut unsupported = Vec::new();
+            for preset_port in world.find_nodes(Some(&preset), &port, None).iter() {
+                let port_symbol = match world
+                    .get(Some(&preset_port), Some(&symbol), None)
+                    .and_then(|n| n.as_str().map(str::to_string))
+                {
+                    Some(s) => s,
+                    None => continue,
+                };
+                let port_value = world
+                    .get(Some(&preset_port), Some(&value), None)
+                    .and_then(|n| {
+                        n.as_float()
+                            .or_else(|| n.as_int().map(|v| v as f32))
+                            .or_else(|| n.as_bool().map(|v| if v { 1.0 } else { 0.0 }))
+                    });
+                match port_value {
+                    Some(v) => values.push((port_symbol, v)),
+                    None => unsupported.push(port_symbol),
+                }
+            }
+            Some(PluginPreset {
+                uri,
+                name,
+                values,
+                unsupported,
+            })
+        })
+        .collect();
+    ret.sort_by(|a, b| a.name.cmp(&b.name));
+    ret
+}
+
+/// Convert notes into paired note on and note off events.
+fn notes_to_events(mut notes: Vec<Note>) -> Result<Vec<MidiEvent>, Error> {
+    for note in notes.iter() {
+        if !(note.start >= 0.0 && note.length > 0.0) {
+            return Err(Error::InvalidRequest(format!(
+                "note must start at or after 0 and have a positive length: {note:?}"
+            )));
+        }
+    }
+    notes.sort_by(|a, b| a.pitch.cmp(&b.pitch).then(a.start.total_cmp(&b.start)));
+    let mut merged: Vec<Note> = Vec::with_capacity(notes.len());
+    for note in notes {
+        match merged.last_mut() {
+            Some(prev) if prev.pitch == note.pitch && note.start < prev.end() => {
+                prev.length = prev.end().max(note.end()) - prev.start;
+            }
+            _ => merged.push(note),
+        }
+    }
+    let mut events = Vec::with_capacity(merged.len() * 2);
+    for note in merged {
+        let pitch = wmidi::Note::try_from(note.pitch)
+            .map_err(|_| Error::InvalidRequest(format!("{} is not a valid pitch", note.pitch)))?;
+        let velocity = wmidi::U7::try_from(note.velocity.max(1)).map_err(|_| {
+            Error::InvalidRequest(format!("{} is not a valid velocity", note.velocity))
+        })?;
+        events.push(MidiEvent {
+            beat: note.start,
+            message: wmidi::MidiMessage::NoteOn(wmidi::Channel::Ch1, pitch, velocity),
+        });
+        events.push(MidiEvent {
+            beat: note.end(),
+            message: wmidi::MidiMessage::NoteOff(wmidi::Channel::Ch1, pitch, wmidi::U7::MIN),
+        });
+    }
+    events.sort_by(|a, b| a.beat.total_cmp(&b.beat));
+    Ok(events)
+}
+
 /// A plugin.
 #[derive(Clone, Debug)]
 pub struct Plugin {
     pub id: String,
     pub name: String,
     pub class: PluginClass,
+    /// The control parameters of the plugin.
+    pub params: Vec<PluginParam>,
+    /// True if the plugin is on the blacklist.
+    pub blacklisted: bool,
+    /// Why the audio engine can not run the plugin, if it can not. Adding
+    /// the plugin to a track fails.
+    pub unsupported: Option<String>,
+}
+
+/// A control parameter of a plugin.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PluginParam {
+    /// The human readable name.
+    pub name: String,
+    /// The unique identifier of the parameter within the plugin.
+    pub symbol: String,
+    /// The port index of the parameter.
+    pub index: usize,
+    /// The default value.
+    pub default_value: f32,
+    /// The minimum value, if any.
+    pub min_value: Option<f32>,
+    /// The maximum value, if any.
+    pub max_value: Option<f32>,
+}
+
+/// A preset for a plugin.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PluginPreset {
+    /// The unique identifier of the preset.
+    pub uri: String,
+    /// The human readable name.
+    pub name: String,
+    /// The control values of the preset, keyed by port symbol.
+    pub values: Vec<(String, f32)>,
+    /// The symbols of ports whose values are not control values.
+    pub unsupported: Vec<String>,
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum PluginClass {
     Instrument,
     Effect,
@@ -301,11 +3323,199 @@ pub struct Track {
 
     /// The track properties.
     pub properties: TrackProperties,
+
+    /// The recorded midi clip, if any.
+    pub clip: Option<Vec<MidiEvent>>,
+
+    /// The step pattern that the clip was created from, if any.
+    pub step_pattern: Option<StepPattern>,
+
+    /// The audio that is played instead of running the plugins if the track
+    /// is frozen.
+    pub frozen: Option<audio_engine::freeze::FrozenAudio>,
+
+    /// The index of the plugin and the error that disabled the track, if a
+    /// plugin failure disabled it. This is set along with
+    /// `TrackProperties::disabled`.
+    pub plugin_error: Option<(usize, String)>,
+}
+
+/// The kind of input that is recorded.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RecordingMode {
+    /// Record midi into a clip.
+    Midi,
+    /// Record audio into a sample.
+    Audio,
+}
+
+impl Track {
+    /// Get the index of the last sampler plugin and the sample that it plays.
+    /// Recorded audio and recorded samples are added to the track as sampler
+    /// plugins.
+    pub fn recorded_sample(
+        &self,
+    ) -> Option<(usize, &Arc<audio_engine::audio_buffer::AudioBuffer>)> {
+        self.plugins
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(index, p)| p.sample.as_ref().map(|sample| (index, sample)))
+    }
+
+    /// Get the length of the clip in beats if the track has a clip.
+    pub fn clip_length(&self) -> Option<f64> {
+        self.clip.as_ref().map(|events| Clip::length_for(events))
+    }
+
+    /// Get the notes of the clip. Notes that are not released before the end
+    /// of the clip end with the clip.
+    pub fn notes(&self) -> Vec<Note> {
+        let (events, length) = match (self.clip.as_ref(), self.clip_length()) {
+            (Some(events), Some(length)) => (events, length),
+            _ => return Vec::new(),
+        };
+        let mut events: Vec<&MidiEvent> = events.iter().collect();
+        events.sort_by(|a, b| a.beat.total_cmp(&b.beat));
+        let mut open: [Option<(f64, u8)>; 128] = [None; 128];
+        let mut notes = Vec::new();
+        let mut close = |pitch: u8, start: f64, velocity: u8, end: f64| {
+            notes.push(Note {
+                start,
+                length: end - start,
+                pitch,
+                velocity,
+            })
+        };
+        for event in events {
+            let (pitch, velocity) = match event.message {
+                wmidi::MidiMessage::NoteOn(_, n, v) => (u8::from(n), u8::from(v)),
+                wmidi::MidiMessage::NoteOff(_, n, _) => (u8::from(n), 0),
+                _ => continue,
+            };
+            if let Some((start, v)) = open[pitch as usize].take() {
+                close(pitch, start, v, event.beat);
+            }
+            if velocity > 0 {
+                open[pitch as usize] = Some((event.beat, velocity));
+            }
+        }
+        for (pitch, note) in open.iter().enumerate() {
+            if let Some((start, v)) = note {
+                close(pitch as u8, *start, *v, length);
+            }
+        }
+        notes.sort_by(|a, b| a.start.total_cmp(&b.start).then(a.pitch.cmp(&b.pitch)));
+        notes
+    }
+}
+
+/// A note within a clip.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Note {
+    /// The start of the note in beats.
+    pub start: f64,
+    /// The length of the note in beats.
+    pub length: f64,
+    /// The midi note number.
+    pub pitch: u8,
+    /// The midi velocity.
+    pub velocity: u8,
 }
 
-#[derive(Copy, Clone, Debug)]
+impl Note {
+    /// The end of the note in beats.
+    pub fn end(&self) -> f64 {
+        self.start + self.length
+    }
+}
+
+/// The number of steps in a step pattern. Each step is a sixteenth note.
+pub const STEP_PATTERN_STEPS: usize = 16;
+
+/// The maximum number of rows in a step pattern.
+pub const STEP_PATTERN_MAX_ROWS: usize = 8;
+
+/// A one measure pattern of notes on a grid.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StepPattern {
+    /// The rows of the pattern.
+    pub rows: Vec<StepRow>,
+}
+
+/// A row of a step pattern.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StepRow {
+    /// The midi note number that is played by the row.
+    pub pitch: u8,
+    /// The steps that play the note.
+    pub steps: [bool; STEP_PATTERN_STEPS],
+}
+
+impl StepPattern {
+    /// The velocity of each step.
+    const VELOCITY: u8 = 100;
+
+    /// Get the notes that are played by the pattern.
+    pub fn notes(&self) -> Vec<Note> {
+        let step_length = 4.0 / STEP_PATTERN_STEPS as f64;
+        self.rows
+            .iter()
+            .flat_map(|row| {
+                row.steps
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, on)| **on)
+                    .map(move |(step, _)| Note {
+                        start: step as f64 * step_length,
+                        length: step_length,
+                        pitch: row.pitch,
+                        velocity: StepPattern::VELOCITY,
+                    })
+            })
+            .collect()
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct TrackProperties {
+    /// If true, recording captures the input of the track.
     pub armed: bool,
+    /// If true, live midi input is sent to the plugins of the track. Arming a
+    /// track turns on monitoring and disarming it turns monitoring off.
+    pub monitoring: bool,
+    /// The index of the midi input that live input is taken from. If `None`,
+    /// the inputs that no track is routed to are used.
+    pub midi_source: Option<usize>,
+    /// The midi channel, from 0 to 15, that live input is taken from. If
+    /// `None`, input from all channels is used.
+    pub midi_channel: Option<u8>,
+    /// The number of semitones, from -24 to 24, that notes are shifted by.
+    pub transpose: i8,
+    /// If true, the midi that the plugins produce is sent to the midi output.
+    pub send_midi: bool,
+    pub solo: bool,
+    pub pan: f32,
+    /// The volume multiplier.
+    pub volume: f32,
+    /// True if a plugin failure disabled the track. The track is silent until
+    /// `State::enable_track` is called. This is set from the audio engine and
+    /// is not changed by undo or redo.
+    pub disabled: bool,
+}
+
+/// A change to some of the properties of a track. Fields that are `None` are
+/// left unchanged.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TrackPropertiesUpdate {
+    pub name: Option<String>,
+    pub armed: Option<bool>,
+    pub monitoring: Option<bool>,
+    pub solo: Option<bool>,
+    /// The stereo panning, from -1.0 to 1.0.
+    pub pan: Option<f32>,
+    /// The volume multiplier. Must be at least 0.0.
+    pub volume: Option<f32>,
 }
 
 // A plugin within a track.
@@ -313,6 +3523,50 @@ pub struct TrackProperties {
 pub struct TrackPlugin {
     // The id of the plugin.
     pub plugin_id: String,
+    /// The values of parameters that have been set, keyed by port index.
+    pub param_values: HashMap<usize, f32>,
+    /// The kit file loaded into a drum kit plugin, if any.
+    pub drum_kit: Option<PathBuf>,
+    /// The sample played by a sampler plugin.
+    pub sample: Option<Arc<audio_engine::audio_buffer::AudioBuffer>>,
+    /// True if the plugin could not be instantiated when the project was
+    /// loaded. A placeholder that passes audio through runs in its place.
+    pub missing: bool,
+    /// The state that an LV2 plugin last saved through the LV2 State extension.
+    /// New instances restore it when there is no running instance to save the
+    /// state from.
+    pub lv2_state: Option<Lv2State>,
+    /// Saves the state of the running instance of an LV2 plugin that supports
+    /// the LV2 State extension.
+    pub lv2_state_handle: Option<Arc<Lv2StateHandle>>,
+}
+
+impl TrackPlugin {
+    /// Get the state of an LV2 plugin that supports the LV2 State extension.
+    /// The state is saved from the running instance if there is one and
+    /// `lv2_state` is returned otherwise.
+    pub fn current_lv2_state(&self, features: &livi::Features) -> Option<Lv2State> {
+        match self
+            .lv2_state_handle
+            .as_ref()
+            .and_then(|h| h.save(features))
+        {
+            Some(Ok(state)) => Some(state),
+            Some(Err(err)) => {
+                warn!("Failed to save the state of {}: {}", self.plugin_id, err);
+                self.lv2_state.clone()
+            }
+            None => self.lv2_state.clone(),
+        }
+    }
+
+    /// Get the current value of `param`.
+    pub fn param_value(&self, param: &PluginParam) -> f32 {
+        self.param_values
+            .get(&param.index)
+            .copied()
+            .unwrap_or(param.default_value)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -322,4 +3576,571 @@ pub struct Metronome {
 
     // The volume of the metronome.
     pub volume: f32,
+
+    /// The fraction of each pair of beats taken by the first beat. 0.5 is
+    /// straight timing and larger values delay the off-beats.
+    pub swing: f32,
+
+    /// If true, the tempo and transport follow the midi clock on the midi
+    /// input instead of `beats_per_minute`.
+    pub external_sync: bool,
+}
+
+/// A control that a midi controller may be mapped to with midi learn.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MidiLearnTarget {
+    /// The volume of the metronome.
+    MetronomeVolume,
+    /// The volume of the track with the id.
+    TrackVolume(i32),
+    /// The gain applied to the output.
+    MasterVolume,
+    /// A control parameter of a plugin. The master track is addressed with
+    /// `MASTER_TRACK_ID`.
+    PluginParam {
+        track_id: i32,
+        plugin_index: usize,
+        port_index: usize,
+    },
+}
+
+impl MidiLearnTarget {
+    /// Get the id of the track that the control belongs to, if any.
+    pub fn track_id(&self) -> Option<i32> {
+        match self {
+            MidiLearnTarget::MetronomeVolume | MidiLearnTarget::MasterVolume => None,
+            MidiLearnTarget::TrackVolume(track_id) => Some(*track_id),
+            MidiLearnTarget::PluginParam { track_id, .. } => Some(*track_id),
+        }
+    }
+}
+
+impl std::fmt::Display for MidiLearnTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MidiLearnTarget::MetronomeVolume => write!(f, "metronome volume"),
+            MidiLearnTarget::TrackVolume(track_id) => write!(f, "track {track_id} volume"),
+            MidiLearnTarget::MasterVolume => write!(f, "master volume"),
+            MidiLearnTarget::PluginParam {
+                track_id,
+                plugin_index,
+                port_index,
+            } => write!(
+                f,
+                "parameter {port_index} of plugin {plugin_index} on track {track_id}"
+            ),
+        }
+    }
+}
+
+/// A midi controller that is mapped to a control. Values from 0 to 127 are
+/// scaled to the range of the control.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MidiMapping {
+    /// The controller number.
+    pub cc: u8,
+    /// The midi channel, from 0 to 15.
+    pub channel: u8,
+    /// The control that the controller sets.
+    pub target: MidiLearnTarget,
+}
+
+/// A level meter that falls over time and holds its peak.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Meter {
+    /// The level in decibels relative to full scale.
+    level_db: Option<f32>,
+    /// The held peak in decibels relative to full scale.
+    peak_db: Option<f32>,
+    /// When the held peak was set.
+    peak_time: Option<Instant>,
+}
+
+impl Meter {
+    /// Get the level in decibels relative to full scale or `None` if it is
+    /// silent.
+    pub fn level_db(&self) -> Option<f32> {
+        self.level_db
+    }
+
+    /// Get the held peak in decibels relative to full scale or `None` if it
+    /// is silent.
+    pub fn peak_db(&self) -> Option<f32> {
+        self.peak_db
+    }
+
+    /// Raise the meter to `peak`, an absolute sample value.
+    fn feed(&mut self, now: Instant, peak: f32) {
+        let db = 20.0 * peak.log10();
+        if db < METER_FLOOR_DB {
+            return;
+        }
+        if self.level_db.is_none_or(|l| db > l) {
+            self.level_db = Some(db);
+        }
+        if self.peak_db.is_none_or(|p| db >= p) {
+            self.peak_db = Some(db);
+            self.peak_time = Some(now);
+        }
+    }
+
+    /// Let the meter fall for `elapsed`. The meter falls to silence if it is
+    /// not fed. The peak is held for `METER_PEAK_HOLD` before it falls.
+    fn decay(&mut self, now: Instant, elapsed: Duration) {
+        let fall = METER_DECAY_DB_PER_SECOND * elapsed.as_secs_f32();
+        self.level_db = self
+            .level_db
+            .map(|l| l - fall)
+            .filter(|l| *l >= METER_FLOOR_DB);
+        let peak_held = self
+            .peak_time
+            .is_some_and(|t| now.duration_since(t) < METER_PEAK_HOLD);
+        if !peak_held {
+            self.peak_db = self
+                .peak_db
+                .map(|p| p - fall)
+                .filter(|p| *p >= METER_FLOOR_DB);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use audio_engine::{plugin_scan::PluginScan, ProcessorConfig};
+    use jack_adapter::dummy::DummyAdapter;
+
+    use super::*;
+
+    const BUFFER_SIZE: usize = 128;
+
+    fn state() -> State {
+        let adapter = DummyAdapter::with_plugins(
+            &ProcessorConfig::default(),
+            44100.0,
+            BUFFER_SIZE,
+            PluginScan::with_world(livi::World::with_plugin_predicate(|_| false)),
+        );
+        State::new(Box::new(adapter), &StateConfig::default())
+    }
+
+    /// Estimate the cost of `plugin_id` and wait for the result.
+    fn estimate(state: &State, plugin_id: &str) -> Result<PluginCost, Error> {
+        let (tx, rx) = mpsc::channel();
+        state.estimate_plugin_cost(plugin_id, 100, Box::new(move |cost| tx.send(cost).unwrap()));
+        rx.recv().unwrap()
+    }
+
+    #[test]
+    fn plugin_cost_is_cached_per_plugin() {
+        let state = state();
+        assert_eq!(state.cached_plugin_cost(GAIN_PLUGIN_ID), None);
+        let cost = estimate(&state, GAIN_PLUGIN_ID).unwrap();
+        assert_eq!(
+            cost.budget,
+            Duration::from_secs_f64(BUFFER_SIZE as f64 / 44100.0)
+        );
+        assert!(cost.average <= cost.max);
+        assert_eq!(state.cached_plugin_cost(GAIN_PLUGIN_ID), Some(cost));
+        assert_eq!(state.cached_plugin_cost(DRUM_KIT_PLUGIN_ID), None);
+        // A cached cost is returned instead of measuring again.
+        assert_eq!(estimate(&state, GAIN_PLUGIN_ID).unwrap(), cost);
+    }
+
+    #[test]
+    fn plugin_cost_of_unknown_plugin_is_an_error() {
+        let state = state();
+        assert!(estimate(&state, "urn:not-a-plugin").is_err());
+        assert_eq!(state.cached_plugin_cost("urn:not-a-plugin"), None);
+    }
+
+    #[test]
+    fn plugin_cost_exceeds_threshold_only_once_estimated() {
+        let state = state();
+        assert!(!state.plugin_cost_exceeds(DRUM_KIT_PLUGIN_ID, 0.0));
+        let fraction = estimate(&state, DRUM_KIT_PLUGIN_ID)
+            .unwrap()
+            .budget_fraction();
+        assert!(fraction > 0.0);
+        assert!(state.plugin_cost_exceeds(DRUM_KIT_PLUGIN_ID, fraction / 2.0));
+        assert!(!state.plugin_cost_exceeds(DRUM_KIT_PLUGIN_ID, fraction * 2.0));
+    }
+
+    fn armed_tracks(state: &State) -> Vec<i32> {
+        state
+            .iter_tracks()
+            .filter(|t| t.properties.armed)
+            .map(|t| t.id)
+            .collect()
+    }
+
+    #[test]
+    fn deleting_the_armed_track_disarms() {
+        let mut state = state();
+        let first = state.create_track(None).unwrap();
+        let second = state.create_track(None).unwrap();
+        state.set_armed(Some(first)).unwrap();
+        assert_eq!(armed_tracks(&state), vec![first]);
+
+        state.delete_tracks(HashSet::from([first])).unwrap();
+        assert_eq!(state.state.armed_track, None);
+        assert!(armed_tracks(&state).is_empty());
+        assert!(state.start_recording().is_err());
+
+        let third = state.create_track(None).unwrap();
+        assert!(armed_tracks(&state).is_empty());
+        state.set_armed(Some(third)).unwrap();
+        assert_eq!(armed_tracks(&state), vec![third]);
+        state.delete_tracks(HashSet::from([second])).unwrap();
+        assert_eq!(state.state.armed_track, Some(third));
+    }
+
+    #[test]
+    fn arming_a_missing_track_is_an_error() {
+        let mut state = state();
+        let track = state.create_track(None).unwrap();
+        state.set_armed(Some(track)).unwrap();
+        assert!(matches!(
+            state.set_armed(Some(track + 100)),
+            Err(Error::TrackNotFound(_))
+        ));
+        assert_eq!(armed_tracks(&state), vec![track]);
+        state.set_armed(None).unwrap();
+        assert!(armed_tracks(&state).is_empty());
+    }
+
+    fn project_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("mini-leebee-{}-{name}.json", std::process::id()))
+    }
+
+    #[test]
+    fn saved_project_loads_back_into_the_same_state() {
+        let mut saved = state();
+        let track = saved.create_track(Some("Bass".to_string())).unwrap();
+        saved.set_track_volume(track, 0.8).unwrap();
+        saved.add_plugin_to_track(track, GAIN_PLUGIN_ID).unwrap();
+        saved.set_plugin_param(track, 0, "0", -6.0).unwrap();
+        saved
+            .set_clip_notes(
+                track,
+                vec![
+                    Note {
+                        start: 0.0,
+                        length: 1.0,
+                        pitch: 36,
+                        velocity: 100,
+                    },
+                    Note {
+                        start: 2.0,
+                        length: 0.5,
+                        pitch: 43,
+                        velocity: 80,
+                    },
+                ],
+            )
+            .unwrap();
+        saved.create_track(None).unwrap();
+        saved.set_metronome(
+            Metronome {
+                beats_per_minute: 96.0,
+                volume: 0.3,
+                swing: 0.6,
+                external_sync: false,
+            },
+            false,
+        );
+        saved.set_master_volume(0.7);
+        saved.set_limiter(false);
+        let path = project_path("round-trip");
+        saved.save_project(&path).unwrap();
+
+        let mut loaded = state();
+        loaded.create_track(Some("Replaced".to_string())).unwrap();
+        let errors = loaded.load_project(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(errors.is_empty(), "{errors:?}");
+
+        assert_eq!(loaded.iter_tracks().count(), saved.iter_tracks().count());
+        for (loaded, saved) in loaded.iter_tracks().zip(saved.iter_tracks()) {
+            assert_eq!(loaded.id, saved.id);
+            assert_eq!(loaded.name, saved.name);
+            assert_eq!(loaded.properties, saved.properties);
+            assert_eq!(loaded.notes(), saved.notes());
+            assert_eq!(loaded.plugins.len(), saved.plugins.len());
+            for (loaded, saved) in loaded.plugins.iter().zip(saved.plugins.iter()) {
+                assert_eq!(loaded.plugin_id, saved.plugin_id);
+                assert_eq!(loaded.param_values, saved.param_values);
+                assert!(!loaded.missing);
+            }
+        }
+        let (metronome, saved_metronome) = (loaded.metronome(), saved.metronome());
+        assert_eq!(metronome.beats_per_minute, saved_metronome.beats_per_minute);
+        assert_eq!(metronome.volume, saved_metronome.volume);
+        assert_eq!(metronome.swing, saved_metronome.swing);
+        assert_eq!(loaded.master_volume(), saved.master_volume());
+        assert_eq!(loaded.is_limiter_enabled(), saved.is_limiter_enabled());
+        assert_eq!(loaded.project_path(), Some(path.as_path()));
+        // New tracks do not reuse the ids of the loaded tracks.
+        let new_track = loaded.create_track(None).unwrap();
+        assert!(saved.iter_tracks().all(|t| t.id < new_track));
+    }
+
+    /// Map controller `cc` on channel 0 to `target` with midi learn.
+    fn map_controller(state: &mut State, cc: u8, target: MidiLearnTarget) {
+        state.start_midi_learn(target).unwrap();
+        state.handle_midi_cc(cc, 0, 0);
+    }
+
+    #[test]
+    fn saved_midi_mappings_load_back() {
+        let mut saved = state();
+        saved.create_track(None).unwrap();
+        let track = saved.create_track(None).unwrap();
+        saved.add_plugin_to_track(track, GAIN_PLUGIN_ID).unwrap();
+        saved.add_plugin_to_master(GAIN_PLUGIN_ID).unwrap();
+        map_controller(&mut saved, 1, MidiLearnTarget::MasterVolume);
+        map_controller(&mut saved, 2, MidiLearnTarget::TrackVolume(track));
+        for (cc, track_id) in [(3, track), (4, MASTER_TRACK_ID)] {
+            map_controller(
+                &mut saved,
+                cc,
+                MidiLearnTarget::PluginParam {
+                    track_id,
+                    plugin_index: 0,
+                    port_index: 0,
+                },
+            );
+        }
+        assert_eq!(saved.midi_mappings().len(), 4);
+        let path = project_path("midi-mappings");
+        saved.save_project(&path).unwrap();
+
+        let mut loaded = state();
+        loaded.create_track(None).unwrap();
+        map_controller(&mut loaded, 5, MidiLearnTarget::MetronomeVolume);
+        loaded
+            .start_midi_learn(MidiLearnTarget::MasterVolume)
+            .unwrap();
+        let errors = loaded.load_project(&path).unwrap();
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(loaded.midi_mappings(), saved.midi_mappings());
+        assert_eq!(loaded.midi_learn_target(), None);
+
+        // Mappings of tracks that are not in the project are dropped.
+        let mut project = project::Project::read(&path).unwrap();
+        project.tracks.retain(|t| t.id != track);
+        let json = serde_json::to_vec(&project).unwrap();
+        std::fs::write(&path, json).unwrap();
+        loaded.load_project(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let kept = [saved.midi_mappings()[0], saved.midi_mappings()[3]];
+        assert_eq!(loaded.midi_mappings(), kept);
+    }
+
+    #[test]
+    fn projects_without_midi_mappings_load() {
+        let mut saved = state();
+        saved.create_track(None).unwrap();
+        map_controller(&mut saved, 1, MidiLearnTarget::MasterVolume);
+        let path = project_path("version-1");
+        saved.save_project(&path).unwrap();
+        let mut project: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        let project = project.as_object_mut().unwrap();
+        project.insert("version".to_string(), 1.into());
+        project.remove("midi_mappings").unwrap();
+        std::fs::write(&path, serde_json::to_vec(project).unwrap()).unwrap();
+
+        let mut loaded = state();
+        map_controller(&mut loaded, 2, MidiLearnTarget::MetronomeVolume);
+        let errors = loaded.load_project(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(loaded.iter_tracks().count(), 1);
+        assert!(loaded.midi_mappings().is_empty());
+    }
+
+    #[test]
+    fn uninstalled_plugins_load_as_missing_placeholders() {
+        let mut saved = state();
+        let track = saved.create_track(None).unwrap();
+        saved.add_plugin_to_track(track, GAIN_PLUGIN_ID).unwrap();
+        saved.set_plugin_param(track, 0, "0", 3.0).unwrap();
+        let path = project_path("missing-plugin");
+        saved.save_project(&path).unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let contents = contents.replace(GAIN_PLUGIN_ID, "urn:not-installed");
+        std::fs::write(&path, contents).unwrap();
+
+        let mut loaded = state();
+        let errors = loaded.load_project(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(errors.len(), 1);
+        let plugin = &loaded.iter_tracks().next().unwrap().plugins[0];
+        assert_eq!(plugin.plugin_id, "urn:not-installed");
+        assert!(plugin.missing);
+        assert_eq!(
+            plugin.param_values,
+            saved.state.tracks[0].plugins[0].param_values
+        );
+    }
+
+    #[test]
+    fn failed_save_keeps_the_previous_samples() {
+        let mut saved = state();
+        let track = saved.create_track(None).unwrap();
+        let sample = audio_engine::audio_buffer::AudioBuffer::from_channels(&[
+            vec![0.5; 64],
+            vec![-0.5; 64],
+        ]);
+        saved.push_sample_plugin(track, sample).unwrap();
+        let path = project_path("failed-save");
+        saved.save_project(&path).unwrap();
+        let samples = project::samples_dir(&path);
+        let sample_files = || std::fs::read_dir(&samples).unwrap().count();
+        assert_eq!(sample_files(), 1);
+
+        // The project can not be written while a directory is in the way of
+        // its temporary file.
+        let tmp_path = path.with_extension("json.tmp");
+        std::fs::create_dir(&tmp_path).unwrap();
+        saved.delete_tracks(HashSet::from([track])).unwrap();
+        assert!(saved.save_project(&path).is_err());
+        std::fs::remove_dir(&tmp_path).unwrap();
+        assert_eq!(sample_files(), 1);
+        let mut loaded = state();
+        let errors = loaded.load_project(&path).unwrap();
+        assert!(errors.is_empty(), "{errors:?}");
+        assert!(loaded.state.tracks[0].plugins[0].sample.is_some());
+
+        // A successful save replaces the samples.
+        saved.save_project(&path).unwrap();
+        assert!(!samples.exists());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn loading_before_the_plugin_scan_finishes_is_an_error() {
+        let adapter = DummyAdapter::with_plugins(
+            &ProcessorConfig::default(),
+            44100.0,
+            BUFFER_SIZE,
+            PluginScan::default(),
+        );
+        let mut state = State::new(Box::new(adapter), &StateConfig::default());
+        let track = state.create_track(None).unwrap();
+        assert!(matches!(
+            state.load_project(&project_path("not-scanned")),
+            Err(Error::ScanningPlugins)
+        ));
+        assert_eq!(
+            state.iter_tracks().map(|t| t.id).collect::<Vec<_>>(),
+            vec![track]
+        );
+    }
+
+    /// Bounce `measures` measures of `state` and return the left channel.
+    fn bounce(state: &State, name: &str, measures: u32) -> Vec<f32> {
+        let path = project_path(name).with_extension("wav");
+        let (tx, rx) = mpsc::channel();
+        state
+            .bounce(
+                &path,
+                measures,
+                Box::new(|_| {}),
+                Box::new(move |res| tx.send(res).unwrap()),
+            )
+            .unwrap();
+        rx.recv().unwrap().unwrap();
+        let mut reader = hound::WavReader::open(&path).unwrap();
+        let samples: Vec<f32> = reader
+            .samples::<f32>()
+            .step_by(2)
+            .map(Result::unwrap)
+            .collect();
+        drop(reader);
+        std::fs::remove_file(&path).unwrap();
+        samples
+    }
+
+    #[test]
+    fn bounce_matches_the_live_output() {
+        let mut state = state();
+        let track = state.create_track(None).unwrap();
+        let sample = audio_engine::audio_buffer::AudioBuffer::from_channels(&[
+            vec![0.5; 44100],
+            vec![0.5; 44100],
+        ]);
+        state.push_sample_plugin(track, sample).unwrap();
+        state
+            .set_clip_notes(
+                track,
+                vec![Note {
+                    start: 0.0,
+                    length: 4.0,
+                    pitch: 60,
+                    velocity: 127,
+                }],
+            )
+            .unwrap();
+        state.set_metronome(
+            Metronome {
+                beats_per_minute: 240.0,
+                volume: 0.0,
+                swing: 0.5,
+                external_sync: false,
+            },
+            false,
+        );
+        // The master volume ramps from unity during the first block.
+        let peak = |samples: &[f32]| {
+            samples[BUFFER_SIZE..]
+                .iter()
+                .fold(0f32, |a, b| a.max(b.abs()))
+        };
+        let full = bounce(&state, "bounce-full", 1);
+        // A measure of 4 beats at 240 beats per minute lasts 1 second.
+        assert_eq!(full.len(), 44100);
+        assert!(peak(&full) > 0.1);
+
+        state.set_master_volume(0.5);
+        let half = bounce(&state, "bounce-half", 1);
+        assert!((peak(&half) - peak(&full) * 0.5).abs() < 1e-3);
+
+        state.add_plugin_to_master(GAIN_PLUGIN_ID).unwrap();
+        state
+            .set_plugin_param(MASTER_TRACK_ID, 0, "0", -60.0)
+            .unwrap();
+        let quiet = bounce(&state, "bounce-quiet", 1);
+        assert!(peak(&quiet) < peak(&half) * 0.01);
+
+        state.remove_plugin_from_master(0).unwrap();
+        state.set_master_volume(8.0);
+        let limited = bounce(&state, "bounce-limited", 1);
+        assert!(peak(&limited) <= 1.0);
+        state.set_limiter(false);
+        let clipped = bounce(&state, "bounce-clipped", 1);
+        assert!(peak(&clipped) > 1.0);
+    }
+
+    #[test]
+    fn stopping_the_output_recording_keeps_every_recorded_frame() {
+        let mut state = state();
+        let path = project_path("output").with_extension("wav");
+        state.start_master_recording(&path).unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        state.stop_master_recording().unwrap();
+        assert!(!state.is_master_recording());
+        // The stats are sent when the engine stops recording.
+        state.update();
+        let (frames_written, overruns) = state.master_recording_stats();
+        let reader = hound::WavReader::open(&path).unwrap();
+        let frames = reader.duration() as u64;
+        drop(reader);
+        std::fs::remove_file(&path).unwrap();
+        assert!(frames_written > 0);
+        assert_eq!(frames, frames_written);
+        assert_eq!(overruns, 0);
+    }
 }
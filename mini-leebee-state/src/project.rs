@@ -0,0 +1,684 @@
+use std::{
+    collections::HashMap,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use audio_engine::{
+    audio_buffer::AudioBuffer,
+    clip::MidiEvent,
+    lv2_state::{Lv2State, Lv2StateProperty},
+    track::MASTER_TRACK_ID,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Error, InnerState, MidiLearnTarget, MidiMapping, StepPattern, StepRow, Track, TrackPlugin,
+    TrackProperties, STEP_PATTERN_STEPS,
+};
+
+/// The version of the project format that is written.
+///
+/// - 1: The initial format.
+/// - 2: Adds `Project::midi_mappings`.
+pub const PROJECT_VERSION: u32 = 2;
+
+/// The start of the file name of autosaves.
+const AUTOSAVE_PREFIX: &str = "autosave-";
+
+/// A project as it is saved to disk.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Project {
+    /// The version of the format. Projects with a newer version can not be
+    /// read.
+    pub version: u32,
+    pub metronome: ProjectMetronome,
+    /// The gain applied to the output.
+    pub master_volume: f32,
+    /// True if the limiter is enabled on the output.
+    pub limiter_enabled: bool,
+    /// The plugins on the master track.
+    pub master_plugins: Vec<ProjectPlugin>,
+    pub tracks: Vec<ProjectTrack>,
+    /// The mappings from midi controllers to controls. Added in version 2.
+    #[serde(default)]
+    pub midi_mappings: Vec<ProjectMidiMapping>,
+}
+
+/// The saved metronome settings.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ProjectMetronome {
+    pub beats_per_minute: f32,
+    pub volume: f32,
+    pub swing: f32,
+}
+
+/// A saved track. Frozen audio is not saved.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ProjectTrack {
+    pub id: i32,
+    pub name: String,
+    pub properties: ProjectTrackProperties,
+    pub plugins: Vec<ProjectPlugin>,
+    /// The events of the midi clip, if any.
+    pub clip: Option<Vec<ProjectMidiEvent>>,
+    /// The rows of the step pattern that the clip was created from, if any.
+    pub step_pattern: Option<Vec<ProjectStepRow>>,
+}
+
+/// The saved properties of a track. Arming and monitoring are not saved.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ProjectTrackProperties {
+    pub midi_source: Option<usize>,
+    pub midi_channel: Option<u8>,
+    pub transpose: i8,
+    pub send_midi: bool,
+    pub solo: bool,
+    pub pan: f32,
+    pub volume: f32,
+}
+
+/// A saved plugin instance. Control parameters, drum kit files and the state
+/// of LV2 plugins that support the LV2 State extension are saved. Only state
+/// that is plain old data is saved. Plugins without the extension are restored
+/// from their control parameters alone. The samples of sampler plugins are
+/// saved as WAV files in the directory returned by `samples_dir`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ProjectPlugin {
+    /// The id of the plugin.
+    pub plugin_id: String,
+    /// The values of parameters that have been set as `(port_index, value)`,
+    /// sorted by port index.
+    pub param_values: Vec<(usize, f32)>,
+    /// The kit file loaded into a drum kit plugin, if any.
+    #[serde(default)]
+    pub drum_kit: Option<PathBuf>,
+    /// The file name of the sample played by a sampler plugin within the
+    /// samples directory of the project, if any.
+    #[serde(default)]
+    pub sample: Option<String>,
+    /// The properties that the plugin saved through the LV2 State extension,
+    /// if it supports the extension.
+    #[serde(default)]
+    pub state: Option<Vec<ProjectStateProperty>>,
+}
+
+/// A saved property of the LV2 State of a plugin.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ProjectStateProperty {
+    /// The URI of the key.
+    pub key: String,
+    /// The URI of the type of the value.
+    pub value_type: String,
+    /// The `LV2_State_Flags` that the value was stored with.
+    pub flags: u32,
+    /// The raw bytes of the value.
+    pub value: Vec<u8>,
+}
+
+/// A saved midi event within a clip.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ProjectMidiEvent {
+    /// The position of the event in beats.
+    pub beat: f64,
+    /// The raw bytes of the midi message.
+    pub message: Vec<u8>,
+}
+
+/// A saved mapping from a midi controller to a control.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ProjectMidiMapping {
+    /// The controller number.
+    pub cc: u8,
+    /// The midi channel, from 0 to 15.
+    pub channel: u8,
+    pub target: ProjectMidiTarget,
+}
+
+/// The saved control of a midi mapping. Tracks are addressed by their saved
+/// id and the master track by `MASTER_TRACK_ID`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ProjectMidiTarget {
+    MetronomeVolume,
+    TrackVolume {
+        track_id: i32,
+    },
+    MasterVolume,
+    PluginParam {
+        track_id: i32,
+        plugin_index: usize,
+        port_index: usize,
+    },
+}
+
+/// A saved row of a step pattern.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ProjectStepRow {
+    pub pitch: u8,
+    pub steps: Vec<bool>,
+}
+
+impl Project {
+    /// Create a project from the current state. The LV2 State of running
+    /// plugins is saved with `features`.
+    pub(crate) fn from_state(state: &InnerState, features: &livi::Features) -> Project {
+        Project {
+            version: PROJECT_VERSION,
+            metronome: ProjectMetronome {
+                beats_per_minute: state.metronome.beats_per_minute,
+                volume: state.metronome.volume,
+                swing: state.metronome.swing,
+            },
+            master_volume: state.master_volume,
+            limiter_enabled: state.limiter_enabled,
+            master_plugins: state
+                .master
+                .plugins
+                .iter()
+                .enumerate()
+                .map(|(index, plugin)| ProjectPlugin::new(MASTER_TRACK_ID, index, plugin, features))
+                .collect(),
+            tracks: state
+                .tracks
+                .iter()
+                .map(|track| ProjectTrack::new(track, features))
+                .collect(),
+            midi_mappings: state
+                .midi_mappings
+                .iter()
+                .map(ProjectMidiMapping::new)
+                .collect(),
+        }
+    }
+
+    /// Read a project from the JSON file at `path`.
+    pub fn read(path: &Path) -> Result<Project, Error> {
+        let contents = std::fs::read(path).map_err(|err| Error::file(path, err))?;
+        let project: Project =
+            serde_json::from_slice(&contents).map_err(|err| Error::file(path, err))?;
+        if project.version > PROJECT_VERSION {
+            return Err(Error::invalid(format!(
+                "{path:?} has project version {} but only versions up to {PROJECT_VERSION} are supported",
+                project.version
+            )));
+        }
+        Ok(project)
+    }
+
+    /// Write the project as JSON to a temporary file next to `path` and
+    /// return the path of the temporary file. It replaces `path` once it is
+    /// renamed to `path`.
+    fn write_tmp(&self, path: &Path) -> Result<PathBuf, Error> {
+        let contents = serde_json::to_vec_pretty(self).map_err(|err| Error::file(path, err))?;
+        path.file_name()
+            .ok_or_else(|| Error::invalid(format!("{path:?} is not a file path")))?;
+        let tmp_path = with_suffix(path, ".tmp");
+        let write = || -> std::io::Result<()> {
+            let mut file = std::fs::File::create(&tmp_path)?;
+            file.write_all(&contents)?;
+            file.sync_all()
+        };
+        if let Err(err) = write() {
+            let _ = std::fs::remove_file(&tmp_path);
+            return Err(Error::file(&tmp_path, err));
+        }
+        Ok(tmp_path)
+    }
+}
+
+/// Save `project` to `path` and `samples` to the samples directory of `path`.
+///
+/// The project and the samples are first written next to their destinations
+/// and then renamed into place, so an interrupted or failed save leaves the
+/// previous save, including its samples, intact. The samples of the previous
+/// save are only removed once the new save is in place.
+pub fn save(
+    path: &Path,
+    project: &Project,
+    samples: &[(String, Arc<AudioBuffer>)],
+    sample_rate: f64,
+) -> Result<(), Error> {
+    let dir = samples_dir(path);
+    let new_dir = with_suffix(&dir, ".tmp");
+    let old_dir = with_suffix(&dir, ".old");
+    // Leftovers from an interrupted save.
+    remove_dir(&new_dir)?;
+    remove_dir(&old_dir)?;
+    let tmp_path = match write_samples(&new_dir, samples, sample_rate)
+        .and_then(|()| project.write_tmp(path))
+    {
+        Ok(tmp_path) => tmp_path,
+        Err(err) => {
+            let _ = remove_dir(&new_dir);
+            return Err(err);
+        }
+    };
+    let had_samples = rename_if_exists(&dir, &old_dir)?;
+    let commit = rename_if_exists(&new_dir, &dir)
+        .and_then(|_| std::fs::rename(&tmp_path, path).map_err(|err| Error::file(path, err)));
+    if let Err(err) = commit {
+        // Put the previous save back.
+        let _ = remove_dir(&dir);
+        if had_samples {
+            let _ = std::fs::rename(&old_dir, &dir);
+        }
+        let _ = remove_dir(&new_dir);
+        let _ = std::fs::remove_file(&tmp_path);
+        return Err(err);
+    }
+    remove_dir(&old_dir)
+}
+
+impl ProjectTrack {
+    fn new(track: &Track, features: &livi::Features) -> ProjectTrack {
+        ProjectTrack {
+            id: track.id,
+            name: track.name.clone(),
+            properties: ProjectTrackProperties::new(&track.properties),
+            plugins: track
+                .plugins
+                .iter()
+                .enumerate()
+                .map(|(index, plugin)| ProjectPlugin::new(track.id, index, plugin, features))
+                .collect(),
+            clip: track
+                .clip
+                .as_ref()
+                .map(|events| events.iter().map(ProjectMidiEvent::new).collect()),
+            step_pattern: track
+                .step_pattern
+                .as_ref()
+                .map(|pattern| pattern.rows.iter().map(ProjectStepRow::new).collect()),
+        }
+    }
+
+    /// Create the track without its plugins. The track is not armed.
+    pub fn to_track(&self) -> Result<Track, Error> {
+        let clip = match self.clip.as_ref() {
+            Some(events) => Some(
+                events
+                    .iter()
+                    .map(ProjectMidiEvent::to_midi_event)
+                    .collect::<Result<Vec<_>, _>>()?,
+            ),
+            None => None,
+        };
+        let step_pattern = match self.step_pattern.as_ref() {
+            Some(rows) => Some(StepPattern {
+                rows: rows
+                    .iter()
+                    .map(ProjectStepRow::to_step_row)
+                    .collect::<Result<Vec<_>, _>>()?,
+            }),
+            None => None,
+        };
+        Ok(Track {
+            id: self.id,
+            name: self.name.clone(),
+            plugins: Vec::with_capacity(self.plugins.len()),
+            properties: TrackProperties {
+                armed: false,
+                monitoring: false,
+                midi_source: self.properties.midi_source,
+                midi_channel: self.properties.midi_channel,
+                transpose: self.properties.transpose,
+                send_midi: self.properties.send_midi,
+                solo: self.properties.solo,
+                pan: self.properties.pan,
+                volume: self.properties.volume,
+                disabled: false,
+            },
+            clip,
+            step_pattern,
+            frozen: None,
+            plugin_error: None,
+        })
+    }
+}
+
+impl ProjectTrackProperties {
+    fn new(properties: &TrackProperties) -> ProjectTrackProperties {
+        ProjectTrackProperties {
+            midi_source: properties.midi_source,
+            midi_channel: properties.midi_channel,
+            transpose: properties.transpose,
+            send_midi: properties.send_midi,
+            solo: properties.solo,
+            pan: properties.pan,
+            volume: properties.volume,
+        }
+    }
+}
+
+impl ProjectPlugin {
+    fn new(
+        track_id: i32,
+        plugin_index: usize,
+        plugin: &TrackPlugin,
+        features: &livi::Features,
+    ) -> ProjectPlugin {
+        let mut param_values: Vec<(usize, f32)> = plugin
+            .param_values
+            .iter()
+            .map(|(port_index, value)| (*port_index, *value))
+            .collect();
+        param_values.sort_by_key(|(port_index, _)| *port_index);
+        ProjectPlugin {
+            plugin_id: plugin.plugin_id.clone(),
+            param_values,
+            drum_kit: plugin.drum_kit.clone(),
+            sample: plugin
+                .sample
+                .as_ref()
+                .map(|_| sample_file_name(track_id, plugin_index)),
+            state: plugin.current_lv2_state(features).map(|state| {
+                state
+                    .properties
+                    .into_iter()
+                    .map(ProjectStateProperty::new)
+                    .collect()
+            }),
+        }
+    }
+
+    /// Load the sample of the plugin from the samples directory of the
+    /// project at `project_path`. The sample is resampled to `sample_rate` if
+    /// the file has a different rate. Returns `None` if the plugin has no
+    /// sample.
+    pub fn load_sample(
+        &self,
+        project_path: &Path,
+        sample_rate: f64,
+    ) -> Result<Option<AudioBuffer>, Error> {
+        let name = match self.sample.as_ref() {
+            Some(name) => name,
+            None => return Ok(None),
+        };
+        let path = samples_dir(project_path).join(name);
+        let sample = AudioBuffer::with_wav(&path).map_err(|err| Error::file(&path, err))?;
+        Ok(Some(match sample.sample_rate() {
+            Some(rate) if rate != sample_rate => sample.resampled(rate, sample_rate),
+            _ => sample,
+        }))
+    }
+
+    /// Create the plugin of a track. The plugin is not marked as missing and
+    /// its sample, if any, is not loaded. The LV2 State is restored once the
+    /// plugin is instantiated.
+    pub fn to_track_plugin(&self) -> TrackPlugin {
+        TrackPlugin {
+            plugin_id: self.plugin_id.clone(),
+            param_values: self.param_values.iter().copied().collect(),
+            drum_kit: self.drum_kit.clone(),
+            sample: None,
+            missing: false,
+            lv2_state: self.state.as_ref().map(|properties| Lv2State {
+                properties: properties
+                    .iter()
+                    .map(ProjectStateProperty::to_lv2_state_property)
+                    .collect(),
+            }),
+            lv2_state_handle: None,
+        }
+    }
+}
+
+impl ProjectStateProperty {
+    fn new(property: Lv2StateProperty) -> ProjectStateProperty {
+        ProjectStateProperty {
+            key: property.key,
+            value_type: property.value_type,
+            flags: property.flags,
+            value: property.value,
+        }
+    }
+
+    fn to_lv2_state_property(&self) -> Lv2StateProperty {
+        Lv2StateProperty {
+            key: self.key.clone(),
+            value_type: self.value_type.clone(),
+            flags: self.flags,
+            value: self.value.clone(),
+        }
+    }
+}
+
+impl ProjectMidiEvent {
+    fn new(event: &MidiEvent) -> ProjectMidiEvent {
+        let mut data = [0u8; 3];
+        let size = event.message.copy_to_slice(&mut data).unwrap_or(0);
+        ProjectMidiEvent {
+            beat: event.beat,
+            message: data[..size].to_vec(),
+        }
+    }
+
+    fn to_midi_event(&self) -> Result<MidiEvent, Error> {
+        let message = wmidi::MidiMessage::try_from(self.message.as_slice())
+            .ok()
+            .and_then(wmidi::MidiMessage::drop_unowned_sysex)
+            .ok_or_else(|| {
+                Error::invalid(format!("{:?} is not a valid midi message", self.message))
+            })?;
+        Ok(MidiEvent {
+            beat: self.beat,
+            message,
+        })
+    }
+}
+
+impl ProjectMidiMapping {
+    fn new(mapping: &MidiMapping) -> ProjectMidiMapping {
+        let target = match mapping.target {
+            MidiLearnTarget::MetronomeVolume => ProjectMidiTarget::MetronomeVolume,
+            MidiLearnTarget::TrackVolume(track_id) => ProjectMidiTarget::TrackVolume { track_id },
+            MidiLearnTarget::MasterVolume => ProjectMidiTarget::MasterVolume,
+            MidiLearnTarget::PluginParam {
+                track_id,
+                plugin_index,
+                port_index,
+            } => ProjectMidiTarget::PluginParam {
+                track_id,
+                plugin_index,
+                port_index,
+            },
+        };
+        ProjectMidiMapping {
+            cc: mapping.cc,
+            channel: mapping.channel,
+            target,
+        }
+    }
+
+    /// Convert the saved mapping to a mapping. `track_ids` maps the saved ids
+    /// of tracks to the ids of the loaded tracks. Returns `None` if the mapping
+    /// targets a track that was not loaded.
+    pub(crate) fn to_midi_mapping(&self, track_ids: &HashMap<i32, i32>) -> Option<MidiMapping> {
+        let track_id = |id: i32| {
+            if id == MASTER_TRACK_ID {
+                Some(MASTER_TRACK_ID)
+            } else {
+                track_ids.get(&id).copied()
+            }
+        };
+        let target = match self.target {
+            ProjectMidiTarget::MetronomeVolume => MidiLearnTarget::MetronomeVolume,
+            ProjectMidiTarget::TrackVolume { track_id: id } => {
+                MidiLearnTarget::TrackVolume(*track_ids.get(&id)?)
+            }
+            ProjectMidiTarget::MasterVolume => MidiLearnTarget::MasterVolume,
+            ProjectMidiTarget::PluginParam {
+                track_id: id,
+                plugin_index,
+                port_index,
+            } => MidiLearnTarget::PluginParam {
+                track_id: track_id(id)?,
+                plugin_index,
+                port_index,
+            },
+        };
+        Some(MidiMapping {
+            cc: self.cc,
+            channel: self.channel,
+            target,
+        })
+    }
+}
+
+impl ProjectStepRow {
+    fn new(row: &StepRow) -> ProjectStepRow {
+        ProjectStepRow {
+            pitch: row.pitch,
+            steps: row.steps.to_vec(),
+        }
+    }
+
+    fn to_step_row(&self) -> Result<StepRow, Error> {
+        let steps = self.steps.as_slice().try_into().map_err(|_| {
+            Error::invalid(format!(
+                "step pattern rows must have {STEP_PATTERN_STEPS} steps but got {}",
+                self.steps.len()
+            ))
+        })?;
+        Ok(StepRow {
+            pitch: self.pitch,
+            steps,
+        })
+    }
+}
+
+/// Get the directory that the samples of the project at `path` are saved
+/// in.
+pub fn samples_dir(path: &Path) -> PathBuf {
+    path.with_extension("samples")
+}
+
+/// Get the file name that the sample of the plugin at `plugin_index` of a track
+/// is saved to.
+fn sample_file_name(track_id: i32, plugin_index: usize) -> String {
+    if track_id == MASTER_TRACK_ID {
+        format!("master-plugin-{plugin_index}.wav")
+    } else {
+        format!("track-{track_id}-plugin-{plugin_index}.wav")
+    }
+}
+
+/// Get the samples of the sampler plugins in `state` along with the file names
+/// that `Project::from_state` saves them under.
+pub(crate) fn samples(state: &InnerState) -> Vec<(String, Arc<AudioBuffer>)> {
+    let master = std::iter::once((MASTER_TRACK_ID, &state.master));
+    let tracks = state.tracks.iter().map(|t| (t.id, t));
+    master
+        .chain(tracks)
+        .flat_map(|(track_id, track)| {
+            track
+                .plugins
+                .iter()
+                .enumerate()
+                .filter_map(move |(index, plugin)| {
+                    let sample = plugin.sample.clone()?;
+                    Some((sample_file_name(track_id, index), sample))
+                })
+        })
+        .collect()
+}
+
+/// Remove the samples directory of the project at `path`, if any.
+pub fn remove_samples(path: &Path) -> Result<(), Error> {
+    remove_dir(&samples_dir(path))
+}
+
+/// Get `path` with `suffix` appended to its file name.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// Remove `dir` and its contents if it exists.
+fn remove_dir(dir: &Path) -> Result<(), Error> {
+    match std::fs::remove_dir_all(dir) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(Error::file(dir, err)),
+        _ => Ok(()),
+    }
+}
+
+/// Rename `from` to `to` if `from` exists. Returns true if it was renamed.
+fn rename_if_exists(from: &Path, to: &Path) -> Result<bool, Error> {
+    match std::fs::rename(from, to) {
+        Ok(()) => Ok(true),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(Error::file(from, err)),
+    }
+}
+
+/// Write `samples` as 32 bit float WAV files to `dir`. The directory is only
+/// created if there are samples.
+fn write_samples(
+    dir: &Path,
+    samples: &[(String, Arc<AudioBuffer>)],
+    sample_rate: f64,
+) -> Result<(), Error> {
+    if samples.is_empty() {
+        return Ok(());
+    }
+    std::fs::create_dir_all(dir).map_err(|err| Error::file(dir, err))?;
+    for (name, sample) in samples {
+        let path = dir.join(name);
+        let spec = hound::WavSpec {
+            channels: sample.channels() as u16,
+            sample_rate: sample_rate as u32,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let write = || -> Result<(), hound::Error> {
+            let mut wav = hound::WavWriter::create(&path, spec)?;
+            for frame in 0..sample.buffer_size() {
+                for channel in sample.iter_channels() {
+                    wav.write_sample(channel[frame])?;
+                }
+            }
+            wav.finalize()
+        };
+        write().map_err(|err| Error::file(&path, err))?;
+    }
+    Ok(())
+}
+
+/// Get the autosaves in `dir` from oldest to newest.
+pub fn autosaves(dir: &Path) -> Vec<PathBuf> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut autosaves: Vec<(u128, PathBuf)> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let path = entry.path();
+            if path.extension()? != "json" {
+                return None;
+            }
+            let timestamp = path
+                .file_stem()?
+                .to_str()?
+                .strip_prefix(AUTOSAVE_PREFIX)?
+                .parse()
+                .ok()?;
+            Some((timestamp, path))
+        })
+        .collect();
+    autosaves.sort();
+    autosaves.into_iter().map(|(_, path)| path).collect()
+}
+
+/// Get the path of a new autosave in `dir`. The name holds the time of the
+/// autosave so that autosaves sort by age.
+pub fn autosave_path(dir: &Path) -> PathBuf {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    dir.join(format!("{AUTOSAVE_PREFIX}{millis}.json"))
+}
@@ -1,11 +1,29 @@
 use std::{
     collections::{HashMap, HashSet},
-    sync::{atomic::AtomicBool, Arc},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
 };
 
 use eframe::egui::{self, Widget};
 use log::*;
-use mini_leebee_state::{Plugin, State};
+use mini_leebee_state::{
+    Error, Meter, MidiLearnTarget, Note, Plugin, PluginClass, PluginPreset, RecordingMode, State,
+    StepPattern, StepRow, TrackPropertiesUpdate, DRUM_KIT_PLUGIN_ID, MASTER_TRACK_ID,
+    METER_FLOOR_DB, STEP_PATTERN_MAX_ROWS, STEP_PATTERN_STEPS,
+};
+
+use crate::{
+    keyboard::Keyboard,
+    settings::Settings,
+    wizard::{Wizard, WizardOutcome},
+};
+
+/// The number of blocks to run when estimating the cost of a plugin.
+const PLUGIN_COST_BLOCKS: usize = 64;
+
+/// The longest sample that can be recorded into the sampler.
+const SAMPLE_RECORDING_SECONDS: f32 = 10.0;
 
 #[derive(Debug)]
 pub struct App {
@@ -18,57 +36,167 @@ pub struct App {
     state: State,
     /// The value of the BPM text. This is not necessarily the currently set BPM.
     bpm_text: String,
-    /// The set of plugins.
+    /// The set of plugins. This is empty until the plugins have been scanned.
     plugins: Vec<Plugin>,
+    /// True once `plugins` has been populated from the plugin scan.
+    plugins_loaded: bool,
     /// A mapping from a plugin id to its index in the plugins vector.
     plugin_to_index: HashMap<String, usize>,
+    /// The class of plugins to list. If `None`, all plugins are listed.
+    plugin_class_filter: Option<PluginClass>,
+    /// Only plugins whose name or id contain this text, ignoring case, are
+    /// listed.
+    plugin_search: String,
+    /// If true, blacklisted plugins are listed so that they may be unhidden.
+    show_hidden_plugins: bool,
     /// The id of the selected track. If invalid, then it is assumed no track is
     /// selected.
     selected_track_id: i32,
     /// If true, the UI should be refreshed using the client.
     refresh: bool,
-    /// If true, the UI has requested a performance profile from the server and
-    /// is still waiting.
-    profile_in_progress: Arc<AtomicBool>,
+    /// The reason the last performance profile failed, if it failed.
+    profile_error: Arc<Mutex<Option<String>>>,
+    /// The user settings.
+    settings: Settings,
+    /// The setup wizard, if it is open.
+    wizard: Option<Wizard>,
+    /// A plugin waiting for confirmation before being added to a track. If the
+    /// track is `None`, then a new track will be created.
+    pending_add: Option<(Option<i32>, Plugin)>,
+    /// The track id and plugin index of the plugin whose parameters are being
+    /// edited.
+    param_editor: Option<(i32, usize)>,
+    /// The presets for each plugin id. Presets are loaded the first time the
+    /// plugin's parameters are edited.
+    plugin_presets: HashMap<String, Vec<PluginPreset>>,
+    /// The path of the wav file to use for the metronome click.
+    metronome_sample_text: String,
+    /// The path of the kit file to load into the drum kit in the parameter
+    /// editor.
+    drum_kit_text: String,
+    /// The measure and beat to seek to if the seek dialog is open.
+    seek_dialog: Option<(i16, i16)>,
+    /// The path of the wav file to record the output to.
+    master_record_path: String,
+    /// The path of the wav file to bounce the project to.
+    bounce_path: String,
+    /// The number of measures to bounce.
+    bounce_measures: u32,
+    /// The fraction of the bounce that has been rendered if a bounce is in
+    /// progress.
+    bounce_progress: Arc<Mutex<Option<f32>>>,
+    /// The on screen keyboard.
+    keyboard: Keyboard,
+    /// The path of the file to save the project to.
+    project_path: String,
+    /// When the project was last autosaved, or when the app started.
+    last_autosave: Instant,
+    /// The newest autosave if it is newer than the last saved project and the
+    /// user has not decided whether to restore it.
+    autosave_restore: Option<PathBuf>,
 }
 
 impl App {
-    /// Create a new application from a client.
-    pub fn new(args: crate::args::Arguments, state: State) -> App {
+    /// Create a new application from a client. If `settings` is `None`, the
+    /// setup wizard is shown.
+    pub fn new(args: crate::args::Arguments, state: State, settings: Option<Settings>) -> App {
         let metronome = state.metronome().clone();
-        let plugins = state.get_plugins();
-        for plugin in plugins.iter() {
-            info!("{:?}", plugin);
-        }
-        let plugin_to_index = plugins
-            .iter()
-            .enumerate()
-            .map(|(idx, p)| (p.id.clone(), idx))
-            .collect();
+        let wizard = match settings {
+            Some(_) => None,
+            None => Some(Wizard::new(&state, Settings::default())),
+        };
+        let last_project = settings.as_ref().and_then(|s| s.last_project.clone());
+        let autosave_restore = autosave_dir()
+            .and_then(|dir| State::latest_autosave(&dir))
+            .filter(|autosave| {
+                match (
+                    modified(autosave),
+                    last_project.as_deref().and_then(modified),
+                ) {
+                    (Some(autosave), Some(saved)) => autosave > saved,
+                    _ => true,
+                }
+            });
         App {
             args,
             state,
             bpm_text: metronome.beats_per_minute.to_string(),
-            plugins,
-            plugin_to_index,
+            plugins: Vec::new(),
+            plugins_loaded: false,
+            plugin_to_index: HashMap::new(),
+            plugin_class_filter: None,
+            plugin_search: String::new(),
+            show_hidden_plugins: false,
             selected_track_id: 0,
             refresh: false,
-            profile_in_progress: Arc::new(AtomicBool::new(false)),
+            profile_error: Arc::new(Mutex::new(None)),
+            settings: settings.unwrap_or_default(),
+            wizard,
+            pending_add: None,
+            param_editor: None,
+            plugin_presets: HashMap::new(),
+            seek_dialog: None,
+            metronome_sample_text: String::new(),
+            drum_kit_text: String::new(),
+            master_record_path: "mini-leebee.wav".to_string(),
+            bounce_path: "mini-leebee-bounce.wav".to_string(),
+            bounce_measures: 8,
+            bounce_progress: Arc::new(Mutex::new(None)),
+            keyboard: Keyboard::default(),
+            project_path: last_project
+                .map(|p| p.display().to_string())
+                .unwrap_or_else(|| "mini-leebee.json".to_string()),
+            last_autosave: Instant::now(),
+            autosave_restore,
         }
     }
 }
 
 impl eframe::App for App {
     fn update(&mut self, ctx: &egui::Context, _: &mut eframe::Frame) {
+        // Scopes from the audio thread are collected into the profiling frame
+        // of the UI.
+        profiling::finish_frame!();
         self.state.update();
+        if !self.plugins_loaded {
+            self.maybe_load_plugins();
+        }
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::COMMAND, egui::Key::S)) {
+            self.save_project();
+        }
+        let redo_modifiers = egui::Modifiers {
+            shift: true,
+            ..egui::Modifiers::COMMAND
+        };
+        if ctx.input_mut(|i| i.consume_key(redo_modifiers, egui::Key::Z)) {
+            self.redo();
+        } else if ctx.input_mut(|i| i.consume_key(egui::Modifiers::COMMAND, egui::Key::Z)) {
+            self.undo();
+        }
+        if self.state.is_disconnected() {
+            egui::TopBottomPanel::top("jack_panel").show(ctx, |ui| self.update_jack_banner(ui));
+        }
         egui::SidePanel::left("left_panel").show(ctx, |ui| self.update_plugin_panel(ui));
         egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
             self.update_top_bar(ui);
         });
+        egui::TopBottomPanel::bottom("keyboard_panel").show(ctx, |ui| {
+            let keyboard = egui::CollapsingHeader::new("Keyboard")
+                .show(ui, |ui| self.keyboard.show(ui, &self.state));
+            if keyboard.body_returned.is_none() {
+                self.keyboard.release_all(&self.state);
+            }
+        });
         egui::CentralPanel::default().show(ctx, |ui| {
             self.update_track_list(ui);
             self.update_track(ui);
         });
+        self.update_wizard(ctx);
+        self.update_pending_add(ctx);
+        self.update_seek_dialog(ctx);
+        self.update_param_editor(ctx);
+        self.update_autosave_restore(ctx);
+        self.maybe_autosave();
         self.maybe_refresh(ctx);
     }
 }
@@ -83,55 +211,540 @@ impl App {
         self.refresh = false;
     }
 
+    /// Populate the plugins once the plugin scan has finished.
+    fn maybe_load_plugins(&mut self) {
+        // Blacklisted plugins are kept so that tracks that already use them
+        // can still find them. They are filtered when listing plugins.
+        let plugins = match self.state.get_plugins(true) {
+            Ok(plugins) => plugins,
+            Err(Error::ScanningPlugins) => return,
+            Err(err) => {
+                error!("Failed to get plugins: {}", err);
+                return;
+            }
+        };
+        for plugin in plugins.iter() {
+            info!("{:?}", plugin);
+        }
+        self.plugin_to_index = plugins
+            .iter()
+            .enumerate()
+            .map(|(idx, p)| (p.id.clone(), idx))
+            .collect();
+        self.plugins = plugins;
+        self.plugins_loaded = true;
+        self.refresh = true;
+    }
+
+    fn save_project(&mut self) {
+        let path = PathBuf::from(&self.project_path);
+        if let Err(err) = self.state.save_project(&path) {
+            error!("Failed to save project: {}", err);
+            return;
+        }
+        self.settings.last_project = Some(path);
+        if let Err(err) = self.settings.save() {
+            error!("Failed to save settings: {}", err);
+        }
+    }
+
+    fn load_project(&mut self, path: &Path) {
+        match self.state.load_project(path) {
+            Ok(errors) => {
+                for err in errors {
+                    error!("Failed to load plugin: {}", err);
+                }
+                self.bpm_text = self.state.metronome().beats_per_minute.to_string();
+                self.selected_track_id = self.state.iter_tracks().next().map_or(0, |t| t.id);
+                self.param_editor = None;
+                self.refresh = true;
+            }
+            Err(err) => error!("Failed to load project: {}", err),
+        }
+    }
+
+    fn undo(&mut self) {
+        match self.state.undo() {
+            Ok(true) => self.after_history_change(),
+            Ok(false) => {}
+            Err(err) => error!("Failed to undo: {}", err),
+        }
+    }
+
+    fn redo(&mut self) {
+        match self.state.redo() {
+            Ok(true) => self.after_history_change(),
+            Ok(false) => {}
+            Err(err) => error!("Failed to redo: {}", err),
+        }
+    }
+
+    /// Drop selections that may no longer exist after an undo or redo.
+    fn after_history_change(&mut self) {
+        if self.state.track(self.selected_track_id).is_none() {
+            self.selected_track_id = 0;
+        }
+        self.param_editor = None;
+        self.refresh = true;
+    }
+
+    /// Autosave the project if the autosave interval has passed. Autosaves
+    /// that are skipped because another save is in progress are tried again
+    /// on the next frame.
+    fn maybe_autosave(&mut self) {
+        let interval = Duration::from_secs(self.args.autosave_minutes * 60);
+        if interval.is_zero() || self.last_autosave.elapsed() < interval {
+            return;
+        }
+        // Autosaving before the user decides would rotate away the autosave
+        // that may be restored.
+        if self.autosave_restore.is_some() {
+            return;
+        }
+        let dir = match autosave_dir() {
+            Some(dir) if self.state.iter_tracks().next().is_some() => dir,
+            _ => {
+                self.last_autosave = Instant::now();
+                return;
+            }
+        };
+        if self.state.autosave(&dir, self.args.autosave_keep) {
+            self.last_autosave = Instant::now();
+        }
+    }
+
+    fn update_autosave_restore(&mut self, ctx: &egui::Context) {
+        let path = match self.autosave_restore.clone() {
+            Some(p) => p,
+            None => return,
+        };
+        let mut restore = false;
+        let mut ignore = false;
+        egui::Window::new("Restore autosave")
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.label(format!(
+                    "An autosave newer than the last saved project was found at {}.",
+                    path.display()
+                ));
+                ui.horizontal(|ui| {
+                    restore = ui
+                        .add_enabled(self.plugins_loaded, egui::Button::new("Restore"))
+                        .on_disabled_hover_text("Plugins are still being scanned")
+                        .clicked();
+                    ignore = ui.button("Ignore").clicked();
+                });
+            });
+        if restore {
+            self.load_project(&path);
+        }
+        if restore || ignore {
+            self.autosave_restore = None;
+        }
+    }
+
+    fn update_jack_banner(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.colored_label(egui::Color32::RED, "JACK disconnected");
+            if ui.button("reconnect").clicked() {
+                match self.state.reconnect() {
+                    Ok(()) => {
+                        if !self.settings.audio_outputs.is_empty() {
+                            self.state
+                                .connect_audio_outputs(&self.settings.audio_outputs);
+                        }
+                        self.refresh = true;
+                    }
+                    Err(err) => error!("Failed to reconnect: {}", err),
+                }
+            }
+        });
+    }
+
+    fn update_wizard(&mut self, ctx: &egui::Context) {
+        let wizard = match self.wizard.as_mut() {
+            Some(w) => w,
+            None => return,
+        };
+        match wizard.show(ctx, &mut self.state, &self.plugins) {
+            WizardOutcome::InProgress => {}
+            WizardOutcome::Closed => self.wizard = None,
+            WizardOutcome::Finished {
+                settings,
+                first_track_plugin,
+            } => {
+                self.wizard = None;
+                if let Err(err) = settings.save() {
+                    error!("Failed to save settings: {}", err);
+                }
+                self.settings = settings;
+                if let Some(plugin) = first_track_plugin {
+                    if let Some(track_id) = create_track_with_plugin(&mut self.state, &plugin) {
+                        self.selected_track_id = track_id;
+                    }
+                    self.refresh = true;
+                }
+            }
+        }
+    }
+
     fn update_plugin_panel(&mut self, ui: &mut egui::Ui) {
+        if !self.plugins_loaded {
+            ui.horizontal(|ui| {
+                ui.spinner();
+                ui.label("Scanning plugins…");
+            });
+            return;
+        }
+        ui.horizontal(|ui| {
+            let filters = [
+                ("All", None),
+                ("Instruments", Some(PluginClass::Instrument)),
+                ("Effects", Some(PluginClass::Effect)),
+            ];
+            for (label, filter) in filters {
+                let label = match filter {
+                    Some(class) => format!("{} {}", plugin_class_icon(class), label),
+                    None => label.to_string(),
+                };
+                ui.selectable_value(&mut self.plugin_class_filter, filter, label);
+            }
+        });
+        ui.horizontal(|ui| {
+            egui::TextEdit::singleline(&mut self.plugin_search)
+                .hint_text("🔍 Search plugins")
+                .ui(ui);
+            if !self.plugin_search.is_empty() && ui.small_button("✖").clicked() {
+                self.plugin_search.clear();
+            }
+        });
+        ui.checkbox(&mut self.show_hidden_plugins, "Show hidden plugins");
+        let search = self.plugin_search.to_lowercase();
+        let mut toggle_hidden = None;
         egui::ScrollArea::vertical().show(ui, |ui| {
-            let selected_track_id = self
-                .state
-                .iter_tracks()
-                .find(|t| t.id == self.selected_track_id)
-                .map(|t| t.id);
+            let selected_track_id = self.state.track(self.selected_track_id).map(|t| t.id);
             for (idx, plugin) in self.plugins.iter().enumerate() {
+                if self
+                    .plugin_class_filter
+                    .is_some_and(|class| class != plugin.class)
+                {
+                    continue;
+                }
+                if !plugin.name.to_lowercase().contains(&search)
+                    && !plugin.id.to_lowercase().contains(&search)
+                {
+                    continue;
+                }
+                let hidden = self.state.is_blacklisted(&plugin.id);
+                if hidden && !self.show_hidden_plugins {
+                    continue;
+                }
                 ui.push_id(idx, |ui| {
-                    ui.label(&plugin.name);
+                    let mut text = egui::RichText::new(format!(
+                        "{} {} ({} params)",
+                        plugin_class_icon(plugin.class),
+                        plugin.name,
+                        plugin.params.len()
+                    ));
+                    if hidden || plugin.unsupported.is_some() {
+                        text = text.weak();
+                    }
+                    let params_label = ui.label(text).context_menu(|ui| {
+                        let label = if hidden {
+                            "Unhide plugin"
+                        } else {
+                            "Hide plugin"
+                        };
+                        if ui.button(label).clicked() {
+                            toggle_hidden = Some((plugin.id.clone(), hidden));
+                            ui.close_menu();
+                        }
+                    });
+                    if !plugin.params.is_empty() {
+                        params_label.on_hover_ui(|ui| {
+                            for param in plugin.params.iter() {
+                                ui.label(format!(
+                                    "{} [{}]: {} ({:?} to {:?})",
+                                    param.name,
+                                    param.symbol,
+                                    param.default_value,
+                                    param.min_value,
+                                    param.max_value
+                                ));
+                            }
+                        });
+                    }
                     ui.horizontal(|ui| {
+                        if let Some(reason) = plugin.unsupported.as_ref() {
+                            ui.weak("Unsupported").on_hover_text(reason);
+                            return;
+                        }
+                        let cost = self.state.cached_plugin_cost(&plugin.id);
+                        let needs_confirmation = self
+                            .state
+                            .plugin_cost_exceeds(&plugin.id, self.args.plugin_cost_threshold);
                         if ui.button("Create Track").clicked() {
-                            let track_name = plugin.name.clone();
-                            let track_id = self.state.create_track(Some(track_name)).unwrap();
-                            self.selected_track_id = track_id;
-                            self.state
-                                .add_plugin_to_track(track_id, &plugin.id)
-                                .unwrap();
-                            self.state.set_armed(Some(self.selected_track_id));
-                            self.refresh = true;
+                            if needs_confirmation {
+                                self.pending_add = Some((None, plugin.clone()));
+                            } else {
+                                if let Some(track_id) =
+                                    create_track_with_plugin(&mut self.state, plugin)
+                                {
+                                    self.selected_track_id = track_id;
+                                }
+                                self.refresh = true;
+                            }
                         }
                         if let Some(track_id) = selected_track_id {
                             if ui.button("Add To Track").clicked() {
-                                self.state
-                                    .add_plugin_to_track(track_id, &plugin.id)
-                                    .unwrap();
-                                self.refresh = true;
+                                if needs_confirmation {
+                                    self.pending_add = Some((Some(track_id), plugin.clone()));
+                                } else {
+                                    if let Err(err) =
+                                        self.state.add_plugin_to_track(track_id, &plugin.id)
+                                    {
+                                        log_add_plugin_error(plugin, err);
+                                    }
+                                    self.refresh = true;
+                                }
+                            }
+                        }
+                        match cost {
+                            Some(cost) => {
+                                ui.label(format!("~{:.0}%", cost.budget_fraction() * 100.0))
+                                    .on_hover_text(format!(
+                                        "average: {:?}, max: {:?}, budget: {:?}",
+                                        cost.average, cost.max, cost.budget
+                                    ));
+                            }
+                            None => {
+                                if ui
+                                    .small_button("⏱")
+                                    .on_hover_text("Estimate CPU cost")
+                                    .clicked()
+                                {
+                                    let ctx = ui.ctx().clone();
+                                    self.state.estimate_plugin_cost(
+                                        &plugin.id,
+                                        PLUGIN_COST_BLOCKS,
+                                        Box::new(move |result| {
+                                            if let Err(err) = result {
+                                                warn!("Failed to estimate plugin cost: {}", err);
+                                            }
+                                            ctx.request_repaint();
+                                        }),
+                                    );
+                                }
                             }
                         }
                     });
                 });
             }
         });
+        match toggle_hidden {
+            Some((plugin_id, true)) => {
+                if let Err(err) = self.state.unblacklist_plugin(&plugin_id) {
+                    error!("Failed to unhide plugin: {}", err);
+                }
+            }
+            Some((plugin_id, false)) => {
+                if let Err(err) = self.state.blacklist_plugin(&plugin_id) {
+                    error!("Failed to hide plugin: {}", err);
+                }
+            }
+            None => {}
+        }
+    }
+
+    fn update_pending_add(&mut self, ctx: &egui::Context) {
+        let (track_id, plugin) = match self.pending_add.as_ref() {
+            Some(p) => p.clone(),
+            None => return,
+        };
+        let fraction = self
+            .state
+            .cached_plugin_cost(&plugin.id)
+            .map(|c| c.budget_fraction())
+            .unwrap_or(0.0);
+        egui::Window::new("Add Plugin")
+            .collapsible(false)
+            .show(ctx, |ui| {
+                ui.label(format!(
+                    "{} uses ~{:.0}% of the processing budget.",
+                    plugin.name,
+                    fraction * 100.0
+                ));
+                ui.horizontal(|ui| {
+                    if ui.button("Add").clicked() {
+                        match track_id {
+                            Some(track_id) => {
+                                if let Err(err) =
+                                    self.state.add_plugin_to_track(track_id, &plugin.id)
+                                {
+                                    log_add_plugin_error(&plugin, err);
+                                }
+                            }
+                            None => {
+                                if let Some(track_id) =
+                                    create_track_with_plugin(&mut self.state, &plugin)
+                                {
+                                    self.selected_track_id = track_id;
+                                }
+                            }
+                        }
+                        self.pending_add = None;
+                        self.refresh = true;
+                    }
+                    if ui.button("Cancel").clicked() {
+                        self.pending_add = None;
+                    }
+                });
+            });
     }
 
     fn update_top_bar(&mut self, ui: &mut egui::Ui) {
         ui.horizontal(|ui| {
+            ui.menu_button("Project", |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("File");
+                    ui.text_edit_singleline(&mut self.project_path);
+                });
+                if ui.button("Save").on_hover_text("Ctrl+S").clicked() {
+                    self.save_project();
+                    ui.close_menu();
+                }
+                if ui.button("Open").clicked() {
+                    self.load_project(&PathBuf::from(&self.project_path));
+                    ui.close_menu();
+                }
+            });
+            ui.menu_button("Edit", |ui| {
+                let undo = egui::Button::new("Undo").shortcut_text("Ctrl+Z");
+                if ui
+                    .add_enabled(self.state.can_undo(), undo)
+                    .on_hover_text("Restored plugins lose any state that is not a parameter.")
+                    .clicked()
+                {
+                    self.undo();
+                    ui.close_menu();
+                }
+                let redo = egui::Button::new("Redo").shortcut_text("Ctrl+Shift+Z");
+                if ui.add_enabled(self.state.can_redo(), redo).clicked() {
+                    self.redo();
+                    ui.close_menu();
+                }
+            });
+            ui.menu_button("Help", |ui| {
+                if ui.button("Setup Wizard").clicked() {
+                    self.wizard = Some(Wizard::new(&self.state, self.settings.clone()));
+                    ui.close_menu();
+                }
+            });
+            ui.menu_button("MIDI", |ui| {
+                let mut midi_clock = self.state.is_midi_clock_enabled();
+                if ui
+                    .checkbox(&mut midi_clock, "Send MIDI clock")
+                    .on_hover_text("Send clock, start and stop to the MIDI output")
+                    .changed()
+                {
+                    self.state.set_midi_clock(midi_clock);
+                }
+                let mut external_sync = self.state.metronome().external_sync;
+                if ui
+                    .checkbox(&mut external_sync, "Sync to external clock")
+                    .on_hover_text("Follow the tempo, start and stop of the MIDI input")
+                    .changed()
+                {
+                    let mut metronome = self.state.metronome().clone();
+                    metronome.external_sync = external_sync;
+                    self.state.set_metronome(metronome, false);
+                }
+                ui.separator();
+                let mappings = self.state.midi_mappings().to_vec();
+                if mappings.is_empty() {
+                    ui.label("No controllers are mapped. Right click a control to learn one.");
+                }
+                for mapping in mappings {
+                    ui.horizontal(|ui| {
+                        ui.label(format!(
+                            "CC {} on channel {}: {}",
+                            mapping.cc,
+                            mapping.channel + 1,
+                            mapping.target
+                        ));
+                        if ui.small_button("✖").on_hover_text("Remove").clicked() {
+                            if let Err(err) =
+                                self.state.remove_midi_mapping(mapping.cc, mapping.channel)
+                            {
+                                error!("Failed to remove MIDI mapping: {}", err);
+                            }
+                        }
+                    });
+                }
+            });
+            ui.menu_button("JACK", |ui| {
+                let mut follow_transport = self.state.is_following_jack_transport();
+                if ui
+                    .checkbox(&mut follow_transport, "Follow JACK transport")
+                    .on_hover_text("Start, stop and position playback with the JACK transport")
+                    .changed()
+                {
+                    self.state.set_follow_jack_transport(follow_transport);
+                }
+                let mut timebase_master = self.state.is_jack_timebase_master();
+                if ui
+                    .checkbox(&mut timebase_master, "Timebase master")
+                    .on_hover_text("Publish the tempo and time signature to other JACK clients")
+                    .changed()
+                {
+                    if let Err(err) = self.state.set_jack_timebase_master(timebase_master) {
+                        error!("Failed to set the JACK timebase master: {}", err);
+                    }
+                }
+            });
+            if let Some(target) = self.state.midi_learn_target() {
+                ui.label("learning…")
+                    .on_hover_text(format!("Move a MIDI controller to map it to {target}"));
+                if ui
+                    .small_button("✖")
+                    .on_hover_text("Cancel MIDI learn")
+                    .clicked()
+                {
+                    self.state.cancel_midi_learn();
+                }
+            }
             let mut metronome_is_on = self.state.metronome().volume > 0.0;
             if ui.button("New Track").clicked() {
-                let track_id = self.state.create_track(None).unwrap();
-                self.selected_track_id = track_id;
-                self.state.set_armed(Some(self.selected_track_id));
-                self.refresh = true;
+                match self.state.create_track(None) {
+                    Ok(track_id) => {
+                        self.selected_track_id = track_id;
+                        if let Err(err) = self.state.set_armed(Some(track_id)) {
+                            log_track_error("arm the new track", err);
+                        }
+                        self.refresh = true;
+                    }
+                    Err(err) => log_track_error("create a track", err),
+                }
             }
             ui.spacing();
-            let bpm_text = egui::TextEdit::singleline(&mut self.bpm_text)
+            if self.state.metronome().external_sync {
+                let text = match self.state.external_tempo() {
+                    Some(bpm) => format!("{bpm:.1} BPM"),
+                    None => "no clock".to_string(),
+                };
+                if self.state.is_external_clock_lost() {
+                    ui.colored_label(egui::Color32::RED, text)
+                        .on_hover_text("The external MIDI clock stopped");
+                } else {
+                    ui.label(text)
+                        .on_hover_text("Synced to the external MIDI clock");
+                }
+            } else if egui::TextEdit::singleline(&mut self.bpm_text)
                 .desired_width(48.0)
-                .ui(ui);
-            if bpm_text.lost_focus() {
+                .ui(ui)
+                .lost_focus()
+            {
                 match self.bpm_text.parse::<f32>() {
                     Ok(bpm) if bpm == self.state.metronome().beats_per_minute => {
                         info!("BPM is unchanged.");
@@ -139,7 +752,7 @@ impl App {
                     Ok(bpm) => {
                         let mut metronome = self.state.metronome().clone();
                         metronome.beats_per_minute = bpm;
-                        self.state.set_metronome(metronome);
+                        self.state.set_metronome(metronome, true);
                     }
                     Err(err) => {
                         warn!("{:?} is not a valid bpm: {}", self.bpm_text, err);
@@ -147,47 +760,453 @@ impl App {
                     }
                 }
             }
-            ui.label(format!("{}", self.state.time_info()));
-            if ui.toggle_value(&mut metronome_is_on, "metronome").clicked() {
+            if ui.button("Tap").on_hover_text("Tap tempo").clicked() {
+                if let Some(bpm) = self.state.tap_tempo() {
+                    self.bpm_text = format!("{bpm:.1}");
+                }
+            }
+            if ui
+                .button("❗")
+                .on_hover_text("MIDI panic: send all notes off to every track")
+                .clicked()
+            {
+                if let Err(err) = self.state.midi_panic() {
+                    error!("Failed to send MIDI panic: {}", err);
+                }
+            }
+            if self.state.is_playing() {
+                if ui.button("⏹").on_hover_text("Stop").clicked() {
+                    self.state.stop();
+                }
+                if self.state.is_recording() {
+                    ui.colored_label(egui::Color32::RED, "⏺ recording");
+                }
+            } else {
+                if ui.button("▶").on_hover_text("Play").clicked() {
+                    self.state.play();
+                }
+                let record = ui
+                    .button("⏺")
+                    .on_hover_text(format!(
+                        "Record after counting in for {} measures",
+                        self.settings.count_in_measures
+                    ))
+                    .context_menu(|ui| {
+                        let count_in = ui.add(
+                            egui::DragValue::new(&mut self.settings.count_in_measures)
+                                .clamp_range(0..=4)
+                                .suffix(" measure count in"),
+                        );
+                        if count_in.changed() {
+                            if let Err(err) = self.settings.save() {
+                                error!("Failed to save settings: {}", err);
+                            }
+                        }
+                        let mut mode = self.state.recording_mode();
+                        ui.horizontal(|ui| {
+                            ui.radio_value(&mut mode, RecordingMode::Midi, "MIDI");
+                            ui.radio_value(&mut mode, RecordingMode::Audio, "Audio");
+                        });
+                        if mode != self.state.recording_mode() {
+                            self.state.set_recording_mode(mode);
+                        }
+                    });
+                if record.clicked() {
+                    if let Err(err) = self
+                        .state
+                        .start_recording_with_count_in(self.settings.count_in_measures)
+                    {
+                        error!("Failed to start recording: {}", err);
+                    }
+                }
+            }
+            if ui.button("⏮").on_hover_text("Return to start").clicked() {
+                self.state.seek(0, 0);
+            }
+            let time_info = self.state.time_info();
+            if self.state.is_counting_in() {
+                let beats_left = -(time_info.measure as i32 * 4 + time_info.beat as i32);
+                ui.label(format!("{}…", beats_left.max(1)));
+            } else if ui
+                .link(format!("{}", time_info))
+                .on_hover_text("Seek")
+                .clicked()
+            {
+                self.seek_dialog = Some((time_info.measure.max(0), time_info.beat.max(0)));
+            }
+            let metronome_toggle = ui
+                .toggle_value(&mut metronome_is_on, "metronome")
+                .context_menu(|ui| {
+                    let mut swing = self.state.metronome().swing;
+                    if ui
+                        .add(egui::Slider::new(&mut swing, 0.0..=1.0).text("swing"))
+                        .changed()
+                    {
+                        let mut metronome = self.state.metronome().clone();
+                        metronome.swing = swing;
+                        self.state.set_metronome(metronome, false);
+                    }
+                    ui.horizontal(|ui| {
+                        ui.label("Click sample");
+                        ui.text_edit_singleline(&mut self.metronome_sample_text);
+                        if ui.button("Load").clicked() {
+                            let path = std::path::Path::new(&self.metronome_sample_text);
+                            match self.state.set_metronome_sample(path) {
+                                Ok(()) => ui.close_menu(),
+                                Err(err) => error!("Failed to load metronome sample: {}", err),
+                            }
+                        }
+                    });
+                    midi_learn_button(ui, &mut self.state, MidiLearnTarget::MetronomeVolume);
+                });
+            if metronome_toggle.clicked() {
                 let volume = if metronome_is_on { 0.5 } else { 0.0 };
                 let mut metronome = self.state.metronome().clone();
                 metronome.volume = volume;
-                self.state.set_metronome(metronome);
+                self.state.set_metronome(metronome, false);
+            }
+            let mut master_volume = self.state.master_volume();
+            let master_fader = ui
+                .add(egui::Slider::new(&mut master_volume, 0.0..=1.0).text("master"))
+                .context_menu(|ui| {
+                    let mut limiter = self.state.is_limiter_enabled();
+                    if ui.checkbox(&mut limiter, "Limiter").changed() {
+                        self.state.set_limiter(limiter);
+                    }
+                    midi_learn_button(ui, &mut self.state, MidiLearnTarget::MasterVolume);
+                });
+            if master_fader.changed() {
+                self.state.set_master_volume(master_volume);
+            }
+            show_meter(ui, &self.state.meter(MASTER_TRACK_ID));
+            if let Some(peak) = self.state.recent_clip() {
+                ui.colored_label(egui::Color32::RED, "CLIP")
+                    .on_hover_text(format!("peak {peak:.2}"));
+            }
+            let mut master_recording = self.state.is_master_recording();
+            let master_record_toggle = ui
+                .toggle_value(&mut master_recording, "record output")
+                .on_hover_text(&self.master_record_path)
+                .context_menu(|ui| {
+                    ui.horizontal(|ui| {
+                        ui.label("Output file");
+                        ui.text_edit_singleline(&mut self.master_record_path);
+                    });
+                });
+            if master_record_toggle.clicked() {
+                let res = if master_recording {
+                    self.state
+                        .start_master_recording(std::path::Path::new(&self.master_record_path))
+                } else {
+                    self.state.stop_master_recording()
+                };
+                if let Err(err) = res {
+                    error!("Failed to record output: {}", err);
+                }
+            }
+            if self.state.is_master_recording() {
+                let (frames, overruns) = self.state.master_recording_stats();
+                ui.label(format!("{:.1}s", frames as f64 / self.state.sample_rate()));
+                if overruns > 0 {
+                    ui.colored_label(egui::Color32::RED, format!("{overruns} frames dropped"));
+                }
+            }
+            let bounce_progress = *self.bounce_progress.lock().unwrap();
+            match bounce_progress {
+                Some(progress) => {
+                    ui.add(
+                        egui::ProgressBar::new(progress)
+                            .desired_width(64.0)
+                            .text("bouncing"),
+                    );
+                }
+                None => {
+                    let bounce = ui
+                        .button("bounce")
+                        .on_hover_text(&self.bounce_path)
+                        .context_menu(|ui| {
+                            ui.horizontal(|ui| {
+                                ui.label("Output file");
+                                ui.text_edit_singleline(&mut self.bounce_path);
+                            });
+                            ui.add(
+                                egui::DragValue::new(&mut self.bounce_measures)
+                                    .clamp_range(1..=999)
+                                    .suffix(" measures"),
+                            );
+                        });
+                    if bounce.clicked() {
+                        self.start_bounce(ui.ctx().clone());
+                    }
+                }
+            }
+            ui.label(self.state.cpu_load()).on_hover_text(format!(
+                "{} xruns\n{} frames at {} Hz",
+                self.state.xruns(),
+                self.state.buffer_size(),
+                self.state.sample_rate()
+            ));
+            let stats = self.state.engine_stats();
+            for (name, dropped) in [
+                ("time info", &stats.time_info_dropped),
+                ("meter", &stats.meters_dropped),
+                ("clip", &stats.clip_detected_dropped),
+                ("MIDI CC", &stats.midi_cc_dropped),
+                ("recording stats", &stats.recording_stats_dropped),
+                ("external tempo", &stats.external_tempo_dropped),
+                ("critical", &stats.critical_dropped),
+            ] {
+                let dropped = dropped.load(std::sync::atomic::Ordering::Relaxed);
+                if dropped > 0 {
+                    ui.label(format!("{name} updates dropped: {dropped}"));
+                }
+            }
+            let midi_output_dropped = self
+                .state
+                .engine_stats()
+                .midi_output_dropped
+                .load(std::sync::atomic::Ordering::Relaxed);
+            if midi_output_dropped > 0 {
+                ui.label(format!("MIDI out events dropped: {midi_output_dropped}"));
             }
-            ui.label(self.state.cpu_load());
             if self.args.enable_profiling {
-                if self
-                    .profile_in_progress
-                    .load(std::sync::atomic::Ordering::Relaxed)
-                {
+                if self.state.is_profiling() {
                     ui.label("profiling in progress...");
                 } else if ui.link("perf profile").clicked() {
-                    self.profile_in_progress
-                        .store(true, std::sync::atomic::Ordering::Relaxed);
-                    let profile_in_progress = self.profile_in_progress.clone();
+                    let profile_error = self.profile_error.clone();
                     let ctx = ui.ctx().clone();
                     self.state.pprof_report(
                         std::time::Duration::from_secs(10),
-                        Box::new(move |svg_report| {
-                            profile_and_show(&ctx, svg_report);
-                            profile_in_progress.store(false, std::sync::atomic::Ordering::Relaxed);
+                        self.args.profile_frequency_hz,
+                        Box::new(move |result| {
+                            let err = match result {
+                                Ok(flamegraph_svg) => {
+                                    profile_and_show(&ctx, flamegraph_svg);
+                                    None
+                                }
+                                Err(err) => {
+                                    error!("Failed to create performance profile: {}", err);
+                                    Some(err.to_string())
+                                }
+                            };
+                            *profile_error.lock().unwrap() = err;
+                            ctx.request_repaint();
                         }),
                     );
                 }
+                if let Some(err) = self.profile_error.lock().unwrap().as_ref() {
+                    ui.colored_label(egui::Color32::RED, err);
+                }
             }
         });
     }
 
+    /// Show a warning with a button to run the plugins of a track again if a
+    /// plugin failure disabled the track.
+    fn show_plugin_error(
+        &mut self,
+        ui: &mut egui::Ui,
+        track_id: i32,
+        plugin_error: Option<&(usize, String)>,
+    ) {
+        let (plugin_index, err) = match plugin_error {
+            Some(e) => e,
+            None => return,
+        };
+        ui.colored_label(egui::Color32::RED, "⚠")
+            .on_hover_text(format!("Disabled by plugin {}: {}", plugin_index + 1, err));
+        if ui
+            .small_button("Re-enable")
+            .on_hover_text("Run the plugins of the track again")
+            .clicked()
+        {
+            if let Err(err) = self.state.enable_track(track_id) {
+                error!("Failed to enable track {}: {}", track_id, err);
+            }
+        }
+    }
+
     fn update_track_list(&mut self, ui: &mut egui::Ui) {
+        let mut is_master_selected = self.selected_track_id == MASTER_TRACK_ID;
+        ui.horizontal(|ui| {
+            show_meter(ui, &self.state.meter(MASTER_TRACK_ID));
+            let plugin_error = self.state.master_track().plugin_error.clone();
+            self.show_plugin_error(ui, MASTER_TRACK_ID, plugin_error.as_ref());
+            let master = self.state.master_track();
+            let mut name = egui::RichText::new(&master.name);
+            if master.properties.disabled {
+                name = name.weak();
+            }
+            if ui.toggle_value(&mut is_master_selected, name).clicked() {
+                self.selected_track_id = if is_master_selected {
+                    MASTER_TRACK_ID
+                } else {
+                    0
+                };
+                if let Err(err) = self.state.set_armed(None) {
+                    log_track_error("disarm the tracks", err);
+                }
+            }
+        });
+        ui.separator();
         let mut tracks_to_delete = HashSet::new();
         let tracks = self.state.iter_tracks().cloned().collect::<Vec<_>>();
         for (idx, track) in tracks.iter().enumerate() {
             ui.push_id(idx, |ui| {
                 ui.horizontal(|ui| {
+                    show_meter(ui, &self.state.meter(track.id));
+                    self.show_plugin_error(ui, track.id, track.plugin_error.as_ref());
                     let mut is_selected = self.selected_track_id == track.id;
-                    if ui.toggle_value(&mut is_selected, &track.name).clicked() {
+                    let mut name = egui::RichText::new(&track.name);
+                    if track.properties.disabled {
+                        name = name.weak();
+                    }
+                    let track_toggle = ui.toggle_value(&mut is_selected, name);
+                    if track_toggle.clicked() {
                         self.selected_track_id = if is_selected { track.id } else { 0 };
-                        self.state.set_armed(Some(self.selected_track_id));
+                        let armed = is_selected.then_some(track.id);
+                        if let Err(err) = self.state.set_armed(armed) {
+                            error!("Failed to arm track {}: {}", track.id, err);
+                        }
+                    }
+                    track_toggle.context_menu(|ui| {
+                        let mut name = track.name.clone();
+                        if ui.text_edit_singleline(&mut name).changed() {
+                            let update = TrackPropertiesUpdate {
+                                name: Some(name),
+                                ..TrackPropertiesUpdate::default()
+                            };
+                            if let Err(err) = self.state.update_track_properties(track.id, update) {
+                                error!("Failed to rename track {}: {}", track.id, err);
+                            }
+                        }
+                        if ui.button("Duplicate").clicked() {
+                            match self.state.duplicate_track(track.id) {
+                                Ok((track_id, errors)) => {
+                                    for err in errors {
+                                        warn!(
+                                            "Plugin left out of duplicated track {}: {}",
+                                            track_id, err
+                                        );
+                                    }
+                                }
+                                Err(err) => log_track_error("duplicate the track", err),
+                            }
+                            self.refresh = true;
+                            ui.close_menu();
+                        }
+                    });
+                    let mut monitoring = track.properties.monitoring;
+                    if ui
+                        .toggle_value(&mut monitoring, "🎧")
+                        .on_hover_text("Monitor MIDI input")
+                        .clicked()
+                    {
+                        if let Err(err) = self.state.set_track_monitoring(track.id, monitoring) {
+                            error!("Failed to set monitoring for track {}: {}", track.id, err);
+                        }
+                    }
+                    let midi_inputs = self.state.midi_input_names().to_vec();
+                    if midi_inputs.len() > 1 {
+                        let source_text = |source: Option<usize>| match source {
+                            Some(s) => format!("In {}", s + 1),
+                            None => "Any".to_string(),
+                        };
+                        let mut source = track.properties.midi_source;
+                        egui::ComboBox::from_id_source("midi_source")
+                            .width(60.0)
+                            .selected_text(source_text(source))
+                            .show_ui(ui, |ui| {
+                                ui.selectable_value(&mut source, None, source_text(None))
+                                    .on_hover_text("Inputs that are not routed to a track");
+                                for (s, name) in midi_inputs.iter().enumerate() {
+                                    ui.selectable_value(&mut source, Some(s), source_text(Some(s)))
+                                        .on_hover_text(name);
+                                }
+                            })
+                            .response
+                            .on_hover_text("MIDI input");
+                        if source != track.properties.midi_source {
+                            if let Err(err) = self.state.set_track_midi_source(track.id, source) {
+                                error!("Failed to set MIDI input for track {}: {}", track.id, err);
+                            }
+                        }
+                    }
+                    let channel_text = |channel: Option<u8>| match channel {
+                        Some(c) => format!("Ch {}", c + 1),
+                        None => "All".to_string(),
+                    };
+                    let mut channel = track.properties.midi_channel;
+                    egui::ComboBox::from_id_source("midi_channel")
+                        .width(60.0)
+                        .selected_text(channel_text(channel))
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(&mut channel, None, channel_text(None));
+                            for c in 0..16 {
+                                ui.selectable_value(&mut channel, Some(c), channel_text(Some(c)));
+                            }
+                        })
+                        .response
+                        .on_hover_text("MIDI input channel");
+                    if channel != track.properties.midi_channel {
+                        if let Err(err) = self.state.set_track_midi_channel(track.id, channel) {
+                            error!("Failed to set MIDI channel for track {}: {}", track.id, err);
+                        }
+                    }
+                    let mut transpose = track.properties.transpose;
+                    let transpose_response = ui
+                        .add(
+                            egui::DragValue::new(&mut transpose)
+                                .clamp_range(-24..=24)
+                                .suffix(" st"),
+                        )
+                        .on_hover_text("Transpose");
+                    if transpose_response.changed() {
+                        if let Err(err) = self.state.set_track_transpose(track.id, transpose) {
+                            error!("Failed to transpose track {}: {}", track.id, err);
+                        }
+                    }
+                    let mut send_midi = track.properties.send_midi;
+                    if ui
+                        .toggle_value(&mut send_midi, "🎹")
+                        .on_hover_text("Send MIDI from plugins to the MIDI output")
+                        .clicked()
+                    {
+                        if let Err(err) = self.state.set_track_send_midi(track.id, send_midi) {
+                            error!("Failed to set MIDI out for track {}: {}", track.id, err);
+                        }
+                    }
+                    if ui.button("🔔").on_hover_text("Play a test tone").clicked() {
+                        if let Err(err) = self.state.trigger_note(
+                            track.id,
+                            60,
+                            100,
+                            std::time::Duration::from_millis(500),
+                        ) {
+                            error!("Failed to play test tone on track {}: {}", track.id, err);
+                        }
+                    }
+                    let mut solo = track.properties.solo;
+                    if ui.toggle_value(&mut solo, "S").clicked() {
+                        if let Err(err) = self.state.set_track_solo(track.id, solo) {
+                            log_track_error("solo the track", err);
+                        }
+                    }
+                    let mut frozen = track.frozen.is_some();
+                    let freeze_toggle = ui.add_enabled(
+                        track.clip.is_some(),
+                        egui::SelectableLabel::new(frozen, "❄"),
+                    );
+                    if freeze_toggle.on_hover_text("Freeze").clicked() {
+                        frozen = !frozen;
+                        let res = if frozen {
+                            self.state.freeze_track(track.id)
+                        } else {
+                            self.state.unfreeze_track(track.id)
+                        };
+                        if let Err(err) = res {
+                            error!("Failed to freeze track: {}", err);
+                        }
                     }
                     if egui::Button::new("🗑")
                         .fill(eframe::epaint::Color32::DARK_RED)
@@ -201,37 +1220,105 @@ impl App {
         }
         if !tracks_to_delete.is_empty() {
             self.refresh = true;
-            self.state.delete_tracks(tracks_to_delete).unwrap();
+            if let Err(err) = self.state.delete_tracks(tracks_to_delete) {
+                log_track_error("delete the tracks", err);
+            }
         }
     }
 
     fn update_track(&mut self, ui: &mut egui::Ui) {
-        let track = match self
-            .state
-            .iter_tracks()
-            .find(|t| t.id == self.selected_track_id)
-        {
+        let track = match self.state.track(self.selected_track_id) {
             Some(t) => t.clone(),
             None => return,
         };
         ui.separator();
         ui.label(&track.name);
-        for (idx, track_plugin) in track.plugins.iter().enumerate() {
-            let plugin_index = match self.plugin_to_index.get(&track_plugin.plugin_id) {
-                Some(idx) => idx,
-                None => {
-                    error!(
-                        "Could not find plugin with id {:?}.",
-                        track_plugin.plugin_id
-                    );
-                    continue;
+        // The master track only has plugins.
+        if track.id != MASTER_TRACK_ID {
+            let mut volume = track.properties.volume;
+            let volume_slider = ui
+                .add(egui::Slider::new(&mut volume, 0.0..=1.0).text("volume"))
+                .context_menu(|ui| {
+                    midi_learn_button(ui, &mut self.state, MidiLearnTarget::TrackVolume(track.id));
+                });
+            if volume_slider.changed() {
+                if let Err(err) = self.state.set_track_volume(track.id, volume) {
+                    log_track_error("set the track volume", err);
                 }
-            };
-            let plugin = match self.plugins.get(*plugin_index) {
-                Some(p) => p,
-                None => {
-                    error!("Could not find plugin with index {:?}.", plugin_index);
-                    continue;
+            }
+            let mut pan = track.properties.pan;
+            if ui
+                .add(egui::Slider::new(&mut pan, -1.0..=1.0).text("pan"))
+                .changed()
+            {
+                if let Err(err) = self.state.set_track_pan(track.id, pan) {
+                    log_track_error("set the track pan", err);
+                }
+            }
+            let mut sampling = self.state.sample_recording_track() == Some(track.id);
+            let sample_toggle = ui
+                .toggle_value(&mut sampling, "🎙 Sample")
+                .on_hover_text("Record the audio input into a sampler on this track");
+            if sample_toggle.clicked() {
+                let res = if sampling {
+                    self.state.record_sample(track.id, SAMPLE_RECORDING_SECONDS)
+                } else {
+                    self.state.stop_sample_recording()
+                };
+                if let Err(err) = res {
+                    error!("Failed to record sample: {}", err);
+                }
+            }
+            if let Some((_, audio)) = track.recorded_sample() {
+                let seconds = audio.buffer_size() as f64 / self.state.sample_rate();
+                ui.horizontal(|ui| {
+                    ui.label(format!("Recorded audio: {seconds:.1}s"));
+                    if ui.button("Normalize").clicked() {
+                        if let Err(err) = self.state.normalize_track_audio(track.id, 1.0) {
+                            error!("Failed to normalize audio: {}", err);
+                        }
+                    }
+                });
+            }
+            if let Some(length) = track.clip_length() {
+                show_notes(ui, &track.notes(), length);
+            }
+            let has_instrument = track.plugins.iter().any(|p| {
+                self.plugin_to_index
+                    .get(&p.plugin_id)
+                    .and_then(|idx| self.plugins.get(*idx))
+                    .map(|p| matches!(p.class, PluginClass::Instrument))
+                    .unwrap_or(false)
+            });
+            if has_instrument {
+                let mut pattern = track.step_pattern.clone().unwrap_or_default();
+                if show_step_pattern(ui, &mut pattern) {
+                    if let Err(err) = self.state.set_step_pattern(track.id, pattern) {
+                        error!("Failed to set step pattern: {}", err);
+                    }
+                }
+            }
+        }
+        for (idx, track_plugin) in track.plugins.iter().enumerate() {
+            let plugin = if track_plugin.missing {
+                None
+            } else {
+                let plugin_index = match self.plugin_to_index.get(&track_plugin.plugin_id) {
+                    Some(idx) => idx,
+                    None => {
+                        error!(
+                            "Could not find plugin with id {:?}.",
+                            track_plugin.plugin_id
+                        );
+                        continue;
+                    }
+                };
+                match self.plugins.get(*plugin_index) {
+                    Some(p) => Some(p),
+                    None => {
+                        error!("Could not find plugin with index {:?}.", plugin_index);
+                        continue;
+                    }
                 }
             };
             ui.push_id(idx, |ui| {
@@ -243,14 +1330,400 @@ impl App {
                         .ui(ui)
                         .clicked()
                     {
-                        self.state.remove_plugin_from_track(track.id, idx).unwrap();
+                        if let Err(err) = self.state.remove_plugin_from_track(track.id, idx) {
+                            log_track_error("remove the plugin", err);
+                        }
+                        self.param_editor = None;
                         self.refresh = true;
                     }
-                    ui.label(&plugin.name);
+                    let moves = [
+                        ("⬆", idx.checked_sub(1)),
+                        ("⬇", Some(idx + 1).filter(|to| *to < track.plugins.len())),
+                    ];
+                    for (label, to) in moves {
+                        if ui
+                            .add_enabled(to.is_some(), egui::Button::new(label))
+                            .clicked()
+                        {
+                            if let Some(to) = to {
+                                if let Err(err) = self.state.move_plugin(track.id, idx, to) {
+                                    error!("Failed to move plugin: {}", err);
+                                }
+                                self.param_editor = None;
+                                self.refresh = true;
+                            }
+                        }
+                    }
+                    match plugin {
+                        Some(plugin) => {
+                            if ui.link(&plugin.name).clicked() {
+                                self.param_editor = Some((track.id, idx));
+                            }
+                        }
+                        None => {
+                            ui.colored_label(
+                                egui::Color32::RED,
+                                format!("{} (missing)", track_plugin.plugin_id),
+                            )
+                            .on_hover_text("The plugin is not installed. Audio passes through.");
+                        }
+                    }
+                });
+            });
+        }
+    }
+
+    /// Start bouncing the project to `self.bounce_path` in the background.
+    fn start_bounce(&mut self, ctx: egui::Context) {
+        *self.bounce_progress.lock().unwrap() = Some(0.0);
+        let progress = self.bounce_progress.clone();
+        let done = self.bounce_progress.clone();
+        let progress_ctx = ctx.clone();
+        let res = self.state.bounce(
+            std::path::Path::new(&self.bounce_path),
+            self.bounce_measures,
+            Box::new(move |p| {
+                *progress.lock().unwrap() = Some(p);
+                progress_ctx.request_repaint();
+            }),
+            Box::new(move |res| {
+                if let Err(err) = res {
+                    error!("Failed to bounce: {}", err);
+                }
+                *done.lock().unwrap() = None;
+                ctx.request_repaint();
+            }),
+        );
+        if let Err(err) = res {
+            error!("Failed to bounce: {}", err);
+            *self.bounce_progress.lock().unwrap() = None;
+        }
+    }
+
+    fn update_seek_dialog(&mut self, ctx: &egui::Context) {
+        let (mut measure, mut beat) = match self.seek_dialog {
+            Some(p) => p,
+            None => return,
+        };
+        let mut open = true;
+        let mut seek = false;
+        egui::Window::new("Seek")
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Measure");
+                    ui.add(egui::DragValue::new(&mut measure).clamp_range(0..=i16::MAX));
+                    ui.label("Beat");
+                    ui.add(egui::DragValue::new(&mut beat).clamp_range(0..=3));
                 });
+                seek = ui.button("Go").clicked();
             });
+        self.seek_dialog = Some((measure, beat));
+        if seek {
+            self.state.seek(measure, beat);
+        }
+        if seek || !open {
+            self.seek_dialog = None;
         }
     }
+
+    fn update_param_editor(&mut self, ctx: &egui::Context) {
+        let (track_id, plugin_index) = match self.param_editor {
+            Some(e) => e,
+            None => return,
+        };
+        let track_plugin = match self
+            .state
+            .track(track_id)
+            .and_then(|t| t.plugins.get(plugin_index))
+        {
+            Some(p) => p.clone(),
+            None => {
+                self.param_editor = None;
+                return;
+            }
+        };
+        let plugin = match self
+            .plugin_to_index
+            .get(&track_plugin.plugin_id)
+            .and_then(|idx| self.plugins.get(*idx))
+        {
+            Some(p) => p,
+            None => {
+                self.param_editor = None;
+                return;
+            }
+        };
+        let state = &self.state;
+        let presets = self
+            .plugin_presets
+            .entry(plugin.id.clone())
+            .or_insert_with(|| match state.get_plugin_presets(&plugin.id) {
+                Ok(presets) => presets,
+                Err(err) => {
+                    error!("Failed to get presets for {}: {}", plugin.name, err);
+                    Vec::new()
+                }
+            });
+        let mut open = true;
+        egui::Window::new(&plugin.name)
+            .id(egui::Id::new("param_editor"))
+            .open(&mut open)
+            .show(ctx, |ui| {
+                if !presets.is_empty() {
+                    let mut selected = None;
+                    egui::ComboBox::from_label("Preset")
+                        .selected_text("Load preset...")
+                        .show_ui(ui, |ui| {
+                            for preset in presets.iter() {
+                                if ui.selectable_label(false, &preset.name).clicked() {
+                                    selected = Some(preset);
+                                }
+                            }
+                        });
+                    if let Some(preset) = selected {
+                        match self
+                            .state
+                            .load_plugin_preset(track_id, plugin_index, &preset.uri)
+                        {
+                            Ok(skipped) if !skipped.is_empty() => warn!(
+                                "Preset {} could not set {}",
+                                preset.name,
+                                skipped.join(", ")
+                            ),
+                            Ok(_) => (),
+                            Err(err) => error!("Failed to load preset {}: {}", preset.name, err),
+                        }
+                    }
+                }
+                if plugin.id == DRUM_KIT_PLUGIN_ID {
+                    ui.horizontal(|ui| {
+                        ui.label("Kit file");
+                        ui.text_edit_singleline(&mut self.drum_kit_text);
+                        if ui.button("Load").clicked() {
+                            let path = std::path::Path::new(&self.drum_kit_text);
+                            if let Err(err) = self.state.load_drum_kit(track_id, plugin_index, path)
+                            {
+                                error!("Failed to load drum kit: {}", err);
+                            }
+                        }
+                    });
+                } else if plugin.params.is_empty() {
+                    ui.label("This plugin has no parameters.");
+                }
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    for param in plugin.params.iter() {
+                        let mut value = track_plugin.param_value(param);
+                        let min = param.min_value.unwrap_or(param.default_value.min(0.0));
+                        let max = param.max_value.unwrap_or(param.default_value.max(1.0));
+                        let slider = ui
+                            .add(egui::Slider::new(&mut value, min..=max).text(&param.name))
+                            .context_menu(|ui| {
+                                let target = MidiLearnTarget::PluginParam {
+                                    track_id,
+                                    plugin_index,
+                                    port_index: param.index,
+                                };
+                                midi_learn_button(ui, &mut self.state, target);
+                            });
+                        if slider.changed() {
+                            if let Err(err) = self.state.set_plugin_param(
+                                track_id,
+                                plugin_index,
+                                &param.index.to_string(),
+                                value,
+                            ) {
+                                error!("Failed to set parameter {}: {}", param.name, err);
+                            }
+                        }
+                    }
+                });
+            });
+        if !open {
+            self.param_editor = None;
+        }
+    }
+}
+
+/// Show an editable grid for `pattern`. Returns true if `pattern` was changed.
+fn show_step_pattern(ui: &mut egui::Ui, pattern: &mut StepPattern) -> bool {
+    let mut changed = false;
+    egui::CollapsingHeader::new("Step Sequencer").show(ui, |ui| {
+        let mut remove = None;
+        for (row_idx, row) in pattern.rows.iter_mut().enumerate() {
+            ui.push_id(row_idx, |ui| {
+                ui.horizontal(|ui| {
+                    changed |= ui
+                        .add(egui::DragValue::new(&mut row.pitch).clamp_range(0..=127))
+                        .on_hover_text("Midi note")
+                        .changed();
+                    for (step, on) in row.steps.iter_mut().enumerate() {
+                        let label = if step % 4 == 0 { "■" } else { "□" };
+                        changed |= ui.toggle_value(on, label).changed();
+                    }
+                    if ui.button("✖").clicked() {
+                        remove = Some(row_idx);
+                    }
+                });
+            });
+        }
+        if let Some(row_idx) = remove {
+            pattern.rows.remove(row_idx);
+            changed = true;
+        }
+        if pattern.rows.len() < STEP_PATTERN_MAX_ROWS && ui.button("Add row").clicked() {
+            let pitch = pattern.rows.last().map(|r| r.pitch + 1).unwrap_or(36);
+            pattern.rows.push(StepRow {
+                pitch: pitch.min(127),
+                steps: [false; STEP_PATTERN_STEPS],
+            });
+            changed = true;
+        }
+    });
+    changed
+}
+
+/// Draw a read-only piano roll of `notes` in a clip that is `length` beats
+/// long.
+fn show_notes(ui: &mut egui::Ui, notes: &[Note], length: f64) {
+    let lowest = notes.iter().map(|n| n.pitch).min().unwrap_or(60).min(60);
+    let highest = notes
+        .iter()
+        .map(|n| n.pitch)
+        .max()
+        .unwrap_or(72)
+        .max(lowest + 12);
+    let rows = (highest - lowest + 1) as f32;
+    let size = egui::vec2(ui.available_width(), 4.0 * rows);
+    let (rect, _) = ui.allocate_exact_size(size, egui::Sense::hover());
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 0.0, ui.visuals().extreme_bg_color);
+    let beat_width = rect.width() / length as f32;
+    for beat in 0..length as usize {
+        let x = rect.left() + beat as f32 * beat_width;
+        let color = if beat % 4 == 0 {
+            ui.visuals().widgets.active.bg_fill
+        } else {
+            ui.visuals().widgets.noninteractive.bg_stroke.color
+        };
+        painter.vline(x, rect.y_range(), (1.0, color));
+    }
+    let row_height = rect.height() / rows;
+    for note in notes {
+        let top = rect.bottom() - (note.pitch - lowest + 1) as f32 * row_height;
+        let note_rect = egui::Rect::from_min_size(
+            egui::pos2(rect.left() + note.start as f32 * beat_width, top),
+            egui::vec2(note.length as f32 * beat_width, row_height),
+        );
+        painter.rect_filled(note_rect, 1.0, ui.visuals().selection.bg_fill);
+    }
+}
+
+/// The level above which a meter is drawn in red.
+const METER_RED_DB: f32 = -1.0;
+
+/// Draw a small vertical level bar for `meter` with a line at its held peak.
+fn show_meter(ui: &mut egui::Ui, meter: &Meter) {
+    let size = egui::vec2(6.0, ui.spacing().interact_size.y);
+    let (rect, _) = ui.allocate_exact_size(size, egui::Sense::hover());
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 0.0, ui.visuals().extreme_bg_color);
+    let y_at = |db: f32| {
+        let fraction = ((db - METER_FLOOR_DB) / -METER_FLOOR_DB).clamp(0.0, 1.0);
+        rect.bottom() - fraction * rect.height()
+    };
+    if let Some(level) = meter.level_db() {
+        let red_y = y_at(METER_RED_DB);
+        let top = y_at(level);
+        let green = egui::Rect::from_x_y_ranges(rect.x_range(), top.max(red_y)..=rect.bottom());
+        painter.rect_filled(green, 0.0, egui::Color32::GREEN);
+        if level > METER_RED_DB {
+            let red = egui::Rect::from_x_y_ranges(rect.x_range(), top..=red_y);
+            painter.rect_filled(red, 0.0, egui::Color32::RED);
+        }
+    }
+    if let Some(peak) = meter.peak_db() {
+        let color = if peak > METER_RED_DB {
+            egui::Color32::RED
+        } else {
+            ui.visuals().strong_text_color()
+        };
+        painter.hline(rect.x_range(), y_at(peak), (1.0, color));
+    }
+}
+
+/// Show a button that maps the next MIDI controller that is moved to
+/// `target`.
+fn midi_learn_button(ui: &mut egui::Ui, state: &mut State, target: MidiLearnTarget) {
+    if ui.button("MIDI learn").clicked() {
+        if let Err(err) = state.start_midi_learn(target) {
+            error!("Failed to start MIDI learn for {}: {}", target, err);
+        }
+        ui.close_menu();
+    }
+}
+
+/// Create a new armed track containing `plugin` and return its id. Returns
+/// `None` if the track could not be created.
+fn create_track_with_plugin(state: &mut State, plugin: &Plugin) -> Option<i32> {
+    let track_id = match state.create_track(Some(plugin.name.clone())) {
+        Ok(track_id) => track_id,
+        Err(err) => {
+            log_track_error("create a track", err);
+            return None;
+        }
+    };
+    if let Err(err) = state.add_plugin_to_track(track_id, &plugin.id) {
+        log_add_plugin_error(plugin, err);
+    }
+    if let Err(err) = state.set_armed(Some(track_id)) {
+        log_track_error("arm the new track", err);
+    }
+    Some(track_id)
+}
+
+/// Log why `action` could not be done to a track.
+fn log_track_error(action: &str, err: Error) {
+    match err {
+        Error::EngineUnavailable => error!(
+            "Could not {}: the audio engine is not available. Try reconnecting to JACK.",
+            action
+        ),
+        Error::TrackNotFound(track_id) => {
+            warn!("Could not {}: track {} no longer exists.", action, track_id)
+        }
+        err => error!("Could not {}: {}", action, err),
+    }
+}
+
+/// Get the icon that is shown next to plugins of `class`.
+fn plugin_class_icon(class: PluginClass) -> &'static str {
+    match class {
+        PluginClass::Instrument => "🎹",
+        PluginClass::Effect => "🔊",
+    }
+}
+
+/// Log why `plugin` could not be added to a track.
+fn log_add_plugin_error(plugin: &Plugin, err: Error) {
+    match err {
+        Error::EngineUnavailable => error!(
+            "Could not add {}: the audio engine is not available. Try reconnecting to JACK.",
+            plugin.name
+        ),
+        Error::PluginInstantiation { source, .. } => {
+            error!("{} could not be loaded: {}", plugin.name, source)
+        }
+        Error::TrackNotFound(track_id) => {
+            warn!(
+                "Could not add {}: track {} no longer exists.",
+                plugin.name, track_id
+            )
+        }
+        err => error!("Could not add {}: {}", plugin.name, err),
+    }
 }
 
 /// Request and retrieve a profile from client and open the results in a
@@ -266,3 +1739,13 @@ fn profile_and_show(ctx: &egui::Context, flamegraph_svg: Vec<u8>) {
     }
     ctx.output_mut(|o| o.open_url(flamegraph_path));
 }
+
+/// Get the directory that autosaves are written to.
+fn autosave_dir() -> Option<PathBuf> {
+    dirs::data_local_dir().map(|d| d.join("mini-leebee"))
+}
+
+/// Get the time the file at `path` was last modified.
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
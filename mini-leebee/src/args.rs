@@ -8,7 +8,93 @@ pub struct Arguments {
     #[arg(short, long, default_value = "info")]
     pub log_level: log::LevelFilter,
 
-    /// If true, profiling will be enabled.
+    /// If true, profiling will be enabled. If built with the `profile-audio`
+    /// feature, the stages of the audio callback are also served to
+    /// puffin_viewer.
     #[arg(short, long, default_value = "false")]
     pub enable_profiling: bool,
+
+    /// The number of samples taken each second by a performance profile.
+    #[arg(long, default_value = "1000")]
+    pub profile_frequency_hz: i32,
+
+    /// The backend that runs the audio engine.
+    #[arg(long, value_enum, default_value = "jack")]
+    pub backend: Backend,
+
+    /// The sample rate of the dummy backend. This is also used by the JACK
+    /// backend until it connects to the JACK server.
+    #[arg(long, default_value = "48000")]
+    pub dummy_sample_rate: f64,
+
+    /// The number of frames in each block of the dummy backend. This is also
+    /// used by the JACK backend until it connects to the JACK server.
+    #[arg(long, default_value = "512")]
+    pub dummy_buffer_size: usize,
+
+    /// The number of notifications that may be queued from the audio engine.
+    #[arg(long, default_value = "2048")]
+    pub notifications_capacity: usize,
+
+    /// The minimum number of milliseconds between time info updates.
+    #[arg(long, default_value = "0")]
+    pub time_info_interval_ms: u64,
+
+    /// The minimum number of milliseconds between meter updates.
+    #[arg(long, default_value = "30")]
+    pub meter_interval_ms: u64,
+
+    /// Ask for confirmation before adding a plugin whose estimated cost is
+    /// above this fraction of the processing budget.
+    #[arg(long, default_value = "0.1")]
+    pub plugin_cost_threshold: f64,
+
+    /// The number of JACK midi input ports. Only the first is automatically
+    /// connected to physical devices.
+    #[arg(long, default_value = "1")]
+    pub midi_inputs: usize,
+
+    /// The directory to load sounds and samples from.
+    #[arg(long, default_value = "resources")]
+    pub resources_dir: std::path::PathBuf,
+
+    /// The number of minutes between autosaves. Autosave is disabled if 0.
+    #[arg(long, default_value = "5")]
+    pub autosave_minutes: u64,
+
+    /// The number of autosaves to keep.
+    #[arg(long, default_value = "10")]
+    pub autosave_keep: usize,
+}
+
+/// A backend that runs the audio engine.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    /// Process audio and midi through JACK.
+    Jack,
+    /// Process on a timer without any audio or midi devices.
+    Dummy,
+}
+
+impl Arguments {
+    /// Get the configuration for the audio engine processor.
+    pub fn processor_config(&self) -> audio_engine::ProcessorConfig {
+        audio_engine::ProcessorConfig {
+            notifications_capacity: self.notifications_capacity,
+            time_info_interval: std::time::Duration::from_millis(self.time_info_interval_ms),
+            meter_interval: std::time::Duration::from_millis(self.meter_interval_ms),
+            resources_dir: self.resources_dir.clone(),
+            midi_inputs: self.midi_inputs,
+            ..audio_engine::ProcessorConfig::default()
+        }
+    }
+
+    /// Get the configuration for the state.
+    pub fn state_config(&self) -> mini_leebee_state::StateConfig {
+        mini_leebee_state::StateConfig {
+            resources_dir: self.resources_dir.clone(),
+            blacklist_path: dirs::config_dir()
+                .map(|d| d.join("mini-leebee").join("blacklist.toml")),
+        }
+    }
 }
@@ -0,0 +1,209 @@
+use eframe::egui;
+use log::*;
+use mini_leebee_state::State;
+
+/// The number of octaves shown on the keyboard.
+const OCTAVES: u8 = 2;
+
+/// The velocity of notes played with the keyboard.
+const VELOCITY: u8 = 100;
+
+/// The computer keys that play notes, in order of semitones from the lowest
+/// note of the keyboard.
+const COMPUTER_KEYS: [egui::Key; 13] = [
+    egui::Key::A,
+    egui::Key::W,
+    egui::Key::S,
+    egui::Key::E,
+    egui::Key::D,
+    egui::Key::F,
+    egui::Key::T,
+    egui::Key::G,
+    egui::Key::Y,
+    egui::Key::H,
+    egui::Key::U,
+    egui::Key::J,
+    egui::Key::K,
+];
+
+/// An on screen piano keyboard that plays the armed track.
+#[derive(Debug)]
+pub struct Keyboard {
+    /// The midi note of the lowest key.
+    lowest_note: u8,
+    /// The note held down with the mouse.
+    mouse_note: Option<u8>,
+    /// The notes held down with the computer keyboard.
+    key_notes: Vec<(egui::Key, u8)>,
+}
+
+impl Default for Keyboard {
+    fn default() -> Keyboard {
+        Keyboard {
+            lowest_note: 48,
+            mouse_note: None,
+            key_notes: Vec::new(),
+        }
+    }
+}
+
+impl Keyboard {
+    /// Show the keyboard and send the notes that are played to `state`.
+    pub fn show(&mut self, ui: &mut egui::Ui, state: &State) {
+        ui.horizontal(|ui| {
+            if ui.button("◀").on_hover_text("Octave down").clicked() {
+                self.release_all(state);
+                self.lowest_note = self.lowest_note.saturating_sub(12);
+            }
+            ui.label(format!("C{}", self.lowest_note as i32 / 12 - 1));
+            if ui.button("▶").on_hover_text("Octave up").clicked() && self.lowest_note < 96 {
+                self.release_all(state);
+                self.lowest_note += 12;
+            }
+            ui.weak("Click the keyboard to play with A W S E D F T G Y H U J K.");
+        });
+        let white_keys = OCTAVES as usize * 7 + 1;
+        let key_width = (ui.available_width() / white_keys as f32).clamp(12.0, 32.0);
+        let size = egui::vec2(key_width * white_keys as f32, key_width * 4.0);
+        let (rect, response) = ui.allocate_exact_size(size, egui::Sense::click_and_drag());
+        if response.clicked() || response.drag_started() {
+            response.request_focus();
+        }
+        let keys = self.keys(rect, key_width);
+
+        // Notes follow the mouse while it is held and are released as soon as
+        // the button is let go, even outside of the keyboard.
+        let pointer = ui.input(|i| (i.pointer.primary_down(), i.pointer.interact_pos()));
+        let hovered_note = match pointer {
+            (true, Some(pos)) if response.is_pointer_button_down_on() => keys
+                .iter()
+                .rev()
+                .find(|(_, key_rect, _)| key_rect.contains(pos))
+                .map(|(note, _, _)| *note),
+            _ => None,
+        };
+        if hovered_note != self.mouse_note {
+            if let Some(note) = self.mouse_note.take() {
+                send_note(state, note, false);
+            }
+            if let Some(note) = hovered_note {
+                send_note(state, note, true);
+            }
+            self.mouse_note = hovered_note;
+        }
+
+        if response.has_focus() {
+            self.handle_computer_keys(ui, state);
+        } else {
+            self.release_keys(state);
+        }
+
+        let painter = ui.painter_at(rect);
+        for (note, key_rect, is_black) in keys.iter() {
+            let held =
+                self.mouse_note == Some(*note) || self.key_notes.iter().any(|(_, n)| n == note);
+            let fill = match (held, is_black) {
+                (true, _) => ui.visuals().selection.bg_fill,
+                (false, true) => egui::Color32::BLACK,
+                (false, false) => egui::Color32::WHITE,
+            };
+            painter.rect(
+                *key_rect,
+                1.0,
+                fill,
+                egui::Stroke::new(1.0, egui::Color32::DARK_GRAY),
+            );
+        }
+        if response.has_focus() {
+            painter.rect_stroke(rect, 0.0, ui.visuals().selection.stroke);
+        }
+    }
+
+    /// Release every note that is held.
+    pub fn release_all(&mut self, state: &State) {
+        if let Some(note) = self.mouse_note.take() {
+            send_note(state, note, false);
+        }
+        self.release_keys(state);
+    }
+
+    /// Release the notes held with the computer keyboard.
+    fn release_keys(&mut self, state: &State) {
+        for (_, note) in self.key_notes.drain(..) {
+            send_note(state, note, false);
+        }
+    }
+
+    /// Play notes for the computer keys that were pressed or released.
+    fn handle_computer_keys(&mut self, ui: &egui::Ui, state: &State) {
+        let events = ui.input(|i| i.events.clone());
+        for event in events {
+            let (key, pressed) = match event {
+                egui::Event::Key {
+                    key,
+                    pressed,
+                    repeat: false,
+                    ..
+                } => (key, pressed),
+                _ => continue,
+            };
+            let offset = match COMPUTER_KEYS.iter().position(|k| *k == key) {
+                Some(offset) => offset as u8,
+                None => continue,
+            };
+            if pressed {
+                if self.key_notes.iter().any(|(k, _)| *k == key) {
+                    continue;
+                }
+                let note = (self.lowest_note + offset).min(127);
+                send_note(state, note, true);
+                self.key_notes.push((key, note));
+            } else if let Some(idx) = self.key_notes.iter().position(|(k, _)| *k == key) {
+                let (_, note) = self.key_notes.remove(idx);
+                send_note(state, note, false);
+            }
+        }
+    }
+
+    /// Get the note, area, and whether it is a black key for each key. Black
+    /// keys come after white keys so that they are drawn on top.
+    fn keys(&self, rect: egui::Rect, key_width: f32) -> Vec<(u8, egui::Rect, bool)> {
+        let mut white = Vec::new();
+        let mut black = Vec::new();
+        let mut white_index = 0;
+        for semitone in 0..=OCTAVES * 12 {
+            let note = self.lowest_note.saturating_add(semitone).min(127);
+            let is_black = matches!(semitone % 12, 1 | 3 | 6 | 8 | 10);
+            if is_black {
+                let x = rect.left() + white_index as f32 * key_width - key_width * 0.3;
+                let key_rect = egui::Rect::from_min_size(
+                    egui::pos2(x, rect.top()),
+                    egui::vec2(key_width * 0.6, rect.height() * 0.6),
+                );
+                black.push((note, key_rect, true));
+            } else {
+                let x = rect.left() + white_index as f32 * key_width;
+                let key_rect = egui::Rect::from_min_size(
+                    egui::pos2(x, rect.top()),
+                    egui::vec2(key_width, rect.height()),
+                );
+                white.push((note, key_rect, false));
+                white_index += 1;
+            }
+        }
+        white.extend(black);
+        white
+    }
+}
+
+/// Send a note on or note off for `note` on the first channel.
+fn send_note(state: &State, note: u8, on: bool) {
+    let bytes = if on {
+        [0x90, note, VELOCITY]
+    } else {
+        [0x80, note, 0]
+    };
+    if let Err(err) = state.send_midi(&bytes) {
+        warn!("Failed to send note {}: {}", note, err);
+    }
+}
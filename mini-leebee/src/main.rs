@@ -1,25 +1,65 @@
 use clap::Parser;
+use jack_adapter::AudioAdapter;
 use log::*;
 
 pub mod app;
 pub mod args;
+pub mod keyboard;
+pub mod settings;
+pub mod wizard;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = args::Arguments::parse();
     env_logger::builder().filter_level(args.log_level).init();
     info!("{:?}", args);
     info!("Working directory: {:?}", std::env::current_dir());
+    let settings = settings::Settings::load();
+    info!("Settings: {:?}", settings);
+    #[cfg(feature = "profile-audio")]
+    let _puffin_server = args.enable_profiling.then(start_puffin_server).flatten();
 
     eframe::run_native(
         "Mini LeeBee",
         eframe::NativeOptions::default(),
         Box::new(|_| {
-            let jack_adapter = jack_adapter::JackAdapter::new().unwrap();
-            jack_adapter.auto_connect();
-            let state = mini_leebee_state::State::new(jack_adapter);
-            Box::new(app::App::new(args, state))
+            let adapter: Box<dyn AudioAdapter> = match args.backend {
+                // If JACK is not running, the app starts disconnected and
+                // connects when reconnecting from the banner or the wizard.
+                args::Backend::Jack => Box::new(jack_adapter::OptionalJackAdapter::new(
+                    &args.processor_config(),
+                    args.dummy_sample_rate,
+                    args.dummy_buffer_size,
+                )),
+                args::Backend::Dummy => Box::new(jack_adapter::DummyAdapter::new(
+                    &args.processor_config(),
+                    args.dummy_sample_rate,
+                    args.dummy_buffer_size,
+                )),
+            };
+            if let Some(s) = settings.as_ref().filter(|s| !s.audio_outputs.is_empty()) {
+                adapter.connect_audio_outputs(&s.audio_outputs);
+            }
+            let state = mini_leebee_state::State::new(adapter, &args.state_config());
+            Box::new(app::App::new(args, state, settings))
         }),
     )
     .unwrap();
     Ok(())
 }
+
+/// Start serving the profiling scopes of the audio engine to puffin_viewer.
+#[cfg(feature = "profile-audio")]
+fn start_puffin_server() -> Option<puffin_http::Server> {
+    profiling::puffin::set_scopes_on(true);
+    let address = format!("127.0.0.1:{}", puffin_http::DEFAULT_PORT);
+    match puffin_http::Server::new(&address) {
+        Ok(server) => {
+            info!("Serving audio profiling scopes on {}.", address);
+            Some(server)
+        }
+        Err(err) => {
+            error!("Failed to start the puffin server on {}: {}", address, err);
+            None
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use std::path::PathBuf;
+
+use log::*;
+use serde::{Deserialize, Serialize};
+
+/// User settings that persist between runs.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Settings {
+    /// The physical ports to connect the audio outputs to. If empty, the
+    /// outputs are connected automatically.
+    #[serde(default)]
+    pub audio_outputs: Vec<String>,
+    /// The number of measures the metronome counts in before recording.
+    #[serde(default = "default_count_in_measures")]
+    pub count_in_measures: u8,
+    /// The file the project was last saved to.
+    #[serde(default)]
+    pub last_project: Option<PathBuf>,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            audio_outputs: Vec::new(),
+            count_in_measures: default_count_in_measures(),
+            last_project: None,
+        }
+    }
+}
+
+impl Settings {
+    /// Get the path to the settings file.
+    pub fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|d| d.join("mini-leebee").join("settings.toml"))
+    }
+
+    /// Load the settings. Returns `None` if no settings have been saved yet.
+    pub fn load() -> Option<Settings> {
+        let path = Settings::path()?;
+        let contents = std::fs::read_to_string(&path).ok()?;
+        match toml::from_str(&contents) {
+            Ok(settings) => Some(settings),
+            Err(err) => {
+                warn!("Failed to parse settings from {:?}: {}", path, err);
+                Some(Settings::default())
+            }
+        }
+    }
+
+    /// Save the settings.
+    pub fn save(&self) -> Result<(), String> {
+        let path = match Settings::path() {
+            Some(p) => p,
+            None => return Err("could not find a config directory".to_string()),
+        };
+        let contents = toml::to_string(self).map_err(|err| err.to_string())?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|err| format!("failed to create {dir:?}: {err}"))?;
+        }
+        std::fs::write(&path, contents).map_err(|err| format!("failed to write {path:?}: {err}"))
+    }
+}
+
+fn default_count_in_measures() -> u8 {
+    1
+}
@@ -0,0 +1,273 @@
+use std::sync::atomic::Ordering;
+
+use eframe::egui;
+use log::*;
+use mini_leebee_state::{Plugin, PluginClass, State};
+
+use crate::settings::Settings;
+
+/// A link with suggestions for LV2 plugins to install.
+const PLUGIN_SUGGESTIONS_URL: &str = "https://lv2plug.in/pages/projects.html";
+
+/// The steps of the setup wizard, in order.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Step {
+    Jack,
+    AudioOutput,
+    MidiInput,
+    Plugins,
+    FirstTrack,
+}
+
+impl Step {
+    fn next(self) -> Step {
+        match self {
+            Step::Jack => Step::AudioOutput,
+            Step::AudioOutput => Step::MidiInput,
+            Step::MidiInput => Step::Plugins,
+            Step::Plugins | Step::FirstTrack => Step::FirstTrack,
+        }
+    }
+
+    fn previous(self) -> Step {
+        match self {
+            Step::Jack | Step::AudioOutput => Step::Jack,
+            Step::MidiInput => Step::AudioOutput,
+            Step::Plugins => Step::MidiInput,
+            Step::FirstTrack => Step::Plugins,
+        }
+    }
+}
+
+/// The result of showing the wizard for a frame.
+#[derive(Debug)]
+pub enum WizardOutcome {
+    /// The wizard is still in progress.
+    InProgress,
+    /// The wizard was closed before it was completed.
+    Closed,
+    /// The wizard was completed.
+    Finished {
+        /// The settings chosen by the user.
+        settings: Settings,
+        /// The plugin to create the first track with, if any.
+        first_track_plugin: Option<Plugin>,
+    },
+}
+
+/// Guides the user through setting up audio, midi, and their first track.
+#[derive(Debug)]
+pub struct Wizard {
+    /// The current step.
+    step: Step,
+    /// The settings being built.
+    settings: Settings,
+    /// The physical ports that audio can be played through.
+    physical_outputs: Vec<String>,
+    /// The number of midi events received when the midi step was entered.
+    midi_events_baseline: u64,
+    /// The index of the instrument to create the first track with.
+    first_track_plugin: Option<usize>,
+    /// The error from the last attempt to reconnect to JACK, if it failed.
+    reconnect_error: Option<String>,
+}
+
+impl Wizard {
+    /// Create a new wizard starting from `settings`.
+    pub fn new(state: &State, mut settings: Settings) -> Wizard {
+        let physical_outputs = state.physical_audio_outputs();
+        if settings.audio_outputs.is_empty() {
+            settings.audio_outputs = physical_outputs.iter().take(2).cloned().collect();
+        }
+        Wizard {
+            step: Step::Jack,
+            settings,
+            physical_outputs,
+            midi_events_baseline: 0,
+            first_track_plugin: None,
+            reconnect_error: None,
+        }
+    }
+
+    /// Show the wizard.
+    pub fn show(
+        &mut self,
+        ctx: &egui::Context,
+        state: &mut State,
+        plugins: &[Plugin],
+    ) -> WizardOutcome {
+        let mut open = true;
+        let mut outcome = WizardOutcome::InProgress;
+        let instruments: Vec<&Plugin> = plugins
+            .iter()
+            .filter(|p| {
+                matches!(p.class, PluginClass::Instrument)
+                    && !p.blacklisted
+                    && p.unsupported.is_none()
+            })
+            .collect();
+        egui::Window::new("Setup")
+            .open(&mut open)
+            .collapsible(false)
+            .show(ctx, |ui| {
+                match self.step {
+                    Step::Jack => self.show_jack(ui, state),
+                    Step::AudioOutput => self.show_audio_output(ui, state),
+                    Step::MidiInput => self.show_midi_input(ui, state),
+                    Step::Plugins if !state.plugins_ready() => {
+                        ui.heading("Plugins");
+                        ui.horizontal(|ui| {
+                            ui.spinner();
+                            ui.label("Scanning plugins…");
+                        });
+                    }
+                    Step::Plugins => show_plugins(ui, plugins.len(), instruments.len()),
+                    Step::FirstTrack => self.show_first_track(ui, &instruments),
+                }
+                ui.separator();
+                ui.horizontal(|ui| {
+                    if self.step != Step::Jack && ui.button("Back").clicked() {
+                        self.step = self.step.previous();
+                    }
+                    if self.step == Step::FirstTrack {
+                        if ui.button("Finish").clicked() {
+                            outcome = WizardOutcome::Finished {
+                                settings: self.settings.clone(),
+                                first_track_plugin: self
+                                    .first_track_plugin
+                                    .and_then(|idx| instruments.get(idx))
+                                    .map(|p| (*p).clone()),
+                            };
+                        }
+                    } else if ui.button("Next").clicked() {
+                        self.leave_step(state);
+                        self.step = self.step.next();
+                        self.enter_step(state);
+                    }
+                });
+            });
+        if !open {
+            return WizardOutcome::Closed;
+        }
+        outcome
+    }
+
+    fn enter_step(&mut self, state: &State) {
+        if self.step == Step::MidiInput {
+            self.midi_events_baseline = state
+                .engine_stats()
+                .midi_events_received
+                .load(Ordering::Relaxed);
+        }
+    }
+
+    fn leave_step(&mut self, state: &State) {
+        if self.step == Step::AudioOutput && !self.settings.audio_outputs.is_empty() {
+            state.connect_audio_outputs(&self.settings.audio_outputs);
+        }
+    }
+
+    fn show_jack(&mut self, ui: &mut egui::Ui, state: &mut State) {
+        ui.heading("JACK");
+        if state.is_disconnected() {
+            ui.colored_label(egui::Color32::RED, "Not connected to the JACK server.");
+            if ui.button("Reconnect").clicked() {
+                match state.reconnect() {
+                    Ok(()) => {
+                        self.physical_outputs = state.physical_audio_outputs();
+                        self.reconnect_error = None;
+                    }
+                    Err(err) => {
+                        error!("Failed to reconnect: {}", err);
+                        self.reconnect_error = Some(err.to_string());
+                    }
+                }
+            }
+            if let Some(err) = self.reconnect_error.as_ref() {
+                ui.colored_label(egui::Color32::RED, err);
+            }
+            return;
+        }
+        ui.label("Connected to the JACK server.");
+        ui.label(format!("Sample rate: {}", state.sample_rate()));
+        ui.label(format!("Buffer size: {}", state.buffer_size()));
+        ui.label(state.cpu_load());
+    }
+
+    fn show_audio_output(&mut self, ui: &mut egui::Ui, state: &State) {
+        ui.heading("Audio Output");
+        if self.physical_outputs.is_empty() {
+            ui.label("No physical audio outputs were found.");
+            return;
+        }
+        self.settings
+            .audio_outputs
+            .resize(2, self.physical_outputs[0].clone());
+        for (idx, (name, output)) in ["Left", "Right"]
+            .iter()
+            .zip(self.settings.audio_outputs.iter_mut())
+            .enumerate()
+        {
+            egui::ComboBox::new(idx, *name)
+                .selected_text(output.as_str())
+                .show_ui(ui, |ui| {
+                    for port in self.physical_outputs.iter() {
+                        ui.selectable_value(output, port.clone(), port);
+                    }
+                });
+        }
+        if ui.button("Test").clicked() {
+            state.connect_audio_outputs(&self.settings.audio_outputs);
+            state.play_sound();
+        }
+    }
+
+    fn show_midi_input(&mut self, ui: &mut egui::Ui, state: &State) {
+        ui.heading("MIDI Input");
+        let midi_events = state
+            .engine_stats()
+            .midi_events_received
+            .load(Ordering::Relaxed);
+        if midi_events > self.midi_events_baseline {
+            ui.label("MIDI input detected!");
+        } else {
+            ui.label("Play a note on your MIDI controller...");
+        }
+    }
+
+    fn show_first_track(&mut self, ui: &mut egui::Ui, instruments: &[&Plugin]) {
+        ui.heading("First Track");
+        if instruments.is_empty() {
+            ui.label("No instruments are installed so no track will be created.");
+            self.first_track_plugin = None;
+            return;
+        }
+        let selected = self
+            .first_track_plugin
+            .and_then(|idx| instruments.get(idx))
+            .map(|p| p.name.as_str())
+            .unwrap_or("None");
+        egui::ComboBox::from_label("Instrument")
+            .selected_text(selected)
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut self.first_track_plugin, None, "None");
+                for (idx, plugin) in instruments.iter().enumerate() {
+                    ui.selectable_value(&mut self.first_track_plugin, Some(idx), &plugin.name);
+                }
+            });
+    }
+}
+
+fn show_plugins(ui: &mut egui::Ui, plugins: usize, instruments: usize) {
+    ui.heading("Plugins");
+    ui.label(format!(
+        "Found {} plugins: {} instruments and {} effects.",
+        plugins,
+        instruments,
+        plugins - instruments
+    ));
+    if instruments == 0 {
+        ui.label("No instruments were found. Install some LV2 instruments to make sound.");
+        ui.hyperlink_to("LV2 plugin suggestions", PLUGIN_SUGGESTIONS_URL);
+    }
+}